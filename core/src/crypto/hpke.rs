@@ -4,6 +4,7 @@
 
 use crate::utils::{Result};
 use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
 use zeroize::Zeroize;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,11 +31,13 @@ pub struct KeyPair {
 }
 
 impl KeyPair {
+    /// Fresh X25519 key pair
     pub fn generate() -> Result<Self> {
-        // TODO: Implement actual key generation
+        // TODO: Add the ML-KEM half of the hybrid KEM
+        let secret = StaticSecret::random_from_rng(rand::rngs::OsRng);
         Ok(Self {
-            public_key: vec![0u8; 32],
-            private_key: vec![0u8; 32],
+            public_key: X25519PublicKey::from(&secret).to_bytes().to_vec(),
+            private_key: secret.to_bytes().to_vec(),
         })
     }
     
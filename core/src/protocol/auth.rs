@@ -2,10 +2,85 @@
 //! 
 //! Provides user authentication, session tokens, and authorization
 
-use crate::utils::{Result};
+use crate::utils::{Error, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use uuid::Uuid;
 
+/// How long an access token is valid for, in seconds
+pub const ACCESS_TOKEN_LIFETIME: i64 = 15 * 60;
+
+/// How long a refresh token stays usable without being used, in seconds
+pub const REFRESH_TOKEN_LIFETIME: i64 = 30 * 24 * 60 * 60;
+
+/// Length of the secret part of a refresh token, in bytes
+pub const REFRESH_TOKEN_LENGTH: usize = 32;
+
+/// Version prefix of tokens produced by [`SessionKeyring::issue`]
+pub const SESSION_TOKEN_VERSION: &str = "v1";
+
+const SESSION_TOKEN_LABEL: &[u8] = b"xipr session token v1";
+const REFRESH_TOKEN_LABEL: &[u8] = b"xipr refresh token v1";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a user may do, within their organisation unless noted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Messages and joins groups with other members
+    Member,
+    /// Adds members and assigns their roles
+    OrgAdmin,
+    /// Reads the organisation's member and device metadata, never content
+    Auditor,
+    /// Runs the platform: creates organisations, but sees none of their metadata
+    Operator,
+}
+
+/// An action checked before a request is served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    SendMessages,
+    ViewMembers,
+    ManageMembers,
+    /// Platform-wide, not scoped to an organisation
+    ManageOrganisations,
+}
+
+impl Role {
+    pub fn grants(self, permission: Permission) -> bool {
+        matches!(
+            (self, permission),
+            (Role::Member, Permission::SendMessages)
+                | (Role::OrgAdmin, Permission::ViewMembers | Permission::ManageMembers)
+                | (Role::Auditor, Permission::ViewMembers)
+                | (Role::Operator, Permission::ManageOrganisations)
+        )
+    }
+}
+
+/// A tenant: users only ever see users, devices and groups of their own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Organisation {
+    pub id: String,
+    pub name: String,
+    pub created_at: i64,
+}
+
+impl Organisation {
+    pub fn new(name: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            created_at: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
@@ -13,6 +88,11 @@ pub struct User {
     pub email: String,
     pub created_at: i64,
     pub last_seen: i64,
+    /// Unset for operators and for users not yet placed in an organisation
+    #[serde(default)]
+    pub org_id: Option<String>,
+    #[serde(default)]
+    pub roles: Vec<Role>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,46 +127,302 @@ impl User {
             email,
             created_at: chrono::Utc::now().timestamp(),
             last_seen: chrono::Utc::now().timestamp(),
+            org_id: None,
+            roles: vec![Role::Member],
         }
     }
+    
+    pub fn in_organisation(mut self, org_id: String) -> Self {
+        self.org_id = Some(org_id);
+        self
+    }
+    
+    pub fn with_roles(mut self, roles: Vec<Role>) -> Self {
+        self.roles = roles;
+        self
+    }
+    
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.roles.iter().any(|role| role.grants(permission))
+    }
+    
+    /// Whether both users belong to the same organisation
+    pub fn shares_organisation(&self, other: &User) -> bool {
+        self.org_id.is_some() && self.org_id == other.org_id
+    }
 }
 
 impl Session {
-    pub fn new(user_id: String, device_id: String) -> Self {
-        let now = chrono::Utc::now().timestamp();
-        let expires_at = now + (24 * 60 * 60); // 24 hours
-        
+    pub fn is_expired(&self) -> bool {
+        chrono::Utc::now().timestamp() > self.expires_at
+    }
+}
+
+/// What a session token vouches for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionClaims {
+    pub session_id: String,
+    pub user_id: String,
+    pub device_id: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+}
+
+impl From<&Session> for SessionClaims {
+    fn from(session: &Session) -> Self {
         Self {
+            session_id: session.id.clone(),
+            user_id: session.user_id.clone(),
+            device_id: session.device_id.clone(),
+            issued_at: session.created_at,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
+/// Server secret that session tokens are MAC'd with
+#[derive(Clone)]
+pub struct SessionKey {
+    key_id: u32,
+    secret: [u8; 32],
+}
+
+impl SessionKey {
+    pub fn generate(key_id: u32) -> Self {
+        Self::from_bytes(key_id, &rand::random())
+    }
+    
+    pub fn from_bytes(key_id: u32, secret: &[u8; 32]) -> Self {
+        Self {
+            key_id,
+            secret: *secret,
+        }
+    }
+    
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+    
+    fn mac(&self, signed: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(SESSION_TOKEN_LABEL);
+        mac.update(signed);
+        mac
+    }
+}
+
+impl std::fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionKey")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Keys that issue and verify session tokens
+///
+/// Tokens are `v1.<key id>.<claims>.<tag>`: base64url JSON claims and an
+/// HMAC-SHA256 tag over everything before it. They are signed, not
+/// encrypted. Any node holding the keys can verify a token without shared
+/// session state. New tokens use the current key; older keys keep verifying
+/// until they are retired, so keys can be rotated without logging anyone out.
+#[derive(Debug, Clone)]
+pub struct SessionKeyring {
+    current: u32,
+    keys: HashMap<u32, SessionKey>,
+}
+
+impl SessionKeyring {
+    pub fn new(current: SessionKey) -> Self {
+        Self {
+            current: current.key_id,
+            keys: HashMap::from([(current.key_id, current)]),
+        }
+    }
+    
+    pub fn current_key_id(&self) -> u32 {
+        self.current
+    }
+    
+    /// Make `key` the signing key; the previous one still verifies
+    pub fn rotate(&mut self, key: SessionKey) {
+        self.current = key.key_id;
+        self.keys.insert(key.key_id, key);
+    }
+    
+    /// Accept tokens signed with `key` without signing with it
+    pub fn add_verification_key(&mut self, key: SessionKey) {
+        self.keys.entry(key.key_id).or_insert(key);
+    }
+    
+    /// Stop accepting tokens signed with a key; the current key cannot be retired
+    pub fn retire(&mut self, key_id: u32) -> bool {
+        key_id != self.current && self.keys.remove(&key_id).is_some()
+    }
+    
+    /// Start a session and issue its first access token
+    pub fn issue(&self, user_id: String, device_id: String, now: i64) -> Result<Session> {
+        let mut session = Session {
             id: Uuid::new_v4().to_string(),
             user_id,
-            token: Uuid::new_v4().to_string(),
+            token: String::new(),
             created_at: now,
-            expires_at,
+            expires_at: now + ACCESS_TOKEN_LIFETIME,
             device_id,
+        };
+        session.token = self.sign(&SessionClaims::from(&session))?;
+        Ok(session)
+    }
+    
+    /// Issue a new access token for an existing session
+    pub fn reissue(&self, session: &Session, now: i64) -> Result<Session> {
+        let mut session = Session {
+            expires_at: now + ACCESS_TOKEN_LIFETIME,
+            ..session.clone()
+        };
+        session.token = self.sign(&SessionClaims::from(&session))?;
+        Ok(session)
+    }
+    
+    /// Check a token's tag and expiry, and recover its session
+    pub fn verify(&self, token: &str, now: i64) -> Result<Session> {
+        let invalid = || Error::Auth("Malformed session token".to_string());
+        
+        let (signed, tag) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let mut parts = signed.splitn(3, '.');
+        let (Some(version), Some(key_id), Some(claims)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if version != SESSION_TOKEN_VERSION {
+            return Err(Error::Auth(format!(
+                "Unsupported session token version {}",
+                version
+            )));
+        }
+        
+        let key_id: u32 = key_id.parse().map_err(|_| invalid())?;
+        let key = self.keys.get(&key_id).ok_or_else(|| {
+            Error::Auth(format!("Session token signed by unknown key {}", key_id))
+        })?;
+        
+        let tag = URL_SAFE_NO_PAD.decode(tag).map_err(|_| invalid())?;
+        key.mac(signed.as_bytes())
+            .verify_slice(&tag)
+            .map_err(|_| Error::Auth("Invalid session token signature".to_string()))?;
+        
+        let claims: SessionClaims = URL_SAFE_NO_PAD
+            .decode(claims)
+            .ok()
+            .and_then(|claims| serde_json::from_slice(&claims).ok())
+            .ok_or_else(invalid)?;
+        if now > claims.expires_at {
+            return Err(Error::Auth("Session token expired".to_string()));
         }
+        
+        Ok(Session {
+            id: claims.session_id,
+            user_id: claims.user_id,
+            token: token.to_string(),
+            created_at: claims.issued_at,
+            expires_at: claims.expires_at,
+            device_id: claims.device_id,
+        })
     }
     
-    pub fn is_expired(&self) -> bool {
-        chrono::Utc::now().timestamp() > self.expires_at
+    fn sign(&self, claims: &SessionClaims) -> Result<String> {
+        let key = &self.keys[&self.current];
+        let signed = format!(
+            "{}.{}.{}",
+            SESSION_TOKEN_VERSION,
+            key.key_id,
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?)
+        );
+        let tag = key.mac(signed.as_bytes()).finalize().into_bytes();
+        Ok(format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(tag)))
+    }
+}
+
+/// Long-lived token that is exchanged for new access tokens
+///
+/// Written as `<family id>.<base64url secret>`. Every use rotates the secret
+/// within the same family, which is the session id. The server keeps only
+/// digests, and presenting a secret that was already rotated away is reuse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshToken {
+    family_id: String,
+    secret: [u8; REFRESH_TOKEN_LENGTH],
+}
+
+impl RefreshToken {
+    pub fn generate(family_id: String) -> Self {
+        Self {
+            family_id,
+            secret: rand::random(),
+        }
+    }
+    
+    pub fn family_id(&self) -> &str {
+        &self.family_id
     }
     
-    pub fn refresh(&mut self) {
-        let now = chrono::Utc::now().timestamp();
-        self.expires_at = now + (24 * 60 * 60); // 24 hours
+    /// The next token in the same family
+    pub fn rotate(&self) -> Self {
+        Self::generate(self.family_id.clone())
+    }
+    
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(REFRESH_TOKEN_LABEL);
+        hasher.update(self.family_id.as_bytes());
+        hasher.update(self.secret);
+        hasher.finalize().into()
+    }
+}
+
+impl std::fmt::Display for RefreshToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.family_id, URL_SAFE_NO_PAD.encode(self.secret))
+    }
+}
+
+impl std::str::FromStr for RefreshToken {
+    type Err = Error;
+    
+    fn from_str(token: &str) -> Result<Self> {
+        let invalid = || Error::Auth("Malformed refresh token".to_string());
+        
+        let (family_id, secret) = token.rsplit_once('.').ok_or_else(invalid)?;
+        let secret = URL_SAFE_NO_PAD
+            .decode(secret)
+            .ok()
+            .and_then(|secret| secret.try_into().ok())
+            .ok_or_else(invalid)?;
+        if family_id.is_empty() {
+            return Err(invalid());
+        }
+        
+        Ok(Self {
+            family_id: family_id.to_string(),
+            secret,
+        })
     }
 }
 
 pub struct AuthManager;
 
 impl AuthManager {
-    pub fn authenticate(request: &AuthRequest) -> Result<AuthResponse> {
+    pub fn authenticate(request: &AuthRequest, keyring: &SessionKeyring) -> Result<AuthResponse> {
         // This would integrate with OPAQUE authentication
         // For now, return a mock response
         
-        let session = Session::new(
+        let session = keyring.issue(
             "user_id".to_string(),
             request.device_id.clone(),
-        );
+            chrono::Utc::now().timestamp(),
+        )?;
         
         Ok(AuthResponse {
             success: true,
@@ -95,10 +431,9 @@ impl AuthManager {
         })
     }
     
-    pub fn validate_session(_token: &str) -> Result<Option<Session>> {
-        // This would validate the session token
-        // For now, return None
-        Ok(None)
+    /// Session for a token, or `None` if it does not verify or has expired
+    pub fn validate_session(keyring: &SessionKeyring, token: &str) -> Result<Option<Session>> {
+        Ok(keyring.verify(token, chrono::Utc::now().timestamp()).ok())
     }
     
     pub fn revoke_session(_session_id: &str) -> Result<bool> {
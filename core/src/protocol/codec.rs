@@ -0,0 +1,111 @@
+//! Streaming codec for message frames
+//!
+//! Puts `MessageFrame`s on any byte stream by prefixing each encoded frame
+//! with its length as a big-endian `u32`
+
+use crate::protocol::transport::MessageFrame;
+use crate::protocol::wire::MAX_FRAME_LENGTH;
+use crate::utils::{Error, Result};
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+/// Size of the length prefix in front of every frame
+const LENGTH_PREFIX: usize = 4;
+
+/// Write buffer size above which sends wait for the socket to drain
+pub const DEFAULT_BACKPRESSURE_BOUNDARY: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    max_frame_length: usize,
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self {
+            max_frame_length: MAX_FRAME_LENGTH,
+        }
+    }
+}
+
+impl FrameCodec {
+    pub fn new(max_frame_length: usize) -> Self {
+        Self {
+            max_frame_length: max_frame_length.min(MAX_FRAME_LENGTH),
+        }
+    }
+
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = MessageFrame;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<MessageFrame>> {
+        if src.len() < LENGTH_PREFIX {
+            return Ok(None);
+        }
+
+        let length = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+
+        // Reject before buffering, so a peer cannot make us allocate an oversized frame
+        if length > self.max_frame_length {
+            return Err(Error::Protocol(format!(
+                "Frame of {} bytes exceeds maximum of {}",
+                length, self.max_frame_length
+            )));
+        }
+
+        if src.len() < LENGTH_PREFIX + length {
+            src.reserve(LENGTH_PREFIX + length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(LENGTH_PREFIX);
+        let frame = src.split_to(length);
+        MessageFrame::decode(&frame).map(Some)
+    }
+}
+
+impl Encoder<MessageFrame> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: MessageFrame, dst: &mut BytesMut) -> Result<()> {
+        Encoder::<&MessageFrame>::encode(self, &frame, dst)
+    }
+}
+
+impl Encoder<&MessageFrame> for FrameCodec {
+    type Error = Error;
+
+    fn encode(&mut self, frame: &MessageFrame, dst: &mut BytesMut) -> Result<()> {
+        let encoded = frame.encode()?;
+
+        if encoded.len() > self.max_frame_length {
+            return Err(Error::Protocol(format!(
+                "Frame of {} bytes exceeds maximum of {}",
+                encoded.len(),
+                self.max_frame_length
+            )));
+        }
+
+        dst.reserve(LENGTH_PREFIX + encoded.len());
+        dst.put_u32(encoded.len() as u32);
+        dst.extend_from_slice(&encoded);
+        Ok(())
+    }
+}
+
+/// Wrap a byte stream (TCP, Unix socket, in-memory duplex) as a stream and sink of frames
+pub fn framed<T>(io: T) -> Framed<T, FrameCodec>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let mut framed = Framed::new(io, FrameCodec::default());
+    framed.set_backpressure_boundary(DEFAULT_BACKPRESSURE_BOUNDARY);
+    framed
+}
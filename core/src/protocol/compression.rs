@@ -0,0 +1,258 @@
+//! Per-session payload compression
+//!
+//! Compression is negotiated when a session is set up and only applies to
+//! `Text` and `Control` frames; other payloads are ciphertext and do not
+//! compress. Payloads are compressed before they go onto the encrypted
+//! channel and padded afterwards, so a compressed size only reveals its
+//! padding bucket. Receivers cap the decompressed size, so a small frame
+//! cannot expand into a decompression bomb.
+
+use crate::protocol::padding::PaddingScheme;
+use crate::protocol::transport::{MessageFrame, MessageType};
+use crate::protocol::wire::MAX_PAYLOAD_LENGTH;
+use crate::utils::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Payloads shorter than this are sent as they are
+pub const MIN_COMPRESSIBLE_LENGTH: usize = 64;
+
+/// Default size of a trained dictionary, in bytes
+pub const DEFAULT_DICTIONARY_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionAlgorithm {
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Zstd => "zstd",
+        }
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim() {
+            "zstd" => Ok(CompressionAlgorithm::Zstd),
+            other => Err(Error::Protocol(format!(
+                "Unknown compression algorithm: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// A zstd dictionary shared by both ends of a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionDictionary {
+    id: u32,
+    bytes: Vec<u8>,
+}
+
+impl CompressionDictionary {
+    /// Wrap dictionary bytes; the id is derived from their digest
+    pub fn new(bytes: Vec<u8>) -> Self {
+        let digest = Sha256::digest(&bytes);
+        Self {
+            id: u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]),
+            bytes,
+        }
+    }
+
+    /// Train a dictionary from sample payloads, such as encoded control messages
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Self> {
+        Ok(Self::new(zstd::dict::from_samples(samples, max_size)?))
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Compression a client proposes at session setup, in order of preference
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionOffer {
+    pub algorithms: Vec<CompressionAlgorithm>,
+    /// Dictionaries the client has
+    pub dictionary_ids: Vec<u32>,
+}
+
+/// Compression agreed for a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionParams {
+    pub algorithm: CompressionAlgorithm,
+    pub dictionary_id: Option<u32>,
+}
+
+impl CompressionParams {
+    /// Pick the client's most preferred algorithm the server supports, and the
+    /// server's dictionary if the client has it too
+    pub fn negotiate(
+        offer: &CompressionOffer,
+        supported: &[CompressionAlgorithm],
+        dictionary: Option<&CompressionDictionary>,
+    ) -> Option<Self> {
+        let algorithm = offer
+            .algorithms
+            .iter()
+            .find(|algorithm| supported.contains(algorithm))?;
+
+        Some(Self {
+            algorithm: *algorithm,
+            dictionary_id: dictionary
+                .map(CompressionDictionary::id)
+                .filter(|id| offer.dictionary_ids.contains(id)),
+        })
+    }
+}
+
+/// Written as `zstd` or `zstd; dict=<id>`, e.g. in a session setup header
+impl fmt::Display for CompressionParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.algorithm.as_str())?;
+        if let Some(id) = self.dictionary_id {
+            write!(f, "; dict={}", id)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CompressionParams {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut parts = value.split(';');
+        let algorithm = parts.next().unwrap_or_default().parse()?;
+
+        let mut dictionary_id = None;
+        for part in parts {
+            let id = part
+                .trim()
+                .strip_prefix("dict=")
+                .and_then(|id| id.parse().ok())
+                .ok_or_else(|| {
+                    Error::Protocol(format!("Malformed compression parameter: {}", part))
+                })?;
+            dictionary_id = Some(id);
+        }
+
+        Ok(Self {
+            algorithm,
+            dictionary_id,
+        })
+    }
+}
+
+/// Compresses outgoing and decompresses incoming frames for one session
+#[derive(Debug, Clone)]
+pub struct FrameCompressor {
+    params: CompressionParams,
+    dictionary: Option<CompressionDictionary>,
+    level: i32,
+    padding: PaddingScheme,
+    max_decompressed_length: usize,
+}
+
+impl FrameCompressor {
+    pub fn new(
+        params: CompressionParams,
+        dictionary: Option<CompressionDictionary>,
+    ) -> Result<Self> {
+        let dictionary = match params.dictionary_id {
+            Some(id) => match dictionary {
+                Some(dictionary) if dictionary.id() == id => Some(dictionary),
+                _ => {
+                    return Err(Error::Protocol(format!(
+                        "Compression dictionary {} is not available",
+                        id
+                    )))
+                }
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            params,
+            dictionary,
+            level: DEFAULT_COMPRESSION_LEVEL,
+            padding: PaddingScheme::Padme,
+            max_decompressed_length: MAX_PAYLOAD_LENGTH,
+        })
+    }
+
+    /// Cap on decompressed payloads; never above `MAX_PAYLOAD_LENGTH`
+    pub fn with_max_decompressed_length(mut self, length: usize) -> Self {
+        self.max_decompressed_length = length.min(MAX_PAYLOAD_LENGTH);
+        self
+    }
+
+    pub fn params(&self) -> CompressionParams {
+        self.params
+    }
+
+    /// Compress a frame's payload if it is compressible and gets smaller
+    pub fn compress(&self, mut frame: MessageFrame) -> Result<MessageFrame> {
+        let compressible = matches!(
+            frame.header.message_type,
+            MessageType::Text | MessageType::Control
+        );
+        if frame.header.compressed || !compressible || frame.payload.len() < MIN_COMPRESSIBLE_LENGTH
+        {
+            return Ok(frame);
+        }
+
+        let compressed = match &self.dictionary {
+            Some(dictionary) => {
+                zstd::bulk::Compressor::with_dictionary(self.level, dictionary.as_bytes())?
+                    .compress(&frame.payload)?
+            }
+            None => zstd::bulk::compress(&frame.payload, self.level)?,
+        };
+
+        // Padding after compression keeps sizes to coarse buckets
+        let padded = self.padding.pad(&compressed)?;
+        if padded.len() >= frame.payload.len() {
+            return Ok(frame);
+        }
+
+        frame.header.payload_length = padded.len() as u32;
+        frame.header.compressed = true;
+        frame.payload = padded;
+        Ok(frame)
+    }
+
+    /// Decompress a frame's payload if it is compressed
+    pub fn decompress(&self, mut frame: MessageFrame) -> Result<MessageFrame> {
+        if !frame.header.compressed {
+            return Ok(frame);
+        }
+
+        let compressed = PaddingScheme::unpad(&frame.payload)?;
+
+        // The capacity bounds the output, so oversized content fails instead of allocating
+        let payload = match &self.dictionary {
+            Some(dictionary) => zstd::bulk::Decompressor::with_dictionary(dictionary.as_bytes())?
+                .decompress(&compressed, self.max_decompressed_length),
+            None => zstd::bulk::decompress(&compressed, self.max_decompressed_length),
+        }
+        .map_err(|e| Error::Protocol(format!("Decompression failed: {}", e)))?;
+
+        frame.header.payload_length = payload.len() as u32;
+        frame.header.compressed = false;
+        frame.payload = payload;
+        Ok(frame)
+    }
+}
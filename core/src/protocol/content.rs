@@ -0,0 +1,198 @@
+//! Message content schema
+//!
+//! Defines what goes inside the encrypted payload of an application message:
+//! text, attachments, receipts, typing indicators, reactions, edits and
+//! deletions. Content is versioned JSON, and content types this client does
+//! not understand are kept intact as [`Content::Unknown`] so that they can be
+//! stored and re-encoded without loss.
+
+use crate::utils::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// Content schema version produced by [`Content::encode`]
+pub const CONTENT_VERSION: u8 = 1;
+
+/// A reference to an earlier message, for replies and quotes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quote {
+    pub message_id: String,
+    pub sender_id: String,
+    /// Excerpt of the quoted message, shown if the original is not available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excerpt: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextContent {
+    pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Quote>,
+}
+
+/// Pointer to an encrypted object in the media store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachmentPointer {
+    pub media_id: String,
+    pub mime_type: String,
+    pub size: u64,
+    /// Per-object key the attachment is encrypted with
+    pub key: Vec<u8>,
+    /// SHA-256 of the encrypted object
+    pub digest: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<Quote>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReceiptKind {
+    Delivered,
+    Read,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub kind: ReceiptKind,
+    pub message_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypingIndicator {
+    /// False when the sender stopped typing
+    pub typing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub target_id: String,
+    pub emoji: String,
+    /// Withdraws the sender's earlier reaction instead of adding one
+    #[serde(default)]
+    pub remove: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    pub target_id: String,
+    pub body: String,
+}
+
+/// Delete-for-everyone of an earlier message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deletion {
+    pub target_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Content {
+    Text(TextContent),
+    Attachment(AttachmentPointer),
+    Receipt(Receipt),
+    Typing(TypingIndicator),
+    Reaction(Reaction),
+    Edit(Edit),
+    Delete(Deletion),
+    /// A content type, or version of one, this client cannot decode
+    Unknown {
+        version: u8,
+        content_type: String,
+        body: serde_json::Value,
+    },
+}
+
+/// What is actually serialized: a version, a type tag and a type-specific body
+#[derive(Debug, Serialize, Deserialize)]
+struct ContentEnvelope {
+    version: u8,
+    #[serde(rename = "type")]
+    content_type: String,
+    body: serde_json::Value,
+}
+
+impl Content {
+    pub fn text(body: impl Into<String>) -> Self {
+        Content::Text(TextContent {
+            body: body.into(),
+            reply_to: None,
+        })
+    }
+
+    /// The type tag this content is serialized under
+    pub fn content_type(&self) -> &str {
+        match self {
+            Content::Text(_) => "text",
+            Content::Attachment(_) => "attachment",
+            Content::Receipt(_) => "receipt",
+            Content::Typing(_) => "typing",
+            Content::Reaction(_) => "reaction",
+            Content::Edit(_) => "edit",
+            Content::Delete(_) => "delete",
+            Content::Unknown { content_type, .. } => content_type,
+        }
+    }
+
+    /// Whether this content is shown as a message of its own, rather than
+    /// acting on other messages or being transient
+    pub fn is_displayable(&self) -> bool {
+        matches!(
+            self,
+            Content::Text(_) | Content::Attachment(_) | Content::Unknown { .. }
+        )
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let (version, body) = match self {
+            Content::Text(text) => (CONTENT_VERSION, serde_json::to_value(text)?),
+            Content::Attachment(attachment) => (CONTENT_VERSION, serde_json::to_value(attachment)?),
+            Content::Receipt(receipt) => (CONTENT_VERSION, serde_json::to_value(receipt)?),
+            Content::Typing(typing) => (CONTENT_VERSION, serde_json::to_value(typing)?),
+            Content::Reaction(reaction) => (CONTENT_VERSION, serde_json::to_value(reaction)?),
+            Content::Edit(edit) => (CONTENT_VERSION, serde_json::to_value(edit)?),
+            Content::Delete(deletion) => (CONTENT_VERSION, serde_json::to_value(deletion)?),
+            Content::Unknown { version, body, .. } => (*version, body.clone()),
+        };
+
+        Ok(serde_json::to_vec(&ContentEnvelope {
+            version,
+            content_type: self.content_type().to_string(),
+            body,
+        })?)
+    }
+
+    /// Decode content, keeping anything unrecognised as [`Content::Unknown`]
+    ///
+    /// Only bytes that are not a content envelope at all are an error. Newer
+    /// versions of a known type are decoded if their body still parses, since
+    /// fields added later are ignored.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let envelope: ContentEnvelope = serde_json::from_slice(bytes)
+            .map_err(|e| Error::Protocol(format!("Malformed message content: {}", e)))?;
+
+        let body = envelope.body.clone();
+        let decoded = match envelope.content_type.as_str() {
+            "text" => serde_json::from_value(body).map(Content::Text),
+            "attachment" => serde_json::from_value(body).map(Content::Attachment),
+            "receipt" => serde_json::from_value(body).map(Content::Receipt),
+            "typing" => serde_json::from_value(body).map(Content::Typing),
+            "reaction" => serde_json::from_value(body).map(Content::Reaction),
+            "edit" => serde_json::from_value(body).map(Content::Edit),
+            "delete" => serde_json::from_value(body).map(Content::Delete),
+            _ => return Ok(envelope.into_unknown()),
+        };
+
+        Ok(decoded.unwrap_or_else(|_| envelope.into_unknown()))
+    }
+}
+
+impl ContentEnvelope {
+    fn into_unknown(self) -> Content {
+        Content::Unknown {
+            version: self.version,
+            content_type: self.content_type,
+            body: self.body,
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Account deletion receipts and tombstones
+//!
+//! When an account is deleted the server purges everything it held for it
+//! and signs two records. The tombstone is published in place of the user,
+//! so other members learn the account is gone rather than finding it merely
+//! unknown, and its id is never handed out again. The receipt goes back to
+//! the user and says what was purged, for their compliance records. Neither
+//! carries anything about the user beyond their id.
+
+use crate::utils::{Error, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const TOMBSTONE_LABEL: &[u8] = b"xipr account tombstone v1";
+const RECEIPT_LABEL: &[u8] = b"xipr deletion receipt v1";
+
+/// Published in the directory in place of a deleted user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub user_id: String,
+    pub org_id: Option<String>,
+    pub deleted_at: i64,
+    /// Server deletion key that produced `signature`
+    pub key_id: u32,
+    pub signature: Vec<u8>,
+}
+
+/// How much of each kind of data a deletion purged
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PurgeSummary {
+    pub sessions: u64,
+    pub devices: u64,
+    pub pre_keys: u64,
+    pub queued_messages: u64,
+    /// Groups the user's devices were taken out of
+    pub groups: u64,
+    pub passkeys: u64,
+    /// Blobs the user had stored with the server
+    pub media_objects: u64,
+    pub second_factor: bool,
+}
+
+/// Signed statement that an account and its data were deleted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletionReceipt {
+    pub receipt_id: String,
+    pub user_id: String,
+    pub org_id: Option<String>,
+    pub deleted_at: i64,
+    pub purged: PurgeSummary,
+    pub key_id: u32,
+    pub signature: Vec<u8>,
+}
+
+impl Tombstone {
+    pub fn verify(&self, public_key: &[u8; 32]) -> Result<()> {
+        verify(
+            public_key,
+            &self.signed_bytes(),
+            &self.signature,
+            "tombstone",
+        )
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = TOMBSTONE_LABEL.to_vec();
+        put_field(&mut bytes, self.user_id.as_bytes());
+        put_field(
+            &mut bytes,
+            self.org_id.as_deref().unwrap_or_default().as_bytes(),
+        );
+        bytes.extend_from_slice(&self.deleted_at.to_be_bytes());
+        bytes.extend_from_slice(&self.key_id.to_be_bytes());
+        bytes
+    }
+}
+
+impl DeletionReceipt {
+    pub fn verify(&self, public_key: &[u8; 32]) -> Result<()> {
+        verify(
+            public_key,
+            &self.signed_bytes(),
+            &self.signature,
+            "deletion receipt",
+        )
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = RECEIPT_LABEL.to_vec();
+        for field in [
+            self.receipt_id.as_bytes(),
+            self.user_id.as_bytes(),
+            self.org_id.as_deref().unwrap_or_default().as_bytes(),
+        ] {
+            put_field(&mut bytes, field);
+        }
+        bytes.extend_from_slice(&self.deleted_at.to_be_bytes());
+
+        let purged = &self.purged;
+        for count in [
+            purged.sessions,
+            purged.devices,
+            purged.pre_keys,
+            purged.queued_messages,
+            purged.groups,
+            purged.passkeys,
+            purged.media_objects,
+        ] {
+            bytes.extend_from_slice(&count.to_be_bytes());
+        }
+        bytes.push(u8::from(purged.second_factor));
+        bytes.extend_from_slice(&self.key_id.to_be_bytes());
+        bytes
+    }
+}
+
+/// Server-side key that signs tombstones and deletion receipts
+pub struct DeletionSigner {
+    key_id: u32,
+    signing_key: SigningKey,
+}
+
+impl DeletionSigner {
+    pub fn generate(key_id: u32) -> Self {
+        Self::from_bytes(key_id, &rand::random())
+    }
+
+    pub fn from_bytes(key_id: u32, secret: &[u8; 32]) -> Self {
+        Self {
+            key_id,
+            signing_key: SigningKey::from_bytes(secret),
+        }
+    }
+
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    pub fn tombstone(&self, user_id: String, org_id: Option<String>, now: i64) -> Tombstone {
+        let mut tombstone = Tombstone {
+            user_id,
+            org_id,
+            deleted_at: now,
+            key_id: self.key_id,
+            signature: Vec::new(),
+        };
+        tombstone.signature = self.sign(&tombstone.signed_bytes());
+        tombstone
+    }
+
+    /// Receipt for the deletion a tombstone records
+    pub fn receipt(&self, tombstone: &Tombstone, purged: PurgeSummary) -> DeletionReceipt {
+        let mut receipt = DeletionReceipt {
+            receipt_id: Uuid::new_v4().to_string(),
+            user_id: tombstone.user_id.clone(),
+            org_id: tombstone.org_id.clone(),
+            deleted_at: tombstone.deleted_at,
+            purged,
+            key_id: self.key_id,
+            signature: Vec::new(),
+        };
+        receipt.signature = self.sign(&receipt.signed_bytes());
+        receipt
+    }
+
+    fn sign(&self, bytes: &[u8]) -> Vec<u8> {
+        self.signing_key.sign(bytes).to_bytes().to_vec()
+    }
+}
+
+fn put_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(field);
+}
+
+fn verify(public_key: &[u8; 32], bytes: &[u8], signature: &[u8], what: &str) -> Result<()> {
+    let key = VerifyingKey::from_bytes(public_key)
+        .map_err(|_| Error::Auth(format!("Malformed {} key", what)))?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| Error::Auth(format!("Malformed {} signature", what)))?;
+    key.verify_strict(bytes, &signature)
+        .map_err(|_| Error::Auth(format!("Invalid {} signature", what)))
+}
@@ -0,0 +1,117 @@
+//! Multi-device fan-out
+//!
+//! Users have several devices, each with its own keys, so a sender encrypts
+//! one copy per recipient device and one sync copy for each of its own other
+//! devices. The router checks those copies against the devices the server
+//! knows to be active and reports what was reached.
+
+use crate::protocol::transport::{Message, MessageRouter};
+use crate::utils::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Source of the active devices registered for a user
+pub trait DeviceDirectory {
+    fn active_devices(&self, user_id: &str) -> Vec<String>;
+}
+
+/// Content encrypted for one device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceCopy {
+    pub device_id: String,
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveryReport {
+    /// Devices a copy was delivered to
+    pub reached: Vec<String>,
+    /// Devices a copy was addressed to that are not active; nothing was delivered to them
+    pub stale: Vec<String>,
+    /// Active devices the sender provided no copy for
+    pub missing: Vec<String>,
+}
+
+impl DeliveryReport {
+    /// Every active device got a copy and no copy was wasted
+    pub fn is_complete(&self) -> bool {
+        self.stale.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Per-device messages to queue, keyed by device id, and the resulting report
+#[derive(Debug, Clone, Default)]
+pub struct FanOut {
+    pub deliveries: Vec<(String, Message)>,
+    pub report: DeliveryReport,
+}
+
+impl MessageRouter {
+    /// Active devices of the message's recipient user
+    pub fn route_to_devices(
+        message: &Message,
+        directory: &dyn DeviceDirectory,
+    ) -> Result<Vec<String>> {
+        let devices = directory.active_devices(&message.recipient_id);
+        if devices.is_empty() {
+            return Err(Error::Protocol(format!(
+                "No active devices for {}",
+                message.recipient_id
+            )));
+        }
+
+        Ok(devices)
+    }
+
+    /// Match per-device copies against the active devices of the recipient and
+    /// of the sender, other than `sender_device`
+    ///
+    /// Each delivery is `message` with its content replaced by that device's
+    /// copy. Sync copies keep the original `recipient_id`, so the sender's
+    /// other devices know which conversation they belong to.
+    pub fn fan_out(
+        message: &Message,
+        sender_device: &str,
+        copies: Vec<DeviceCopy>,
+        sync_copies: Vec<DeviceCopy>,
+        directory: &dyn DeviceDirectory,
+    ) -> FanOut {
+        let mut expected: Vec<String> = directory
+            .active_devices(&message.recipient_id)
+            .into_iter()
+            .chain(directory.active_devices(&message.sender_id))
+            .filter(|device| device != sender_device)
+            .collect();
+        expected.sort();
+        expected.dedup();
+
+        let active: HashSet<&String> = expected.iter().collect();
+        let mut fan_out = FanOut::default();
+        let mut covered = HashSet::new();
+
+        for copy in copies.into_iter().chain(sync_copies) {
+            if !active.contains(&copy.device_id) {
+                fan_out.report.stale.push(copy.device_id);
+                continue;
+            }
+
+            // A device gets at most one copy
+            if !covered.insert(copy.device_id.clone()) {
+                continue;
+            }
+
+            let mut delivery = message.clone();
+            delivery.content = copy.content;
+            fan_out.report.reached.push(copy.device_id.clone());
+            fan_out.deliveries.push((copy.device_id, delivery));
+        }
+
+        fan_out.report.missing = expected
+            .iter()
+            .filter(|device| !covered.contains(*device))
+            .cloned()
+            .collect();
+
+        fan_out
+    }
+}
@@ -0,0 +1,333 @@
+//! Second authentication factors
+//!
+//! TOTP (RFC 6238) with the parameters every authenticator app supports:
+//! HMAC-SHA1, six digits and 30-second steps. The server keeps TOTP secrets
+//! sealed under a [`FactorKey`] and keeps only digests of recovery codes.
+//! New users get a one-time enrolment token instead, which lets them log in
+//! once to enrol their first factor.
+
+use crate::utils::{Error, Result};
+use chacha20poly1305::{aead::Aead, aead::Payload, ChaCha20Poly1305, KeyInit};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+/// Length of a TOTP step, in seconds
+pub const TOTP_STEP: i64 = 30;
+
+/// Number of digits in a TOTP code
+pub const TOTP_DIGITS: usize = 6;
+
+/// Steps either side of the current one whose codes are still accepted
+pub const TOTP_SKEW_STEPS: i64 = 1;
+
+/// Length of a generated TOTP secret, in bytes, as RFC 4226 recommends
+pub const TOTP_SECRET_LENGTH: usize = 20;
+
+/// Number of recovery codes handed out at a time
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Characters in a recovery code, leaving out ones that are easily confused
+const RECOVERY_CODE_ALPHABET: &[u8] = b"0123456789abcdefghjkmnpqrstvwxyz";
+const RECOVERY_CODE_GROUPS: usize = 3;
+const RECOVERY_CODE_GROUP_LENGTH: usize = 4;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const FACTOR_SEAL_LABEL: &[u8] = b"xipr totp secret v1";
+const RECOVERY_CODE_LABEL: &[u8] = b"xipr recovery code v1";
+const ENROLMENT_TOKEN_LABEL: &[u8] = b"xipr enrolment token v1";
+
+/// What a user presents as their second factor
+///
+/// An enrolment token is only accepted until the user enrols a factor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SecondFactor {
+    Totp { code: String },
+    RecoveryCode { code: String },
+    EnrolmentToken { token: String },
+}
+
+/// Shared secret between the server and an authenticator app
+#[derive(Clone, PartialEq, Eq)]
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Self {
+        Self(rand::random::<[u8; TOTP_SECRET_LENGTH]>().to_vec())
+    }
+
+    pub fn from_bytes(secret: &[u8]) -> Result<Self> {
+        if secret.len() < 16 {
+            return Err(Error::Auth("TOTP secret too short".to_string()));
+        }
+        Ok(Self(secret.to_vec()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Unpadded base32, as authenticator apps expect it typed in
+    pub fn to_base32(&self) -> String {
+        let mut encoded = String::new();
+        for chunk in self.0.chunks(5) {
+            let mut buffer = [0u8; 5];
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            let bits = u64::from_be_bytes([
+                0, 0, 0, buffer[0], buffer[1], buffer[2], buffer[3], buffer[4],
+            ]);
+            let chars = (chunk.len() * 8).div_ceil(5);
+            for i in 0..chars {
+                let index = (bits >> (35 - i * 5)) & 0x1f;
+                encoded.push(BASE32_ALPHABET[index as usize] as char);
+            }
+        }
+        encoded
+    }
+
+    /// Read a secret as [`Self::to_base32`] writes it; case, spaces and padding are ignored
+    pub fn from_base32(encoded: &str) -> Result<Self> {
+        let mut bytes = Vec::new();
+        let mut bits = 0u32;
+        let mut pending = 0u32;
+        for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+            let value = BASE32_ALPHABET
+                .iter()
+                .position(|&symbol| symbol as char == c.to_ascii_uppercase())
+                .ok_or_else(|| Error::Auth("Invalid base32 in TOTP secret".to_string()))?;
+            bits = (bits << 5) | value as u32;
+            pending += 5;
+            if pending >= 8 {
+                pending -= 8;
+                bytes.push((bits >> pending) as u8);
+                bits &= (1 << pending) - 1;
+            }
+        }
+        Self::from_bytes(&bytes)
+    }
+
+    /// `otpauth://` URI to show as a QR code during enrolment
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            percent_encode(issuer),
+            percent_encode(account),
+            self.to_base32(),
+            percent_encode(issuer),
+            TOTP_DIGITS,
+            TOTP_STEP
+        )
+    }
+
+    /// Code for the step containing the Unix time `now`
+    pub fn code_at(&self, now: i64) -> String {
+        self.code_for_step(now.div_euclid(TOTP_STEP))
+    }
+
+    /// Step whose code matches, within the allowed skew
+    ///
+    /// Callers must refuse steps at or before the last one accepted, so a
+    /// code cannot be replayed.
+    pub fn verify(&self, code: &str, now: i64) -> Option<i64> {
+        let code = code.trim();
+        if code.len() != TOTP_DIGITS || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+
+        let current = now.div_euclid(TOTP_STEP);
+        (current - TOTP_SKEW_STEPS..=current + TOTP_SKEW_STEPS)
+            .find(|step| constant_time_eq(self.code_for_step(*step).as_bytes(), code.as_bytes()))
+    }
+
+    fn code_for_step(&self, step: i64) -> String {
+        let mut mac =
+            <Hmac<Sha1> as Mac>::new_from_slice(&self.0).expect("HMAC accepts any key length");
+        mac.update(&(step as u64).to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        // RFC 4226 dynamic truncation
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(TOTP_DIGITS as u32),
+            width = TOTP_DIGITS
+        )
+    }
+}
+
+impl std::fmt::Debug for TotpSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TotpSecret").finish_non_exhaustive()
+    }
+}
+
+impl Drop for TotpSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// A [`TotpSecret`] sealed for storage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedFactor {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Server key that TOTP secrets are sealed under at rest
+///
+/// Sealed secrets are bound to their user, so one cannot be moved to
+/// another account.
+#[derive(Clone)]
+pub struct FactorKey {
+    secret: [u8; 32],
+}
+
+impl FactorKey {
+    pub fn generate() -> Self {
+        Self::from_bytes(&rand::random())
+    }
+
+    pub fn from_bytes(secret: &[u8; 32]) -> Self {
+        Self { secret: *secret }
+    }
+
+    pub fn seal(&self, user_id: &str, secret: &TotpSecret) -> Result<SealedFactor> {
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = self
+            .cipher()
+            .encrypt(
+                nonce.as_slice().into(),
+                Payload {
+                    msg: secret.as_bytes(),
+                    aad: &factor_aad(user_id),
+                },
+            )
+            .map_err(|_| Error::Crypto("Cannot seal TOTP secret".to_string()))?;
+
+        Ok(SealedFactor {
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    pub fn open(&self, user_id: &str, sealed: &SealedFactor) -> Result<TotpSecret> {
+        if sealed.nonce.len() != 12 {
+            return Err(Error::Crypto("Malformed sealed TOTP secret".to_string()));
+        }
+
+        let mut secret = self
+            .cipher()
+            .decrypt(
+                sealed.nonce.as_slice().into(),
+                Payload {
+                    msg: &sealed.ciphertext,
+                    aad: &factor_aad(user_id),
+                },
+            )
+            .map_err(|_| Error::Crypto("Cannot open sealed TOTP secret".to_string()))?;
+        let opened = TotpSecret::from_bytes(&secret);
+        secret.zeroize();
+        opened
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(&self.secret.into())
+    }
+}
+
+impl std::fmt::Debug for FactorKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FactorKey").finish_non_exhaustive()
+    }
+}
+
+impl Drop for FactorKey {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+/// A fresh set of single-use recovery codes, written `xxxx-xxxx-xxxx`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_code(RECOVERY_CODE_GROUPS))
+        .collect()
+}
+
+/// A new one-time enrolment token, twice as long as a recovery code
+pub fn generate_enrolment_token() -> String {
+    generate_code(2 * RECOVERY_CODE_GROUPS)
+}
+
+/// What the server stores for a recovery code
+///
+/// Case, spaces and dashes are ignored, so codes can be typed loosely.
+pub fn recovery_code_digest(user_id: &str, code: &str) -> [u8; 32] {
+    code_digest(RECOVERY_CODE_LABEL, user_id, code)
+}
+
+/// What the server stores for an enrolment token, normalized like a recovery code
+pub fn enrolment_token_digest(user_id: &str, token: &str) -> [u8; 32] {
+    code_digest(ENROLMENT_TOKEN_LABEL, user_id, token)
+}
+
+fn generate_code(groups: usize) -> String {
+    let groups: Vec<String> = (0..groups)
+        .map(|_| {
+            (0..RECOVERY_CODE_GROUP_LENGTH)
+                .map(|_| {
+                    let index = rand::random::<u8>() as usize % RECOVERY_CODE_ALPHABET.len();
+                    RECOVERY_CODE_ALPHABET[index] as char
+                })
+                .collect()
+        })
+        .collect();
+    groups.join("-")
+}
+
+fn code_digest(label: &[u8], user_id: &str, code: &str) -> [u8; 32] {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    let mut hasher = Sha256::new();
+    hasher.update(label);
+    hasher.update((user_id.len() as u32).to_be_bytes());
+    hasher.update(user_id.as_bytes());
+    hasher.update(normalized.as_bytes());
+    hasher.finalize().into()
+}
+
+fn factor_aad(user_id: &str) -> Vec<u8> {
+    let mut aad = FACTOR_SEAL_LABEL.to_vec();
+    aad.extend_from_slice(user_id.as_bytes());
+    aad
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -2,13 +2,268 @@
 //! 
 //! Provides 1:1 and group messaging with forward secrecy
 
-use crate::utils::Result;
+use crate::protocol::content::Content;
+use crate::protocol::padding::PaddingScheme;
+use crate::protocol::receive::{ReceiveBuffer, ReceiveEvent};
+use crate::protocol::roster::{Credential, GroupPermissions, GroupRole, Member, Roster};
+use crate::utils::{Error, Result};
+use chacha20poly1305::{aead::Aead, aead::Payload, ChaCha20Poly1305, KeyInit};
+use hkdf::Hkdf;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+/// HKDF label for the application content key of an epoch
+const CONTENT_KEY_LABEL: &[u8] = b"xipr mls application key v1";
+
+/// Extension type for the required capabilities group context extension (RFC 9420)
+pub const EXTENSION_REQUIRED_CAPABILITIES: u16 = 0x0003;
+
+/// Extension type for the XIPRNET admin roles group context extension (private range)
+pub const EXTENSION_ADMIN_ROLES: u16 = 0xF000;
+
+/// Proposal types used by XIPRNET groups (RFC 9420)
+pub const PROPOSAL_ADD: u16 = 0x0001;
+pub const PROPOSAL_UPDATE: u16 = 0x0002;
+pub const PROPOSAL_REMOVE: u16 = 0x0003;
+pub const PROPOSAL_GROUP_CONTEXT_EXTENSIONS: u16 = 0x0007;
+
+/// Credential type for basic credentials (RFC 9420)
+pub const CREDENTIAL_BASIC: u16 = 0x0001;
+
+/// Default cap on the number of devices in a group
+pub const DEFAULT_MAX_MEMBERS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CipherSuite {
+    /// MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519
+    Mls128X25519Aes128GcmSha256Ed25519,
+    /// MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519
+    Mls128X25519ChaCha20Poly1305Sha256Ed25519,
+    /// ML-KEM-768 + X25519 hybrid KEM with AES-256-GCM, SHA-384 and Ed25519
+    Mls256XWingAes256GcmSha384Ed25519,
+}
+
+impl CipherSuite {
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            CipherSuite::Mls128X25519Aes128GcmSha256Ed25519 => 0x0001,
+            CipherSuite::Mls128X25519ChaCha20Poly1305Sha256Ed25519 => 0x0003,
+            CipherSuite::Mls256XWingAes256GcmSha384Ed25519 => 0xF001,
+        }
+    }
+
+    pub fn from_u16(value: u16) -> Result<Self> {
+        match value {
+            0x0001 => Ok(CipherSuite::Mls128X25519Aes128GcmSha256Ed25519),
+            0x0003 => Ok(CipherSuite::Mls128X25519ChaCha20Poly1305Sha256Ed25519),
+            0xF001 => Ok(CipherSuite::Mls256XWingAes256GcmSha384Ed25519),
+            other => Err(Error::Protocol(format!(
+                "Unsupported cipher suite: {:#06x}",
+                other
+            ))),
+        }
+    }
+
+    pub fn is_post_quantum(&self) -> bool {
+        matches!(self, CipherSuite::Mls256XWingAes256GcmSha384Ed25519)
+    }
+}
+
+/// Capabilities every member of a group must advertise in its KeyPackage
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequiredCapabilities {
+    pub extension_types: Vec<u16>,
+    pub proposal_types: Vec<u16>,
+    pub credential_types: Vec<u16>,
+}
+
+/// Capabilities advertised by a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub cipher_suites: Vec<CipherSuite>,
+    pub extension_types: Vec<u16>,
+    pub proposal_types: Vec<u16>,
+    pub credential_types: Vec<u16>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            cipher_suites: vec![
+                CipherSuite::Mls128X25519Aes128GcmSha256Ed25519,
+                CipherSuite::Mls128X25519ChaCha20Poly1305Sha256Ed25519,
+                CipherSuite::Mls256XWingAes256GcmSha384Ed25519,
+            ],
+            extension_types: vec![EXTENSION_REQUIRED_CAPABILITIES, EXTENSION_ADMIN_ROLES],
+            proposal_types: vec![
+                PROPOSAL_ADD,
+                PROPOSAL_UPDATE,
+                PROPOSAL_REMOVE,
+                PROPOSAL_GROUP_CONTEXT_EXTENSIONS,
+            ],
+            credential_types: vec![CREDENTIAL_BASIC],
+        }
+    }
+}
+
+impl Capabilities {
+    pub fn satisfies(&self, required: &RequiredCapabilities) -> bool {
+        required
+            .extension_types
+            .iter()
+            .all(|t| self.extension_types.contains(t))
+            && required
+                .proposal_types
+                .iter()
+                .all(|t| self.proposal_types.contains(t))
+            && required
+                .credential_types
+                .iter()
+                .all(|t| self.credential_types.contains(t))
+    }
+}
+
+/// Group policy applied when the group is created and whenever a commit is processed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupPolicy {
+    pub allowed_cipher_suites: Vec<CipherSuite>,
+    /// Most devices the group may hold; commits that would exceed it are rejected
+    #[serde(default = "default_max_members")]
+    pub max_members: usize,
+    /// Number of past epochs whose secrets are kept for out-of-order decryption
+    pub max_past_epochs: usize,
+    /// Padding applied to application messages before encryption
+    pub padding: PaddingScheme,
+    pub required_capabilities: RequiredCapabilities,
+}
+
+impl Default for GroupPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_cipher_suites: vec![
+                CipherSuite::Mls128X25519Aes128GcmSha256Ed25519,
+                CipherSuite::Mls256XWingAes256GcmSha384Ed25519,
+            ],
+            max_members: DEFAULT_MAX_MEMBERS,
+            max_past_epochs: 3,
+            padding: PaddingScheme::default(),
+            required_capabilities: RequiredCapabilities {
+                extension_types: vec![EXTENSION_ADMIN_ROLES],
+                proposal_types: vec![],
+                credential_types: vec![CREDENTIAL_BASIC],
+            },
+        }
+    }
+}
+
+fn default_max_members() -> usize {
+    DEFAULT_MAX_MEMBERS
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MlsConfig {
-    pub cipher_suite: u16,
+    pub cipher_suite: CipherSuite,
+    pub group_id: Vec<u8>,
+    pub policy: GroupPolicy,
+}
+
+impl MlsConfig {
+    pub fn new(group_id: Vec<u8>) -> Self {
+        Self {
+            cipher_suite: CipherSuite::Mls128X25519Aes128GcmSha256Ed25519,
+            group_id,
+            policy: GroupPolicy::default(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.group_id.is_empty() {
+            return Err(Error::Protocol("Group id must not be empty".to_string()));
+        }
+
+        if !self
+            .policy
+            .allowed_cipher_suites
+            .contains(&self.cipher_suite)
+        {
+            return Err(Error::Protocol(format!(
+                "Cipher suite {:#06x} is not allowed by group policy",
+                self.cipher_suite.as_u16()
+            )));
+        }
+
+        if self.policy.max_members == 0 {
+            return Err(Error::Protocol(
+                "Group policy must allow at least one member".to_string(),
+            ));
+        }
+
+        self.policy.padding.validate()
+    }
+}
+
+/// Identities holding the admin role, and the role needed for each administrative action
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminRoles {
+    pub admins: Vec<Vec<u8>>,
+    pub permissions: GroupPermissions,
+}
+
+impl AdminRoles {
+    pub fn is_admin(&self, identity: &[u8]) -> bool {
+        self.admins.iter().any(|admin| admin == identity)
+    }
+
+    pub fn role_of(&self, identity: &[u8]) -> GroupRole {
+        if self.is_admin(identity) {
+            GroupRole::Admin
+        } else {
+            GroupRole::Member
+        }
+    }
+}
+
+/// Group context extensions shared by all members and agreed on through commits
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupContextExtensions {
+    pub name: String,
+    pub required_capabilities: RequiredCapabilities,
+    pub admin_roles: AdminRoles,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyPackage {
+    pub credential: Credential,
+    pub cipher_suite: CipherSuite,
+    pub capabilities: Capabilities,
+    pub init_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Proposal {
+    Add(KeyPackage),
+    Remove { member_id: Vec<u8> },
+    GroupContextExtensions(GroupContextExtensions),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
     pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub committer: Vec<u8>,
+    pub proposals: Vec<Proposal>,
+}
+
+/// Group state handed to new members
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Welcome {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub roster: Roster,
+    pub extensions: GroupContextExtensions,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,68 +271,550 @@ pub struct MlsClient {
     pub client_id: String,
     pub identity: Vec<u8>,
     pub client_state: Vec<u8>,
+    pub credential: Credential,
+    pub capabilities: Capabilities,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MlsGroup {
     pub group_id: Vec<u8>,
     pub group_state: Vec<u8>,
+    pub epoch: u64,
+    pub config: MlsConfig,
+    pub extensions: GroupContextExtensions,
+    pub roster: Roster,
+    pub own_identity: Vec<u8>,
+    /// Secrets for the current epoch followed by up to `max_past_epochs` older ones
+    pub epoch_secrets: VecDeque<EpochSecret>,
+    /// Generation of the next application message we send in this epoch
+    pub own_generation: u32,
+    pub receive_buffer: ReceiveBuffer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Zeroize)]
+pub struct EpochSecret {
+    pub epoch: u64,
+    pub secret: Vec<u8>,
+    /// Membership during the epoch, which senders of its messages are checked against
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub roster: Roster,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MlsContentType {
+    Application,
+    Proposal,
+    Commit,
+}
+
+/// Outer envelope visible to the delivery service.
+///
+/// Everything else, including the sender and timestamp, is inside the padded ciphertext.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MlsMessage {
-    pub message_id: String,
     pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub content_type: MlsContentType,
+    pub ciphertext: Vec<u8>,
+}
+
+/// A group message as the delivery service queues it for one member device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeliveredMessage {
+    pub group_id: Vec<u8>,
+    /// Position in the group's total order of handshake and application messages
+    pub group_sequence: u64,
+    pub epoch: u64,
+    pub content_type: MlsContentType,
+    pub payload: Vec<u8>,
+}
+
+/// Application content carried inside an encrypted `MlsMessage`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplicationContent {
+    pub message_id: String,
     pub sender_id: String,
-    pub content: Vec<u8>,
+    /// Position in the sender's secret tree ratchet for this epoch
+    pub generation: u32,
     pub timestamp: i64,
+    pub content: Vec<u8>,
 }
 
 impl MlsClient {
     pub fn new(client_id: String) -> Result<Self> {
+        Self::for_device(client_id.clone(), client_id)
+    }
+
+    pub fn for_device(user_id: String, device_id: String) -> Result<Self> {
         // TODO: Implement actual MLS client creation
+        let credential = Credential {
+            user_id,
+            device_id: device_id.clone(),
+            signature_key: vec![0u8; 32],
+        };
+
         Ok(Self {
-            client_id,
-            identity: vec![0u8; 32],
+            client_id: device_id,
+            identity: credential.identity(),
             client_state: vec![0u8; 64],
+            credential,
+            capabilities: Capabilities::default(),
+        })
+    }
+
+    pub fn key_package(&self, cipher_suite: CipherSuite) -> Result<KeyPackage> {
+        if !self.capabilities.cipher_suites.contains(&cipher_suite) {
+            return Err(Error::Protocol(format!(
+                "Client does not support cipher suite {:#06x}",
+                cipher_suite.as_u16()
+            )));
+        }
+
+        // TODO: Generate and sign a real HPKE init key
+        Ok(KeyPackage {
+            credential: self.credential.clone(),
+            cipher_suite,
+            capabilities: self.capabilities.clone(),
+            init_key: vec![0u8; 32],
         })
     }
     
-    pub fn create_group(&mut self, group_id: Vec<u8>) -> Result<MlsGroup> {
+    pub fn create_group(&mut self, config: MlsConfig) -> Result<MlsGroup> {
+        config.validate()?;
+
+        if !self
+            .capabilities
+            .cipher_suites
+            .contains(&config.cipher_suite)
+            || !self
+                .capabilities
+                .satisfies(&config.policy.required_capabilities)
+        {
+            return Err(Error::Protocol(
+                "Client capabilities do not satisfy group policy".to_string(),
+            ));
+        }
+
+        let extensions = GroupContextExtensions {
+            name: String::new(),
+            required_capabilities: config.policy.required_capabilities.clone(),
+            admin_roles: AdminRoles {
+                admins: vec![self.identity.clone()],
+                permissions: GroupPermissions::default(),
+            },
+        };
+
+        let mut roster = Roster::new();
+        roster.add(self.credential.clone(), 0);
+
         // TODO: Implement actual MLS group creation
         Ok(MlsGroup {
-            group_id,
+            group_id: config.group_id.clone(),
             group_state: vec![0u8; 128],
+            epoch: 0,
+            config,
+            extensions,
+            own_identity: self.identity.clone(),
+            epoch_secrets: VecDeque::from([EpochSecret {
+                epoch: 0,
+                secret: vec![0u8; 32],
+                roster: roster.clone(),
+            }]),
+            roster,
+            own_generation: 0,
+            receive_buffer: ReceiveBuffer::new(),
         })
     }
     
-    pub fn join_group(&mut self, group_id: Vec<u8>, _welcome: Vec<u8>) -> Result<MlsGroup> {
-        // TODO: Implement actual MLS group join
+    pub fn join_group(&mut self, config: MlsConfig, welcome: Vec<u8>) -> Result<MlsGroup> {
+        config.validate()?;
+
+        // TODO: Decrypt the Welcome with our KeyPackage init key
+        let welcome: Welcome = serde_json::from_slice(&welcome)?;
+
+        if welcome.group_id != config.group_id {
+            return Err(Error::Protocol(
+                "Welcome is for a different group".to_string(),
+            ));
+        }
+
+        if !welcome.roster.contains(&self.identity) {
+            return Err(Error::Protocol(
+                "Welcome roster does not include this client".to_string(),
+            ));
+        }
+
         Ok(MlsGroup {
-            group_id,
+            group_id: welcome.group_id,
             group_state: vec![0u8; 128],
+            epoch: welcome.epoch,
+            config,
+            extensions: welcome.extensions,
+            own_identity: self.identity.clone(),
+            epoch_secrets: VecDeque::from([EpochSecret {
+                epoch: welcome.epoch,
+                secret: vec![0u8; 32],
+                roster: welcome.roster.clone(),
+            }]),
+            roster: welcome.roster,
+            own_generation: 0,
+            receive_buffer: ReceiveBuffer::new(),
         })
     }
     
-    pub fn send_message(&mut self, _group: &mut MlsGroup, content: &[u8]) -> Result<Vec<u8>> {
-        // TODO: Implement actual MLS message sending
-        Ok(content.to_vec())
+    pub fn send_message(&mut self, group: &mut MlsGroup, content: &[u8]) -> Result<Vec<u8>> {
+        let application = ApplicationContent {
+            message_id: Uuid::new_v4().to_string(),
+            sender_id: self.client_id.clone(),
+            generation: group.own_generation,
+            timestamp: chrono::Utc::now().timestamp(),
+            content: content.to_vec(),
+        };
+
+        let padded = group
+            .config
+            .policy
+            .padding
+            .pad(&serde_json::to_vec(&application)?)?;
+
+        let message = MlsMessage {
+            group_id: group.group_id.clone(),
+            epoch: group.epoch,
+            content_type: MlsContentType::Application,
+            ciphertext: group.encrypt_content(&padded)?,
+        };
+        group.own_generation += 1;
+
+        Ok(serde_json::to_vec(&message)?)
+    }
+
+    /// Encode structured content and send it as an application message
+    pub fn send_content(&mut self, group: &mut MlsGroup, content: &Content) -> Result<Vec<u8>> {
+        self.send_message(group, &content.encode()?)
     }
     
-    pub fn receive_message(&mut self, _group: &mut MlsGroup, message: Vec<u8>) -> Result<Vec<u8>> {
-        // TODO: Implement actual MLS message receiving
-        Ok(message)
+    pub fn receive_message(
+        &mut self,
+        group: &mut MlsGroup,
+        message: Vec<u8>,
+    ) -> Result<Vec<ReceiveEvent>> {
+        group.receive_application(&message)
     }
 }
 
 impl MlsGroup {
-    pub fn add_member(&mut self, _client: &MlsClient) -> Result<Vec<u8>> {
-        // TODO: Implement actual MLS member addition
-        Ok(vec![0u8; 32])
+    pub fn add_member(&mut self, client: &MlsClient) -> Result<Vec<u8>> {
+        let key_package = client.key_package(self.config.cipher_suite)?;
+        self.commit(vec![Proposal::Add(key_package)])
     }
     
-    pub fn remove_member(&mut self, _member_id: &[u8]) -> Result<Vec<u8>> {
-        // TODO: Implement actual MLS member removal
-        Ok(vec![0u8; 32])
+    pub fn remove_member(&mut self, member_id: &[u8]) -> Result<Vec<u8>> {
+        self.commit(vec![Proposal::Remove {
+            member_id: member_id.to_vec(),
+        }])
+    }
+
+    pub fn rename(&mut self, name: String) -> Result<Vec<u8>> {
+        let mut extensions = self.extensions.clone();
+        extensions.name = name;
+        self.commit(vec![Proposal::GroupContextExtensions(extensions)])
     }
-}
\ No newline at end of file
+
+    pub fn set_role(&mut self, member_id: &[u8], role: GroupRole) -> Result<Vec<u8>> {
+        let mut extensions = self.extensions.clone();
+        extensions.admin_roles.admins.retain(|admin| admin != member_id);
+        if role == GroupRole::Admin {
+            extensions.admin_roles.admins.push(member_id.to_vec());
+        }
+        self.commit(vec![Proposal::GroupContextExtensions(extensions)])
+    }
+
+    pub fn update_extensions(&mut self, extensions: GroupContextExtensions) -> Result<Vec<u8>> {
+        self.commit(vec![Proposal::GroupContextExtensions(extensions)])
+    }
+
+    /// Welcome for members added in the current epoch
+    pub fn welcome(&self) -> Result<Vec<u8>> {
+        let welcome = Welcome {
+            group_id: self.group_id.clone(),
+            epoch: self.epoch,
+            roster: self.roster.clone(),
+            extensions: self.extensions.clone(),
+        };
+
+        // TODO: Encrypt the group secrets to each new member's init key
+        Ok(serde_json::to_vec(&welcome)?)
+    }
+
+    /// Build a commit from our own proposals, apply it locally and return its encoding
+    fn commit(&mut self, proposals: Vec<Proposal>) -> Result<Vec<u8>> {
+        let commit = Commit {
+            group_id: self.group_id.clone(),
+            epoch: self.epoch,
+            committer: self.own_identity.clone(),
+            proposals,
+        };
+
+        self.process_commit(&commit)?;
+
+        // TODO: Frame and sign the commit as an MLS PublicMessage
+        Ok(serde_json::to_vec(&commit)?)
+    }
+
+    /// Check a commit against the group policy without applying it
+    pub fn validate_commit(&self, commit: &Commit) -> Result<()> {
+        self.apply_commit(commit).map(|_| ())
+    }
+
+    /// Validate a commit against the group policy and advance to the next epoch
+    pub fn process_commit(&mut self, commit: &Commit) -> Result<()> {
+        let (roster, extensions) = self.apply_commit(commit)?;
+
+        self.roster = roster;
+        self.extensions = extensions;
+
+        // TODO: Update the ratchet tree and derive the new epoch secret from the commit secret
+        self.epoch += 1;
+        self.own_generation = 0;
+        self.epoch_secrets.push_front(EpochSecret {
+            epoch: self.epoch,
+            secret: vec![0u8; 32],
+            roster: self.roster.clone(),
+        });
+
+        while self.epoch_secrets.len() > self.config.policy.max_past_epochs + 1 {
+            if let Some(mut expired) = self.epoch_secrets.pop_back() {
+                expired.zeroize();
+            }
+        }
+
+        let oldest_epoch = self.epoch_secrets.back().map_or(self.epoch, |s| s.epoch);
+        self.receive_buffer.prune(oldest_epoch);
+        Ok(())
+    }
+
+    /// Compute the roster and extensions that result from a commit, enforcing policy
+    fn apply_commit(&self, commit: &Commit) -> Result<(Roster, GroupContextExtensions)> {
+        if commit.group_id != self.group_id {
+            return Err(Error::Protocol(
+                "Commit is for a different group".to_string(),
+            ));
+        }
+
+        if commit.epoch != self.epoch {
+            return Err(Error::Protocol(format!(
+                "Commit epoch {} does not match group epoch {}",
+                commit.epoch, self.epoch
+            )));
+        }
+
+        if !self.roster.contains(&commit.committer) {
+            return Err(Error::Protocol(
+                "Committer is not a member of the group".to_string(),
+            ));
+        }
+
+        let admin_roles = &self.extensions.admin_roles;
+        let committer_role = admin_roles.role_of(&commit.committer);
+        let mut roster = self.roster.clone();
+        let mut extensions = self.extensions.clone();
+
+        for proposal in &commit.proposals {
+            match proposal {
+                Proposal::Add(key_package) => {
+                    if committer_role < admin_roles.permissions.add_members {
+                        return Err(Error::Protocol(
+                            "Committer may not add members".to_string(),
+                        ));
+                    }
+
+                    if key_package.cipher_suite != self.config.cipher_suite
+                        || !self
+                            .config
+                            .policy
+                            .allowed_cipher_suites
+                            .contains(&key_package.cipher_suite)
+                    {
+                        return Err(Error::Protocol(format!(
+                            "KeyPackage cipher suite {:#06x} is not allowed in this group",
+                            key_package.cipher_suite.as_u16()
+                        )));
+                    }
+
+                    if roster.contains(&key_package.credential.identity()) {
+                        return Err(Error::Protocol(
+                            "Device is already a member of the group".to_string(),
+                        ));
+                    }
+
+                    roster.add(key_package.credential.clone(), self.epoch + 1);
+                }
+                Proposal::Remove { member_id } => {
+                    // Members may always remove themselves
+                    if member_id != &commit.committer
+                        && committer_role < admin_roles.permissions.remove_members
+                    {
+                        return Err(Error::Protocol(
+                            "Committer may not remove members".to_string(),
+                        ));
+                    }
+
+                    if roster.remove(member_id).is_none() {
+                        return Err(Error::Protocol(
+                            "Removed device is not a member of the group".to_string(),
+                        ));
+                    }
+
+                    extensions.admin_roles.admins.retain(|admin| admin != member_id);
+                }
+                Proposal::GroupContextExtensions(proposed) => {
+                    let rename_only = GroupContextExtensions {
+                        name: proposed.name.clone(),
+                        ..extensions.clone()
+                    } == *proposed;
+
+                    let required_role = if rename_only {
+                        admin_roles.permissions.rename
+                    } else {
+                        GroupRole::Admin
+                    };
+
+                    if committer_role < required_role {
+                        return Err(Error::Protocol(
+                            "Committer may not change group context extensions".to_string(),
+                        ));
+                    }
+
+                    extensions = proposed.clone();
+                }
+            }
+        }
+
+        if roster.len() > self.config.policy.max_members {
+            return Err(Error::Protocol(format!(
+                "Group would exceed its limit of {} members",
+                self.config.policy.max_members
+            )));
+        }
+
+        // Admins must stay members, and a non-empty group keeps at least one of them
+        extensions
+            .admin_roles
+            .admins
+            .retain(|admin| roster.contains(admin));
+
+        if !roster.is_empty() && extensions.admin_roles.admins.is_empty() {
+            return Err(Error::Protocol(
+                "Group must keep at least one admin".to_string(),
+            ));
+        }
+
+        // Added members must satisfy the capabilities in force after this commit
+        for proposal in &commit.proposals {
+            if let Proposal::Add(key_package) = proposal {
+                if !key_package
+                    .capabilities
+                    .satisfies(&extensions.required_capabilities)
+                {
+                    return Err(Error::Protocol(
+                        "KeyPackage does not advertise the required capabilities".to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok((roster, extensions))
+    }
+
+    pub fn has_epoch_secret(&self, epoch: u64) -> bool {
+        self.epoch_secrets.iter().any(|secret| secret.epoch == epoch)
+    }
+
+    /// Roster as it stood in a retained epoch
+    pub fn roster_at(&self, epoch: u64) -> Option<&Roster> {
+        self.epoch_secrets
+            .iter()
+            .find(|secret| secret.epoch == epoch)
+            .map(|secret| &secret.roster)
+    }
+
+    /// Seal padded content under the current epoch's secret; the nonce is prepended
+    fn encrypt_content(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        // TODO: Encrypt under the epoch's application secret ratchet rather than one key per epoch
+        let cipher = self.content_cipher(self.epoch)?;
+        let nonce: [u8; 12] = rand::random();
+        let ciphertext = cipher
+            .encrypt(
+                nonce.as_slice().into(),
+                Payload {
+                    msg: plaintext,
+                    aad: &self.content_aad(self.epoch),
+                },
+            )
+            .map_err(|_| Error::Crypto("Cannot encrypt application content".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    pub(crate) fn decrypt_content(&self, epoch: u64, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let cipher = self.content_cipher(epoch)?;
+        if ciphertext.len() < 12 {
+            return Err(Error::Crypto("Application content is truncated".to_string()));
+        }
+
+        let (nonce, ciphertext) = ciphertext.split_at(12);
+        cipher
+            .decrypt(
+                nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: &self.content_aad(epoch),
+                },
+            )
+            .map_err(|_| Error::Crypto("Cannot decrypt application content".to_string()))
+    }
+
+    fn content_cipher(&self, epoch: u64) -> Result<ChaCha20Poly1305> {
+        let secret = self
+            .epoch_secrets
+            .iter()
+            .find(|secret| secret.epoch == epoch)
+            .ok_or_else(|| Error::Protocol(format!("No secret retained for epoch {}", epoch)))?;
+
+        let mut info = CONTENT_KEY_LABEL.to_vec();
+        info.extend_from_slice(&epoch.to_be_bytes());
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&self.group_id), &secret.secret)
+            .expand(&info, &mut key)
+            .map_err(|_| Error::Crypto("Application key derivation failed".to_string()))?;
+        let cipher = ChaCha20Poly1305::new(&key.into());
+        key.zeroize();
+        Ok(cipher)
+    }
+
+    /// Binds the ciphertext to its group and epoch
+    fn content_aad(&self, epoch: u64) -> Vec<u8> {
+        let mut aad = CONTENT_KEY_LABEL.to_vec();
+        aad.extend_from_slice(&(self.group_id.len() as u32).to_be_bytes());
+        aad.extend_from_slice(&self.group_id);
+        aad.extend_from_slice(&epoch.to_be_bytes());
+        aad
+    }
+
+    pub fn is_admin(&self, identity: &[u8]) -> bool {
+        self.extensions.admin_roles.is_admin(identity)
+    }
+
+    pub fn role_of(&self, identity: &[u8]) -> Option<GroupRole> {
+        self.roster
+            .get(identity)
+            .map(|_| self.extensions.admin_roles.role_of(identity))
+    }
+
+    pub fn members(&self) -> &[Member] {
+        self.roster.members()
+    }
+}
@@ -1,9 +1,43 @@
 //! Protocol implementation for XIPRNET messaging
 
+pub mod codec;
+pub mod compression;
+pub mod content;
+pub mod deletion;
+pub mod fanout;
+pub mod mfa;
 pub mod mls;
+pub mod padding;
+pub mod proof;
+pub mod provisioning;
+pub mod quic;
+pub mod receive;
+pub mod replay;
+pub mod roster;
+pub mod sealed;
+pub mod throttle;
 pub mod transport;
+pub mod webauthn;
+pub mod wire;
 pub mod auth;
 
+pub use codec::*;
+pub use compression::*;
+pub use content::*;
+pub use deletion::*;
+pub use fanout::*;
+pub use mfa::*;
 pub use mls::*;
+pub use padding::*;
+pub use proof::*;
+pub use provisioning::*;
+pub use quic::*;
+pub use receive::*;
+pub use replay::*;
+pub use roster::*;
+pub use sealed::*;
+pub use throttle::*;
 pub use transport::*;
+pub use webauthn::*;
+pub use wire::*;
 pub use auth::*;
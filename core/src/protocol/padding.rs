@@ -0,0 +1,113 @@
+//! Length padding for encrypted content
+//!
+//! Hides exact plaintext lengths from anyone who only sees ciphertext sizes
+
+use crate::utils::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// Length prefix written in front of the padded content
+const LENGTH_PREFIX: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaddingScheme {
+    /// Pad to a multiple of the block size
+    Block(usize),
+    /// Pad to the smallest bucket that fits; larger content falls back to the last bucket's multiple
+    Buckets(Vec<usize>),
+    /// Padmé: leaks at most O(log log n) bits of the length
+    Padme,
+}
+
+impl Default for PaddingScheme {
+    fn default() -> Self {
+        PaddingScheme::Block(256)
+    }
+}
+
+impl PaddingScheme {
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            PaddingScheme::Block(0) => Err(Error::Protocol(
+                "Padding block size must be non-zero".to_string(),
+            )),
+            PaddingScheme::Buckets(buckets) => {
+                if buckets.is_empty() || buckets.contains(&0) {
+                    return Err(Error::Protocol(
+                        "Padding buckets must be non-empty and non-zero".to_string(),
+                    ));
+                }
+
+                if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(Error::Protocol(
+                        "Padding buckets must be strictly increasing".to_string(),
+                    ));
+                }
+
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Size a buffer of `length` bytes is padded to
+    pub fn padded_length(&self, length: usize) -> usize {
+        match self {
+            PaddingScheme::Block(block) => round_up(length, *block),
+            PaddingScheme::Buckets(buckets) => match buckets.iter().find(|&&b| b >= length) {
+                Some(bucket) => *bucket,
+                None => round_up(length, *buckets.last().unwrap_or(&1)),
+            },
+            PaddingScheme::Padme => padme(length),
+        }
+    }
+
+    /// Length-prefix `content` and zero-pad it to the scheme's target size
+    pub fn pad(&self, content: &[u8]) -> Result<Vec<u8>> {
+        let length = u32::try_from(content.len())
+            .map_err(|_| Error::Protocol("Content too large to pad".to_string()))?;
+
+        let target = self.padded_length(LENGTH_PREFIX + content.len());
+        let mut padded = Vec::with_capacity(target);
+        padded.extend_from_slice(&length.to_be_bytes());
+        padded.extend_from_slice(content);
+        padded.resize(target, 0);
+        Ok(padded)
+    }
+
+    /// Strip padding added by [`PaddingScheme::pad`], rejecting non-zero padding bytes
+    pub fn unpad(padded: &[u8]) -> Result<Vec<u8>> {
+        if padded.len() < LENGTH_PREFIX {
+            return Err(Error::Protocol("Padded content is truncated".to_string()));
+        }
+
+        let (prefix, rest) = padded.split_at(LENGTH_PREFIX);
+        let length = u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize;
+
+        if length > rest.len() {
+            return Err(Error::Protocol("Padded content is truncated".to_string()));
+        }
+
+        let (content, padding) = rest.split_at(length);
+        if padding.iter().any(|&byte| byte != 0) {
+            return Err(Error::Protocol("Padding contains non-zero bytes".to_string()));
+        }
+
+        Ok(content.to_vec())
+    }
+}
+
+fn round_up(length: usize, multiple: usize) -> usize {
+    length.div_ceil(multiple.max(1)) * multiple.max(1)
+}
+
+fn padme(length: usize) -> usize {
+    if length < 2 {
+        return length;
+    }
+
+    let exponent = usize::BITS - 1 - length.leading_zeros();
+    let exponent_bits = u32::BITS - exponent.leading_zeros();
+    let last_bits = exponent - exponent_bits;
+    let mask = (1usize << last_bits) - 1;
+    (length + mask) & !mask
+}
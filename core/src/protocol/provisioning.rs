@@ -0,0 +1,261 @@
+//! Device linking
+//!
+//! A new device shows a QR code holding a provisioning id and a one-time
+//! X25519 public key. An existing device of the same user scans it and
+//! uploads the user's identity material sealed to that key, together with a
+//! digest of a one-time link code inside the sealed message. Only the new
+//! device can open the envelope, and it proves so to the server by
+//! presenting the link code. The server relays ciphertext and never sees the
+//! identity material.
+
+use crate::utils::{Error, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{aead::Aead, aead::Payload, ChaCha20Poly1305, KeyInit};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+use zeroize::Zeroize;
+
+/// Scheme and path of the URI encoded in a linking QR code
+pub const PROVISIONING_URI_PREFIX: &str = "xipr://link";
+
+/// How long a provisioning slot waits for the envelope and the new device, in seconds
+pub const PROVISIONING_LIFETIME: i64 = 10 * 60;
+
+/// Length of the link code inside a provisioning message, in bytes
+pub const LINK_CODE_LENGTH: usize = 32;
+
+const ENVELOPE_LABEL: &[u8] = b"xipr provisioning envelope v1";
+const LINK_CODE_LABEL: &[u8] = b"xipr link code v1";
+
+/// What a linking QR code carries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvisioningCode {
+    pub provisioning_id: String,
+    pub public_key: [u8; 32],
+}
+
+impl std::fmt::Display for ProvisioningCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}?id={}&key={}",
+            PROVISIONING_URI_PREFIX,
+            self.provisioning_id,
+            URL_SAFE_NO_PAD.encode(self.public_key)
+        )
+    }
+}
+
+impl std::str::FromStr for ProvisioningCode {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        let invalid = || Error::Protocol("Malformed provisioning code".to_string());
+
+        let query = uri
+            .strip_prefix(PROVISIONING_URI_PREFIX)
+            .and_then(|rest| rest.strip_prefix('?'))
+            .ok_or_else(invalid)?;
+
+        let mut provisioning_id = None;
+        let mut public_key = None;
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some(("id", id)) if !id.is_empty() => provisioning_id = Some(id.to_string()),
+                Some(("key", key)) => {
+                    public_key = URL_SAFE_NO_PAD
+                        .decode(key)
+                        .ok()
+                        .and_then(|key| key.try_into().ok())
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            provisioning_id: provisioning_id.ok_or_else(invalid)?,
+            public_key: public_key.ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// The new device's one-time key for a provisioning slot
+///
+/// Opening an envelope consumes it, so a slot can be used once.
+pub struct ProvisioningKeys {
+    provisioning_id: String,
+    secret: EphemeralSecret,
+    public_key: PublicKey,
+}
+
+impl ProvisioningKeys {
+    pub fn generate(provisioning_id: String) -> Self {
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let public_key = PublicKey::from(&secret);
+        Self {
+            provisioning_id,
+            secret,
+            public_key,
+        }
+    }
+
+    /// What to show in the QR code
+    pub fn code(&self) -> ProvisioningCode {
+        ProvisioningCode {
+            provisioning_id: self.provisioning_id.clone(),
+            public_key: self.public_key.to_bytes(),
+        }
+    }
+
+    pub fn open(self, envelope: &ProvisionEnvelope) -> Result<ProvisionMessage> {
+        let ephemeral = PublicKey::from(envelope.validate()?);
+
+        let shared = self.secret.diffie_hellman(&ephemeral);
+        let cipher = envelope_cipher(shared.as_bytes(), &self.provisioning_id)?;
+        let mut plaintext = cipher
+            .decrypt(
+                envelope.nonce.as_slice().into(),
+                Payload {
+                    msg: &envelope.ciphertext,
+                    aad: &envelope_aad(&self.provisioning_id, ephemeral.as_bytes()),
+                },
+            )
+            .map_err(|_| Error::Crypto("Cannot open provisioning envelope".to_string()))?;
+
+        let message = serde_json::from_slice(&plaintext);
+        plaintext.zeroize();
+        Ok(message?)
+    }
+}
+
+/// Identity material an existing device hands to a new one
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProvisionMessage {
+    pub user_id: String,
+    /// Device that did the linking
+    pub linked_by: String,
+    pub identity_public_key: Vec<u8>,
+    pub identity_private_key: Vec<u8>,
+    /// One-time code the new device presents to the server to complete the link
+    pub link_code: Vec<u8>,
+}
+
+impl ProvisionMessage {
+    /// New message with a fresh link code
+    pub fn new(
+        user_id: String,
+        linked_by: String,
+        identity_public_key: Vec<u8>,
+        identity_private_key: Vec<u8>,
+    ) -> Self {
+        Self {
+            user_id,
+            linked_by,
+            identity_public_key,
+            identity_private_key,
+            link_code: rand::random::<[u8; LINK_CODE_LENGTH]>().to_vec(),
+        }
+    }
+
+    /// What the server stores to check the link code against
+    pub fn link_code_digest(&self) -> [u8; 32] {
+        link_code_digest(&self.link_code)
+    }
+}
+
+impl std::fmt::Debug for ProvisionMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProvisionMessage")
+            .field("user_id", &self.user_id)
+            .field("linked_by", &self.linked_by)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for ProvisionMessage {
+    fn drop(&mut self) {
+        self.identity_private_key.zeroize();
+        self.link_code.zeroize();
+    }
+}
+
+/// Digest of a link code, as the linking device uploads it
+pub fn link_code_digest(link_code: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(LINK_CODE_LABEL);
+    hasher.update(link_code);
+    hasher.finalize().into()
+}
+
+/// A [`ProvisionMessage`] sealed to the new device's one-time key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvisionEnvelope {
+    pub ephemeral_public_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl ProvisionEnvelope {
+    /// Check the key and nonce lengths, returning the one-time public key
+    pub fn validate(&self) -> Result<[u8; 32]> {
+        let malformed = || Error::Crypto("Malformed provisioning envelope".to_string());
+        let ephemeral: [u8; 32] = self
+            .ephemeral_public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| malformed())?;
+        if self.nonce.len() != 12 {
+            return Err(malformed());
+        }
+        Ok(ephemeral)
+    }
+
+    pub fn seal(code: &ProvisioningCode, message: &ProvisionMessage) -> Result<Self> {
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let ephemeral = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&PublicKey::from(code.public_key));
+
+        let cipher = envelope_cipher(shared.as_bytes(), &code.provisioning_id)?;
+        let nonce: [u8; 12] = rand::random();
+        let mut plaintext = serde_json::to_vec(message)?;
+        let ciphertext = cipher.encrypt(
+            nonce.as_slice().into(),
+            Payload {
+                msg: &plaintext,
+                aad: &envelope_aad(&code.provisioning_id, ephemeral.as_bytes()),
+            },
+        );
+        plaintext.zeroize();
+
+        Ok(Self {
+            ephemeral_public_key: ephemeral.to_bytes().to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext
+                .map_err(|_| Error::Crypto("Cannot seal provisioning envelope".to_string()))?,
+        })
+    }
+}
+
+fn envelope_cipher(shared: &[u8; 32], provisioning_id: &str) -> Result<ChaCha20Poly1305> {
+    if shared.iter().all(|byte| *byte == 0) {
+        return Err(Error::Crypto("Degenerate provisioning key".to_string()));
+    }
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(provisioning_id.as_bytes()), shared)
+        .expand(ENVELOPE_LABEL, &mut key)
+        .map_err(|_| Error::Crypto("Provisioning key derivation failed".to_string()))?;
+    let cipher = ChaCha20Poly1305::new(&key.into());
+    key.zeroize();
+    Ok(cipher)
+}
+
+fn envelope_aad(provisioning_id: &str, ephemeral_public_key: &[u8; 32]) -> Vec<u8> {
+    let mut aad = ENVELOPE_LABEL.to_vec();
+    aad.extend_from_slice(&(provisioning_id.len() as u32).to_be_bytes());
+    aad.extend_from_slice(provisioning_id.as_bytes());
+    aad.extend_from_slice(ephemeral_public_key);
+    aad
+}
@@ -0,0 +1,376 @@
+//! QUIC transport for the client–server link
+//!
+//! Every conversation gets its own bidirectional stream, so a lost packet only
+//! stalls the conversation it belongs to. A stream starts with a header naming
+//! its conversation, followed by frames in the same length-prefixed framing as
+//! [`crate::protocol::codec`].
+//!
+//! Connections survive a change of client address. Resumed connections may
+//! carry 0-RTT data, which an attacker can replay, so only idempotent frames
+//! go out before the handshake is confirmed. Both ends enforce this: senders
+//! hold other frames back, and receivers do not hand them over until the
+//! handshake completes, which a replayed handshake never does.
+
+use crate::protocol::codec::FrameCodec;
+use crate::protocol::transport::MessageFrame;
+use crate::protocol::wire::MAX_ID_LENGTH;
+use crate::utils::{Error, Result};
+use futures::{SinkExt, StreamExt};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Connecting, Connection, Endpoint, IdleTimeout, RecvStream, SendStream, VarInt};
+use rustls::pki_types::pem::PemObject;
+use rustls::RootCertStore;
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+pub use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+/// ALPN identifier of this protocol
+pub const ALPN_PROTOCOL: &[u8] = b"xipr/1";
+
+/// Connections with no traffic for this long are closed
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Interval of client keep-alives, well inside typical NAT binding timeouts
+pub const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Conversation streams each side may have open at once
+pub const MAX_CONVERSATION_STREAMS: u32 = 256;
+
+/// Conversation that carries session control rather than messages
+pub const SESSION_CONVERSATION: &str = "session";
+
+fn network_error(e: impl fmt::Display) -> Error {
+    Error::Network(e.to_string())
+}
+
+fn crypto_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn transport_config(keep_alive: Option<Duration>) -> Arc<quinn::TransportConfig> {
+    let mut transport = quinn::TransportConfig::default();
+    transport
+        .max_idle_timeout(IdleTimeout::try_from(DEFAULT_IDLE_TIMEOUT).ok())
+        .keep_alive_interval(keep_alive)
+        .max_concurrent_bidi_streams(VarInt::from_u32(MAX_CONVERSATION_STREAMS));
+    Arc::new(transport)
+}
+
+/// Read a PEM certificate chain and private key for [`QuicServer::bind`]
+pub fn load_pem_identity(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert_chain = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::Crypto(format!("Invalid certificate chain: {}", e)))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| Error::Crypto(format!("Invalid private key: {}", e)))?;
+
+    Ok((cert_chain, key))
+}
+
+/// Server end of the link
+pub struct QuicServer {
+    endpoint: Endpoint,
+}
+
+impl QuicServer {
+    pub fn bind(
+        addr: SocketAddr,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self> {
+        let mut tls = rustls::ServerConfig::builder_with_provider(crypto_provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| Error::Crypto(e.to_string()))?
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)
+            .map_err(|e| Error::Crypto(e.to_string()))?;
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        // QUIC only allows all or nothing; what may be sent early is up to `ConversationStream`
+        tls.max_early_data_size = u32::MAX;
+
+        let crypto = QuicServerConfig::try_from(tls).map_err(|e| Error::Crypto(e.to_string()))?;
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        config
+            .transport_config(transport_config(None))
+            .migration(true);
+
+        Ok(Self {
+            endpoint: Endpoint::server(config, addr)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Wait for the next connection; `None` once the endpoint is closed
+    pub async fn accept(&self) -> Option<Result<QuicLink>> {
+        let incoming = self.endpoint.accept().await?;
+        Some(match incoming.accept() {
+            Ok(connecting) => QuicLink::establish(connecting).await,
+            Err(e) => Err(network_error(e)),
+        })
+    }
+
+    pub fn close(&self) {
+        self.endpoint.close(VarInt::from_u32(0), b"shutdown");
+    }
+}
+
+/// Client end of the link
+pub struct QuicClient {
+    endpoint: Endpoint,
+}
+
+impl QuicClient {
+    /// Bind a local address and trust server certificates issued by `trust_anchors`
+    pub fn bind(addr: SocketAddr, trust_anchors: Vec<CertificateDer<'static>>) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for anchor in trust_anchors {
+            roots
+                .add(anchor)
+                .map_err(|e| Error::Crypto(e.to_string()))?;
+        }
+
+        let mut tls = rustls::ClientConfig::builder_with_provider(crypto_provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| Error::Crypto(e.to_string()))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        tls.enable_early_data = true;
+
+        let crypto = QuicClientConfig::try_from(tls).map_err(|e| Error::Crypto(e.to_string()))?;
+        let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+        config.transport_config(transport_config(Some(DEFAULT_KEEP_ALIVE_INTERVAL)));
+
+        let mut endpoint = Endpoint::client(addr)?;
+        endpoint.set_default_client_config(config);
+        Ok(Self { endpoint })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Connect to a server, resuming with 0-RTT when an earlier session allows it
+    pub async fn connect(&self, server: SocketAddr, server_name: &str) -> Result<QuicLink> {
+        let connecting = self
+            .endpoint
+            .connect(server, server_name)
+            .map_err(network_error)?;
+        QuicLink::establish(connecting).await
+    }
+
+    /// Move every connection to a new local address, e.g. after a network change
+    ///
+    /// Open connections and streams carry on; the server validates the new path.
+    pub fn rebind(&self, addr: SocketAddr) -> Result<()> {
+        Ok(self.endpoint.rebind(UdpSocket::bind(addr)?)?)
+    }
+
+    pub fn close(&self) {
+        self.endpoint.close(VarInt::from_u32(0), b"shutdown");
+    }
+}
+
+/// An established connection, shared by all of its conversation streams
+#[derive(Clone)]
+pub struct QuicLink {
+    connection: Connection,
+    /// Becomes true once the handshake is confirmed and 0-RTT data no longer goes out
+    confirmed: watch::Receiver<bool>,
+    early: bool,
+}
+
+impl QuicLink {
+    async fn establish(connecting: Connecting) -> Result<Self> {
+        match connecting.into_0rtt() {
+            Ok((connection, handshake)) => {
+                let (confirm, confirmed) = watch::channel(false);
+                let watched = connection.clone();
+                tokio::spawn(async move {
+                    // Resolves when the handshake completes, whether or not 0-RTT was
+                    // accepted, and also when the connection fails first. Dropping `confirm`
+                    // then fails every wait, so early frames held back are never released.
+                    handshake.await;
+                    if watched.close_reason().is_none() {
+                        confirm.send_replace(true);
+                    }
+                });
+
+                Ok(Self {
+                    connection,
+                    confirmed,
+                    early: true,
+                })
+            }
+            Err(connecting) => Ok(Self {
+                connection: connecting.await.map_err(network_error)?,
+                confirmed: watch::channel(true).1,
+                early: false,
+            }),
+        }
+    }
+
+    /// Whether this connection started before its handshake completed
+    ///
+    /// Always true on the server, where a client may send 0-RTT data at any time.
+    pub fn is_early(&self) -> bool {
+        self.early
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        *self.confirmed.borrow()
+    }
+
+    /// Wait until the handshake is confirmed
+    pub async fn confirmed(&self) -> Result<()> {
+        let mut confirmed = self.confirmed.clone();
+        confirmed
+            .wait_for(|confirmed| *confirmed)
+            .await
+            .map(|_| ())
+            .map_err(|_| Error::Network("Connection closed during handshake".to_string()))
+    }
+
+    /// Current address of the peer, which changes when a client migrates
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    /// Open a stream for a conversation
+    pub async fn open_conversation(&self, conversation_id: &str) -> Result<ConversationStream> {
+        if conversation_id.is_empty() || conversation_id.len() > MAX_ID_LENGTH {
+            return Err(Error::Protocol(format!(
+                "Conversation id must be 1 to {} bytes",
+                MAX_ID_LENGTH
+            )));
+        }
+
+        let (mut send, recv) = self.connection.open_bi().await.map_err(network_error)?;
+
+        // Peers only see a stream once something is written on it
+        let mut header = vec![conversation_id.len() as u8];
+        header.extend_from_slice(conversation_id.as_bytes());
+        send.write_all(&header).await.map_err(network_error)?;
+
+        Ok(ConversationStream::new(
+            conversation_id.to_string(),
+            send,
+            recv,
+            self.confirmed.clone(),
+        ))
+    }
+
+    /// Wait for the peer to open a conversation stream
+    pub async fn accept_conversation(&self) -> Result<ConversationStream> {
+        let (send, mut recv) = self.connection.accept_bi().await.map_err(network_error)?;
+
+        let mut length = [0u8; 1];
+        recv.read_exact(&mut length).await.map_err(network_error)?;
+        let length = length[0] as usize;
+        if length == 0 || length > MAX_ID_LENGTH {
+            return Err(Error::Protocol(format!(
+                "Invalid conversation id length {}",
+                length
+            )));
+        }
+
+        let mut id = vec![0u8; length];
+        recv.read_exact(&mut id).await.map_err(network_error)?;
+        let conversation_id = String::from_utf8(id)
+            .map_err(|_| Error::Protocol("Conversation id is not UTF-8".to_string()))?;
+
+        Ok(ConversationStream::new(
+            conversation_id,
+            send,
+            recv,
+            self.confirmed.clone(),
+        ))
+    }
+
+    pub fn close(&self, reason: &str) {
+        self.connection
+            .close(VarInt::from_u32(0), reason.as_bytes());
+    }
+
+    /// Wait until the connection is closed by either side or times out
+    pub async fn closed(&self) -> Error {
+        network_error(self.connection.closed().await)
+    }
+}
+
+/// One conversation's frames, in both directions
+pub struct ConversationStream {
+    conversation_id: String,
+    reader: FramedRead<RecvStream, FrameCodec>,
+    writer: FramedWrite<SendStream, FrameCodec>,
+    confirmed: watch::Receiver<bool>,
+}
+
+impl ConversationStream {
+    fn new(
+        conversation_id: String,
+        send: SendStream,
+        recv: RecvStream,
+        confirmed: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            conversation_id,
+            reader: FramedRead::new(recv, FrameCodec::default()),
+            writer: FramedWrite::new(send, FrameCodec::default()),
+            confirmed,
+        }
+    }
+
+    pub fn conversation_id(&self) -> &str {
+        &self.conversation_id
+    }
+
+    /// Send a frame; frames that are not idempotent wait for a confirmed handshake
+    pub async fn send(&mut self, frame: &MessageFrame) -> Result<()> {
+        if !frame.header.message_type.is_idempotent() {
+            self.wait_confirmed().await?;
+        }
+        self.writer.send(frame).await
+    }
+
+    /// Next frame from the peer, or `None` once the peer finished the stream
+    ///
+    /// Frames that are not idempotent are only returned once the handshake is
+    /// confirmed, so replayed 0-RTT data never gets past this point.
+    pub async fn recv(&mut self) -> Result<Option<MessageFrame>> {
+        let Some(frame) = self.reader.next().await.transpose()? else {
+            return Ok(None);
+        };
+
+        if !frame.header.message_type.is_idempotent() {
+            self.wait_confirmed().await?;
+        }
+        Ok(Some(frame))
+    }
+
+    /// Finish the sending side; the peer's `recv` returns `None` after the last frame
+    pub fn finish(&mut self) -> Result<()> {
+        // `send` flushes, so nothing is left in the codec's buffer
+        self.writer.get_mut().finish().map_err(network_error)
+    }
+
+    async fn wait_confirmed(&mut self) -> Result<()> {
+        self.confirmed
+            .wait_for(|confirmed| *confirmed)
+            .await
+            .map(|_| ())
+            .map_err(|_| Error::Network("Connection closed during handshake".to_string()))
+    }
+}
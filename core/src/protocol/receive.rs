@@ -0,0 +1,248 @@
+//! MLS receive pipeline
+//!
+//! Buffers messages that arrive ahead of their epoch, drops duplicates and
+//! reports messages that can never be decrypted so they can be retried or resynced
+
+use crate::protocol::mls::{ApplicationContent, Commit, MlsContentType, MlsGroup, MlsMessage};
+use crate::protocol::padding::PaddingScheme;
+use crate::utils::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Upper bound on messages held while waiting for a commit
+pub const MAX_BUFFERED_MESSAGES: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UndecryptableReason {
+    /// The message's epoch is older than the retained past epochs
+    EpochExpired,
+    /// The buffer was full and this was the oldest waiting message
+    BufferOverflow,
+    /// The epoch secret was available but decryption or parsing failed
+    DecryptionFailed(String),
+    /// A buffered commit conflicts with the commit that was applied for its epoch
+    CommitRejected(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReceiveEvent {
+    Application(ApplicationContent),
+    CommitApplied { epoch: u64 },
+    Buffered { epoch: u64 },
+    Duplicate,
+    /// Raw message is returned so the caller can request a retransmit or resync
+    Undecryptable {
+        epoch: u64,
+        reason: UndecryptableReason,
+        message: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum PendingMessage {
+    Commit(Commit),
+    Application(MlsMessage),
+}
+
+impl PendingMessage {
+    fn epoch(&self) -> u64 {
+        match self {
+            PendingMessage::Commit(commit) => commit.epoch,
+            PendingMessage::Application(message) => message.epoch,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            PendingMessage::Commit(commit) => serde_json::to_vec(commit),
+            PendingMessage::Application(message) => serde_json::to_vec(message),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// Per-group receive state kept alongside the group
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReceiveBuffer {
+    pending: Vec<PendingMessage>,
+    /// Generations already delivered, keyed by epoch and sender
+    seen: HashMap<u64, HashMap<String, BTreeSet<u32>>>,
+}
+
+impl ReceiveBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Forget delivery history for epochs whose secrets are no longer retained
+    pub fn prune(&mut self, oldest_epoch: u64) {
+        self.seen.retain(|epoch, _| *epoch >= oldest_epoch);
+    }
+
+    fn push(&mut self, message: PendingMessage, events: &mut Vec<ReceiveEvent>) {
+        events.push(ReceiveEvent::Buffered {
+            epoch: message.epoch(),
+        });
+        self.pending.push(message);
+
+        if self.pending.len() > MAX_BUFFERED_MESSAGES {
+            let dropped = self.pending.remove(0);
+            events.push(ReceiveEvent::Undecryptable {
+                epoch: dropped.epoch(),
+                reason: UndecryptableReason::BufferOverflow,
+                message: dropped.encode(),
+            });
+        }
+    }
+
+    /// Record a generation and report whether it was new
+    fn mark_seen(&mut self, epoch: u64, sender_id: &str, generation: u32) -> bool {
+        self.seen
+            .entry(epoch)
+            .or_default()
+            .entry(sender_id.to_string())
+            .or_default()
+            .insert(generation)
+    }
+}
+
+impl MlsGroup {
+    /// Feed an encoded application message into the receive pipeline
+    pub fn receive_application(&mut self, message: &[u8]) -> Result<Vec<ReceiveEvent>> {
+        let message: MlsMessage = serde_json::from_slice(message)?;
+
+        if message.group_id != self.group_id {
+            return Err(Error::Protocol(
+                "Message is for a different group".to_string(),
+            ));
+        }
+
+        if message.content_type != MlsContentType::Application {
+            return Err(Error::Protocol(
+                "Expected an application message".to_string(),
+            ));
+        }
+
+        let mut events = Vec::new();
+        if message.epoch > self.epoch {
+            self.receive_buffer
+                .push(PendingMessage::Application(message), &mut events);
+        } else {
+            events.push(self.decrypt_application(&message));
+        }
+
+        Ok(events)
+    }
+
+    /// Feed an encoded commit into the receive pipeline, releasing anything it unblocks
+    pub fn receive_commit(&mut self, commit: &[u8]) -> Result<Vec<ReceiveEvent>> {
+        let commit: Commit = serde_json::from_slice(commit)?;
+
+        if commit.group_id != self.group_id {
+            return Err(Error::Protocol(
+                "Commit is for a different group".to_string(),
+            ));
+        }
+
+        let mut events = Vec::new();
+        if commit.epoch < self.epoch {
+            // Already applied, usually our own commit echoed back
+            events.push(ReceiveEvent::Duplicate);
+        } else if commit.epoch > self.epoch {
+            self.receive_buffer
+                .push(PendingMessage::Commit(commit), &mut events);
+        } else {
+            self.process_commit(&commit)?;
+            events.push(ReceiveEvent::CommitApplied { epoch: self.epoch });
+            self.drain_buffer(&mut events);
+        }
+
+        Ok(events)
+    }
+
+    /// Apply buffered commits in epoch order and release application messages they unblock
+    fn drain_buffer(&mut self, events: &mut Vec<ReceiveEvent>) {
+        loop {
+            let pending = std::mem::take(&mut self.receive_buffer.pending);
+            let (ready, waiting): (Vec<_>, Vec<_>) = pending
+                .into_iter()
+                .partition(|message| message.epoch() <= self.epoch);
+            self.receive_buffer.pending = waiting;
+
+            if ready.is_empty() {
+                return;
+            }
+
+            for message in ready {
+                match message {
+                    PendingMessage::Application(message) => {
+                        events.push(self.decrypt_application(&message));
+                    }
+                    PendingMessage::Commit(commit) if commit.epoch == self.epoch => {
+                        match self.process_commit(&commit) {
+                            Ok(()) => events.push(ReceiveEvent::CommitApplied { epoch: self.epoch }),
+                            Err(e) => events.push(ReceiveEvent::Undecryptable {
+                                epoch: commit.epoch,
+                                reason: UndecryptableReason::CommitRejected(e.to_string()),
+                                message: serde_json::to_vec(&commit).unwrap_or_default(),
+                            }),
+                        }
+                    }
+                    PendingMessage::Commit(_) => events.push(ReceiveEvent::Duplicate),
+                }
+            }
+        }
+    }
+
+    fn decrypt_application(&mut self, message: &MlsMessage) -> ReceiveEvent {
+        let undecryptable = |reason| ReceiveEvent::Undecryptable {
+            epoch: message.epoch,
+            reason,
+            message: serde_json::to_vec(message).unwrap_or_default(),
+        };
+
+        if !self.has_epoch_secret(message.epoch) {
+            return undecryptable(UndecryptableReason::EpochExpired);
+        }
+
+        let application = self
+            .decrypt_content(message.epoch, &message.ciphertext)
+            .and_then(|padded| PaddingScheme::unpad(&padded))
+            .and_then(|content| Ok(serde_json::from_slice::<ApplicationContent>(&content)?));
+
+        let application = match application {
+            Ok(application) => application,
+            Err(e) => return undecryptable(UndecryptableReason::DecryptionFailed(e.to_string())),
+        };
+
+        // TODO: Authenticate the sender against its leaf signature key
+        // Judge the sender by the epoch it sent in: a member removed since may have
+        // sent it, one added since cannot have
+        let sender_was_member = self
+            .roster_at(message.epoch)
+            .is_some_and(|roster| roster.contains(application.sender_id.as_bytes()));
+        if !sender_was_member {
+            return undecryptable(UndecryptableReason::DecryptionFailed(
+                "Sender is not a member of the group".to_string(),
+            ));
+        }
+
+        if !self.receive_buffer.mark_seen(
+            message.epoch,
+            &application.sender_id,
+            application.generation,
+        ) {
+            return ReceiveEvent::Duplicate;
+        }
+
+        ReceiveEvent::Application(application)
+    }
+}
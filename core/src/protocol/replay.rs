@@ -0,0 +1,207 @@
+//! Replay and reordering protection
+//!
+//! Tracks what each sender device has already sent: recent message ids, and
+//! for sequenced messages a sliding window over sequence numbers in the style
+//! of the IPsec anti-replay window.
+
+use crate::protocol::transport::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use thiserror::Error;
+
+/// Sequence numbers this far behind the highest one seen are rejected
+pub const REPLAY_WINDOW_SIZE: u64 = 128;
+
+/// Why a message was refused
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+pub enum MessageRejection {
+    #[error("missing {0}")]
+    MissingField(String),
+
+    #[error("timestamp {timestamp} is more than {max_skew}s behind {now}")]
+    TimestampTooOld {
+        timestamp: i64,
+        now: i64,
+        max_skew: i64,
+    },
+
+    #[error("timestamp {timestamp} is more than {max_skew}s ahead of {now}")]
+    TimestampInFuture {
+        timestamp: i64,
+        now: i64,
+        max_skew: i64,
+    },
+
+    #[error("message {message_id} was already received")]
+    Replayed { message_id: String },
+
+    #[error("sequence number {sequence} was already received")]
+    SequenceReplayed { sequence: u64 },
+
+    #[error("sequence number {sequence} is outside the window below {highest}")]
+    SequenceTooOld { sequence: u64, highest: u64 },
+
+    #[error("timestamp {timestamp} is not after {evicted}, the newest id no longer tracked")]
+    BeyondHistory { timestamp: i64, evicted: i64 },
+}
+
+impl MessageRejection {
+    /// True if the message itself was fine but had already been received
+    pub fn is_replay(&self) -> bool {
+        matches!(
+            self,
+            MessageRejection::Replayed { .. } | MessageRejection::SequenceReplayed { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RouterConfig {
+    /// How far in the past a message timestamp may be, in seconds
+    pub max_past_skew: i64,
+    /// How far in the future a message timestamp may be, in seconds
+    pub max_future_skew: i64,
+    /// Message ids remembered per sender device
+    pub max_tracked_ids: usize,
+    /// Sender devices tracked before idle ones are pruned
+    pub max_tracked_senders: usize,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            max_past_skew: 5 * 60,
+            max_future_skew: 60,
+            max_tracked_ids: 4096,
+            max_tracked_senders: 100_000,
+        }
+    }
+}
+
+/// Replay state for one sender device
+#[derive(Debug, Default)]
+struct SenderWindow {
+    /// Recent message ids with their timestamps, oldest first
+    ids: VecDeque<(String, i64)>,
+    seen: HashSet<String>,
+    highest_sequence: u64,
+    /// Bit `n` set means `highest_sequence - n` was received
+    bitmap: u128,
+    last_timestamp: i64,
+    /// Newest timestamp among ids dropped to stay within `max_tracked_ids`.
+    /// Their replays would go unnoticed, so nothing this old is accepted.
+    evicted: Option<i64>,
+}
+
+impl SenderWindow {
+    fn check(&self, message: &Message) -> Result<(), MessageRejection> {
+        if self.seen.contains(&message.id) {
+            return Err(MessageRejection::Replayed {
+                message_id: message.id.clone(),
+            });
+        }
+
+        if let Some(evicted) = self.evicted.filter(|evicted| message.timestamp <= *evicted) {
+            return Err(MessageRejection::BeyondHistory {
+                timestamp: message.timestamp,
+                evicted,
+            });
+        }
+
+        // Unsequenced messages are only checked by id
+        let sequence = message.sequence_number;
+        if sequence == 0 || sequence > self.highest_sequence {
+            return Ok(());
+        }
+
+        let offset = self.highest_sequence - sequence;
+        if offset >= REPLAY_WINDOW_SIZE {
+            return Err(MessageRejection::SequenceTooOld {
+                sequence,
+                highest: self.highest_sequence,
+            });
+        }
+
+        if self.bitmap & (1 << offset) != 0 {
+            return Err(MessageRejection::SequenceReplayed { sequence });
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, message: &Message, oldest_allowed: i64, max_ids: usize) {
+        // Ids older than the skew tolerance are rejected by timestamp, so need no tracking
+        while self
+            .ids
+            .front()
+            .is_some_and(|(_, timestamp)| *timestamp < oldest_allowed)
+        {
+            if let Some((id, _)) = self.ids.pop_front() {
+                self.seen.remove(&id);
+            }
+        }
+
+        while self.ids.len() >= max_ids {
+            let Some((id, timestamp)) = self.ids.pop_front() else {
+                break;
+            };
+            self.seen.remove(&id);
+            self.evicted = Some(self.evicted.map_or(timestamp, |e| e.max(timestamp)));
+        }
+
+        self.seen.insert(message.id.clone());
+        self.ids.push_back((message.id.clone(), message.timestamp));
+        self.last_timestamp = self.last_timestamp.max(message.timestamp);
+
+        let sequence = message.sequence_number;
+        if sequence == 0 {
+            return;
+        }
+
+        if sequence > self.highest_sequence {
+            let shift = sequence - self.highest_sequence;
+            self.bitmap = if shift >= REPLAY_WINDOW_SIZE {
+                0
+            } else {
+                self.bitmap << shift
+            };
+            self.bitmap |= 1;
+            self.highest_sequence = sequence;
+        } else {
+            self.bitmap |= 1 << (self.highest_sequence - sequence);
+        }
+    }
+}
+
+/// Per-sender replay windows
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    senders: HashMap<String, SenderWindow>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject `message` if `sender_device` already sent it, otherwise remember it
+    pub fn check_and_record(
+        &mut self,
+        sender_device: &str,
+        message: &Message,
+        now: i64,
+        config: &RouterConfig,
+    ) -> Result<(), MessageRejection> {
+        let oldest_allowed = now - config.max_past_skew;
+
+        if self.senders.len() >= config.max_tracked_senders {
+            self.senders
+                .retain(|_, window| window.last_timestamp >= oldest_allowed);
+        }
+
+        let window = self.senders.entry(sender_device.to_string()).or_default();
+        window.check(message)?;
+        window.record(message, oldest_allowed, config.max_tracked_ids.max(1));
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Group membership roster
+//!
+//! Tracks who is in an MLS group as derived from the ratchet tree leaves
+
+use serde::{Deserialize, Serialize};
+
+/// Basic credential bound to a member's leaf node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credential {
+    pub user_id: String,
+    pub device_id: String,
+    pub signature_key: Vec<u8>,
+}
+
+impl Credential {
+    /// MLS identity of the credential; one leaf per device
+    pub fn identity(&self) -> Vec<u8> {
+        self.device_id.as_bytes().to_vec()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum GroupRole {
+    Member,
+    Admin,
+}
+
+/// Minimum role needed for each administrative action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupPermissions {
+    pub add_members: GroupRole,
+    pub remove_members: GroupRole,
+    pub rename: GroupRole,
+}
+
+impl Default for GroupPermissions {
+    fn default() -> Self {
+        Self {
+            add_members: GroupRole::Admin,
+            remove_members: GroupRole::Admin,
+            rename: GroupRole::Admin,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub leaf_index: u32,
+    pub credential: Credential,
+    pub join_epoch: u64,
+}
+
+impl Member {
+    pub fn identity(&self) -> Vec<u8> {
+        self.credential.identity()
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.credential.user_id
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.credential.device_id
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Roster {
+    members: Vec<Member>,
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn get(&self, identity: &[u8]) -> Option<&Member> {
+        self.members.iter().find(|member| member.identity() == identity)
+    }
+
+    pub fn contains(&self, identity: &[u8]) -> bool {
+        self.get(identity).is_some()
+    }
+
+    /// Place a new member in the leftmost free leaf and return its index
+    pub fn add(&mut self, credential: Credential, join_epoch: u64) -> u32 {
+        let mut leaf_index = 0;
+        while self.members.iter().any(|member| member.leaf_index == leaf_index) {
+            leaf_index += 1;
+        }
+
+        self.members.push(Member {
+            leaf_index,
+            credential,
+            join_epoch,
+        });
+        self.members.sort_by_key(|member| member.leaf_index);
+        leaf_index
+    }
+
+    pub fn remove(&mut self, identity: &[u8]) -> Option<Member> {
+        let position = self.members.iter().position(|member| member.identity() == identity)?;
+        Some(self.members.remove(position))
+    }
+
+    pub fn devices_for_user(&self, user_id: &str) -> Vec<&Member> {
+        self.members
+            .iter()
+            .filter(|member| member.user_id() == user_id)
+            .collect()
+    }
+
+    /// Distinct user ids in the group, sorted
+    pub fn user_ids(&self) -> Vec<String> {
+        let mut user_ids: Vec<String> = self
+            .members
+            .iter()
+            .map(|member| member.user_id().to_string())
+            .collect();
+        user_ids.sort();
+        user_ids.dedup();
+        user_ids
+    }
+}
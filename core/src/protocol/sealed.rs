@@ -0,0 +1,393 @@
+//! Sealed-sender envelopes
+//!
+//! The sender's identity travels inside an envelope sealed to the recipient
+//! device's X25519 key, vouched for by a short-lived certificate the server
+//! signs. The server routes the envelope by recipient alone and never learns
+//! who sent it.
+
+use crate::crypto::hpke::{KeyPair, PublicKey};
+use crate::protocol::transport::{Message, MessageType};
+use crate::utils::{Error, Result};
+use chacha20poly1305::{aead::Aead, aead::Payload, ChaCha20Poly1305, KeyInit};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+/// Envelope format version produced by [`SealedEnvelope::seal`]
+pub const SEALED_ENVELOPE_VERSION: u8 = 2;
+
+/// How long a sender certificate is valid for, in seconds
+pub const SENDER_CERTIFICATE_LIFETIME: i64 = 24 * 60 * 60;
+
+/// Placeholder put in the `sender_id` of sealed frames
+pub const UNIDENTIFIED_SENDER: &str = "unidentified";
+
+/// Length of a recipient delivery token, in bytes
+pub const DELIVERY_TOKEN_LENGTH: usize = 16;
+
+const CERTIFICATE_LABEL: &[u8] = b"xipr sender certificate v1";
+const ENVELOPE_LABEL: &[u8] = b"xipr sealed envelope v2";
+const DELIVERY_TOKEN_LABEL: &[u8] = b"xipr delivery token v1";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderCertificate {
+    pub user_id: String,
+    pub device_id: String,
+    /// Sender's identity key, for matching against its MLS credential
+    pub identity_key: Vec<u8>,
+    pub expires_at: i64,
+    /// Server signing key that produced `signature`
+    pub key_id: u32,
+    pub signature: Vec<u8>,
+}
+
+impl SenderCertificate {
+    /// Check the signature against a trusted server key and the expiry against `now`
+    pub fn verify(&self, trust: &CertificateTrust, now: i64) -> Result<()> {
+        let key = trust.keys.get(&self.key_id).ok_or_else(|| {
+            Error::Auth(format!(
+                "Sender certificate signed by unknown key {}",
+                self.key_id
+            ))
+        })?;
+
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|_| Error::Auth("Malformed sender certificate signature".to_string()))?;
+        key.verify_strict(&self.signed_bytes(), &signature)
+            .map_err(|_| Error::Auth("Invalid sender certificate signature".to_string()))?;
+
+        if now > self.expires_at {
+            return Err(Error::Auth("Sender certificate expired".to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = CERTIFICATE_LABEL.to_vec();
+        for field in [
+            self.user_id.as_bytes(),
+            self.device_id.as_bytes(),
+            &self.identity_key,
+        ] {
+            bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes.extend_from_slice(&self.expires_at.to_be_bytes());
+        bytes.extend_from_slice(&self.key_id.to_be_bytes());
+        bytes
+    }
+}
+
+/// Server-side key that signs sender certificates
+pub struct CertificateSigner {
+    key_id: u32,
+    signing_key: SigningKey,
+}
+
+impl CertificateSigner {
+    pub fn generate(key_id: u32) -> Self {
+        Self::from_bytes(key_id, &rand::random())
+    }
+
+    pub fn from_bytes(key_id: u32, secret: &[u8; 32]) -> Self {
+        Self {
+            key_id,
+            signing_key: SigningKey::from_bytes(secret),
+        }
+    }
+
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    pub fn issue(
+        &self,
+        user_id: String,
+        device_id: String,
+        identity_key: Vec<u8>,
+        now: i64,
+    ) -> SenderCertificate {
+        let mut certificate = SenderCertificate {
+            user_id,
+            device_id,
+            identity_key,
+            expires_at: now + SENDER_CERTIFICATE_LIFETIME,
+            key_id: self.key_id,
+            signature: Vec::new(),
+        };
+
+        certificate.signature = self
+            .signing_key
+            .sign(&certificate.signed_bytes())
+            .to_bytes()
+            .to_vec();
+        certificate
+    }
+}
+
+/// Server keys a client accepts sender certificates from, by key id
+#[derive(Debug, Clone, Default)]
+pub struct CertificateTrust {
+    keys: HashMap<u32, VerifyingKey>,
+}
+
+impl CertificateTrust {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_key(&mut self, key_id: u32, public_key: &[u8]) -> Result<()> {
+        let bytes: [u8; 32] = public_key
+            .try_into()
+            .map_err(|_| Error::Crypto("Server public key must be 32 bytes".to_string()))?;
+        let key = VerifyingKey::from_bytes(&bytes)
+            .map_err(|_| Error::Crypto("Invalid server public key".to_string()))?;
+
+        self.keys.insert(key_id, key);
+        Ok(())
+    }
+
+    pub fn remove_key(&mut self, key_id: u32) -> bool {
+        self.keys.remove(&key_id).is_some()
+    }
+}
+
+/// What the recipient recovers from an envelope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedContent {
+    pub certificate: SenderCertificate,
+    pub message_type: MessageType,
+    pub content: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedEnvelope {
+    pub version: u8,
+    pub recipient_id: String,
+    /// When the envelope was sealed; authenticated, and checked by the server for replays
+    pub sent_at: i64,
+    /// Sender's one-time X25519 key, agreed with the recipient's key to seal the envelope
+    pub ephemeral_public_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl SealedEnvelope {
+    /// Encrypt the sender certificate and content to the recipient's key
+    pub fn seal(
+        certificate: SenderCertificate,
+        message_type: MessageType,
+        content: Vec<u8>,
+        recipient_id: String,
+        recipient_public_key: &PublicKey,
+    ) -> Result<Self> {
+        let recipient_public_key: [u8; 32] = recipient_public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::Crypto("Recipient public key must be 32 bytes".to_string()))?;
+
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let ephemeral = X25519PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&X25519PublicKey::from(recipient_public_key));
+
+        let cipher = envelope_cipher(shared.as_bytes(), &recipient_id)?;
+        let sent_at = chrono::Utc::now().timestamp();
+        let nonce: [u8; 12] = rand::random();
+        let mut plaintext = serde_json::to_vec(&SealedContent {
+            certificate,
+            message_type,
+            content,
+        })?;
+        let ciphertext = cipher.encrypt(
+            nonce.as_slice().into(),
+            Payload {
+                msg: &plaintext,
+                aad: &envelope_aad(
+                    SEALED_ENVELOPE_VERSION,
+                    &recipient_id,
+                    sent_at,
+                    ephemeral.as_bytes(),
+                ),
+            },
+        );
+        plaintext.zeroize();
+
+        Ok(Self {
+            version: SEALED_ENVELOPE_VERSION,
+            recipient_id,
+            sent_at,
+            ephemeral_public_key: ephemeral.to_bytes().to_vec(),
+            nonce: nonce.to_vec(),
+            ciphertext: ciphertext
+                .map_err(|_| Error::Crypto("Cannot seal envelope".to_string()))?,
+        })
+    }
+
+    /// Decrypt and verify the sender certificate
+    ///
+    /// The caller still has to check `certificate.identity_key` against the
+    /// identity it expects for that sender, e.g. the sender's MLS credential.
+    pub fn unseal(
+        &self,
+        key_pair: &KeyPair,
+        trust: &CertificateTrust,
+        now: i64,
+    ) -> Result<SealedContent> {
+        if self.version != SEALED_ENVELOPE_VERSION {
+            return Err(Error::Protocol(format!(
+                "Unsupported sealed envelope version {}",
+                self.version
+            )));
+        }
+
+        let malformed = || Error::Crypto("Malformed sealed envelope".to_string());
+        let ephemeral: [u8; 32] = self
+            .ephemeral_public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| malformed())?;
+        if self.nonce.len() != 12 {
+            return Err(malformed());
+        }
+        let mut private_key: [u8; 32] = key_pair
+            .private_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::Crypto("Private key must be 32 bytes".to_string()))?;
+        let secret = StaticSecret::from(private_key);
+        private_key.zeroize();
+
+        let shared = secret.diffie_hellman(&X25519PublicKey::from(ephemeral));
+        let cipher = envelope_cipher(shared.as_bytes(), &self.recipient_id)?;
+
+        // Binding the recipient into the AAD stops an envelope being replayed to someone else
+        let mut plaintext = cipher
+            .decrypt(
+                self.nonce.as_slice().into(),
+                Payload {
+                    msg: &self.ciphertext,
+                    aad: &envelope_aad(self.version, &self.recipient_id, self.sent_at, &ephemeral),
+                },
+            )
+            .map_err(|_| Error::Crypto("Cannot open sealed envelope".to_string()))?;
+        let content = serde_json::from_slice::<SealedContent>(&plaintext);
+        plaintext.zeroize();
+
+        let content = content?;
+        content.certificate.verify(trust, now)?;
+        Ok(content)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Identifies the envelope for replay checks, however it is re-encoded
+    pub fn replay_id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(ENVELOPE_LABEL);
+        hasher.update(&self.ciphertext);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Wrap the envelope in a `Sealed` message that names only the recipient
+    ///
+    /// The message id and timestamp come from the envelope, so the same
+    /// envelope always makes the same message.
+    pub fn to_message(&self) -> Result<Message> {
+        let mut message = Message::new(
+            UNIDENTIFIED_SENDER.to_string(),
+            self.recipient_id.clone(),
+            MessageType::Sealed,
+            self.to_bytes()?,
+        );
+        message.id = self.replay_id();
+        message.timestamp = self.sent_at;
+        Ok(message)
+    }
+
+    pub fn from_message(message: &Message) -> Result<Self> {
+        if message.message_type != MessageType::Sealed {
+            return Err(Error::Protocol("Not a sealed message".to_string()));
+        }
+
+        Self::from_bytes(&message.content)
+    }
+}
+
+fn envelope_cipher(shared: &[u8; 32], recipient_id: &str) -> Result<ChaCha20Poly1305> {
+    // A low-order ephemeral or recipient key gives an all-zero agreement
+    if shared.iter().all(|byte| *byte == 0) {
+        return Err(Error::Crypto("Degenerate sealed envelope key".to_string()));
+    }
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(recipient_id.as_bytes()), shared)
+        .expand(ENVELOPE_LABEL, &mut key)
+        .map_err(|_| Error::Crypto("Sealed envelope key derivation failed".to_string()))?;
+    let cipher = ChaCha20Poly1305::new(&key.into());
+    key.zeroize();
+    Ok(cipher)
+}
+
+fn envelope_aad(
+    version: u8,
+    recipient_id: &str,
+    sent_at: i64,
+    ephemeral_public_key: &[u8; 32],
+) -> Vec<u8> {
+    let mut aad = ENVELOPE_LABEL.to_vec();
+    aad.push(version);
+    aad.extend_from_slice(&(recipient_id.len() as u32).to_be_bytes());
+    aad.extend_from_slice(recipient_id.as_bytes());
+    aad.extend_from_slice(&sent_at.to_be_bytes());
+    aad.extend_from_slice(ephemeral_public_key);
+    aad
+}
+
+/// Secret a recipient hands to its contacts so they can send sealed messages
+///
+/// The server only stores the digest, and accepts unauthenticated deliveries
+/// to a device only with a token matching it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryToken([u8; DELIVERY_TOKEN_LENGTH]);
+
+impl DeliveryToken {
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let token = bytes.try_into().map_err(|_| {
+            Error::Auth(format!(
+                "Delivery token must be {} bytes",
+                DELIVERY_TOKEN_LENGTH
+            ))
+        })?;
+        Ok(Self(token))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(DELIVERY_TOKEN_LABEL);
+        hasher.update(self.0);
+        hasher.finalize().into()
+    }
+}
@@ -0,0 +1,263 @@
+//! Login throttling policy and proof-of-work challenges
+//!
+//! Failed logins are counted per account and per source address. Past a
+//! first threshold a client must solve a proof-of-work challenge with each
+//! attempt; past a second one it must also wait, for a backoff that doubles
+//! with every further failure. Counts are forgotten a window after the last
+//! failure. Where the counts live is up to the server; this module only
+//! decides, against a [`Clock`] that tests can drive by hand.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+/// Length of a proof-of-work challenge's random seed, in bytes
+pub const POW_SEED_LENGTH: usize = 16;
+
+const POW_LABEL: &[u8] = b"xipr login proof of work v1";
+
+/// Source of the current time, in Unix seconds
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        chrono::Utc::now().timestamp()
+    }
+}
+
+/// A clock that only moves when told to; clones share the same time
+#[derive(Debug, Clone, Default)]
+pub struct FakeClock {
+    now: Arc<AtomicI64>,
+}
+
+impl FakeClock {
+    pub fn new(now: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, secs: i64) {
+        self.now.fetch_add(secs, Ordering::SeqCst);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// Recent failed logins for one account or source address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempts {
+    pub failures: u32,
+    pub last_failure: i64,
+}
+
+impl Attempts {
+    /// Count one more failure at `now`, starting afresh if the window has passed
+    pub fn record_failure(previous: Option<Attempts>, now: i64, window: i64) -> Attempts {
+        let failures = match previous {
+            Some(attempts) if !attempts.expired(now, window) => attempts.failures,
+            _ => 0,
+        };
+        Attempts {
+            failures: failures.saturating_add(1),
+            last_failure: now,
+        }
+    }
+
+    pub fn expired(&self, now: i64, window: i64) -> bool {
+        now >= self.last_failure + window
+    }
+}
+
+/// Thresholds for one kind of key, accounts or source addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThrottleLimits {
+    /// Failures after which every attempt needs a solved challenge
+    pub challenge_after: u32,
+    /// Failures after which attempts must also wait out a backoff
+    pub backoff_after: u32,
+    /// Seconds after the last failure that the count is forgotten
+    pub window: i64,
+}
+
+impl ThrottleLimits {
+    /// Seconds until attempts are allowed again, or 0
+    pub fn retry_after(&self, attempts: &Attempts, policy: &ThrottlePolicy, now: i64) -> i64 {
+        if attempts.expired(now, self.window) || attempts.failures < self.backoff_after {
+            return 0;
+        }
+
+        let doublings = (attempts.failures - self.backoff_after).min(30);
+        let backoff = policy
+            .base_backoff
+            .saturating_mul(1 << doublings)
+            .min(policy.max_backoff);
+        (attempts.last_failure + backoff - now).max(0)
+    }
+
+    pub fn needs_challenge(&self, attempts: &Attempts, now: i64) -> bool {
+        !attempts.expired(now, self.window) && attempts.failures >= self.challenge_after
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThrottlePolicy {
+    pub account: ThrottleLimits,
+    /// Looser than `account`: many users can share an address
+    pub source: ThrottleLimits,
+    /// Wait after the first failure past `backoff_after`, in seconds
+    pub base_backoff: i64,
+    pub max_backoff: i64,
+    /// Leading zero bits a challenge's hash must have
+    pub pow_difficulty: u8,
+    /// How long a challenge can be answered, in seconds
+    pub challenge_lifetime: i64,
+    /// Challenges one source address is handed within a `challenge_lifetime`
+    /// before it has to wait for more
+    pub max_challenges: u32,
+}
+
+impl Default for ThrottlePolicy {
+    fn default() -> Self {
+        Self {
+            account: ThrottleLimits {
+                challenge_after: 3,
+                backoff_after: 5,
+                window: 60 * 60,
+            },
+            source: ThrottleLimits {
+                challenge_after: 20,
+                backoff_after: 100,
+                window: 60 * 60,
+            },
+            base_backoff: 1,
+            max_backoff: 15 * 60,
+            pow_difficulty: 20,
+            challenge_lifetime: 5 * 60,
+            max_challenges: 10,
+        }
+    }
+}
+
+/// What to do with a login attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleDecision {
+    Allow,
+    /// Allowed only with a solved proof-of-work challenge
+    Challenge,
+    /// Refused until `retry_after` seconds have passed
+    Throttled {
+        retry_after: i64,
+    },
+}
+
+impl ThrottlePolicy {
+    /// Decide on an attempt given the failures recorded for its account and source
+    ///
+    /// Attempts whose account is not known yet, such as passkey logins, pass
+    /// `None` for it.
+    pub fn evaluate(
+        &self,
+        account: Option<&Attempts>,
+        source: Option<&Attempts>,
+        now: i64,
+    ) -> ThrottleDecision {
+        let retry_after = account
+            .map(|attempts| self.account.retry_after(attempts, self, now))
+            .into_iter()
+            .chain(source.map(|attempts| self.source.retry_after(attempts, self, now)))
+            .max()
+            .unwrap_or(0);
+        if retry_after > 0 {
+            return ThrottleDecision::Throttled { retry_after };
+        }
+
+        let challenged = account
+            .is_some_and(|attempts| self.account.needs_challenge(attempts, now))
+            || source.is_some_and(|attempts| self.source.needs_challenge(attempts, now));
+        if challenged {
+            return ThrottleDecision::Challenge;
+        }
+        ThrottleDecision::Allow
+    }
+}
+
+/// A proof-of-work puzzle handed to a client that failed too often
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowChallenge {
+    pub seed: Vec<u8>,
+    pub difficulty: u8,
+    pub expires_at: i64,
+}
+
+/// A client's answer to a [`PowChallenge`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowSolution {
+    pub seed: Vec<u8>,
+    pub nonce: u64,
+}
+
+impl PowChallenge {
+    pub fn generate(difficulty: u8, expires_at: i64) -> Self {
+        Self {
+            seed: rand::random::<[u8; POW_SEED_LENGTH]>().to_vec(),
+            difficulty,
+            expires_at,
+        }
+    }
+
+    /// Find a nonce for the challenge, bound to the account it is for
+    ///
+    /// Takes about `2^difficulty` hashes.
+    pub fn solve(&self, account: &str) -> PowSolution {
+        let nonce = (0..=u64::MAX)
+            .find(|nonce| self.accepts(account, *nonce))
+            .expect("some nonce meets any difficulty up to 64 bits");
+        PowSolution {
+            seed: self.seed.clone(),
+            nonce,
+        }
+    }
+
+    /// Whether a solution answers this challenge for the account before it expires
+    pub fn verify(&self, account: &str, solution: &PowSolution, now: i64) -> bool {
+        now < self.expires_at && solution.seed == self.seed && self.accepts(account, solution.nonce)
+    }
+
+    fn accepts(&self, account: &str, nonce: u64) -> bool {
+        let mut hasher = Sha256::new();
+        hasher.update(POW_LABEL);
+        hasher.update(&self.seed);
+        hasher.update((account.len() as u64).to_be_bytes());
+        hasher.update(account.as_bytes());
+        hasher.update(nonce.to_be_bytes());
+        leading_zero_bits(&hasher.finalize()) >= u32::from(self.difficulty)
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut zeros = 0;
+    for byte in bytes {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
@@ -2,10 +2,17 @@
 //! 
 //! Provides message serialization, routing, and delivery
 
-use crate::utils::{Result};
+use crate::protocol::mls::DeliveredMessage;
+use crate::protocol::replay::{MessageRejection, ReplayGuard, RouterConfig};
+use crate::protocol::wire::WIRE_VERSION;
+use crate::utils::{Error, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
 use uuid::Uuid;
 
+/// Message ids remembered by a default `MessageDeduplicator`
+pub const DEFAULT_DEDUP_CAPACITY: usize = 4096;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: String,
@@ -17,21 +24,46 @@ pub struct Message {
     pub sequence_number: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MessageType {
     Text,
     Binary,
     Control,
     Heartbeat,
+    /// Sealed-sender envelope; the header sender is a placeholder
+    Sealed,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl MessageType {
+    /// Whether handling a frame twice has no further effect, so it may travel
+    /// as replayable 0-RTT data
+    pub fn is_idempotent(&self) -> bool {
+        matches!(self, MessageType::Control | MessageType::Heartbeat)
+    }
+}
+
+impl std::str::FromStr for MessageType {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(MessageType::Text),
+            "binary" => Ok(MessageType::Binary),
+            "control" => Ok(MessageType::Control),
+            "heartbeat" => Ok(MessageType::Heartbeat),
+            "sealed" => Ok(MessageType::Sealed),
+            other => Err(Error::Protocol(format!("Unknown message type: {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MessageFrame {
     pub header: MessageHeader,
     pub payload: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MessageHeader {
     pub version: u8,
     pub message_type: MessageType,
@@ -41,6 +73,9 @@ pub struct MessageHeader {
     pub timestamp: i64,
     pub sequence_number: u64,
     pub payload_length: u32,
+    /// Payload is compressed with the session's negotiated compression
+    #[serde(default)]
+    pub compressed: bool,
 }
 
 impl Message {
@@ -63,7 +98,7 @@ impl Message {
     
     pub fn to_frame(&self) -> Result<MessageFrame> {
         let header = MessageHeader {
-            version: 1,
+            version: WIRE_VERSION,
             message_type: self.message_type.clone(),
             message_id: self.id.clone(),
             sender_id: self.sender_id.clone(),
@@ -71,49 +106,191 @@ impl Message {
             timestamp: self.timestamp,
             sequence_number: self.sequence_number,
             payload_length: self.content.len() as u32,
+            compressed: false,
         };
         
-        let payload = serde_json::to_vec(&self.content)?;
-        
         Ok(MessageFrame {
             header,
-            payload,
+            payload: self.content.clone(),
         })
     }
     
     pub fn from_frame(frame: &MessageFrame) -> Result<Self> {
-        let content: Vec<u8> = serde_json::from_slice(&frame.payload)?;
+        if frame.header.compressed {
+            return Err(Error::Protocol("Frame must be decompressed first".to_string()));
+        }
+        
+        if frame.header.payload_length as usize != frame.payload.len() {
+            return Err(Error::Protocol("Header payload length does not match payload".to_string()));
+        }
         
         Ok(Self {
             id: frame.header.message_id.clone(),
             sender_id: frame.header.sender_id.clone(),
             recipient_id: frame.header.recipient_id.clone(),
             message_type: frame.header.message_type.clone(),
-            content,
+            content: frame.payload.clone(),
             timestamp: frame.header.timestamp,
             sequence_number: frame.header.sequence_number,
         })
     }
 }
 
-pub struct MessageRouter;
+/// Transport-level control carried in `MessageType::Control` frames
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlMessage {
+    /// Everything up to and including `sequence` has been received
+    Ack { sequence: u64 },
+    /// Binds a link to a session, for transports without an HTTP upgrade
+    Authenticate {
+        token: String,
+        last_ack: u64,
+        /// `RequestProof` over `ProofTarget::link`, for device-bound sessions
+        #[serde(default)]
+        proof: Option<String>,
+    },
+    /// The server refused the message with this id; the link stays open
+    Rejected {
+        message_id: String,
+        rejection: MessageRejection,
+    },
+    /// A message of an MLS group the device belongs to, in group order
+    Group(DeliveredMessage),
+}
+
+impl ControlMessage {
+    pub fn to_message(&self, sender_id: String, recipient_id: String) -> Result<Message> {
+        Ok(Message::new(
+            sender_id,
+            recipient_id,
+            MessageType::Control,
+            serde_json::to_vec(self)?,
+        ))
+    }
+    
+    pub fn from_message(message: &Message) -> Result<Self> {
+        if message.message_type != MessageType::Control {
+            return Err(Error::Protocol("Not a control message".to_string()));
+        }
+        
+        Ok(serde_json::from_slice(&message.content)?)
+    }
+}
+
+/// Drops redelivered messages on the client
+///
+/// Delivery is at-least-once, so a message can arrive again after a lost
+/// acknowledgement or a reconnect. Remembers the most recent message ids.
+#[derive(Debug, Clone)]
+pub struct MessageDeduplicator {
+    capacity: usize,
+    seen: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl Default for MessageDeduplicator {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_CAPACITY)
+    }
+}
+
+impl MessageDeduplicator {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+    
+    /// Returns true the first time a message id is seen, false for duplicates
+    pub fn first_seen(&mut self, message_id: &str) -> bool {
+        if self.seen.contains(message_id) {
+            return false;
+        }
+        
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        
+        self.seen.insert(message_id.to_string());
+        self.order.push_back(message_id.to_string());
+        true
+    }
+}
+
+/// Validates and routes messages, rejecting replays
+#[derive(Debug, Default)]
+pub struct MessageRouter {
+    config: RouterConfig,
+    replay: ReplayGuard,
+}
 
 impl MessageRouter {
+    pub fn new(config: RouterConfig) -> Self {
+        Self {
+            config,
+            replay: ReplayGuard::new(),
+        }
+    }
+    
+    /// User a message is routed to
+    ///
+    /// Delivery fans out from this user to their active devices; see
+    /// `route_to_devices` and `fan_out`.
     pub fn route_message(message: &Message) -> Result<String> {
-        // Simple routing logic - in practice would be more sophisticated
+        if message.recipient_id.is_empty() {
+            return Err(Error::Protocol("Message has no recipient".to_string()));
+        }
         Ok(message.recipient_id.clone())
     }
     
-    pub fn validate_message(message: &Message) -> Result<bool> {
-        // Validate message integrity and format
-        if message.id.is_empty() || message.sender_id.is_empty() || message.recipient_id.is_empty() {
-            return Ok(false);
+    /// Check required fields and that the timestamp is within the skew tolerances of `now`
+    pub fn validate_message(
+        &self,
+        message: &Message,
+        now: i64,
+    ) -> std::result::Result<(), MessageRejection> {
+        for (field, value) in [
+            ("id", &message.id),
+            ("sender_id", &message.sender_id),
+            ("recipient_id", &message.recipient_id),
+        ] {
+            if value.is_empty() {
+                return Err(MessageRejection::MissingField(field.to_string()));
+            }
+        }
+        
+        if message.timestamp < now - self.config.max_past_skew {
+            return Err(MessageRejection::TimestampTooOld {
+                timestamp: message.timestamp,
+                now,
+                max_skew: self.config.max_past_skew,
+            });
         }
         
-        if message.timestamp <= 0 {
-            return Ok(false);
+        if message.timestamp > now + self.config.max_future_skew {
+            return Err(MessageRejection::TimestampInFuture {
+                timestamp: message.timestamp,
+                now,
+                max_skew: self.config.max_future_skew,
+            });
         }
         
-        Ok(true)
+        Ok(())
+    }
+    
+    /// Validate a message from `sender_device` and reject it if it was seen before
+    pub fn accept_message(
+        &mut self,
+        sender_device: &str,
+        message: &Message,
+        now: i64,
+    ) -> std::result::Result<(), MessageRejection> {
+        self.validate_message(message, now)?;
+        self.replay
+            .check_and_record(sender_device, message, now, &self.config)
     }
 }
@@ -0,0 +1,792 @@
+//! WebAuthn passkeys
+//!
+//! Relying party checks for registration and assertion ceremonies (W3C
+//! WebAuthn Level 2), for ES256 and EdDSA credentials with "none" or
+//! "packed" attestation. Packed attestation certificates are checked to sign
+//! the registration but are not chained to any trust anchor.
+//!
+//! [`SoftwareAuthenticator`] plays the authenticator side with keys in
+//! memory, so ceremonies can be run end to end without hardware.
+
+use crate::utils::{Error, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use ed25519_dalek::{Signer, Verifier};
+use p256::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Length of a ceremony challenge, in bytes
+pub const WEBAUTHN_CHALLENGE_LENGTH: usize = 32;
+
+/// COSE algorithm identifier for ECDSA with P-256 and SHA-256
+pub const COSE_ALG_ES256: i64 = -7;
+
+/// COSE algorithm identifier for Ed25519
+pub const COSE_ALG_EDDSA: i64 = -8;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+const FLAG_EXTENSIONS: u8 = 0x80;
+
+const CLIENT_DATA_CREATE: &str = "webauthn.create";
+const CLIENT_DATA_GET: &str = "webauthn.get";
+
+/// Public key of a credential, as registered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "alg", rename_all = "snake_case")]
+pub enum CredentialPublicKey {
+    /// Uncompressed P-256 point
+    Es256 {
+        x: [u8; 32],
+        y: [u8; 32],
+    },
+    EdDsa {
+        key: [u8; 32],
+    },
+}
+
+impl CredentialPublicKey {
+    /// COSE algorithm identifier
+    pub fn algorithm(&self) -> i64 {
+        match self {
+            Self::Es256 { .. } => COSE_ALG_ES256,
+            Self::EdDsa { .. } => COSE_ALG_EDDSA,
+        }
+    }
+
+    /// Parse a COSE_Key (RFC 9053) of a supported algorithm
+    pub fn from_cose(key: &Value) -> Result<Self> {
+        let invalid = |reason: &str| Error::Auth(format!("Unsupported credential key: {}", reason));
+        let map = key.as_map().ok_or_else(|| invalid("not a map"))?;
+        let field = |label: i64| {
+            map.iter()
+                .find(|(key, _)| key.as_integer() == Some(label.into()))
+                .map(|(_, value)| value)
+        };
+        let integer = |label: i64| {
+            field(label)
+                .and_then(Value::as_integer)
+                .and_then(|value| i64::try_from(value).ok())
+        };
+        let coordinate = |label: i64| -> Option<[u8; 32]> {
+            field(label)?.as_bytes()?.as_slice().try_into().ok()
+        };
+
+        match (integer(1), integer(3), integer(-1)) {
+            // EC2, ES256, P-256
+            (Some(2), Some(COSE_ALG_ES256), Some(1)) => {
+                let x = coordinate(-2).ok_or_else(|| invalid("bad x coordinate"))?;
+                let y = coordinate(-3).ok_or_else(|| invalid("bad y coordinate"))?;
+                let key = Self::Es256 { x, y };
+                key.es256_key()?;
+                Ok(key)
+            }
+            // OKP, EdDSA, Ed25519
+            (Some(1), Some(COSE_ALG_EDDSA), Some(6)) => {
+                let key = coordinate(-2).ok_or_else(|| invalid("bad public key"))?;
+                ed25519_dalek::VerifyingKey::from_bytes(&key)
+                    .map_err(|_| invalid("not an Ed25519 point"))?;
+                Ok(Self::EdDsa { key })
+            }
+            _ => Err(invalid("only ES256 on P-256 and EdDSA on Ed25519")),
+        }
+    }
+
+    pub fn to_cose(&self) -> Value {
+        let entry = |label: i64, value: Value| (Value::from(label), value);
+        match self {
+            Self::Es256 { x, y } => Value::Map(vec![
+                entry(1, 2.into()),
+                entry(3, COSE_ALG_ES256.into()),
+                entry(-1, 1.into()),
+                entry(-2, Value::Bytes(x.to_vec())),
+                entry(-3, Value::Bytes(y.to_vec())),
+            ]),
+            Self::EdDsa { key } => Value::Map(vec![
+                entry(1, 1.into()),
+                entry(3, COSE_ALG_EDDSA.into()),
+                entry(-1, 6.into()),
+                entry(-2, Value::Bytes(key.to_vec())),
+            ]),
+        }
+    }
+
+    /// Check a signature: ASN.1 DER for ES256, 64 raw bytes for EdDSA
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let invalid = || Error::Auth("Invalid credential signature".to_string());
+        match self {
+            Self::Es256 { .. } => {
+                let signature =
+                    p256::ecdsa::DerSignature::try_from(signature).map_err(|_| invalid())?;
+                self.es256_key()?
+                    .verify(message, &signature)
+                    .map_err(|_| invalid())
+            }
+            Self::EdDsa { key } => {
+                let key = ed25519_dalek::VerifyingKey::from_bytes(key).map_err(|_| invalid())?;
+                let signature =
+                    ed25519_dalek::Signature::from_slice(signature).map_err(|_| invalid())?;
+                key.verify_strict(message, &signature)
+                    .map_err(|_| invalid())
+            }
+        }
+    }
+
+    fn es256_key(&self) -> Result<p256::ecdsa::VerifyingKey> {
+        let Self::Es256 { x, y } = self else {
+            return Err(Error::Auth("Not an ES256 key".to_string()));
+        };
+        let point = p256::EncodedPoint::from_affine_coordinates(x.into(), y.into(), false);
+        p256::ecdsa::VerifyingKey::from_encoded_point(&point)
+            .map_err(|_| Error::Auth("Unsupported credential key: not a P-256 point".to_string()))
+    }
+}
+
+/// Credential created during registration, as carried in authenticator data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestedCredential {
+    pub aaguid: [u8; 16],
+    pub credential_id: Vec<u8>,
+    pub public_key: CredentialPublicKey,
+}
+
+/// What the authenticator signs over, besides the client data hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: [u8; 32],
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let invalid = || Error::Auth("Malformed authenticator data".to_string());
+        if data.len() < 37 {
+            return Err(invalid());
+        }
+
+        let rp_id_hash = data[..32].try_into().unwrap();
+        let flags = data[32];
+        let sign_count = u32::from_be_bytes(data[33..37].try_into().unwrap());
+        let mut rest = &data[37..];
+
+        let mut attested_credential = None;
+        if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            if rest.len() < 18 {
+                return Err(invalid());
+            }
+            let aaguid = rest[..16].try_into().unwrap();
+            let length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+            rest = &rest[18..];
+            if rest.len() < length {
+                return Err(invalid());
+            }
+            let credential_id = rest[..length].to_vec();
+            rest = &rest[length..];
+
+            let key: Value = ciborium::from_reader(&mut rest).map_err(|_| invalid())?;
+            attested_credential = Some(AttestedCredential {
+                aaguid,
+                credential_id,
+                public_key: CredentialPublicKey::from_cose(&key)?,
+            });
+        }
+
+        // Extensions are not used, but must be well-formed if present
+        if flags & FLAG_EXTENSIONS != 0 {
+            let _: Value = ciborium::from_reader(&mut rest).map_err(|_| invalid())?;
+        }
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            rp_id_hash,
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.rp_id_hash.to_vec();
+        data.push(self.flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        if let Some(credential) = &self.attested_credential {
+            data.extend_from_slice(&credential.aaguid);
+            data.extend_from_slice(&(credential.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&credential.credential_id);
+            ciborium::into_writer(&credential.public_key.to_cose(), &mut data)
+                .expect("writing to a Vec cannot fail");
+        }
+        data
+    }
+
+    pub fn user_present(&self) -> bool {
+        self.flags & FLAG_USER_PRESENT != 0
+    }
+
+    pub fn user_verified(&self) -> bool {
+        self.flags & FLAG_USER_VERIFIED != 0
+    }
+}
+
+/// `PublicKeyCredential` returned by `navigator.credentials.create()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationResponse {
+    pub client_data_json: Vec<u8>,
+    pub attestation_object: Vec<u8>,
+}
+
+/// `PublicKeyCredential` returned by `navigator.credentials.get()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssertionResponse {
+    pub credential_id: Vec<u8>,
+    pub client_data_json: Vec<u8>,
+    pub authenticator_data: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub user_handle: Option<Vec<u8>>,
+}
+
+/// How a registration was attested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationType {
+    /// "none": nothing is vouched for
+    None,
+    /// "packed" signed by the credential key itself
+    SelfAttestation,
+    /// "packed" signed by an attestation certificate
+    Basic,
+}
+
+/// A registration that passed every check
+#[derive(Debug, Clone)]
+pub struct VerifiedCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: CredentialPublicKey,
+    pub sign_count: u32,
+    pub aaguid: [u8; 16],
+    pub attestation: AttestationType,
+    /// DER attestation certificate, for [`AttestationType::Basic`]
+    pub attestation_certificate: Option<Vec<u8>>,
+    pub user_verified: bool,
+}
+
+#[derive(Deserialize)]
+struct CollectedClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool,
+}
+
+/// The server side of WebAuthn ceremonies
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    id: String,
+    origin: String,
+}
+
+impl RelyingParty {
+    /// `id` is the RP ID (a domain), `origin` the web origin ceremonies run on
+    pub fn new(id: impl Into<String>, origin: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            origin: origin.into(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    /// Check a new credential against the challenge issued for its registration
+    pub fn verify_registration(
+        &self,
+        response: &RegistrationResponse,
+        challenge: &[u8],
+        require_user_verification: bool,
+    ) -> Result<VerifiedCredential> {
+        self.check_client_data(&response.client_data_json, CLIENT_DATA_CREATE, challenge)?;
+
+        let invalid = |reason: &str| Error::Auth(format!("Invalid attestation: {}", reason));
+        let object: Value = ciborium::from_reader(response.attestation_object.as_slice())
+            .map_err(|_| invalid("not CBOR"))?;
+        let object = object.as_map().ok_or_else(|| invalid("not a map"))?;
+        let field = |name: &str| {
+            object
+                .iter()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value)
+        };
+        let format = field("fmt")
+            .and_then(Value::as_text)
+            .ok_or_else(|| invalid("missing fmt"))?;
+        let statement = field("attStmt")
+            .and_then(Value::as_map)
+            .ok_or_else(|| invalid("missing attStmt"))?;
+        let raw_data = field("authData")
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| invalid("missing authData"))?;
+
+        let data = AuthenticatorData::parse(raw_data)?;
+        self.check_authenticator_data(&data, require_user_verification)?;
+        let credential = data
+            .attested_credential
+            .clone()
+            .ok_or_else(|| invalid("no attested credential"))?;
+
+        let (attestation, attestation_certificate) = match format {
+            "none" if statement.is_empty() => (AttestationType::None, None),
+            "none" => return Err(invalid("statement for none attestation")),
+            "packed" => {
+                let mut signed = raw_data.clone();
+                signed.extend_from_slice(&Sha256::digest(&response.client_data_json));
+                verify_packed(statement, &signed, &credential.public_key)?
+            }
+            other => return Err(invalid(&format!("unsupported format {}", other))),
+        };
+
+        Ok(VerifiedCredential {
+            credential_id: credential.credential_id,
+            public_key: credential.public_key,
+            sign_count: data.sign_count,
+            aaguid: credential.aaguid,
+            attestation,
+            attestation_certificate,
+            user_verified: data.user_verified(),
+        })
+    }
+
+    /// Check an assertion by a registered credential, returning its new signature counter
+    ///
+    /// A counter that fails to increase means the credential was cloned, and
+    /// the assertion is refused. Authenticators that never count report 0.
+    pub fn verify_assertion(
+        &self,
+        response: &AssertionResponse,
+        challenge: &[u8],
+        public_key: &CredentialPublicKey,
+        stored_sign_count: u32,
+        require_user_verification: bool,
+    ) -> Result<u32> {
+        self.check_client_data(&response.client_data_json, CLIENT_DATA_GET, challenge)?;
+
+        let data = AuthenticatorData::parse(&response.authenticator_data)?;
+        self.check_authenticator_data(&data, require_user_verification)?;
+
+        let mut signed = response.authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&response.client_data_json));
+        public_key.verify(&signed, &response.signature)?;
+
+        if (data.sign_count != 0 || stored_sign_count != 0) && data.sign_count <= stored_sign_count
+        {
+            return Err(Error::Auth(
+                "Signature counter did not increase; the authenticator may be cloned".to_string(),
+            ));
+        }
+        Ok(data.sign_count)
+    }
+
+    fn check_client_data(
+        &self,
+        client_data_json: &[u8],
+        kind: &str,
+        challenge: &[u8],
+    ) -> Result<()> {
+        let client_data: CollectedClientData = serde_json::from_slice(client_data_json)
+            .map_err(|_| Error::Auth("Malformed client data".to_string()))?;
+
+        if client_data.kind != kind {
+            return Err(Error::Auth(format!("Expected a {} ceremony", kind)));
+        }
+        if URL_SAFE_NO_PAD
+            .decode(&client_data.challenge)
+            .ok()
+            .as_deref()
+            != Some(challenge)
+        {
+            return Err(Error::Auth("Challenge mismatch".to_string()));
+        }
+        if client_data.origin != self.origin || client_data.cross_origin {
+            return Err(Error::Auth(format!(
+                "Unexpected origin {}",
+                client_data.origin
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_authenticator_data(
+        &self,
+        data: &AuthenticatorData,
+        require_user_verification: bool,
+    ) -> Result<()> {
+        if data.rp_id_hash != rp_id_hash(&self.id) {
+            return Err(Error::Auth(
+                "Credential is for another relying party".to_string(),
+            ));
+        }
+        if !data.user_present() {
+            return Err(Error::Auth("User not present".to_string()));
+        }
+        if require_user_verification && !data.user_verified() {
+            return Err(Error::Auth("User not verified".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn verify_packed(
+    statement: &[(Value, Value)],
+    signed: &[u8],
+    credential_key: &CredentialPublicKey,
+) -> Result<(AttestationType, Option<Vec<u8>>)> {
+    let invalid = |reason: &str| Error::Auth(format!("Invalid packed attestation: {}", reason));
+    let field = |name: &str| {
+        statement
+            .iter()
+            .find(|(key, _)| key.as_text() == Some(name))
+            .map(|(_, value)| value)
+    };
+    let algorithm = field("alg")
+        .and_then(Value::as_integer)
+        .and_then(|alg| i64::try_from(alg).ok())
+        .ok_or_else(|| invalid("missing alg"))?;
+    let signature = field("sig")
+        .and_then(Value::as_bytes)
+        .ok_or_else(|| invalid("missing sig"))?;
+
+    let Some(chain) = field("x5c") else {
+        if algorithm != credential_key.algorithm() {
+            return Err(invalid("alg differs from the credential key"));
+        }
+        credential_key.verify(signed, signature)?;
+        return Ok((AttestationType::SelfAttestation, None));
+    };
+
+    let certificate = chain
+        .as_array()
+        .and_then(|chain| chain.first())
+        .and_then(Value::as_bytes)
+        .ok_or_else(|| invalid("empty x5c"))?;
+    if algorithm != COSE_ALG_ES256 {
+        return Err(invalid("attestation certificates must be ES256"));
+    }
+    let key = certificate_public_key(certificate)
+        .and_then(|spki| p256::ecdsa::VerifyingKey::from_public_key_der(spki).ok())
+        .ok_or_else(|| invalid("unreadable attestation certificate"))?;
+    let signature = p256::ecdsa::DerSignature::try_from(signature.as_slice())
+        .map_err(|_| invalid("bad sig"))?;
+    key.verify(signed, &signature)
+        .map_err(|_| invalid("signature does not verify"))?;
+
+    Ok((AttestationType::Basic, Some(certificate.clone())))
+}
+
+/// DER SubjectPublicKeyInfo of an X.509 certificate
+fn certificate_public_key(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_element(certificate, 0x30)?;
+    let (_, mut tbs, _) = der_element(certificate, 0x30)?;
+
+    // Optional explicit version, then serial, signature algorithm, issuer, validity, subject
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs, 0xa0)?.2;
+    }
+    for tag in [0x02, 0x30, 0x30, 0x30, 0x30] {
+        tbs = der_element(tbs, tag)?.2;
+    }
+    der_element(tbs, 0x30).map(|(whole, _, _)| whole)
+}
+
+/// Split off one DER element with the given tag: (whole element, contents, rest)
+fn der_element(input: &[u8], tag: u8) -> Option<(&[u8], &[u8], &[u8])> {
+    if *input.first()? != tag {
+        return None;
+    }
+
+    let first = *input.get(1)?;
+    let (length, header) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        if octets == 0 || octets > 4 {
+            return None;
+        }
+        let length = input
+            .get(2..2 + octets)?
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+        (length, 2 + octets)
+    };
+
+    let end = header.checked_add(length)?;
+    Some((input.get(..end)?, input.get(header..end)?, &input[end..]))
+}
+
+/// Fresh random challenge for a ceremony
+pub fn generate_challenge() -> [u8; WEBAUTHN_CHALLENGE_LENGTH] {
+    rand::random()
+}
+
+fn rp_id_hash(rp_id: &str) -> [u8; 32] {
+    Sha256::digest(rp_id.as_bytes()).into()
+}
+
+fn client_data_json(kind: &str, challenge: &[u8], origin: &str) -> Vec<u8> {
+    serde_json::json!({
+        "type": kind,
+        "challenge": URL_SAFE_NO_PAD.encode(challenge),
+        "origin": origin,
+        "crossOrigin": false,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Attestation a [`SoftwareAuthenticator`] produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttestationFormat {
+    None,
+    /// Signed by the attestation certificate if one is set, else by the credential key
+    Packed,
+}
+
+#[derive(Clone)]
+enum SoftwareKey {
+    Es256(p256::ecdsa::SigningKey),
+    EdDsa(ed25519_dalek::SigningKey),
+}
+
+impl SoftwareKey {
+    fn public_key(&self) -> CredentialPublicKey {
+        match self {
+            Self::Es256(key) => {
+                let point = key.verifying_key().to_encoded_point(false);
+                CredentialPublicKey::Es256 {
+                    x: (*point.x().unwrap()).into(),
+                    y: (*point.y().unwrap()).into(),
+                }
+            }
+            Self::EdDsa(key) => CredentialPublicKey::EdDsa {
+                key: key.verifying_key().to_bytes(),
+            },
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Es256(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(message);
+                signature.to_der().as_bytes().to_vec()
+            }
+            Self::EdDsa(key) => key.sign(message).to_bytes().to_vec(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SoftwareCredential {
+    rp_id: String,
+    user_handle: Vec<u8>,
+    key: SoftwareKey,
+    sign_count: u32,
+}
+
+/// Authenticator that keeps its credentials in memory
+///
+/// For tests and development only: it vouches for nothing, and cloning it
+/// clones its credentials, which relying parties notice from the counter.
+#[derive(Clone)]
+pub struct SoftwareAuthenticator {
+    aaguid: [u8; 16],
+    credentials: HashMap<Vec<u8>, SoftwareCredential>,
+    attestation: Option<(Vec<u8>, p256::ecdsa::SigningKey)>,
+    user_verification: bool,
+}
+
+impl Default for SoftwareAuthenticator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SoftwareAuthenticator {
+    pub fn new() -> Self {
+        Self {
+            aaguid: [0; 16],
+            credentials: HashMap::new(),
+            attestation: None,
+            user_verification: true,
+        }
+    }
+
+    /// Sign packed attestations with a DER certificate and its PKCS#8 P-256 key
+    pub fn with_attestation_certificate(
+        mut self,
+        certificate: Vec<u8>,
+        key: &[u8],
+    ) -> Result<Self> {
+        let key = p256::ecdsa::SigningKey::from_pkcs8_der(key)
+            .map_err(|_| Error::Crypto("Attestation key must be PKCS#8 P-256".to_string()))?;
+        self.attestation = Some((certificate, key));
+        Ok(self)
+    }
+
+    /// Report user presence but never user verification
+    pub fn without_user_verification(mut self) -> Self {
+        self.user_verification = false;
+        self
+    }
+
+    /// Create a credential, as `navigator.credentials.create()` would
+    pub fn make_credential(
+        &mut self,
+        rp: &RelyingParty,
+        challenge: &[u8],
+        user_handle: &[u8],
+        algorithm: i64,
+        format: AttestationFormat,
+    ) -> Result<RegistrationResponse> {
+        let key = match algorithm {
+            COSE_ALG_ES256 => {
+                SoftwareKey::Es256(p256::ecdsa::SigningKey::random(&mut rand::rngs::OsRng))
+            }
+            COSE_ALG_EDDSA => {
+                SoftwareKey::EdDsa(ed25519_dalek::SigningKey::from_bytes(&rand::random()))
+            }
+            other => {
+                return Err(Error::Crypto(format!("Unsupported algorithm {}", other)));
+            }
+        };
+        let credential_id = rand::random::<[u8; 16]>().to_vec();
+
+        let data = AuthenticatorData {
+            rp_id_hash: rp_id_hash(rp.id()),
+            flags: self.flags() | FLAG_ATTESTED_CREDENTIAL,
+            sign_count: 0,
+            attested_credential: Some(AttestedCredential {
+                aaguid: self.aaguid,
+                credential_id: credential_id.clone(),
+                public_key: key.public_key(),
+            }),
+        }
+        .to_bytes();
+        let client_data_json = client_data_json(CLIENT_DATA_CREATE, challenge, rp.origin());
+
+        let text = |text: &str| Value::Text(text.to_string());
+        let statement = match format {
+            AttestationFormat::None => Vec::new(),
+            AttestationFormat::Packed => {
+                let mut signed = data.clone();
+                signed.extend_from_slice(&Sha256::digest(&client_data_json));
+                match &self.attestation {
+                    Some((certificate, attestation_key)) => {
+                        let signature: p256::ecdsa::Signature = attestation_key.sign(&signed);
+                        vec![
+                            (text("alg"), COSE_ALG_ES256.into()),
+                            (
+                                text("sig"),
+                                Value::Bytes(signature.to_der().as_bytes().to_vec()),
+                            ),
+                            (
+                                text("x5c"),
+                                Value::Array(vec![Value::Bytes(certificate.clone())]),
+                            ),
+                        ]
+                    }
+                    None => vec![
+                        (text("alg"), algorithm.into()),
+                        (text("sig"), Value::Bytes(key.sign(&signed))),
+                    ],
+                }
+            }
+        };
+        let format = match format {
+            AttestationFormat::None => "none",
+            AttestationFormat::Packed => "packed",
+        };
+
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(
+            &Value::Map(vec![
+                (text("fmt"), text(format)),
+                (text("attStmt"), Value::Map(statement)),
+                (text("authData"), Value::Bytes(data)),
+            ]),
+            &mut attestation_object,
+        )
+        .expect("writing to a Vec cannot fail");
+
+        self.credentials.insert(
+            credential_id,
+            SoftwareCredential {
+                rp_id: rp.id().to_string(),
+                user_handle: user_handle.to_vec(),
+                key,
+                sign_count: 0,
+            },
+        );
+        Ok(RegistrationResponse {
+            client_data_json,
+            attestation_object,
+        })
+    }
+
+    /// Sign a challenge, as `navigator.credentials.get()` would
+    ///
+    /// Uses the first allowed credential it holds for the relying party, or
+    /// any of them when `allow_credentials` is empty.
+    pub fn get_assertion(
+        &mut self,
+        rp: &RelyingParty,
+        challenge: &[u8],
+        allow_credentials: &[Vec<u8>],
+    ) -> Result<AssertionResponse> {
+        let flags = self.flags();
+        let (credential_id, credential) = self
+            .credentials
+            .iter_mut()
+            .filter(|(_, credential)| credential.rp_id == rp.id())
+            .find(|(id, _)| allow_credentials.is_empty() || allow_credentials.contains(id))
+            .ok_or_else(|| Error::Auth("No credential for this relying party".to_string()))?;
+
+        credential.sign_count += 1;
+        let authenticator_data = AuthenticatorData {
+            rp_id_hash: rp_id_hash(rp.id()),
+            flags,
+            sign_count: credential.sign_count,
+            attested_credential: None,
+        }
+        .to_bytes();
+        let client_data_json = client_data_json(CLIENT_DATA_GET, challenge, rp.origin());
+
+        let mut signed = authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+        Ok(AssertionResponse {
+            credential_id: credential_id.clone(),
+            client_data_json,
+            authenticator_data,
+            signature: credential.key.sign(&signed),
+            user_handle: Some(credential.user_handle.clone()),
+        })
+    }
+
+    fn flags(&self) -> u8 {
+        match self.user_verification {
+            true => FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+            false => FLAG_USER_PRESENT,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Binary wire format for message frames
+//!
+//! Frames are encoded in TLS presentation language style: fixed-width
+//! big-endian integers and length-prefixed opaque vectors.
+//!
+//! ```text
+//! struct {
+//!     uint8  version = 1;
+//!     uint8  message_type;            // high bit set if the payload is compressed
+//!     opaque message_id<0..2^8-1>;
+//!     opaque sender_id<0..2^8-1>;
+//!     opaque recipient_id<0..2^8-1>;
+//!     int64  timestamp;
+//!     uint64 sequence_number;
+//!     opaque payload<0..2^32-1>;
+//! } MessageFrame;
+//! ```
+
+use crate::protocol::transport::{MessageFrame, MessageHeader, MessageType};
+use crate::utils::{Error, Result};
+
+/// Wire format version produced by [`MessageFrame::encode`]
+pub const WIRE_VERSION: u8 = 1;
+
+/// Maximum length of the id fields in a header, in bytes
+pub const MAX_ID_LENGTH: usize = 128;
+
+/// Maximum payload carried by a single frame; larger objects go through the media store
+pub const MAX_PAYLOAD_LENGTH: usize = 1024 * 1024;
+
+/// Bit of the message type octet that marks a compressed payload
+pub const COMPRESSED_FLAG: u8 = 0x80;
+
+/// Fixed-size part of an encoded frame: version, type, timestamp, sequence, payload length
+const FIXED_LENGTH: usize = 1 + 1 + 8 + 8 + 4;
+
+/// Largest possible encoded frame
+pub const MAX_FRAME_LENGTH: usize = FIXED_LENGTH + 3 * (1 + MAX_ID_LENGTH) + MAX_PAYLOAD_LENGTH;
+
+impl MessageType {
+    pub fn to_wire(&self) -> u8 {
+        match self {
+            MessageType::Text => 1,
+            MessageType::Binary => 2,
+            MessageType::Control => 3,
+            MessageType::Heartbeat => 4,
+            MessageType::Sealed => 5,
+        }
+    }
+
+    pub fn from_wire(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(MessageType::Text),
+            2 => Ok(MessageType::Binary),
+            3 => Ok(MessageType::Control),
+            4 => Ok(MessageType::Heartbeat),
+            5 => Ok(MessageType::Sealed),
+            other => Err(Error::Protocol(format!("Unknown message type: {}", other))),
+        }
+    }
+}
+
+impl MessageFrame {
+    /// Encode the frame, rejecting fields that exceed the wire limits
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let header = &self.header;
+
+        if header.version != WIRE_VERSION {
+            return Err(Error::Protocol(format!(
+                "Cannot encode frame version {}",
+                header.version
+            )));
+        }
+
+        if self.payload.len() > MAX_PAYLOAD_LENGTH {
+            return Err(Error::Protocol(format!(
+                "Payload of {} bytes exceeds maximum of {}",
+                self.payload.len(),
+                MAX_PAYLOAD_LENGTH
+            )));
+        }
+
+        if header.payload_length as usize != self.payload.len() {
+            return Err(Error::Protocol(
+                "Header payload length does not match payload".to_string(),
+            ));
+        }
+
+        let mut out = Vec::with_capacity(self.encoded_length());
+        out.push(header.version);
+        let flags = if header.compressed { COMPRESSED_FLAG } else { 0 };
+        out.push(header.message_type.to_wire() | flags);
+        put_id(&mut out, "message_id", &header.message_id)?;
+        put_id(&mut out, "sender_id", &header.sender_id)?;
+        put_id(&mut out, "recipient_id", &header.recipient_id)?;
+        out.extend_from_slice(&header.timestamp.to_be_bytes());
+        out.extend_from_slice(&header.sequence_number.to_be_bytes());
+        out.extend_from_slice(&header.payload_length.to_be_bytes());
+        out.extend_from_slice(&self.payload);
+        Ok(out)
+    }
+
+    /// Decode exactly one frame; unknown versions and trailing bytes are errors
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+
+        let version = reader.u8()?;
+        if version != WIRE_VERSION {
+            return Err(Error::Protocol(format!(
+                "Unsupported frame version: {}",
+                version
+            )));
+        }
+
+        let type_octet = reader.u8()?;
+        let compressed = type_octet & COMPRESSED_FLAG != 0;
+        let message_type = MessageType::from_wire(type_octet & !COMPRESSED_FLAG)?;
+        let message_id = reader.id("message_id")?;
+        let sender_id = reader.id("sender_id")?;
+        let recipient_id = reader.id("recipient_id")?;
+        let timestamp = i64::from_be_bytes(reader.array()?);
+        let sequence_number = u64::from_be_bytes(reader.array()?);
+        let payload_length = u32::from_be_bytes(reader.array()?);
+
+        if payload_length as usize > MAX_PAYLOAD_LENGTH {
+            return Err(Error::Protocol(format!(
+                "Payload of {} bytes exceeds maximum of {}",
+                payload_length, MAX_PAYLOAD_LENGTH
+            )));
+        }
+
+        let payload = reader.take(payload_length as usize)?.to_vec();
+
+        if !reader.is_empty() {
+            return Err(Error::Protocol(format!(
+                "{} trailing bytes after frame",
+                reader.remaining()
+            )));
+        }
+
+        Ok(MessageFrame {
+            header: MessageHeader {
+                version,
+                message_type,
+                message_id,
+                sender_id,
+                recipient_id,
+                timestamp,
+                sequence_number,
+                payload_length,
+                compressed,
+            },
+            payload,
+        })
+    }
+
+    /// Size of the frame once encoded
+    pub fn encoded_length(&self) -> usize {
+        FIXED_LENGTH
+            + 3
+            + self.header.message_id.len()
+            + self.header.sender_id.len()
+            + self.header.recipient_id.len()
+            + self.payload.len()
+    }
+}
+
+fn put_id(out: &mut Vec<u8>, field: &str, value: &str) -> Result<()> {
+    if value.len() > MAX_ID_LENGTH {
+        return Err(Error::Protocol(format!(
+            "{} of {} bytes exceeds maximum of {}",
+            field,
+            value.len(),
+            MAX_ID_LENGTH
+        )));
+    }
+
+    out.push(value.len() as u8);
+    out.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if length > self.bytes.len() {
+            return Err(Error::Protocol("Frame is truncated".to_string()));
+        }
+
+        let (head, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn id(&mut self, field: &str) -> Result<String> {
+        let length = self.u8()? as usize;
+        if length > MAX_ID_LENGTH {
+            return Err(Error::Protocol(format!(
+                "{} of {} bytes exceeds maximum of {}",
+                field, length, MAX_ID_LENGTH
+            )));
+        }
+
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| Error::Protocol(format!("{} is not valid UTF-8", field)))
+    }
+}
@@ -2,10 +2,17 @@
 //! 
 //! Provides secure local storage for messages, keys, and user data
 
-use crate::utils::{Result};
+use crate::protocol::content::{Content, Deletion, Edit, Reaction};
+use crate::protocol::mls::ApplicationContent;
+use crate::protocol::roster::Roster;
+use crate::utils::{Error, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use zeroize::Zeroize;
+
+/// Edits, deletions and reactions held until the message they target arrives
+pub const MAX_PENDING_CHANGES: usize = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedStorage {
@@ -24,21 +31,52 @@ pub struct StorageConfig {
 pub struct MessageStore {
     pub messages: Vec<StoredMessage>,
     pub conversations: Vec<Conversation>,
+    /// Changes that arrived before their target, oldest first
+    #[serde(default)]
+    pub pending_changes: Vec<PendingChange>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredMessage {
     pub id: String,
     pub conversation_id: String,
+    /// Device that sent the message
     pub sender_id: String,
+    /// User owning `sender_id`; any of the user's devices may edit or delete the message
+    #[serde(default)]
+    pub user_id: String,
     pub content: Vec<u8>,
     pub timestamp: i64,
     pub is_read: bool,
+    /// Timestamp of the latest edit applied
+    #[serde(default)]
+    pub edited_at: Option<i64>,
+    /// Deleted for everyone by its sender; `content` has been wiped
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(default)]
+    pub reactions: Vec<StoredReaction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredReaction {
+    pub user_id: String,
+    pub emoji: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingChange {
+    pub conversation_id: String,
+    pub user_id: String,
+    pub timestamp: i64,
+    /// The encoded edit, deletion or reaction
+    pub content: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conversation {
     pub id: String,
+    /// User ids taken from the group's MLS roster, never from server claims
     pub participant_ids: Vec<String>,
     pub last_message_id: Option<String>,
     pub last_activity: i64,
@@ -72,11 +110,18 @@ impl EncryptedStorage {
     }
 }
 
+impl Default for MessageStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MessageStore {
     pub fn new() -> Self {
         Self {
             messages: Vec::new(),
             conversations: Vec::new(),
+            pending_changes: Vec::new(),
         }
     }
     
@@ -101,6 +146,25 @@ impl MessageStore {
         self.conversations.iter().find(|conv| conv.id == id)
     }
     
+    /// Replace a conversation's participants with the users in the group roster.
+    ///
+    /// Returns whether the participant list changed.
+    pub fn sync_participants(&mut self, conversation_id: &str, roster: &Roster) -> Result<bool> {
+        let conversation = self
+            .conversations
+            .iter_mut()
+            .find(|conv| conv.id == conversation_id)
+            .ok_or_else(|| Error::Storage(format!("Unknown conversation: {}", conversation_id)))?;
+
+        let participant_ids = roster.user_ids();
+        if conversation.participant_ids == participant_ids {
+            return Ok(false);
+        }
+
+        conversation.participant_ids = participant_ids;
+        Ok(true)
+    }
+    
     pub fn mark_message_read(&mut self, message_id: &str) -> Result<bool> {
         if let Some(message) = self.messages.iter_mut().find(|msg| msg.id == message_id) {
             message.is_read = true;
@@ -109,4 +173,267 @@ impl MessageStore {
             Ok(false)
         }
     }
+    
+    /// Store or apply a decrypted application message.
+    ///
+    /// Displayable content (including unknown types, kept verbatim) becomes a
+    /// new message; edits, deletions and reactions are applied to the message
+    /// they target in the same conversation, or held until it arrives. The
+    /// sender device is resolved to its user through `roster`, which should be
+    /// the roster the receive pipeline checked the sender against. Returns
+    /// whether the store's messages changed.
+    pub fn apply_application(
+        &mut self,
+        conversation_id: &str,
+        roster: &Roster,
+        application: &ApplicationContent,
+    ) -> Result<bool> {
+        let content = Content::decode(&application.content)?;
+        let user_id = roster
+            .get(application.sender_id.as_bytes())
+            .map(|member| member.user_id().to_string())
+            .ok_or_else(|| {
+                Error::Auth(format!(
+                    "{} is not a member of the group",
+                    application.sender_id
+                ))
+            })?;
+        
+        match &content {
+            Content::Edit(_) | Content::Delete(_) | Content::Reaction(_) => {
+                self.apply_change(conversation_id, &user_id, &content, application.timestamp)
+            }
+            Content::Receipt(_) | Content::Typing(_) => Ok(false),
+            _ => {
+                if self
+                    .find_message(conversation_id, &application.message_id)
+                    .is_some()
+                {
+                    return Ok(false);
+                }
+                
+                self.add_message(StoredMessage {
+                    id: application.message_id.clone(),
+                    conversation_id: conversation_id.to_string(),
+                    sender_id: application.sender_id.clone(),
+                    user_id,
+                    content: application.content.clone(),
+                    timestamp: application.timestamp,
+                    is_read: false,
+                    edited_at: None,
+                    deleted: false,
+                    reactions: Vec::new(),
+                })?;
+                self.apply_pending(conversation_id, &application.message_id);
+                Ok(true)
+            }
+        }
+    }
+    
+    /// Replace the body of a text message. Only its author may edit it, and
+    /// an edit older than one already applied is ignored.
+    pub fn apply_edit(
+        &mut self,
+        conversation_id: &str,
+        user_id: &str,
+        edit: &Edit,
+        timestamp: i64,
+    ) -> Result<bool> {
+        let Some(message) = self.authored_message(conversation_id, user_id, &edit.target_id)?
+        else {
+            self.hold(
+                conversation_id,
+                user_id,
+                Content::Edit(edit.clone()),
+                timestamp,
+            )?;
+            return Ok(false);
+        };
+        
+        if message.deleted
+            || message
+                .edited_at
+                .is_some_and(|edited_at| edited_at >= timestamp)
+        {
+            return Ok(false);
+        }
+        
+        let Content::Text(mut text) = Content::decode(&message.content)? else {
+            return Err(Error::Protocol("Only text messages can be edited".to_string()));
+        };
+        
+        text.body = edit.body.clone();
+        message.content.zeroize();
+        message.content = Content::Text(text).encode()?;
+        message.edited_at = Some(timestamp);
+        Ok(true)
+    }
+    
+    /// Delete a message for everyone, wiping its content. Only its author may delete it.
+    pub fn apply_deletion(
+        &mut self,
+        conversation_id: &str,
+        user_id: &str,
+        deletion: &Deletion,
+        timestamp: i64,
+    ) -> Result<bool> {
+        let Some(message) = self.authored_message(conversation_id, user_id, &deletion.target_id)?
+        else {
+            self.hold(
+                conversation_id,
+                user_id,
+                Content::Delete(deletion.clone()),
+                timestamp,
+            )?;
+            return Ok(false);
+        };
+        
+        if message.deleted {
+            return Ok(false);
+        }
+        
+        message.content.zeroize();
+        message.reactions.clear();
+        message.deleted = true;
+        Ok(true)
+    }
+    
+    /// Add or withdraw a reaction. Each user has at most one reaction per message.
+    pub fn apply_reaction(
+        &mut self,
+        conversation_id: &str,
+        user_id: &str,
+        reaction: &Reaction,
+        timestamp: i64,
+    ) -> Result<bool> {
+        let Some(message) = self.find_message(conversation_id, &reaction.target_id) else {
+            self.hold(
+                conversation_id,
+                user_id,
+                Content::Reaction(reaction.clone()),
+                timestamp,
+            )?;
+            return Ok(false);
+        };
+        
+        if message.deleted {
+            return Ok(false);
+        }
+        
+        let before = message.reactions.clone();
+        message
+            .reactions
+            .retain(|existing| existing.user_id != user_id);
+        if !reaction.remove {
+            message.reactions.push(StoredReaction {
+                user_id: user_id.to_string(),
+                emoji: reaction.emoji.clone(),
+            });
+        }
+        
+        Ok(message.reactions != before)
+    }
+    
+    fn apply_change(
+        &mut self,
+        conversation_id: &str,
+        user_id: &str,
+        content: &Content,
+        timestamp: i64,
+    ) -> Result<bool> {
+        match content {
+            Content::Edit(edit) => self.apply_edit(conversation_id, user_id, edit, timestamp),
+            Content::Delete(deletion) => {
+                self.apply_deletion(conversation_id, user_id, deletion, timestamp)
+            }
+            Content::Reaction(reaction) => {
+                self.apply_reaction(conversation_id, user_id, reaction, timestamp)
+            }
+            _ => Ok(false),
+        }
+    }
+    
+    /// Keep a change whose target has not arrived, dropping the oldest beyond the limit
+    fn hold(
+        &mut self,
+        conversation_id: &str,
+        user_id: &str,
+        content: Content,
+        timestamp: i64,
+    ) -> Result<()> {
+        if self.pending_changes.len() >= MAX_PENDING_CHANGES {
+            self.pending_changes.remove(0);
+        }
+        
+        self.pending_changes.push(PendingChange {
+            conversation_id: conversation_id.to_string(),
+            user_id: user_id.to_string(),
+            timestamp,
+            content: content.encode()?,
+        });
+        Ok(())
+    }
+    
+    /// Apply the held changes for a message that just arrived, oldest first
+    fn apply_pending(&mut self, conversation_id: &str, message_id: &str) {
+        let (mut ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_changes)
+            .into_iter()
+            .partition(|change| {
+                change.conversation_id == conversation_id
+                    && Content::decode(&change.content)
+                        .is_ok_and(|content| change_target(&content) == Some(message_id))
+            });
+        self.pending_changes = waiting;
+        ready.sort_by_key(|change| change.timestamp);
+        
+        for change in ready {
+            // The target did not exist when the change was held, so authorship is only
+            // checked now; a change from anyone but the author fails here and is dropped
+            if let Ok(content) = Content::decode(&change.content) {
+                let _ =
+                    self.apply_change(conversation_id, &change.user_id, &content, change.timestamp);
+            }
+        }
+    }
+    
+    fn find_message(
+        &mut self,
+        conversation_id: &str,
+        message_id: &str,
+    ) -> Option<&mut StoredMessage> {
+        self.messages
+            .iter_mut()
+            .find(|msg| msg.conversation_id == conversation_id && msg.id == message_id)
+    }
+    
+    /// A message, if it exists, checking that `user_id` wrote it
+    fn authored_message(
+        &mut self,
+        conversation_id: &str,
+        user_id: &str,
+        message_id: &str,
+    ) -> Result<Option<&mut StoredMessage>> {
+        let Some(message) = self.find_message(conversation_id, message_id) else {
+            return Ok(None);
+        };
+        
+        if message.user_id != user_id {
+            return Err(Error::Auth(format!(
+                "{} cannot modify message {} sent by {}",
+                user_id, message_id, message.user_id
+            )));
+        }
+        
+        Ok(Some(message))
+    }
+}
+
+/// The message an edit, deletion or reaction applies to
+fn change_target(content: &Content) -> Option<&str> {
+    match content {
+        Content::Edit(edit) => Some(&edit.target_id),
+        Content::Delete(deletion) => Some(&deletion.target_id),
+        Content::Reaction(reaction) => Some(&reaction.target_id),
+        _ => None,
+    }
 }
\ No newline at end of file
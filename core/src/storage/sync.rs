@@ -33,6 +33,7 @@ pub struct SyncMessage {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncConversation {
     pub id: String,
+    /// Advisory only; clients take participants from the MLS roster
     pub participant_ids: Vec<String>,
     pub last_message_id: Option<String>,
     pub last_activity: i64,
@@ -1,5 +1,6 @@
 //! Error handling for XIPRNET
 
+use crate::protocol::replay::MessageRejection;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,6 +20,9 @@ pub enum Error {
     #[error("Network error: {0}")]
     Network(String),
     
+    #[error("Message rejected: {0}")]
+    Rejected(#[from] MessageRejection),
+    
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
     
@@ -2,8 +2,12 @@
 //! 
 //! Provides secure logging with sensitive data redaction
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use tracing::{info, warn, error};
 
+/// Authentication failures logged since startup, watched for alerting
+static AUTH_FAILURES: AtomicU64 = AtomicU64::new(0);
+
 pub fn init_logging() {
     tracing_subscriber::fmt::init();
 }
@@ -21,9 +25,15 @@ pub fn log_auth_success(user_id: &str) {
 }
 
 pub fn log_auth_failure(user_id: &str, reason: &str) {
+    AUTH_FAILURES.fetch_add(1, Ordering::Relaxed);
     warn!("Authentication failed for user: {} - {}", user_id, reason);
 }
 
+/// How many times [`log_auth_failure`] has been called
+pub fn auth_failure_count() -> u64 {
+    AUTH_FAILURES.load(Ordering::Relaxed)
+}
+
 pub fn log_crypto_error(operation: &str, error: &str) {
     error!("Crypto error in {}: {}", operation, error);
 }
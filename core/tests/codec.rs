@@ -0,0 +1,71 @@
+//! Frame codec over in-memory streams
+
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use tokio_util::codec::{Decoder, Encoder};
+use xipr_core::protocol::codec::{framed, FrameCodec};
+use xipr_core::protocol::transport::{Message, MessageFrame, MessageType};
+
+fn frame(content: &[u8]) -> MessageFrame {
+    Message::new(
+        "alice".to_string(),
+        "bob".to_string(),
+        MessageType::Binary,
+        content.to_vec(),
+    )
+    .to_frame()
+    .unwrap()
+}
+
+#[tokio::test]
+async fn exchanges_frames_over_duplex() {
+    let (client, server) = tokio::io::duplex(64);
+    let mut client = framed(client);
+    let mut server = framed(server);
+
+    let sent: Vec<MessageFrame> = (0..16u8).map(|i| frame(&[i; 100])).collect();
+    let expected = sent.clone();
+
+    // The duplex buffer is smaller than a frame, so both sides must interleave
+    let writer = tokio::spawn(async move {
+        for frame in sent {
+            client.send(frame).await.unwrap();
+        }
+    });
+
+    for expected in expected {
+        let received = server.next().await.unwrap().unwrap();
+        assert_eq!(received, expected);
+    }
+
+    writer.await.unwrap();
+}
+
+#[test]
+fn waits_for_partial_frames() {
+    let mut codec = FrameCodec::default();
+    let sent = frame(b"partial");
+    let mut encoded = BytesMut::new();
+    codec.encode(&sent, &mut encoded).unwrap();
+
+    let mut buffer = BytesMut::new();
+    for byte in &encoded[..encoded.len() - 1] {
+        buffer.extend_from_slice(&[*byte]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+    }
+
+    buffer.extend_from_slice(&encoded[encoded.len() - 1..]);
+    assert_eq!(codec.decode(&mut buffer).unwrap(), Some(sent));
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn rejects_oversized_frames() {
+    let mut codec = FrameCodec::new(32);
+
+    let mut encoded = BytesMut::new();
+    assert!(codec.encode(frame(&[0u8; 64]), &mut encoded).is_err());
+
+    let mut buffer = BytesMut::from(&1024u32.to_be_bytes()[..]);
+    assert!(codec.decode(&mut buffer).is_err());
+}
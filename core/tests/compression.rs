@@ -0,0 +1,172 @@
+//! Per-session frame compression
+
+use xipr_core::protocol::compression::{
+    CompressionAlgorithm, CompressionDictionary, CompressionOffer, CompressionParams,
+    FrameCompressor, MIN_COMPRESSIBLE_LENGTH,
+};
+use xipr_core::protocol::content::{Content, Receipt, ReceiptKind};
+use xipr_core::protocol::transport::{Message, MessageFrame, MessageType};
+
+fn frame(message_type: MessageType, content: Vec<u8>) -> MessageFrame {
+    Message::new(
+        "alice".to_string(),
+        "bob".to_string(),
+        message_type,
+        content,
+    )
+    .to_frame()
+    .unwrap()
+}
+
+fn receipt(index: usize) -> Vec<u8> {
+    Content::Receipt(Receipt {
+        kind: if index.is_multiple_of(2) {
+            ReceiptKind::Delivered
+        } else {
+            ReceiptKind::Read
+        },
+        message_ids: (0..3)
+            .map(|n| format!("7f3c2a1e-{:04x}-4b5d-9e8f-{:012x}", n, index * 31 + n))
+            .collect(),
+    })
+    .encode()
+    .unwrap()
+}
+
+fn zstd(dictionary_id: Option<u32>) -> CompressionParams {
+    CompressionParams {
+        algorithm: CompressionAlgorithm::Zstd,
+        dictionary_id,
+    }
+}
+
+#[test]
+fn round_trips_compressible_frames() {
+    let compressor = FrameCompressor::new(zstd(None), None).unwrap();
+    let original = frame(MessageType::Text, "hello ".repeat(200).into_bytes());
+
+    let compressed = compressor.compress(original.clone()).unwrap();
+    assert!(compressed.header.compressed);
+    assert!(compressed.payload.len() < original.payload.len());
+    assert_eq!(
+        compressed.header.payload_length as usize,
+        compressed.payload.len()
+    );
+
+    // The flag survives the wire encoding
+    let decoded = MessageFrame::decode(&compressed.encode().unwrap()).unwrap();
+    assert!(decoded.header.compressed);
+
+    let restored = compressor.decompress(decoded).unwrap();
+    assert_eq!(restored, original);
+    Message::from_frame(&restored).unwrap();
+}
+
+#[test]
+fn trained_dictionary_round_trips_control_payloads() {
+    let samples: Vec<Vec<u8>> = (0..1000).map(receipt).collect();
+    let dictionary = CompressionDictionary::train(&samples, 4096).unwrap();
+    let params = zstd(Some(dictionary.id()));
+
+    let with_dictionary = FrameCompressor::new(params, Some(dictionary)).unwrap();
+    let without_dictionary = FrameCompressor::new(zstd(None), None).unwrap();
+    let original = frame(MessageType::Control, receipt(5000));
+
+    let compressed = with_dictionary.compress(original.clone()).unwrap();
+    assert!(compressed.header.compressed);
+    assert!(
+        compressed.payload.len()
+            <= without_dictionary
+                .compress(original.clone())
+                .unwrap()
+                .payload
+                .len()
+    );
+    assert_eq!(with_dictionary.decompress(compressed).unwrap(), original);
+}
+
+#[test]
+fn leaves_small_and_binary_frames_alone() {
+    let compressor = FrameCompressor::new(zstd(None), None).unwrap();
+
+    let small = frame(MessageType::Text, vec![b'a'; MIN_COMPRESSIBLE_LENGTH - 1]);
+    assert_eq!(compressor.compress(small.clone()).unwrap(), small);
+
+    // Binary payloads are ciphertext and gain nothing
+    let binary = frame(MessageType::Binary, vec![0; 4096]);
+    assert_eq!(compressor.compress(binary.clone()).unwrap(), binary);
+
+    // Uncompressed frames pass straight through decompression
+    assert_eq!(compressor.decompress(binary.clone()).unwrap(), binary);
+}
+
+#[test]
+fn rejects_payloads_over_the_decompression_limit() {
+    let sender = FrameCompressor::new(zstd(None), None).unwrap();
+    let receiver = FrameCompressor::new(zstd(None), None)
+        .unwrap()
+        .with_max_decompressed_length(1024);
+
+    let bomb = sender
+        .compress(frame(MessageType::Text, vec![b'a'; 64 * 1024]))
+        .unwrap();
+    assert!(bomb.header.compressed);
+    assert!(bomb.payload.len() < 1024);
+
+    assert!(receiver.decompress(bomb).is_err());
+}
+
+#[test]
+fn negotiates_the_clients_preferred_supported_algorithm() {
+    let dictionary = CompressionDictionary::new(b"shared dictionary".to_vec());
+    let supported = [CompressionAlgorithm::Zstd];
+
+    let none = CompressionOffer::default();
+    assert_eq!(CompressionParams::negotiate(&none, &supported, None), None);
+
+    let without_dictionary = CompressionOffer {
+        algorithms: vec![CompressionAlgorithm::Zstd],
+        dictionary_ids: vec![dictionary.id().wrapping_add(1)],
+    };
+    assert_eq!(
+        CompressionParams::negotiate(&without_dictionary, &supported, Some(&dictionary)),
+        Some(zstd(None))
+    );
+
+    let with_dictionary = CompressionOffer {
+        algorithms: vec![CompressionAlgorithm::Zstd],
+        dictionary_ids: vec![dictionary.id()],
+    };
+    let params =
+        CompressionParams::negotiate(&with_dictionary, &supported, Some(&dictionary)).unwrap();
+    assert_eq!(params, zstd(Some(dictionary.id())));
+
+    assert_eq!(
+        params.to_string().parse::<CompressionParams>().unwrap(),
+        params
+    );
+    assert_eq!("zstd".parse::<CompressionParams>().unwrap(), zstd(None));
+    assert!("brotli".parse::<CompressionParams>().is_err());
+    assert!("zstd; dict=x".parse::<CompressionParams>().is_err());
+}
+
+#[test]
+fn requires_the_negotiated_dictionary() {
+    let dictionary = CompressionDictionary::new(b"shared dictionary".to_vec());
+    let other = CompressionDictionary::new(b"another dictionary".to_vec());
+    let params = zstd(Some(dictionary.id()));
+
+    assert!(FrameCompressor::new(params, None).is_err());
+    assert!(FrameCompressor::new(params, Some(other)).is_err());
+    assert!(FrameCompressor::new(params, Some(dictionary)).is_ok());
+}
+
+#[test]
+fn compressed_frames_are_not_readable_as_messages() {
+    let compressor = FrameCompressor::new(zstd(None), None).unwrap();
+    let compressed = compressor
+        .compress(frame(MessageType::Text, "hello ".repeat(200).into_bytes()))
+        .unwrap();
+
+    assert!(Message::from_frame(&compressed).is_err());
+}
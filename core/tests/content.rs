@@ -0,0 +1,426 @@
+//! Message content schema and applying it to the `MessageStore`
+
+use xipr_core::protocol::content::{
+    AttachmentPointer, Content, Deletion, Edit, Quote, Reaction, Receipt, ReceiptKind, TextContent,
+    TypingIndicator, CONTENT_VERSION,
+};
+use xipr_core::protocol::mls::ApplicationContent;
+use xipr_core::protocol::roster::{Credential, Roster};
+use xipr_core::storage::local::{MessageStore, StoredReaction};
+
+fn application(
+    message_id: &str,
+    sender_id: &str,
+    timestamp: i64,
+    content: Content,
+) -> ApplicationContent {
+    ApplicationContent {
+        message_id: message_id.to_string(),
+        sender_id: sender_id.to_string(),
+        generation: 0,
+        timestamp,
+        content: content.encode().unwrap(),
+    }
+}
+
+/// Alice with two devices, bob and mallory with one each
+fn roster() -> Roster {
+    let mut roster = Roster::new();
+    for (user_id, device_id) in [
+        ("alice", "alice-phone"),
+        ("alice", "alice-laptop"),
+        ("bob", "bob-phone"),
+        ("mallory", "mallory-phone"),
+    ] {
+        let credential = Credential {
+            user_id: user_id.to_string(),
+            device_id: device_id.to_string(),
+            signature_key: vec![0; 32],
+        };
+        roster.add(credential, 1);
+    }
+    roster
+}
+
+fn body(store: &MessageStore, message_id: &str) -> String {
+    let message = store
+        .messages
+        .iter()
+        .find(|msg| msg.id == message_id)
+        .unwrap();
+    match Content::decode(&message.content).unwrap() {
+        Content::Text(text) => text.body,
+        other => panic!("not text: {:?}", other),
+    }
+}
+
+#[test]
+fn round_trips_every_content_type() {
+    let contents = vec![
+        Content::Text(TextContent {
+            body: "hi".to_string(),
+            reply_to: Some(Quote {
+                message_id: "m0".to_string(),
+                sender_id: "bob".to_string(),
+                excerpt: Some("hello".to_string()),
+            }),
+        }),
+        Content::Attachment(AttachmentPointer {
+            media_id: "media-1".to_string(),
+            mime_type: "image/png".to_string(),
+            size: 1024,
+            key: vec![1; 32],
+            digest: vec![2; 32],
+            file_name: Some("cat.png".to_string()),
+            caption: None,
+            reply_to: None,
+        }),
+        Content::Receipt(Receipt {
+            kind: ReceiptKind::Read,
+            message_ids: vec!["m1".to_string(), "m2".to_string()],
+        }),
+        Content::Typing(TypingIndicator { typing: true }),
+        Content::Reaction(Reaction {
+            target_id: "m1".to_string(),
+            emoji: "👍".to_string(),
+            remove: false,
+        }),
+        Content::Edit(Edit {
+            target_id: "m1".to_string(),
+            body: "hi!".to_string(),
+        }),
+        Content::Delete(Deletion {
+            target_id: "m1".to_string(),
+        }),
+    ];
+
+    for content in contents {
+        let decoded = Content::decode(&content.encode().unwrap()).unwrap();
+        assert_eq!(decoded, content);
+    }
+}
+
+#[test]
+fn preserves_unknown_content_types() {
+    let bytes = br#"{"version":3,"type":"poll","body":{"question":"lunch?","options":["a","b"]}}"#;
+
+    let content = Content::decode(bytes).unwrap();
+    assert_eq!(content.content_type(), "poll");
+    assert!(matches!(content, Content::Unknown { version: 3, .. }));
+    assert_eq!(
+        Content::decode(&content.encode().unwrap()).unwrap(),
+        content
+    );
+}
+
+#[test]
+fn decodes_newer_versions_of_known_types() {
+    let bytes = format!(
+        r#"{{"version":{},"type":"text","body":{{"body":"hi","mentions":["bob"]}}}}"#,
+        CONTENT_VERSION + 1
+    );
+    assert_eq!(
+        Content::decode(bytes.as_bytes()).unwrap(),
+        Content::text("hi")
+    );
+
+    // A known type whose body no longer parses is kept rather than dropped
+    let incompatible = br#"{"version":2,"type":"text","body":{"segments":[]}}"#;
+    assert!(matches!(
+        Content::decode(incompatible).unwrap(),
+        Content::Unknown { .. }
+    ));
+
+    assert!(Content::decode(b"not json").is_err());
+}
+
+#[test]
+fn applies_edits_deletions_and_reactions() {
+    let mut store = MessageStore::default();
+    let edit = |target: &str, body: &str| {
+        Content::Edit(Edit {
+            target_id: target.to_string(),
+            body: body.to_string(),
+        })
+    };
+
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m1", "alice-phone", 10, Content::text("helo"))
+        )
+        .unwrap());
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m2", "alice-phone", 20, edit("m1", "hello"))
+        )
+        .unwrap());
+    assert_eq!(body(&store, "m1"), "hello");
+
+    // Edits arriving out of order do not roll back a newer one
+    assert!(!store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m3", "alice-phone", 15, edit("m1", "hell"))
+        )
+        .unwrap());
+    assert_eq!(body(&store, "m1"), "hello");
+
+    // Only the sender may edit or delete
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m4", "mallory-phone", 30, edit("m1", "pwned"))
+        )
+        .is_err());
+
+    let react = |emoji: &str, remove: bool| {
+        Content::Reaction(Reaction {
+            target_id: "m1".to_string(),
+            emoji: emoji.to_string(),
+            remove,
+        })
+    };
+    store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m5", "bob-phone", 40, react("👍", false)),
+        )
+        .unwrap();
+    store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m6", "bob-phone", 41, react("❤️", false)),
+        )
+        .unwrap();
+    let message = store.messages.iter().find(|msg| msg.id == "m1").unwrap();
+    assert_eq!(
+        message.reactions,
+        vec![StoredReaction {
+            user_id: "bob".to_string(),
+            emoji: "❤️".to_string(),
+        }]
+    );
+
+    store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m7", "bob-phone", 42, react("❤️", true)),
+        )
+        .unwrap();
+    let message = store.messages.iter().find(|msg| msg.id == "m1").unwrap();
+    assert!(message.reactions.is_empty());
+
+    let delete = Content::Delete(Deletion {
+        target_id: "m1".to_string(),
+    });
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m8", "bob-phone", 50, delete.clone())
+        )
+        .is_err());
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m9", "alice-phone", 50, delete)
+        )
+        .unwrap());
+
+    let message = store.messages.iter().find(|msg| msg.id == "m1").unwrap();
+    assert!(message.deleted);
+    assert!(message.content.is_empty());
+    assert!(!store
+        .apply_application(
+            "c1",
+            &roster(),
+            &application("m10", "alice-phone", 60, edit("m1", "back"))
+        )
+        .unwrap());
+
+    // Only displayable content becomes a message of its own
+    assert_eq!(store.get_messages("c1").len(), 1);
+}
+
+#[test]
+fn stores_unknown_content_verbatim() {
+    let mut store = MessageStore::default();
+    let unknown = ApplicationContent {
+        message_id: "m1".to_string(),
+        sender_id: "alice-phone".to_string(),
+        generation: 0,
+        timestamp: 10,
+        content: br#"{"version":1,"type":"poll","body":{"question":"lunch?"}}"#.to_vec(),
+    };
+
+    assert!(store.apply_application("c1", &roster(), &unknown).unwrap());
+    assert_eq!(store.messages[0].content, unknown.content);
+
+    // Redelivery of the same message is not stored twice
+    assert!(!store.apply_application("c1", &roster(), &unknown).unwrap());
+}
+
+#[test]
+fn changes_apply_per_user_and_per_conversation() {
+    let mut store = MessageStore::default();
+    let roster = roster();
+    let edit = |body: &str| {
+        Content::Edit(Edit {
+            target_id: "m1".to_string(),
+            body: body.to_string(),
+        })
+    };
+
+    store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m1", "alice-phone", 10, Content::text("one")),
+        )
+        .unwrap();
+    store
+        .apply_application(
+            "c2",
+            &roster,
+            &application("m1", "bob-phone", 10, Content::text("two")),
+        )
+        .unwrap();
+    assert_eq!(store.messages.len(), 2);
+
+    // Any of alice's devices may edit her message, and only in its own conversation
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m2", "alice-laptop", 20, edit("uno"))
+        )
+        .unwrap());
+    assert_eq!(body(&store, "m1"), "uno");
+    assert!(store
+        .apply_application(
+            "c2",
+            &roster,
+            &application("m3", "alice-phone", 30, edit("dos"))
+        )
+        .is_err());
+    let other = store
+        .get_messages("c2")
+        .into_iter()
+        .find(|msg| msg.id == "m1")
+        .unwrap();
+    assert_eq!(
+        Content::decode(&other.content).unwrap(),
+        Content::text("two")
+    );
+
+    // Devices outside the roster are refused
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m4", "eve-phone", 40, edit("hacked"))
+        )
+        .is_err());
+}
+
+#[test]
+fn changes_wait_for_their_target() {
+    let mut store = MessageStore::default();
+    let roster = roster();
+    let edit = |target: &str, body: &str| {
+        Content::Edit(Edit {
+            target_id: target.to_string(),
+            body: body.to_string(),
+        })
+    };
+
+    // The edit and a forged one overtake the message they change
+    assert!(!store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m2", "alice-laptop", 20, edit("m1", "fixed"))
+        )
+        .unwrap());
+    assert!(!store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m3", "mallory-phone", 30, edit("m1", "pwned"))
+        )
+        .unwrap());
+    assert_eq!(store.pending_changes.len(), 2);
+
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m1", "alice-phone", 10, Content::text("fixd"))
+        )
+        .unwrap());
+    assert_eq!(body(&store, "m1"), "fixed");
+    assert!(store.pending_changes.is_empty());
+
+    // A deletion that arrives first deletes the message as soon as it is stored
+    let delete = Content::Delete(Deletion {
+        target_id: "m4".to_string(),
+    });
+    store
+        .apply_application("c1", &roster, &application("m5", "bob-phone", 50, delete))
+        .unwrap();
+    store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m4", "bob-phone", 40, Content::text("oops")),
+        )
+        .unwrap();
+    let deleted = store.messages.iter().find(|msg| msg.id == "m4").unwrap();
+    assert!(deleted.deleted);
+    assert!(deleted.content.is_empty());
+}
+
+#[test]
+fn held_changes_from_other_users_are_dropped_when_the_target_arrives() {
+    let mut store = MessageStore::default();
+    let roster = roster();
+
+    // Nothing to check authorship against yet, so both are held
+    let edit = Content::Edit(Edit {
+        target_id: "m1".to_string(),
+        body: "pwned".to_string(),
+    });
+    let delete = Content::Delete(Deletion {
+        target_id: "m1".to_string(),
+    });
+    assert!(!store
+        .apply_application("c1", &roster, &application("m2", "mallory-phone", 20, edit))
+        .unwrap());
+    assert!(!store
+        .apply_application("c1", &roster, &application("m3", "bob-phone", 30, delete))
+        .unwrap());
+    assert_eq!(store.pending_changes.len(), 2);
+
+    assert!(store
+        .apply_application(
+            "c1",
+            &roster,
+            &application("m1", "alice-phone", 10, Content::text("mine"))
+        )
+        .unwrap());
+    assert_eq!(body(&store, "m1"), "mine");
+    let message = store.messages.iter().find(|msg| msg.id == "m1").unwrap();
+    assert!(!message.deleted);
+    assert!(message.edited_at.is_none());
+    assert!(store.pending_changes.is_empty());
+}
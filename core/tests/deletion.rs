@@ -0,0 +1,69 @@
+//! Signed tombstones and deletion receipts
+
+use xipr_core::protocol::deletion::{DeletionSigner, PurgeSummary};
+
+fn purged() -> PurgeSummary {
+    PurgeSummary {
+        sessions: 3,
+        devices: 2,
+        pre_keys: 40,
+        queued_messages: 7,
+        groups: 1,
+        passkeys: 1,
+        media_objects: 1,
+        second_factor: true,
+    }
+}
+
+#[test]
+fn receipts_verify_and_match_their_tombstone() {
+    let signer = DeletionSigner::generate(4);
+    let tombstone = signer.tombstone("alice".to_string(), Some("acme".to_string()), 1_000);
+    let receipt = signer.receipt(&tombstone, purged());
+
+    tombstone.verify(&signer.public_key()).unwrap();
+    receipt.verify(&signer.public_key()).unwrap();
+    assert_eq!(receipt.user_id, tombstone.user_id);
+    assert_eq!(receipt.org_id, tombstone.org_id);
+    assert_eq!(receipt.deleted_at, 1_000);
+    assert_eq!(receipt.key_id, 4);
+
+    let other = DeletionSigner::generate(4);
+    assert!(tombstone.verify(&other.public_key()).is_err());
+    assert!(receipt.verify(&other.public_key()).is_err());
+}
+
+#[test]
+fn tampered_receipts_and_tombstones_fail_to_verify() {
+    let signer = DeletionSigner::from_bytes(1, &[7; 32]);
+    let key = signer.public_key();
+    let tombstone = signer.tombstone("alice".to_string(), None, 1_000);
+    let receipt = signer.receipt(&tombstone, purged());
+
+    let mut understated = receipt.clone();
+    understated.purged.queued_messages = 0;
+    assert!(understated.verify(&key).is_err());
+
+    let mut kept_factor = receipt.clone();
+    kept_factor.purged.second_factor = false;
+    assert!(kept_factor.verify(&key).is_err());
+
+    let mut moved = tombstone.clone();
+    moved.org_id = Some("acme".to_string());
+    assert!(moved.verify(&key).is_err());
+
+    let mut backdated = tombstone;
+    backdated.deleted_at -= 1;
+    assert!(backdated.verify(&key).is_err());
+}
+
+#[test]
+fn receipts_and_tombstones_are_domain_separated() {
+    let signer = DeletionSigner::generate(1);
+    let tombstone = signer.tombstone("alice".to_string(), None, 1_000);
+    let mut receipt = signer.receipt(&tombstone, PurgeSummary::default());
+
+    // A tombstone's signature must not pass for a receipt of the same deletion
+    receipt.signature = tombstone.signature.clone();
+    assert!(receipt.verify(&signer.public_key()).is_err());
+}
@@ -0,0 +1,108 @@
+//! Linking a new device through a sealed provisioning envelope
+
+use xipr_core::protocol::provisioning::{
+    link_code_digest, ProvisionEnvelope, ProvisionMessage, ProvisioningCode, ProvisioningKeys,
+    LINK_CODE_LENGTH,
+};
+
+fn message() -> ProvisionMessage {
+    ProvisionMessage::new(
+        "alice".to_string(),
+        "alice-phone".to_string(),
+        vec![1; 32],
+        vec![2; 32],
+    )
+}
+
+#[test]
+fn new_device_opens_what_the_linking_device_sealed() {
+    let keys = ProvisioningKeys::generate("slot-1".to_string());
+    let scanned: ProvisioningCode = keys.code().to_string().parse().unwrap();
+    assert_eq!(scanned, keys.code());
+
+    let sent = message();
+    let envelope = ProvisionEnvelope::seal(&scanned, &sent).unwrap();
+    assert!(!envelope
+        .ciphertext
+        .windows(32)
+        .any(|window| window == [2; 32].as_slice()));
+
+    let received = keys.open(&envelope).unwrap();
+    assert_eq!(received.user_id, "alice");
+    assert_eq!(received.linked_by, "alice-phone");
+    assert_eq!(received.identity_private_key, vec![2; 32]);
+    assert_eq!(received.link_code.len(), LINK_CODE_LENGTH);
+    assert_eq!(received.link_code_digest(), sent.link_code_digest());
+    assert_eq!(
+        link_code_digest(&received.link_code),
+        sent.link_code_digest()
+    );
+}
+
+#[test]
+fn only_the_slot_key_opens_the_envelope() {
+    let keys = ProvisioningKeys::generate("slot-1".to_string());
+    let envelope = ProvisionEnvelope::seal(&keys.code(), &message()).unwrap();
+
+    let other = ProvisioningKeys::generate("slot-1".to_string());
+    assert!(other.open(&envelope).is_err());
+
+    let mut tampered = envelope.clone();
+    tampered.ciphertext[0] ^= 1;
+    assert!(keys.open(&tampered).is_err());
+}
+
+#[test]
+fn malformed_envelopes_are_refused() {
+    let keys = ProvisioningKeys::generate("slot-1".to_string());
+    let envelope = ProvisionEnvelope::seal(&keys.code(), &message()).unwrap();
+    envelope.validate().unwrap();
+
+    for nonce_length in [0, 11, 13, 24] {
+        let mut bad_nonce = envelope.clone();
+        bad_nonce.nonce = vec![0; nonce_length];
+        assert!(bad_nonce.validate().is_err());
+        let keys = ProvisioningKeys::generate("slot-1".to_string());
+        assert!(keys.open(&bad_nonce).is_err());
+    }
+
+    let mut bad_key = envelope;
+    bad_key.ephemeral_public_key.pop();
+    assert!(bad_key.validate().is_err());
+    assert!(keys.open(&bad_key).is_err());
+}
+
+#[test]
+fn envelopes_are_bound_to_their_slot() {
+    let keys = ProvisioningKeys::generate("slot-1".to_string());
+    let mut code = keys.code();
+    code.provisioning_id = "slot-2".to_string();
+
+    let envelope = ProvisionEnvelope::seal(&code, &message()).unwrap();
+    assert!(keys.open(&envelope).is_err());
+}
+
+#[test]
+fn every_message_gets_a_fresh_link_code() {
+    assert_ne!(message().link_code, message().link_code);
+    assert_ne!(message().link_code_digest(), message().link_code_digest());
+}
+
+#[test]
+fn rejects_malformed_codes() {
+    let code = ProvisioningKeys::generate("slot-1".to_string()).code();
+    let uri = code.to_string();
+    assert!(uri.starts_with("xipr://link?id=slot-1&key="));
+
+    assert!("https://example.com/?id=slot-1"
+        .parse::<ProvisioningCode>()
+        .is_err());
+    assert!("xipr://link?key=AAAA".parse::<ProvisioningCode>().is_err());
+    assert!("xipr://link?id=slot-1&key=short"
+        .parse::<ProvisioningCode>()
+        .is_err());
+    assert!(uri
+        .replace("id=slot-1", "id=")
+        .parse::<ProvisioningCode>()
+        .is_err());
+}
@@ -0,0 +1,98 @@
+//! Multi-device fan-out in `MessageRouter`
+
+use std::collections::HashMap;
+use xipr_core::protocol::fanout::{DeviceCopy, DeviceDirectory};
+use xipr_core::protocol::transport::{Message, MessageRouter, MessageType};
+
+struct Directory(HashMap<&'static str, Vec<&'static str>>);
+
+impl DeviceDirectory for Directory {
+    fn active_devices(&self, user_id: &str) -> Vec<String> {
+        self.0
+            .get(user_id)
+            .map(|devices| devices.iter().map(|device| device.to_string()).collect())
+            .unwrap_or_default()
+    }
+}
+
+fn directory() -> Directory {
+    Directory(HashMap::from([
+        ("alice", vec!["alice-phone", "alice-laptop"]),
+        ("bob", vec!["bob-phone", "bob-tablet"]),
+    ]))
+}
+
+fn copy(device_id: &str) -> DeviceCopy {
+    DeviceCopy {
+        device_id: device_id.to_string(),
+        content: format!("for {}", device_id).into_bytes(),
+    }
+}
+
+fn message() -> Message {
+    Message::new(
+        "alice".to_string(),
+        "bob".to_string(),
+        MessageType::Text,
+        Vec::new(),
+    )
+}
+
+#[test]
+fn routes_user_to_active_devices() {
+    assert_eq!(MessageRouter::route_message(&message()).unwrap(), "bob");
+    let devices = MessageRouter::route_to_devices(&message(), &directory()).unwrap();
+    assert_eq!(devices, vec!["bob-phone", "bob-tablet"]);
+
+    let mut unknown = message();
+    unknown.recipient_id = "carol".to_string();
+    assert!(MessageRouter::route_to_devices(&unknown, &directory()).is_err());
+
+    unknown.recipient_id.clear();
+    assert!(MessageRouter::route_message(&unknown).is_err());
+}
+
+#[test]
+fn fans_out_per_device_and_sync_copies() {
+    let message = message();
+    let fan_out = MessageRouter::fan_out(
+        &message,
+        "alice-phone",
+        vec![copy("bob-phone"), copy("bob-tablet")],
+        vec![copy("alice-laptop")],
+        &directory(),
+    );
+
+    assert!(fan_out.report.is_complete());
+    assert_eq!(
+        fan_out.report.reached,
+        vec!["bob-phone", "bob-tablet", "alice-laptop"]
+    );
+
+    for (device, delivery) in &fan_out.deliveries {
+        assert_eq!(delivery.id, message.id);
+        assert_eq!(delivery.recipient_id, "bob");
+        assert_eq!(delivery.content, format!("for {}", device).into_bytes());
+    }
+}
+
+#[test]
+fn reports_stale_and_missing_devices() {
+    let fan_out = MessageRouter::fan_out(
+        &message(),
+        "alice-phone",
+        vec![
+            copy("bob-phone"),
+            copy("bob-old-phone"),
+            copy("alice-phone"),
+        ],
+        Vec::new(),
+        &directory(),
+    );
+
+    assert_eq!(fan_out.report.reached, vec!["bob-phone"]);
+    // The sending device never gets a copy of its own message
+    assert_eq!(fan_out.report.stale, vec!["bob-old-phone", "alice-phone"]);
+    assert_eq!(fan_out.report.missing, vec!["alice-laptop", "bob-tablet"]);
+    assert_eq!(fan_out.deliveries.len(), 1);
+}
@@ -0,0 +1,195 @@
+//! Cipher-suite selection and group policy enforcement on commits
+
+use xipr_core::protocol::mls::{
+    CipherSuite, Commit, MlsClient, MlsConfig, MlsGroup, Proposal, EXTENSION_ADMIN_ROLES,
+};
+
+fn client(name: &str) -> MlsClient {
+    MlsClient::for_device(name.to_string(), format!("{}-phone", name)).unwrap()
+}
+
+/// A group created by alice, with the given members joined through her commits
+fn group(config: MlsConfig, members: &[&MlsClient]) -> (MlsClient, MlsGroup) {
+    let mut alice = client("alice");
+    let mut group = alice.create_group(config).unwrap();
+    for member in members {
+        group.add_member(member).unwrap();
+    }
+    (alice, group)
+}
+
+fn join(member: &mut MlsClient, config: MlsConfig, group: &MlsGroup) -> MlsGroup {
+    member.join_group(config, group.welcome().unwrap()).unwrap()
+}
+
+#[test]
+fn cipher_suites_round_trip_their_code_points() {
+    for suite in [
+        CipherSuite::Mls128X25519Aes128GcmSha256Ed25519,
+        CipherSuite::Mls128X25519ChaCha20Poly1305Sha256Ed25519,
+        CipherSuite::Mls256XWingAes256GcmSha384Ed25519,
+    ] {
+        assert_eq!(CipherSuite::from_u16(suite.as_u16()).unwrap(), suite);
+    }
+
+    assert!(CipherSuite::from_u16(0x0002).is_err());
+    assert!(CipherSuite::Mls256XWingAes256GcmSha384Ed25519.is_post_quantum());
+    assert!(!CipherSuite::Mls128X25519Aes128GcmSha256Ed25519.is_post_quantum());
+}
+
+#[test]
+fn groups_are_created_only_with_allowed_suites() {
+    let mut config = MlsConfig::new(b"group".to_vec());
+    config.validate().unwrap();
+
+    config.cipher_suite = CipherSuite::Mls256XWingAes256GcmSha384Ed25519;
+    let group = client("alice").create_group(config.clone()).unwrap();
+    assert_eq!(group.config.cipher_suite.as_u16(), 0xF001);
+
+    // Allowed by clients, but not by the default policy
+    config.cipher_suite = CipherSuite::Mls128X25519ChaCha20Poly1305Sha256Ed25519;
+    assert!(config.validate().is_err());
+    assert!(client("alice").create_group(config).is_err());
+
+    assert!(MlsConfig::new(Vec::new()).validate().is_err());
+
+    let mut config = MlsConfig::new(b"group".to_vec());
+    config.policy.max_members = 0;
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn clients_only_join_with_suites_they_support() {
+    let mut alice = client("alice");
+    alice
+        .capabilities
+        .cipher_suites
+        .retain(|suite| !suite.is_post_quantum());
+
+    let mut config = MlsConfig::new(b"group".to_vec());
+    config.cipher_suite = CipherSuite::Mls256XWingAes256GcmSha384Ed25519;
+    assert!(alice.create_group(config.clone()).is_err());
+    assert!(alice.key_package(config.cipher_suite).is_err());
+
+    // Nor can a group on that suite add her
+    let (_, mut group) = group(config, &[]);
+    let epoch = group.epoch;
+    assert!(group.add_member(&alice).is_err());
+    assert_eq!(group.epoch, epoch);
+}
+
+#[test]
+fn key_packages_for_another_suite_are_rejected() {
+    let mut config = MlsConfig::new(b"group".to_vec());
+    config
+        .policy
+        .allowed_cipher_suites
+        .push(CipherSuite::Mls128X25519ChaCha20Poly1305Sha256Ed25519);
+    let (alice, group) = group(config, &[]);
+
+    let key_package = client("bob")
+        .key_package(CipherSuite::Mls128X25519ChaCha20Poly1305Sha256Ed25519)
+        .unwrap();
+    let commit = Commit {
+        group_id: group.group_id.clone(),
+        epoch: group.epoch,
+        committer: alice.identity.clone(),
+        proposals: vec![Proposal::Add(key_package)],
+    };
+    assert!(group.validate_commit(&commit).is_err());
+}
+
+#[test]
+fn commits_may_not_grow_the_group_past_max_members() {
+    let mut config = MlsConfig::new(b"group".to_vec());
+    config.policy.max_members = 2;
+    let bob = client("bob");
+    let (_, mut group) = group(config, &[&bob]);
+    assert_eq!(group.members().len(), 2);
+
+    let epoch = group.epoch;
+    assert!(group.add_member(&client("carol")).is_err());
+    assert_eq!(group.epoch, epoch);
+    assert_eq!(group.members().len(), 2);
+
+    // Swapping one member for another in a single commit stays within the limit
+    let carol = client("carol");
+    let commit = Commit {
+        group_id: group.group_id.clone(),
+        epoch,
+        committer: group.own_identity.clone(),
+        proposals: vec![
+            Proposal::Remove {
+                member_id: bob.identity.clone(),
+            },
+            Proposal::Add(carol.key_package(group.config.cipher_suite).unwrap()),
+        ],
+    };
+    group.process_commit(&commit).unwrap();
+    assert!(group.roster.contains(&carol.identity));
+}
+
+#[test]
+fn only_admins_add_and_remove_members() {
+    let config = MlsConfig::new(b"group".to_vec());
+    let mut bob = client("bob");
+    let carol = client("carol");
+    let (alice, mut alice_group) = group(config.clone(), &[&bob, &carol]);
+    let mut bob_group = join(&mut bob, config, &alice_group);
+
+    assert!(!bob_group.is_admin(&bob.identity));
+    assert!(bob_group.add_member(&client("dave")).is_err());
+    assert!(bob_group.remove_member(&carol.identity).is_err());
+    assert!(bob_group.remove_member(&alice.identity).is_err());
+    assert_eq!(bob_group.epoch, alice_group.epoch);
+
+    // Anyone may leave
+    let commit = Commit {
+        group_id: bob_group.group_id.clone(),
+        epoch: bob_group.epoch,
+        committer: bob.identity.clone(),
+        proposals: vec![Proposal::Remove {
+            member_id: bob.identity.clone(),
+        }],
+    };
+    bob_group.validate_commit(&commit).unwrap();
+
+    alice_group.remove_member(&carol.identity).unwrap();
+    assert!(!alice_group.roster.contains(&carol.identity));
+}
+
+#[test]
+fn commits_from_outside_the_group_or_epoch_are_rejected() {
+    let (alice, group) = group(MlsConfig::new(b"group".to_vec()), &[]);
+    let commit = |group_id: &[u8], epoch: u64, committer: &[u8]| Commit {
+        group_id: group_id.to_vec(),
+        epoch,
+        committer: committer.to_vec(),
+        proposals: Vec::new(),
+    };
+
+    group
+        .validate_commit(&commit(&group.group_id, group.epoch, &alice.identity))
+        .unwrap();
+    assert!(group
+        .validate_commit(&commit(b"other", group.epoch, &alice.identity))
+        .is_err());
+    assert!(group
+        .validate_commit(&commit(&group.group_id, group.epoch + 1, &alice.identity))
+        .is_err());
+    assert!(group
+        .validate_commit(&commit(&group.group_id, group.epoch, b"mallory"))
+        .is_err());
+}
+
+#[test]
+fn added_members_need_the_required_capabilities() {
+    let mut bob = client("bob");
+    bob.capabilities
+        .extension_types
+        .retain(|extension| *extension != EXTENSION_ADMIN_ROLES);
+
+    let (_, mut group) = group(MlsConfig::new(b"group".to_vec()), &[]);
+    assert!(group.add_member(&bob).is_err());
+    assert_eq!(group.members().len(), 1);
+}
@@ -0,0 +1,151 @@
+//! Login throttling decisions and proof-of-work challenges against a fake clock
+
+use xipr_core::protocol::throttle::{
+    Attempts, Clock, FakeClock, PowChallenge, ThrottleDecision, ThrottleLimits, ThrottlePolicy,
+};
+
+fn policy() -> ThrottlePolicy {
+    ThrottlePolicy {
+        account: ThrottleLimits {
+            challenge_after: 3,
+            backoff_after: 5,
+            window: 3600,
+        },
+        source: ThrottleLimits {
+            challenge_after: 10,
+            backoff_after: 20,
+            window: 3600,
+        },
+        base_backoff: 2,
+        max_backoff: 60,
+        pow_difficulty: 8,
+        challenge_lifetime: 300,
+        max_challenges: 5,
+    }
+}
+
+/// Record `count` failures one second apart, returning the last count
+fn fail(clock: &FakeClock, previous: Option<Attempts>, count: u32) -> Attempts {
+    let mut attempts = previous;
+    for _ in 0..count {
+        attempts = Some(Attempts::record_failure(attempts, clock.now(), 3600));
+        clock.advance(1);
+    }
+    attempts.unwrap()
+}
+
+#[test]
+fn escalates_from_challenge_to_backoff() {
+    let clock = FakeClock::new(1_000_000);
+    let policy = policy();
+    assert_eq!(
+        policy.evaluate(None, None, clock.now()),
+        ThrottleDecision::Allow
+    );
+
+    let attempts = fail(&clock, None, 2);
+    assert_eq!(
+        policy.evaluate(Some(&attempts), None, clock.now()),
+        ThrottleDecision::Allow
+    );
+
+    let attempts = fail(&clock, Some(attempts), 1);
+    assert_eq!(
+        policy.evaluate(Some(&attempts), None, clock.now()),
+        ThrottleDecision::Challenge
+    );
+
+    // The fifth failure waits the base backoff, counted from the failure itself
+    let attempts = fail(&clock, Some(attempts), 2);
+    clock.set(attempts.last_failure);
+    assert_eq!(
+        policy.evaluate(Some(&attempts), None, clock.now()),
+        ThrottleDecision::Throttled { retry_after: 2 }
+    );
+    clock.advance(2);
+    assert_eq!(
+        policy.evaluate(Some(&attempts), None, clock.now()),
+        ThrottleDecision::Challenge
+    );
+}
+
+#[test]
+fn backoff_doubles_up_to_the_maximum() {
+    let clock = FakeClock::new(0);
+    let policy = policy();
+    let mut attempts = fail(&clock, None, 5);
+
+    for expected in [4, 8, 16, 32, 60, 60] {
+        attempts = Attempts::record_failure(Some(attempts), clock.now(), 3600);
+        assert_eq!(
+            policy.evaluate(Some(&attempts), None, clock.now()),
+            ThrottleDecision::Throttled {
+                retry_after: expected
+            }
+        );
+        clock.advance(expected);
+    }
+}
+
+#[test]
+fn forgets_failures_a_window_after_the_last() {
+    let clock = FakeClock::new(0);
+    let policy = policy();
+    let attempts = fail(&clock, None, 8);
+
+    clock.set(attempts.last_failure + 3599);
+    assert_ne!(
+        policy.evaluate(Some(&attempts), None, clock.now()),
+        ThrottleDecision::Allow
+    );
+    clock.advance(1);
+    assert_eq!(
+        policy.evaluate(Some(&attempts), None, clock.now()),
+        ThrottleDecision::Allow
+    );
+
+    let restarted = Attempts::record_failure(Some(attempts), clock.now(), 3600);
+    assert_eq!(restarted.failures, 1);
+}
+
+#[test]
+fn source_addresses_have_their_own_looser_limits() {
+    let clock = FakeClock::new(0);
+    let policy = policy();
+
+    // Stuffing many accounts from one address: each account stays under its limit
+    let source = fail(&clock, None, 10);
+    let account = fail(&clock, None, 1);
+    assert_eq!(
+        policy.evaluate(Some(&account), Some(&source), clock.now()),
+        ThrottleDecision::Challenge
+    );
+    assert_eq!(
+        policy.evaluate(None, Some(&source), clock.now()),
+        ThrottleDecision::Challenge
+    );
+
+    let source = fail(&clock, Some(source), 10);
+    clock.set(source.last_failure);
+    assert_eq!(
+        policy.evaluate(Some(&account), Some(&source), clock.now()),
+        ThrottleDecision::Throttled { retry_after: 2 }
+    );
+}
+
+#[test]
+fn proof_of_work_binds_to_challenge_account_and_expiry() {
+    let clock = FakeClock::new(1_000);
+    // Enough bits that a solution passing for another account by chance is negligible
+    let challenge = PowChallenge::generate(16, clock.now() + 300);
+    let solution = challenge.solve("alice");
+
+    assert!(challenge.verify("alice", &solution, clock.now()));
+    assert!(!challenge.verify("bob", &solution, clock.now()));
+
+    let other = PowChallenge::generate(16, clock.now() + 300);
+    assert!(!other.verify("alice", &solution, clock.now()));
+
+    clock.advance(300);
+    assert!(!challenge.verify("alice", &solution, clock.now()));
+}
@@ -0,0 +1,142 @@
+//! Length padding and sealing of MLS application content
+
+use xipr_core::protocol::mls::{MlsClient, MlsConfig, MlsMessage};
+use xipr_core::protocol::padding::PaddingScheme;
+use xipr_core::protocol::receive::{ReceiveEvent, UndecryptableReason};
+
+#[test]
+fn block_padding_rounds_up_to_the_block() {
+    let scheme = PaddingScheme::Block(256);
+    assert_eq!(scheme.padded_length(0), 0);
+    assert_eq!(scheme.padded_length(1), 256);
+    assert_eq!(scheme.padded_length(256), 256);
+    assert_eq!(scheme.padded_length(257), 512);
+
+    // The length prefix counts towards the block
+    assert_eq!(scheme.pad(&[7; 252]).unwrap().len(), 256);
+    assert_eq!(scheme.pad(&[7; 253]).unwrap().len(), 512);
+}
+
+#[test]
+fn bucket_padding_picks_the_smallest_fit() {
+    let scheme = PaddingScheme::Buckets(vec![64, 256, 1024]);
+    assert_eq!(scheme.padded_length(1), 64);
+    assert_eq!(scheme.padded_length(64), 64);
+    assert_eq!(scheme.padded_length(65), 256);
+    assert_eq!(scheme.padded_length(1024), 1024);
+
+    // Past the last bucket, multiples of it
+    assert_eq!(scheme.padded_length(1025), 2048);
+    assert_eq!(scheme.padded_length(5000), 5120);
+}
+
+#[test]
+fn padme_overhead_stays_within_its_bound() {
+    let scheme = PaddingScheme::Padme;
+    assert_eq!(scheme.padded_length(0), 0);
+    assert_eq!(scheme.padded_length(1), 1);
+    assert_eq!(scheme.padded_length(100), 104);
+    assert_eq!(scheme.padded_length(1000), 1024);
+
+    for length in 2..100_000 {
+        let padded = scheme.padded_length(length);
+        assert!(padded >= length);
+        // Overhead is under 2^(E - S) <= length / 2^S, and S >= 3 once length >= 16
+        if length >= 16 {
+            assert!((padded - length) * 8 < length, "{} -> {}", length, padded);
+        }
+        // Never more than a doubling at small sizes
+        assert!(padded < 2 * length);
+    }
+}
+
+#[test]
+fn invalid_schemes_are_rejected() {
+    assert!(PaddingScheme::Block(0).validate().is_err());
+    assert!(PaddingScheme::Buckets(Vec::new()).validate().is_err());
+    assert!(PaddingScheme::Buckets(vec![0, 64]).validate().is_err());
+    assert!(PaddingScheme::Buckets(vec![256, 64]).validate().is_err());
+    assert!(PaddingScheme::Buckets(vec![64, 64]).validate().is_err());
+    PaddingScheme::Padme.validate().unwrap();
+}
+
+#[test]
+fn padding_round_trips() {
+    for scheme in [
+        PaddingScheme::Block(16),
+        PaddingScheme::Buckets(vec![32, 128]),
+        PaddingScheme::Padme,
+    ] {
+        for length in [0, 1, 27, 28, 29, 500] {
+            let content = vec![0xab; length];
+            let padded = scheme.pad(&content).unwrap();
+            assert_eq!(padded.len(), scheme.padded_length(4 + length));
+            assert_eq!(PaddingScheme::unpad(&padded).unwrap(), content);
+        }
+    }
+}
+
+#[test]
+fn malformed_padding_is_rejected() {
+    let padded = PaddingScheme::Block(32).pad(b"hello").unwrap();
+
+    // Too short for the length prefix
+    assert!(PaddingScheme::unpad(&[]).is_err());
+    assert!(PaddingScheme::unpad(&padded[..3]).is_err());
+
+    // A length running past the end
+    let mut overlong = padded.clone();
+    overlong[..4].copy_from_slice(&33u32.to_be_bytes());
+    assert!(PaddingScheme::unpad(&overlong).is_err());
+
+    // Non-zero bytes after the content
+    let mut dirty = padded.clone();
+    *dirty.last_mut().unwrap() = 1;
+    assert!(PaddingScheme::unpad(&dirty).is_err());
+
+    // Shortening the prefix would turn content into padding
+    let mut shortened = padded;
+    shortened[..4].copy_from_slice(&4u32.to_be_bytes());
+    assert!(PaddingScheme::unpad(&shortened).is_err());
+}
+
+#[test]
+fn application_content_is_sealed_and_authenticated() {
+    let config = MlsConfig::new(b"group".to_vec());
+    let mut alice = MlsClient::new("alice".to_string()).unwrap();
+    let mut bob = MlsClient::new("bob".to_string()).unwrap();
+    let mut alice_group = alice.create_group(config.clone()).unwrap();
+    alice_group.add_member(&bob).unwrap();
+    let mut bob_group = bob
+        .join_group(config, alice_group.welcome().unwrap())
+        .unwrap();
+
+    let sent = alice
+        .send_message(&mut alice_group, b"meet at noon")
+        .unwrap();
+    let message: MlsMessage = serde_json::from_slice(&sent).unwrap();
+    // Nonce, padded block and tag; the plaintext never appears
+    assert_eq!(message.ciphertext.len(), 12 + 256 + 16);
+    assert!(!message
+        .ciphertext
+        .windows(b"meet at noon".len())
+        .any(|window| window == b"meet at noon"));
+
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert!(
+        matches!(&events[..], [ReceiveEvent::Application(application)] if application.content == b"meet at noon")
+    );
+
+    let mut tampered = message;
+    tampered.ciphertext[20] ^= 1;
+    let events = bob
+        .receive_message(&mut bob_group, serde_json::to_vec(&tampered).unwrap())
+        .unwrap();
+    assert!(matches!(
+        &events[..],
+        [ReceiveEvent::Undecryptable {
+            reason: UndecryptableReason::DecryptionFailed(_),
+            ..
+        }]
+    ));
+}
@@ -0,0 +1,242 @@
+//! WebAuthn ceremonies against the software authenticator
+
+use xipr_core::protocol::webauthn::{
+    AttestationFormat, AttestationType, RegistrationResponse, RelyingParty, SoftwareAuthenticator,
+    COSE_ALG_EDDSA, COSE_ALG_ES256,
+};
+
+const CHALLENGE: &[u8] = &[9; 32];
+
+fn rp() -> RelyingParty {
+    RelyingParty::new("xipr.example", "https://xipr.example")
+}
+
+#[test]
+fn registers_and_asserts_with_either_algorithm() {
+    for (algorithm, format, attestation) in [
+        (
+            COSE_ALG_ES256,
+            AttestationFormat::None,
+            AttestationType::None,
+        ),
+        (
+            COSE_ALG_EDDSA,
+            AttestationFormat::None,
+            AttestationType::None,
+        ),
+        (
+            COSE_ALG_ES256,
+            AttestationFormat::Packed,
+            AttestationType::SelfAttestation,
+        ),
+        (
+            COSE_ALG_EDDSA,
+            AttestationFormat::Packed,
+            AttestationType::SelfAttestation,
+        ),
+    ] {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let registration = authenticator
+            .make_credential(&rp(), CHALLENGE, b"alice", algorithm, format)
+            .unwrap();
+        let credential = rp()
+            .verify_registration(&registration, CHALLENGE, true)
+            .unwrap();
+        assert_eq!(credential.public_key.algorithm(), algorithm);
+        assert_eq!(credential.attestation, attestation);
+        assert_eq!(credential.sign_count, 0);
+        assert!(credential.user_verified);
+
+        let challenge = [4; 32];
+        let assertion = authenticator
+            .get_assertion(
+                &rp(),
+                &challenge,
+                std::slice::from_ref(&credential.credential_id),
+            )
+            .unwrap();
+        assert_eq!(assertion.credential_id, credential.credential_id);
+        assert_eq!(assertion.user_handle.as_deref(), Some(b"alice".as_slice()));
+        let count = rp()
+            .verify_assertion(&assertion, &challenge, &credential.public_key, 0, true)
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
+
+#[test]
+fn verifies_packed_attestation_certificates() {
+    let key = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+    let certificate = rcgen::CertificateParams::new(vec!["attestation.example".to_string()])
+        .unwrap()
+        .self_signed(&key)
+        .unwrap();
+
+    let mut authenticator = SoftwareAuthenticator::new()
+        .with_attestation_certificate(certificate.der().to_vec(), &key.serialize_der())
+        .unwrap();
+    let registration = authenticator
+        .make_credential(
+            &rp(),
+            CHALLENGE,
+            b"alice",
+            COSE_ALG_EDDSA,
+            AttestationFormat::Packed,
+        )
+        .unwrap();
+
+    let credential = rp()
+        .verify_registration(&registration, CHALLENGE, true)
+        .unwrap();
+    assert_eq!(credential.attestation, AttestationType::Basic);
+    assert_eq!(
+        credential.attestation_certificate.as_deref(),
+        Some(certificate.der().as_ref())
+    );
+
+    // A certificate for another key does not vouch for the registration
+    let other = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+    let mut authenticator = SoftwareAuthenticator::new()
+        .with_attestation_certificate(certificate.der().to_vec(), &other.serialize_der())
+        .unwrap();
+    let registration = authenticator
+        .make_credential(
+            &rp(),
+            CHALLENGE,
+            b"alice",
+            COSE_ALG_ES256,
+            AttestationFormat::Packed,
+        )
+        .unwrap();
+    assert!(rp()
+        .verify_registration(&registration, CHALLENGE, true)
+        .is_err());
+}
+
+#[test]
+fn binds_ceremonies_to_challenge_origin_and_rp_id() {
+    let mut authenticator = SoftwareAuthenticator::new();
+    let registration = authenticator
+        .make_credential(
+            &rp(),
+            CHALLENGE,
+            b"alice",
+            COSE_ALG_ES256,
+            AttestationFormat::None,
+        )
+        .unwrap();
+
+    assert!(rp()
+        .verify_registration(&registration, &[8; 32], true)
+        .is_err());
+    assert!(RelyingParty::new("xipr.example", "https://evil.example")
+        .verify_registration(&registration, CHALLENGE, true)
+        .is_err());
+    assert!(RelyingParty::new("evil.example", "https://xipr.example")
+        .verify_registration(&registration, CHALLENGE, true)
+        .is_err());
+
+    let credential = rp()
+        .verify_registration(&registration, CHALLENGE, true)
+        .unwrap();
+    let assertion = authenticator.get_assertion(&rp(), CHALLENGE, &[]).unwrap();
+
+    // Registration and assertion client data are not interchangeable
+    assert!(rp()
+        .verify_registration(
+            &RegistrationResponse {
+                client_data_json: assertion.client_data_json.clone(),
+                attestation_object: registration.attestation_object.clone(),
+            },
+            CHALLENGE,
+            true
+        )
+        .is_err());
+    assert!(rp()
+        .verify_assertion(&assertion, &[8; 32], &credential.public_key, 0, true)
+        .is_err());
+
+    let mut tampered = assertion.clone();
+    tampered.authenticator_data[32] |= 0x02;
+    assert!(rp()
+        .verify_assertion(&tampered, CHALLENGE, &credential.public_key, 0, true)
+        .is_err());
+}
+
+#[test]
+fn requires_user_verification_when_asked() {
+    let mut authenticator = SoftwareAuthenticator::new().without_user_verification();
+    let registration = authenticator
+        .make_credential(
+            &rp(),
+            CHALLENGE,
+            b"alice",
+            COSE_ALG_ES256,
+            AttestationFormat::None,
+        )
+        .unwrap();
+
+    assert!(rp()
+        .verify_registration(&registration, CHALLENGE, true)
+        .is_err());
+    let credential = rp()
+        .verify_registration(&registration, CHALLENGE, false)
+        .unwrap();
+    assert!(!credential.user_verified);
+
+    let assertion = authenticator.get_assertion(&rp(), CHALLENGE, &[]).unwrap();
+    assert!(rp()
+        .verify_assertion(&assertion, CHALLENGE, &credential.public_key, 0, true)
+        .is_err());
+}
+
+#[test]
+fn refuses_counters_that_do_not_increase() {
+    let mut authenticator = SoftwareAuthenticator::new();
+    let registration = authenticator
+        .make_credential(
+            &rp(),
+            CHALLENGE,
+            b"alice",
+            COSE_ALG_EDDSA,
+            AttestationFormat::None,
+        )
+        .unwrap();
+    let credential = rp()
+        .verify_registration(&registration, CHALLENGE, true)
+        .unwrap();
+
+    let mut clone = authenticator.clone();
+    let first = authenticator.get_assertion(&rp(), CHALLENGE, &[]).unwrap();
+    let count = rp()
+        .verify_assertion(&first, CHALLENGE, &credential.public_key, 0, true)
+        .unwrap();
+
+    let cloned = clone.get_assertion(&rp(), CHALLENGE, &[]).unwrap();
+    assert!(rp()
+        .verify_assertion(&cloned, CHALLENGE, &credential.public_key, count, true)
+        .is_err());
+    assert!(rp()
+        .verify_assertion(&first, CHALLENGE, &credential.public_key, count, true)
+        .is_err());
+}
+
+#[test]
+fn holds_no_credential_for_other_relying_parties() {
+    let mut authenticator = SoftwareAuthenticator::new();
+    authenticator
+        .make_credential(
+            &rp(),
+            CHALLENGE,
+            b"alice",
+            COSE_ALG_ES256,
+            AttestationFormat::None,
+        )
+        .unwrap();
+
+    let other = RelyingParty::new("other.example", "https://other.example");
+    assert!(authenticator.get_assertion(&other, CHALLENGE, &[]).is_err());
+    assert!(authenticator
+        .get_assertion(&rp(), CHALLENGE, &[vec![1, 2, 3]])
+        .is_err());
+}
@@ -0,0 +1,198 @@
+//! QUIC transport over an in-process loopback
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use xipr_core::protocol::quic::{
+    CertificateDer, ConversationStream, PrivateKeyDer, QuicClient, QuicLink, QuicServer,
+};
+use xipr_core::protocol::transport::{Message, MessageFrame, MessageType};
+
+const SERVER_NAME: &str = "localhost";
+
+/// A server and a client on loopback, with the client trusting the server's certificate
+struct Loopback {
+    server: QuicServer,
+    client: QuicClient,
+    server_addr: SocketAddr,
+}
+
+impl Loopback {
+    fn new() -> Self {
+        let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()]).unwrap();
+        let cert: CertificateDer<'static> = certified.cert.der().clone();
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+
+        let server = QuicServer::bind(localhost(), vec![cert.clone()], key).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let client = QuicClient::bind(localhost(), vec![cert]).unwrap();
+
+        Self {
+            server,
+            client,
+            server_addr,
+        }
+    }
+
+    /// Connect, returning the client's and the server's end of the link
+    async fn connect(&self) -> (QuicLink, QuicLink) {
+        let (client, server) = tokio::join!(
+            self.client.connect(self.server_addr, SERVER_NAME),
+            self.server.accept()
+        );
+        (client.unwrap(), server.unwrap().unwrap())
+    }
+}
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+fn frame(recipient: &str, message_type: MessageType, content: &[u8]) -> MessageFrame {
+    Message::new(
+        "alice".to_string(),
+        recipient.to_string(),
+        message_type,
+        content.to_vec(),
+    )
+    .to_frame()
+    .unwrap()
+}
+
+async fn recv(stream: &mut ConversationStream) -> MessageFrame {
+    tokio::time::timeout(Duration::from_secs(5), stream.recv())
+        .await
+        .expect("timed out waiting for a frame")
+        .unwrap()
+        .expect("stream finished")
+}
+
+#[tokio::test]
+async fn carries_frames_both_ways_on_a_conversation_stream() {
+    let loopback = Loopback::new();
+    let (client, server) = loopback.connect().await;
+
+    let sent = frame("bob", MessageType::Text, b"hello");
+    let mut outgoing = client.open_conversation("bob").await.unwrap();
+    outgoing.send(&sent).await.unwrap();
+
+    let mut incoming = server.accept_conversation().await.unwrap();
+    assert_eq!(incoming.conversation_id(), "bob");
+    assert_eq!(recv(&mut incoming).await, sent);
+
+    let reply = frame("alice", MessageType::Text, b"hi");
+    incoming.send(&reply).await.unwrap();
+    assert_eq!(recv(&mut outgoing).await, reply);
+
+    outgoing.finish().unwrap();
+    assert_eq!(incoming.recv().await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn conversations_do_not_block_each_other() {
+    let loopback = Loopback::new();
+    let (client, server) = loopback.connect().await;
+
+    let mut stalled = client.open_conversation("bob").await.unwrap();
+    let mut flowing = client.open_conversation("carol").await.unwrap();
+    stalled
+        .send(&frame("bob", MessageType::Text, &[1; 4096]))
+        .await
+        .unwrap();
+
+    let mut streams = vec![
+        server.accept_conversation().await.unwrap(),
+        server.accept_conversation().await.unwrap(),
+    ];
+    let index = streams
+        .iter()
+        .position(|stream| stream.conversation_id() == "carol")
+        .unwrap();
+    let mut carol = streams.swap_remove(index);
+
+    // Nobody reads bob's stream, yet carol's frames keep arriving in order
+    for n in 0..20u8 {
+        let sent = frame("carol", MessageType::Text, &[n]);
+        flowing.send(&sent).await.unwrap();
+        assert_eq!(recv(&mut carol).await, sent);
+    }
+}
+
+#[tokio::test]
+async fn survives_client_migration() {
+    let loopback = Loopback::new();
+    let (client, server) = loopback.connect().await;
+
+    let mut outgoing = client.open_conversation("bob").await.unwrap();
+    let before = frame("bob", MessageType::Text, b"before");
+    outgoing.send(&before).await.unwrap();
+    let mut incoming = server.accept_conversation().await.unwrap();
+    assert_eq!(recv(&mut incoming).await, before);
+
+    loopback.client.rebind(localhost()).unwrap();
+    let new_addr = loopback.client.local_addr().unwrap();
+
+    let after = frame("bob", MessageType::Text, b"after");
+    outgoing.send(&after).await.unwrap();
+    assert_eq!(recv(&mut incoming).await, after);
+    assert_eq!(server.remote_address(), new_addr);
+
+    let reply = frame("alice", MessageType::Text, b"reply");
+    incoming.send(&reply).await.unwrap();
+    assert_eq!(recv(&mut outgoing).await, reply);
+}
+
+#[tokio::test]
+async fn resumes_with_0rtt_and_holds_back_non_idempotent_frames() {
+    let loopback = Loopback::new();
+
+    // A full round trip leaves the client with a session ticket
+    let (client, server) = loopback.connect().await;
+    assert!(!client.is_early());
+    let mut outgoing = client.open_conversation("bob").await.unwrap();
+    outgoing
+        .send(&frame("bob", MessageType::Heartbeat, b""))
+        .await
+        .unwrap();
+    let mut incoming = server.accept_conversation().await.unwrap();
+    recv(&mut incoming).await;
+    incoming
+        .send(&frame("alice", MessageType::Heartbeat, b""))
+        .await
+        .unwrap();
+    recv(&mut outgoing).await;
+    client.close("done");
+
+    let (client, server) = loopback.connect().await;
+    assert!(client.is_early());
+
+    let heartbeat = frame("bob", MessageType::Heartbeat, b"");
+    let text = frame("bob", MessageType::Text, b"not idempotent");
+    let mut outgoing = client.open_conversation("bob").await.unwrap();
+    outgoing.send(&heartbeat).await.unwrap();
+    // Waits for the handshake rather than going out as early data
+    outgoing.send(&text).await.unwrap();
+    assert!(client.is_confirmed());
+
+    let mut incoming = server.accept_conversation().await.unwrap();
+    assert_eq!(recv(&mut incoming).await, heartbeat);
+    assert_eq!(recv(&mut incoming).await, text);
+    assert!(server.is_confirmed());
+}
+
+#[tokio::test]
+async fn rejects_invalid_conversation_ids() {
+    let loopback = Loopback::new();
+    let (client, _server) = loopback.connect().await;
+
+    assert!(client.open_conversation("").await.is_err());
+    assert!(client.open_conversation(&"x".repeat(129)).await.is_err());
+}
+
+#[test]
+fn only_control_and_heartbeats_are_idempotent() {
+    assert!(MessageType::Heartbeat.is_idempotent());
+    assert!(MessageType::Control.is_idempotent());
+    assert!(!MessageType::Text.is_idempotent());
+    assert!(!MessageType::Binary.is_idempotent());
+    assert!(!MessageType::Sealed.is_idempotent());
+}
@@ -0,0 +1,175 @@
+//! Out-of-order delivery, duplicates and sender checks in the receive pipeline
+
+use xipr_core::protocol::mls::{MlsClient, MlsConfig, MlsGroup};
+use xipr_core::protocol::receive::{ReceiveEvent, UndecryptableReason};
+
+fn config() -> MlsConfig {
+    MlsConfig::new(b"group".to_vec())
+}
+
+/// Alice's group at epoch 2 with bob and carol joined
+fn group() -> (
+    MlsClient,
+    MlsGroup,
+    MlsClient,
+    MlsGroup,
+    MlsClient,
+    MlsGroup,
+) {
+    let mut alice = MlsClient::new("alice".to_string()).unwrap();
+    let mut bob = MlsClient::new("bob".to_string()).unwrap();
+    let mut carol = MlsClient::new("carol".to_string()).unwrap();
+
+    let mut alice_group = alice.create_group(config()).unwrap();
+    alice_group.add_member(&bob).unwrap();
+    let mut bob_group = bob
+        .join_group(config(), alice_group.welcome().unwrap())
+        .unwrap();
+    let commit = alice_group.add_member(&carol).unwrap();
+    bob_group.receive_commit(&commit).unwrap();
+    let carol_group = carol
+        .join_group(config(), alice_group.welcome().unwrap())
+        .unwrap();
+
+    (alice, alice_group, bob, bob_group, carol, carol_group)
+}
+
+fn content(events: &[ReceiveEvent]) -> Vec<&[u8]> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            ReceiveEvent::Application(application) => Some(application.content.as_slice()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn rejected_sender(events: &[ReceiveEvent]) -> bool {
+    matches!(
+        events,
+        [ReceiveEvent::Undecryptable {
+            reason: UndecryptableReason::DecryptionFailed(reason),
+            ..
+        }] if reason.contains("not a member")
+    )
+}
+
+#[test]
+fn removed_members_messages_from_before_the_removal_are_accepted() {
+    let (_, mut alice_group, mut bob, mut bob_group, mut carol, mut carol_group) = group();
+    let sent = carol.send_message(&mut carol_group, b"bye").unwrap();
+
+    // Bob learns of the removal before carol's last message reaches him
+    let commit = alice_group.remove_member(&carol.identity).unwrap();
+    bob_group.receive_commit(&commit).unwrap();
+    assert!(!bob_group.roster.contains(&carol.identity));
+
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert_eq!(content(&events), [b"bye".as_slice()]);
+}
+
+#[test]
+fn members_added_later_cannot_send_in_earlier_epochs() {
+    let (_, mut alice_group, mut bob, mut bob_group, _, _) = group();
+    let mut dave = MlsClient::new("dave".to_string()).unwrap();
+    let mut before_dave = bob_group.clone();
+
+    let commit = alice_group.add_member(&dave).unwrap();
+    bob_group.receive_commit(&commit).unwrap();
+    let mut dave_group = dave
+        .join_group(config(), alice_group.welcome().unwrap())
+        .unwrap();
+
+    // Dave claims a message in the epoch before he joined, which bob still retains
+    let backdated = dave.send_message(&mut before_dave, b"I was here").unwrap();
+    let events = bob.receive_message(&mut bob_group, backdated).unwrap();
+    assert!(rejected_sender(&events));
+
+    let sent = dave.send_message(&mut dave_group, b"hello").unwrap();
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert_eq!(content(&events), [b"hello".as_slice()]);
+}
+
+#[test]
+fn outsiders_are_rejected() {
+    let (_, _, mut bob, mut bob_group, _, _) = group();
+    let mut mallory = MlsClient::new("mallory".to_string()).unwrap();
+    let mut stolen = bob_group.clone();
+
+    let sent = mallory.send_message(&mut stolen, b"hi").unwrap();
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert!(rejected_sender(&events));
+}
+
+#[test]
+fn messages_ahead_of_their_commit_wait_for_it() {
+    let (_, mut alice_group, mut bob, mut bob_group, mut carol, mut carol_group) = group();
+
+    let first = alice_group.rename("one".to_string()).unwrap();
+    carol_group.receive_commit(&first).unwrap();
+    let second = alice_group.rename("two".to_string()).unwrap();
+    carol_group.receive_commit(&second).unwrap();
+    let sent = carol.send_message(&mut carol_group, b"after both").unwrap();
+
+    // Bob gets everything in reverse
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert!(matches!(&events[..], [ReceiveEvent::Buffered { epoch: 4 }]));
+    let events = bob_group.receive_commit(&second).unwrap();
+    assert!(matches!(&events[..], [ReceiveEvent::Buffered { epoch: 3 }]));
+
+    let events = bob_group.receive_commit(&first).unwrap();
+    assert!(matches!(
+        &events[..],
+        [
+            ReceiveEvent::CommitApplied { epoch: 3 },
+            ReceiveEvent::CommitApplied { epoch: 4 },
+            ReceiveEvent::Application(_),
+        ]
+    ));
+    assert_eq!(content(&events), [b"after both".as_slice()]);
+    assert_eq!(bob_group.extensions.name, "two");
+    assert!(bob_group.receive_buffer.is_empty());
+}
+
+#[test]
+fn repeated_messages_and_commits_are_duplicates() {
+    let (_, mut alice_group, mut bob, mut bob_group, mut carol, mut carol_group) = group();
+
+    let sent = carol.send_message(&mut carol_group, b"once").unwrap();
+    let events = bob.receive_message(&mut bob_group, sent.clone()).unwrap();
+    assert_eq!(content(&events), [b"once".as_slice()]);
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert!(matches!(&events[..], [ReceiveEvent::Duplicate]));
+
+    // A second message from the same sender is new
+    let sent = carol.send_message(&mut carol_group, b"twice").unwrap();
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert_eq!(content(&events), [b"twice".as_slice()]);
+
+    let commit = alice_group.rename("renamed".to_string()).unwrap();
+    bob_group.receive_commit(&commit).unwrap();
+    let events = bob_group.receive_commit(&commit).unwrap();
+    assert!(matches!(&events[..], [ReceiveEvent::Duplicate]));
+}
+
+#[test]
+fn messages_for_expired_epochs_are_undecryptable() {
+    let (_, mut alice_group, mut bob, mut bob_group, mut carol, mut carol_group) = group();
+    let sent = carol.send_message(&mut carol_group, b"late").unwrap();
+
+    for round in 0..=bob_group.config.policy.max_past_epochs {
+        let commit = alice_group.rename(format!("round {}", round)).unwrap();
+        bob_group.receive_commit(&commit).unwrap();
+    }
+
+    let events = bob.receive_message(&mut bob_group, sent).unwrap();
+    assert!(matches!(
+        &events[..],
+        [ReceiveEvent::Undecryptable {
+            epoch: 2,
+            reason: UndecryptableReason::EpochExpired,
+            ..
+        }]
+    ));
+    assert!(bob_group.roster_at(2).is_none());
+}
@@ -0,0 +1,143 @@
+//! Replay and clock-skew checks in `MessageRouter`
+
+use xipr_core::protocol::replay::{MessageRejection, RouterConfig, REPLAY_WINDOW_SIZE};
+use xipr_core::protocol::transport::{Message, MessageRouter, MessageType};
+
+const NOW: i64 = 1_700_000_000;
+
+fn message(id: &str, sequence: u64) -> Message {
+    let mut message = Message::new(
+        "alice".to_string(),
+        "bob".to_string(),
+        MessageType::Text,
+        b"hi".to_vec(),
+    );
+    message.id = id.to_string();
+    message.timestamp = NOW;
+    message.sequence_number = sequence;
+    message
+}
+
+#[test]
+fn rejects_replayed_message_id() {
+    let mut router = MessageRouter::default();
+    let original = message("m1", 0);
+
+    assert_eq!(router.accept_message("alice-phone", &original, NOW), Ok(()));
+    assert_eq!(
+        router.accept_message("alice-phone", &original, NOW),
+        Err(MessageRejection::Replayed {
+            message_id: "m1".to_string()
+        })
+    );
+
+    // Windows are per sender device
+    assert_eq!(
+        router.accept_message("alice-laptop", &original, NOW),
+        Ok(())
+    );
+}
+
+#[test]
+fn sliding_window_allows_reordering_but_not_replays() {
+    let mut router = MessageRouter::default();
+
+    assert!(router.accept_message("d", &message("a", 10), NOW).is_ok());
+    assert!(router.accept_message("d", &message("b", 12), NOW).is_ok());
+    assert!(router.accept_message("d", &message("c", 11), NOW).is_ok());
+
+    assert_eq!(
+        router.accept_message("d", &message("d", 11), NOW),
+        Err(MessageRejection::SequenceReplayed { sequence: 11 })
+    );
+
+    let highest = 12 + REPLAY_WINDOW_SIZE;
+    assert!(router
+        .accept_message("d", &message("e", highest), NOW)
+        .is_ok());
+    assert_eq!(
+        router.accept_message("d", &message("f", 12), NOW),
+        Err(MessageRejection::SequenceTooOld {
+            sequence: 12,
+            highest
+        })
+    );
+    assert!(router.accept_message("d", &message("g", 13), NOW).is_ok());
+}
+
+#[test]
+fn enforces_clock_skew() {
+    let config = RouterConfig::default();
+    let router = MessageRouter::new(config.clone());
+
+    let mut old = message("m1", 0);
+    old.timestamp = NOW - config.max_past_skew - 1;
+    assert!(matches!(
+        router.validate_message(&old, NOW),
+        Err(MessageRejection::TimestampTooOld { .. })
+    ));
+
+    let mut future = message("m2", 0);
+    future.timestamp = NOW + config.max_future_skew + 1;
+    assert!(matches!(
+        router.validate_message(&future, NOW),
+        Err(MessageRejection::TimestampInFuture { .. })
+    ));
+
+    let mut skewed = message("m3", 0);
+    skewed.timestamp = NOW + config.max_future_skew;
+    assert_eq!(router.validate_message(&skewed, NOW), Ok(()));
+}
+
+#[test]
+fn rejects_missing_fields() {
+    let router = MessageRouter::default();
+    let mut anonymous = message("m1", 0);
+    anonymous.sender_id.clear();
+
+    assert_eq!(
+        router.validate_message(&anonymous, NOW),
+        Err(MessageRejection::MissingField("sender_id".to_string()))
+    );
+}
+
+#[test]
+fn rejected_messages_are_not_recorded() {
+    let mut router = MessageRouter::default();
+    let mut future = message("m1", 0);
+    future.timestamp = NOW + 3600;
+
+    assert!(router.accept_message("d", &future, NOW).is_err());
+    assert!(router.accept_message("d", &future, NOW + 3600).is_ok());
+}
+
+#[test]
+fn ids_evicted_for_space_cannot_be_replayed() {
+    let config = RouterConfig {
+        max_tracked_ids: 2,
+        ..RouterConfig::default()
+    };
+    let mut router = MessageRouter::new(config);
+
+    let mut first = message("m1", 0);
+    first.timestamp = NOW - 2;
+    let mut second = message("m2", 0);
+    second.timestamp = NOW - 1;
+    assert!(router.accept_message("d", &first, NOW).is_ok());
+    assert!(router.accept_message("d", &second, NOW).is_ok());
+    assert!(router.accept_message("d", &message("m3", 0), NOW).is_ok());
+
+    // m1 no longer fits in the window, so anything as old as it is refused
+    assert_eq!(
+        router.accept_message("d", &first, NOW),
+        Err(MessageRejection::BeyondHistory {
+            timestamp: NOW - 2,
+            evicted: NOW - 2,
+        })
+    );
+    assert!(matches!(
+        router.accept_message("d", &second, NOW),
+        Err(MessageRejection::Replayed { .. })
+    ));
+    assert!(router.accept_message("d", &message("m4", 0), NOW).is_ok());
+}
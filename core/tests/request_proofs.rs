@@ -0,0 +1,115 @@
+//! Proof of possession for device-bound sessions
+
+use xipr_core::protocol::proof::{
+    ProofTarget, RequestProof, SigningKey, MAX_PROOF_SKEW, PROOF_VERSION,
+};
+
+const NOW: i64 = 1_700_000_000;
+const TOKEN: &str = "v1.1.claims.tag";
+
+fn target<'a>(method: &'a str, path: &'a str, body: &'a [u8]) -> ProofTarget<'a> {
+    ProofTarget {
+        method,
+        path,
+        query: "",
+        body,
+        token: TOKEN,
+    }
+}
+
+#[test]
+fn verifies_under_the_device_key_after_a_round_trip() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let request = target("POST", "/api/v1/messages", b"{\"recipient_id\":\"bob\"}");
+
+    let proof = RequestProof::sign(&key, &request, NOW);
+    let encoded = proof.to_string();
+    assert!(encoded.starts_with(&format!("{}.{}.", PROOF_VERSION, NOW)));
+
+    let parsed: RequestProof = encoded.parse().unwrap();
+    assert_eq!(parsed, proof);
+    assert!(parsed
+        .verify(&key.verifying_key(), &request, NOW + 5)
+        .is_ok());
+}
+
+#[test]
+fn is_bound_to_the_request_token_and_key() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let body = b"{\"recipient_id\":\"bob\"}";
+    let proof = RequestProof::sign(&key, &target("POST", "/api/v1/messages", body), NOW);
+    let verifying_key = key.verifying_key();
+
+    for other in [
+        target("GET", "/api/v1/messages", body),
+        target("POST", "/api/v1/sync", body),
+        target("POST", "/api/v1/messages", b"{\"recipient_id\":\"eve\"}"),
+        ProofTarget {
+            query: "limit=1000",
+            ..target("POST", "/api/v1/messages", body)
+        },
+        ProofTarget {
+            token: "v1.1.other.tag",
+            ..target("POST", "/api/v1/messages", body)
+        },
+    ] {
+        assert!(proof.verify(&verifying_key, &other, NOW).is_err());
+    }
+
+    let thief = SigningKey::from_bytes(&[8; 32]).verifying_key();
+    assert!(proof
+        .verify(&thief, &target("POST", "/api/v1/messages", body), NOW)
+        .is_err());
+}
+
+#[test]
+fn expires_outside_the_skew_window() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let request = target("GET", "/api/v1/ws", b"");
+    let proof = RequestProof::sign(&key, &request, NOW);
+    let verifying_key = key.verifying_key();
+
+    assert!(proof
+        .verify(&verifying_key, &request, NOW + MAX_PROOF_SKEW)
+        .is_ok());
+    assert!(proof
+        .verify(&verifying_key, &request, NOW - MAX_PROOF_SKEW)
+        .is_ok());
+    assert!(proof
+        .verify(&verifying_key, &request, NOW + MAX_PROOF_SKEW + 1)
+        .is_err());
+    assert!(proof
+        .verify(&verifying_key, &request, NOW - MAX_PROOF_SKEW - 1)
+        .is_err());
+}
+
+#[test]
+fn every_proof_has_a_fresh_nonce() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let link = ProofTarget::link(TOKEN);
+
+    let first = RequestProof::sign(&key, &link, NOW);
+    let second = RequestProof::sign(&key, &link, NOW);
+    assert_ne!(first.nonce(), second.nonce());
+    assert!(second.verify(&key.verifying_key(), &link, NOW).is_ok());
+}
+
+#[test]
+fn rejects_malformed_proofs() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let encoded = RequestProof::sign(&key, &ProofTarget::link(TOKEN), NOW).to_string();
+
+    assert!(encoded
+        .replacen("v1", "v2", 1)
+        .parse::<RequestProof>()
+        .is_err());
+    assert!(format!("{}.extra", encoded)
+        .parse::<RequestProof>()
+        .is_err());
+    assert!("v1.now.AAAA.AAAA".parse::<RequestProof>().is_err());
+    assert!("not a proof".parse::<RequestProof>().is_err());
+
+    let mut parts: Vec<&str> = encoded.split('.').collect();
+    parts[3] = "c2hvcnQ";
+    assert!(parts.join(".").parse::<RequestProof>().is_err());
+}
@@ -0,0 +1,75 @@
+//! Roles, permissions and organisation membership
+
+use xipr_core::protocol::auth::{Permission, Role, User};
+
+#[test]
+fn roles_grant_only_their_permissions() {
+    let grants = |role: Role| {
+        [
+            Permission::SendMessages,
+            Permission::ViewMembers,
+            Permission::ManageMembers,
+            Permission::ManageOrganisations,
+        ]
+        .into_iter()
+        .filter(|permission| role.grants(*permission))
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(grants(Role::Member), [Permission::SendMessages]);
+    assert_eq!(
+        grants(Role::OrgAdmin),
+        [Permission::ViewMembers, Permission::ManageMembers]
+    );
+    assert_eq!(grants(Role::Auditor), [Permission::ViewMembers]);
+    assert_eq!(grants(Role::Operator), [Permission::ManageOrganisations]);
+}
+
+#[test]
+fn users_hold_the_union_of_their_roles() {
+    let user = User::new("alice".to_string(), "alice@example.com".to_string());
+    assert_eq!(user.roles, [Role::Member]);
+    assert!(user.has_permission(Permission::SendMessages));
+    assert!(!user.has_permission(Permission::ViewMembers));
+
+    let admin = user.with_roles(vec![Role::Member, Role::OrgAdmin]);
+    assert!(admin.has_permission(Permission::SendMessages));
+    assert!(admin.has_permission(Permission::ManageMembers));
+    assert!(!admin.has_permission(Permission::ManageOrganisations));
+
+    assert!(!User::new("bob".to_string(), String::new())
+        .with_roles(Vec::new())
+        .has_permission(Permission::SendMessages));
+}
+
+#[test]
+fn only_users_of_one_organisation_share_it() {
+    let alice = User::new("alice".to_string(), String::new()).in_organisation("acme".to_string());
+    let bob = User::new("bob".to_string(), String::new()).in_organisation("acme".to_string());
+    let mallory =
+        User::new("mallory".to_string(), String::new()).in_organisation("evil".to_string());
+    let operator = User::new("root".to_string(), String::new());
+
+    assert!(alice.shares_organisation(&bob));
+    assert!(!alice.shares_organisation(&mallory));
+    assert!(!alice.shares_organisation(&operator));
+    assert!(!operator.shares_organisation(&operator));
+}
+
+#[test]
+fn users_serialize_roles_in_snake_case() {
+    let user = User::new("alice".to_string(), String::new())
+        .in_organisation("acme".to_string())
+        .with_roles(vec![Role::OrgAdmin, Role::Auditor]);
+    let json = serde_json::to_value(&user).unwrap();
+    assert_eq!(json["org_id"], "acme");
+    assert_eq!(json["roles"], serde_json::json!(["org_admin", "auditor"]));
+
+    // Users stored before organisations existed have neither
+    let legacy: User = serde_json::from_str(
+        r#"{"id":"1","username":"carol","email":"","created_at":0,"last_seen":0}"#,
+    )
+    .unwrap();
+    assert_eq!(legacy.org_id, None);
+    assert!(legacy.roles.is_empty());
+}
@@ -0,0 +1,177 @@
+//! Group roster, member roles and admin-only group changes
+
+use xipr_core::protocol::mls::{Commit, MlsClient, MlsConfig, MlsGroup};
+use xipr_core::protocol::roster::{Credential, GroupRole, Roster};
+use xipr_core::storage::{Conversation, MessageStore};
+
+fn credential(user_id: &str, device_id: &str) -> Credential {
+    Credential {
+        user_id: user_id.to_string(),
+        device_id: device_id.to_string(),
+        signature_key: vec![0; 32],
+    }
+}
+
+fn client(user_id: &str, device: &str) -> MlsClient {
+    MlsClient::for_device(user_id.to_string(), format!("{}-{}", user_id, device)).unwrap()
+}
+
+/// Alice's group with bob as a plain member, and bob's view of it
+fn group() -> (MlsClient, MlsGroup, MlsClient, MlsGroup) {
+    let config = MlsConfig::new(b"group".to_vec());
+    let mut alice = client("alice", "phone");
+    let mut bob = client("bob", "phone");
+    let mut alice_group = alice.create_group(config.clone()).unwrap();
+    alice_group.add_member(&bob).unwrap();
+    let bob_group = bob
+        .join_group(config, alice_group.welcome().unwrap())
+        .unwrap();
+    (alice, alice_group, bob, bob_group)
+}
+
+#[test]
+fn members_fill_the_leftmost_free_leaf() {
+    let mut roster = Roster::new();
+    assert_eq!(roster.add(credential("alice", "alice-phone"), 0), 0);
+    assert_eq!(roster.add(credential("alice", "alice-laptop"), 1), 1);
+    assert_eq!(roster.add(credential("bob", "bob-phone"), 1), 2);
+
+    roster.remove(b"alice-laptop").unwrap();
+    assert!(roster.remove(b"alice-laptop").is_none());
+    assert_eq!(roster.add(credential("carol", "carol-phone"), 2), 1);
+
+    let leaves: Vec<u32> = roster.members().iter().map(|m| m.leaf_index).collect();
+    assert_eq!(leaves, [0, 1, 2]);
+    assert_eq!(roster.get(b"carol-phone").unwrap().join_epoch, 2);
+}
+
+#[test]
+fn users_are_grouped_across_their_devices() {
+    let mut roster = Roster::new();
+    roster.add(credential("bob", "bob-phone"), 0);
+    roster.add(credential("alice", "alice-phone"), 0);
+    roster.add(credential("alice", "alice-laptop"), 0);
+
+    assert_eq!(roster.user_ids(), ["alice", "bob"]);
+    let devices: Vec<&str> = roster
+        .devices_for_user("alice")
+        .iter()
+        .map(|member| member.device_id())
+        .collect();
+    assert_eq!(devices, ["alice-phone", "alice-laptop"]);
+}
+
+#[test]
+fn welcomes_carry_the_roster_and_roles() {
+    let (alice, alice_group, bob, bob_group) = group();
+
+    assert_eq!(bob_group.epoch, alice_group.epoch);
+    assert_eq!(bob_group.roster, alice_group.roster);
+    assert_eq!(bob_group.role_of(&alice.identity), Some(GroupRole::Admin));
+    assert_eq!(bob_group.role_of(&bob.identity), Some(GroupRole::Member));
+    assert_eq!(bob_group.role_of(b"mallory"), None);
+
+    // A welcome that does not list the client cannot be joined
+    let mut carol = client("carol", "phone");
+    assert!(carol
+        .join_group(alice_group.config.clone(), alice_group.welcome().unwrap())
+        .is_err());
+}
+
+#[test]
+fn only_admins_change_roles() {
+    let (alice, mut alice_group, bob, mut bob_group) = group();
+
+    assert!(bob_group.set_role(&bob.identity, GroupRole::Admin).is_err());
+    assert!(bob_group
+        .set_role(&alice.identity, GroupRole::Member)
+        .is_err());
+
+    alice_group
+        .set_role(&bob.identity, GroupRole::Admin)
+        .unwrap();
+    assert_eq!(alice_group.role_of(&bob.identity), Some(GroupRole::Admin));
+
+    // With a second admin in place alice may step down
+    alice_group
+        .set_role(&alice.identity, GroupRole::Member)
+        .unwrap();
+    assert!(!alice_group.is_admin(&alice.identity));
+}
+
+#[test]
+fn the_last_admin_cannot_step_down() {
+    let (alice, mut alice_group, _, _) = group();
+    let epoch = alice_group.epoch;
+
+    assert!(alice_group
+        .set_role(&alice.identity, GroupRole::Member)
+        .is_err());
+    assert_eq!(alice_group.epoch, epoch);
+    assert!(alice_group.is_admin(&alice.identity));
+}
+
+#[test]
+fn removed_admins_lose_the_role() {
+    let (_, mut alice_group, bob, _) = group();
+    alice_group
+        .set_role(&bob.identity, GroupRole::Admin)
+        .unwrap();
+    alice_group.remove_member(&bob.identity).unwrap();
+
+    assert!(!alice_group.is_admin(&bob.identity));
+    assert_eq!(alice_group.extensions.admin_roles.admins.len(), 1);
+}
+
+#[test]
+fn renames_follow_the_rename_permission() {
+    let (_, mut alice_group, _, mut bob_group) = group();
+    assert!(bob_group.rename("bob's".to_string()).is_err());
+
+    // Admins may open renaming up to everyone
+    let mut extensions = alice_group.extensions.clone();
+    extensions.admin_roles.permissions.rename = GroupRole::Member;
+    let commit = alice_group.update_extensions(extensions).unwrap();
+    bob_group
+        .process_commit(&serde_json::from_slice::<Commit>(&commit).unwrap())
+        .unwrap();
+
+    bob_group.rename("Weekend".to_string()).unwrap();
+    assert_eq!(bob_group.extensions.name, "Weekend");
+
+    // Renaming does not extend to other context changes
+    let mut extensions = bob_group.extensions.clone();
+    extensions.admin_roles.permissions.add_members = GroupRole::Member;
+    assert!(bob_group.update_extensions(extensions).is_err());
+}
+
+#[test]
+fn conversation_participants_come_from_the_roster() {
+    let (_, alice_group, _, _) = group();
+    let mut store = MessageStore::new();
+    store
+        .add_conversation(Conversation {
+            id: "conversation".to_string(),
+            participant_ids: vec!["alice".to_string(), "mallory".to_string()],
+            last_message_id: None,
+            last_activity: 0,
+        })
+        .unwrap();
+
+    assert!(store
+        .sync_participants("conversation", &alice_group.roster)
+        .unwrap());
+    assert_eq!(
+        store
+            .get_conversation("conversation")
+            .unwrap()
+            .participant_ids,
+        ["alice", "bob"]
+    );
+    assert!(!store
+        .sync_participants("conversation", &alice_group.roster)
+        .unwrap());
+    assert!(store
+        .sync_participants("other", &alice_group.roster)
+        .is_err());
+}
@@ -0,0 +1,196 @@
+//! Sealed-sender envelopes and sender certificates
+
+use xipr_core::crypto::hpke::KeyPair;
+use xipr_core::protocol::sealed::{
+    CertificateSigner, CertificateTrust, DeliveryToken, SealedEnvelope, SenderCertificate,
+    SENDER_CERTIFICATE_LIFETIME, UNIDENTIFIED_SENDER,
+};
+use xipr_core::protocol::transport::MessageType;
+
+const NOW: i64 = 1_700_000_000;
+
+fn signer() -> CertificateSigner {
+    CertificateSigner::from_bytes(7, &[0x42; 32])
+}
+
+fn trust(signer: &CertificateSigner) -> CertificateTrust {
+    let mut trust = CertificateTrust::new();
+    trust
+        .add_key(signer.key_id(), &signer.public_key())
+        .unwrap();
+    trust
+}
+
+fn certificate(signer: &CertificateSigner) -> SenderCertificate {
+    signer.issue(
+        "alice".to_string(),
+        "alice-phone".to_string(),
+        vec![0xaa; 32],
+        NOW,
+    )
+}
+
+fn seal(certificate: SenderCertificate, recipient: &KeyPair) -> SealedEnvelope {
+    SealedEnvelope::seal(
+        certificate,
+        MessageType::Binary,
+        b"hello".to_vec(),
+        "bob-laptop".to_string(),
+        &recipient.public_key,
+    )
+    .unwrap()
+}
+
+#[test]
+fn unseals_sender_and_content() {
+    let signer = signer();
+    let recipient = KeyPair::generate().unwrap();
+    let envelope = seal(certificate(&signer), &recipient);
+
+    let content = envelope.unseal(&recipient, &trust(&signer), NOW).unwrap();
+    assert_eq!(content.certificate, certificate(&signer));
+    assert_eq!(content.message_type, MessageType::Binary);
+    assert_eq!(content.content, b"hello");
+}
+
+#[test]
+fn frames_carry_only_the_recipient() {
+    let recipient = KeyPair::generate().unwrap();
+    let envelope = seal(certificate(&signer()), &recipient);
+
+    let message = envelope.to_message().unwrap();
+    assert_eq!(message.sender_id, UNIDENTIFIED_SENDER);
+    assert_eq!(message.recipient_id, "bob-laptop");
+    assert_eq!(message.message_type, MessageType::Sealed);
+
+    let decoded = SealedEnvelope::from_message(&message).unwrap();
+    assert_eq!(decoded.recipient_id, envelope.recipient_id);
+}
+
+#[test]
+fn resent_envelopes_keep_their_replay_id() {
+    let recipient = KeyPair::generate().unwrap();
+    let envelope = seal(certificate(&signer()), &recipient);
+
+    // The server keys replay checks on these, so a resent envelope must not look new
+    let first = envelope.to_message().unwrap();
+    let again = SealedEnvelope::from_message(&first)
+        .unwrap()
+        .to_message()
+        .unwrap();
+    assert_eq!(first.id, envelope.replay_id());
+    assert_eq!(again.id, first.id);
+    assert_eq!(first.timestamp, envelope.sent_at);
+    assert_eq!(again.timestamp, first.timestamp);
+
+    let other = seal(certificate(&signer()), &recipient);
+    assert_ne!(other.replay_id(), envelope.replay_id());
+
+    // The send time is bound to the ciphertext
+    let mut backdated = envelope.clone();
+    backdated.sent_at -= 60;
+    assert!(backdated
+        .unseal(&recipient, &trust(&signer()), NOW)
+        .is_err());
+}
+
+#[test]
+fn rejects_expired_certificate() {
+    let signer = signer();
+    let recipient = KeyPair::generate().unwrap();
+    let envelope = seal(certificate(&signer), &recipient);
+
+    let later = NOW + SENDER_CERTIFICATE_LIFETIME + 1;
+    assert!(envelope.unseal(&recipient, &trust(&signer), later).is_err());
+}
+
+#[test]
+fn rejects_untrusted_and_tampered_certificates() {
+    let signer = signer();
+
+    let other = CertificateSigner::from_bytes(7, &[0x43; 32]);
+    assert!(certificate(&other).verify(&trust(&signer), NOW).is_err());
+
+    let mut unknown_key = certificate(&signer);
+    unknown_key.key_id = 8;
+    assert!(unknown_key.verify(&trust(&signer), NOW).is_err());
+
+    let mut forged = certificate(&signer);
+    forged.user_id = "mallory".to_string();
+    assert!(forged.verify(&trust(&signer), NOW).is_err());
+
+    let mut extended = certificate(&signer);
+    extended.expires_at += 1;
+    assert!(extended.verify(&trust(&signer), NOW).is_err());
+
+    let mut trust = trust(&signer);
+    assert!(certificate(&signer).verify(&trust, NOW).is_ok());
+    assert!(trust.remove_key(signer.key_id()));
+    assert!(certificate(&signer).verify(&trust, NOW).is_err());
+}
+
+#[test]
+fn rejects_redirected_envelope() {
+    let signer = signer();
+    let recipient = KeyPair::generate().unwrap();
+    let mut envelope = seal(certificate(&signer), &recipient);
+
+    envelope.recipient_id = "carol-phone".to_string();
+    assert!(envelope.unseal(&recipient, &trust(&signer), NOW).is_err());
+}
+
+#[test]
+fn envelopes_hide_the_sender() {
+    let recipient = KeyPair::generate().unwrap();
+    let envelope = seal(certificate(&signer()), &recipient);
+    let bytes = envelope.to_bytes().unwrap();
+
+    for secret in [b"alice".as_slice(), b"alice-phone", b"hello"] {
+        assert!(
+            !bytes.windows(secret.len()).any(|window| window == secret),
+            "{} is visible in the envelope",
+            String::from_utf8_lossy(secret)
+        );
+    }
+}
+
+#[test]
+fn only_the_recipient_key_opens_the_envelope() {
+    let signer = signer();
+    let recipient = KeyPair::generate().unwrap();
+    let envelope = seal(certificate(&signer), &recipient);
+
+    let other = KeyPair::generate().unwrap();
+    assert_ne!(other.public_key, recipient.public_key);
+    assert!(envelope.unseal(&other, &trust(&signer), NOW).is_err());
+
+    let mut tampered = envelope.clone();
+    tampered.ciphertext[0] ^= 1;
+    assert!(tampered.unseal(&recipient, &trust(&signer), NOW).is_err());
+
+    let mut short_nonce = envelope.clone();
+    short_nonce.nonce.pop();
+    assert!(short_nonce
+        .unseal(&recipient, &trust(&signer), NOW)
+        .is_err());
+
+    // A low-order recipient key would make the agreement public
+    assert!(SealedEnvelope::seal(
+        certificate(&signer),
+        MessageType::Binary,
+        b"hello".to_vec(),
+        "bob-laptop".to_string(),
+        &vec![0u8; 32],
+    )
+    .is_err());
+}
+
+#[test]
+fn delivery_tokens_match_by_digest() {
+    let token = DeliveryToken::generate();
+    let copy = DeliveryToken::from_bytes(token.as_bytes()).unwrap();
+    assert_eq!(token.digest(), copy.digest());
+    assert_ne!(token.digest(), DeliveryToken::generate().digest());
+
+    assert!(DeliveryToken::from_bytes(&[0u8; 15]).is_err());
+}
@@ -0,0 +1,116 @@
+//! TOTP codes, sealed secrets and recovery codes
+
+use std::collections::HashSet;
+use xipr_core::protocol::mfa::{
+    generate_recovery_codes, recovery_code_digest, FactorKey, SecondFactor, TotpSecret,
+    RECOVERY_CODE_COUNT, TOTP_STEP,
+};
+
+fn rfc_secret() -> TotpSecret {
+    TotpSecret::from_bytes(b"12345678901234567890").unwrap()
+}
+
+#[test]
+fn matches_the_rfc_6238_sha1_vectors() {
+    let secret = rfc_secret();
+    for (time, code) in [
+        (59, "287082"),
+        (1_111_111_109, "081804"),
+        (1_111_111_111, "050471"),
+        (1_234_567_890, "005924"),
+        (2_000_000_000, "279037"),
+        (20_000_000_000, "353130"),
+    ] {
+        assert_eq!(secret.code_at(time), code);
+    }
+}
+
+#[test]
+fn accepts_one_step_of_skew_and_reports_the_step() {
+    let secret = rfc_secret();
+    let now = 1_111_111_111;
+    let step = now / TOTP_STEP;
+
+    assert_eq!(secret.verify(&secret.code_at(now), now), Some(step));
+    assert_eq!(
+        secret.verify(&secret.code_at(now - TOTP_STEP), now),
+        Some(step - 1)
+    );
+    assert_eq!(
+        secret.verify(&secret.code_at(now + TOTP_STEP), now),
+        Some(step + 1)
+    );
+    assert_eq!(
+        secret.verify(&secret.code_at(now - 2 * TOTP_STEP), now),
+        None
+    );
+    assert_eq!(secret.verify("12345", now), None);
+    assert_eq!(secret.verify("abcdef", now), None);
+}
+
+#[test]
+fn encodes_the_secret_for_authenticator_apps() {
+    let secret = rfc_secret();
+    assert_eq!(secret.to_base32(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    assert!(TotpSecret::from_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap() == secret);
+    assert!(TotpSecret::from_base32("GEZDGNBVGY3TQOJ1").is_err());
+    assert_eq!(
+        secret.provisioning_uri("XIPRNET", "alice@example.com"),
+        "otpauth://totp/XIPRNET:alice%40example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\
+         &issuer=XIPRNET&algorithm=SHA1&digits=6&period=30"
+    );
+    assert!(TotpSecret::from_bytes(&[0; 10]).is_err());
+}
+
+#[test]
+fn sealed_secrets_open_only_for_their_user_and_key() {
+    let key = FactorKey::generate();
+    let secret = TotpSecret::generate();
+    let sealed = key.seal("alice", &secret).unwrap();
+    assert!(!sealed
+        .ciphertext
+        .windows(secret.as_bytes().len())
+        .any(|window| window == secret.as_bytes()));
+
+    assert_eq!(key.open("alice", &sealed).unwrap(), secret);
+    assert!(key.open("bob", &sealed).is_err());
+    assert!(FactorKey::generate().open("alice", &sealed).is_err());
+}
+
+#[test]
+fn recovery_codes_are_distinct_and_digested_loosely() {
+    let codes = generate_recovery_codes();
+    assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+    assert_eq!(codes.iter().collect::<HashSet<_>>().len(), codes.len());
+    assert!(codes.iter().all(|code| code.len() == 14));
+
+    let code = &codes[0];
+    let digest = recovery_code_digest("alice", code);
+    assert_eq!(
+        recovery_code_digest(
+            "alice",
+            &format!(" {} ", code.to_uppercase().replace('-', ""))
+        ),
+        digest
+    );
+    assert_ne!(recovery_code_digest("bob", code), digest);
+}
+
+#[test]
+fn second_factors_are_tagged_by_type() {
+    let factor: SecondFactor =
+        serde_json::from_str(r#"{"type":"recovery_code","code":"abcd-efgh-jkmn"}"#).unwrap();
+    assert_eq!(
+        factor,
+        SecondFactor::RecoveryCode {
+            code: "abcd-efgh-jkmn".to_string()
+        }
+    );
+    assert_eq!(
+        serde_json::to_string(&SecondFactor::Totp {
+            code: "123456".to_string()
+        })
+        .unwrap(),
+        r#"{"type":"totp","code":"123456"}"#
+    );
+}
@@ -0,0 +1,134 @@
+//! Self-verifying session tokens
+
+use xipr_core::protocol::auth::{
+    RefreshToken, SessionKey, SessionKeyring, ACCESS_TOKEN_LIFETIME,
+};
+
+const NOW: i64 = 1_700_000_000;
+
+fn keyring(key_id: u32, secret: u8) -> SessionKeyring {
+    SessionKeyring::new(SessionKey::from_bytes(key_id, &[secret; 32]))
+}
+
+#[test]
+fn any_node_with_the_key_verifies_a_token() {
+    let issuer = keyring(1, 7);
+    let session = issuer
+        .issue("alice".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap();
+    assert!(session.token.starts_with("v1.1."));
+    assert_eq!(session.expires_at, NOW + ACCESS_TOKEN_LIFETIME);
+
+    // Another node, sharing only the key
+    let verified = keyring(1, 7).verify(&session.token, NOW + 60).unwrap();
+    assert_eq!(verified.id, session.id);
+    assert_eq!(verified.user_id, "alice");
+    assert_eq!(verified.device_id, "alice-phone");
+    assert_eq!(verified.expires_at, session.expires_at);
+}
+
+#[test]
+fn rejects_tampered_foreign_and_expired_tokens() {
+    let issuer = keyring(1, 7);
+    let token = issuer
+        .issue("alice".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap()
+        .token;
+
+    // Swap in claims for another user, keeping the tag
+    let forged = issuer
+        .issue("mallory".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap()
+        .token;
+    let claims = forged.split('.').nth(2).unwrap();
+    let mut parts: Vec<&str> = token.split('.').collect();
+    parts[2] = claims;
+    assert!(issuer.verify(&parts.join("."), NOW).is_err());
+
+    assert!(keyring(1, 8).verify(&token, NOW).is_err());
+    assert!(keyring(2, 7).verify(&token, NOW).is_err());
+    assert!(issuer.verify(&token, NOW + ACCESS_TOKEN_LIFETIME + 1).is_err());
+    assert!(issuer.verify("not a token", NOW).is_err());
+    assert!(issuer.verify(&token.replacen("v1", "v2", 1), NOW).is_err());
+}
+
+#[test]
+fn rotation_keeps_old_tokens_valid_until_the_key_is_retired() {
+    let mut keys = keyring(1, 7);
+    let old = keys
+        .issue("alice".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap()
+        .token;
+
+    keys.rotate(SessionKey::from_bytes(2, &[9; 32]));
+    let new = keys
+        .issue("alice".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap()
+        .token;
+    assert_eq!(keys.current_key_id(), 2);
+    assert!(new.starts_with("v1.2."));
+    assert!(keys.verify(&old, NOW).is_ok());
+
+    assert!(!keys.retire(2));
+    assert!(keys.retire(1));
+    assert!(keys.verify(&old, NOW).is_err());
+    assert!(keys.verify(&new, NOW).is_ok());
+}
+
+#[test]
+fn verification_keys_do_not_sign() {
+    let mut keys = keyring(2, 9);
+    keys.add_verification_key(SessionKey::from_bytes(1, &[7; 32]));
+
+    let old = keyring(1, 7)
+        .issue("alice".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap()
+        .token;
+    assert!(keys.verify(&old, NOW).is_ok());
+
+    let issued = keys
+        .issue("alice".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap();
+    assert!(issued.token.starts_with("v1.2."));
+}
+
+#[test]
+fn reissue_keeps_the_session_and_extends_it() {
+    let keys = keyring(1, 7);
+    let session = keys
+        .issue("alice".to_string(), "alice-phone".to_string(), NOW)
+        .unwrap();
+
+    let later = NOW + ACCESS_TOKEN_LIFETIME - 60;
+    let reissued = keys.reissue(&session, later).unwrap();
+    assert_eq!(reissued.id, session.id);
+    assert_eq!(reissued.created_at, session.created_at);
+    assert_eq!(reissued.expires_at, later + ACCESS_TOKEN_LIFETIME);
+    assert_ne!(reissued.token, session.token);
+
+    let verified = keys.verify(&reissued.token, later + 120).unwrap();
+    assert_eq!(verified.id, session.id);
+    assert!(keys.verify(&session.token, later + 120).is_err());
+}
+
+#[test]
+fn refresh_tokens_round_trip_and_rotate_within_their_family() {
+    let token = RefreshToken::generate("session-1".to_string());
+    let parsed: RefreshToken = token.to_string().parse().unwrap();
+    assert_eq!(parsed.family_id(), "session-1");
+    assert_eq!(parsed.digest(), token.digest());
+
+    let next = token.rotate();
+    assert_eq!(next.family_id(), "session-1");
+    assert_ne!(next.digest(), token.digest());
+    assert_ne!(next.to_string(), token.to_string());
+
+    // The digest covers the family, so a secret cannot be moved to another one
+    let secret = token.to_string().rsplit_once('.').unwrap().1.to_string();
+    let moved: RefreshToken = format!("session-2.{}", secret).parse().unwrap();
+    assert_ne!(moved.digest(), token.digest());
+
+    assert!("no-separator".parse::<RefreshToken>().is_err());
+    assert!("session-1.not base64!".parse::<RefreshToken>().is_err());
+    assert!("session-1.c2hvcnQ".parse::<RefreshToken>().is_err());
+}
@@ -0,0 +1,139 @@
+//! Golden vectors for the binary `MessageFrame` wire format
+
+use xipr_core::protocol::transport::{MessageFrame, MessageHeader, MessageType};
+use xipr_core::protocol::wire::{MAX_ID_LENGTH, MAX_PAYLOAD_LENGTH, WIRE_VERSION};
+
+fn frame(message_type: MessageType, payload: &[u8]) -> MessageFrame {
+    MessageFrame {
+        header: MessageHeader {
+            version: WIRE_VERSION,
+            message_type,
+            message_id: "m1".to_string(),
+            sender_id: "alice".to_string(),
+            recipient_id: "bob".to_string(),
+            timestamp: 1_700_000_000,
+            sequence_number: 42,
+            payload_length: payload.len() as u32,
+            compressed: false,
+        },
+        payload: payload.to_vec(),
+    }
+}
+
+const VECTORS: &[(MessageType, &[u8], &str)] = &[
+    (
+        MessageType::Text,
+        b"hi",
+        "0101026d3105616c69636503626f62000000006553f100000000000000002a000000026869",
+    ),
+    (
+        MessageType::Binary,
+        &[0x00, 0xff],
+        "0102026d3105616c69636503626f62000000006553f100000000000000002a0000000200ff",
+    ),
+    (
+        MessageType::Control,
+        &[0x01],
+        "0103026d3105616c69636503626f62000000006553f100000000000000002a0000000101",
+    ),
+    (
+        MessageType::Heartbeat,
+        &[],
+        "0104026d3105616c69636503626f62000000006553f100000000000000002a00000000",
+    ),
+    (
+        MessageType::Sealed,
+        &[0xab, 0xcd],
+        "0105026d3105616c69636503626f62000000006553f100000000000000002a00000002abcd",
+    ),
+];
+
+fn unhex(vector: &str) -> Vec<u8> {
+    hex::decode(vector).unwrap()
+}
+
+#[test]
+fn encodes_golden_vectors() {
+    for (message_type, payload, expected) in VECTORS {
+        let encoded = frame(message_type.clone(), payload).encode().unwrap();
+        assert_eq!(hex::encode(encoded), *expected, "{:?}", message_type);
+    }
+}
+
+#[test]
+fn decodes_golden_vectors() {
+    for (message_type, payload, vector) in VECTORS {
+        let decoded = MessageFrame::decode(&unhex(vector)).unwrap();
+        assert_eq!(decoded, frame(message_type.clone(), payload));
+    }
+}
+
+#[test]
+fn compressed_flag_is_the_high_bit_of_the_type() {
+    let mut compressed = frame(MessageType::Control, &[0x01]);
+    compressed.header.compressed = true;
+
+    let vector = "0183026d3105616c69636503626f62000000006553f100000000000000002a0000000101";
+    assert_eq!(hex::encode(compressed.encode().unwrap()), vector);
+    assert_eq!(MessageFrame::decode(&unhex(vector)).unwrap(), compressed);
+}
+
+#[test]
+fn rejects_trailing_bytes() {
+    let mut bytes = unhex(VECTORS[0].2);
+    bytes.push(0);
+    assert!(MessageFrame::decode(&bytes).is_err());
+}
+
+#[test]
+fn rejects_truncated_frames() {
+    let bytes = unhex(VECTORS[0].2);
+    for length in 0..bytes.len() {
+        assert!(MessageFrame::decode(&bytes[..length]).is_err());
+    }
+}
+
+#[test]
+fn rejects_unknown_version_and_type() {
+    let mut bytes = unhex(VECTORS[0].2);
+    bytes[0] = 2;
+    assert!(MessageFrame::decode(&bytes).is_err());
+
+    let mut bytes = unhex(VECTORS[0].2);
+    bytes[1] = 0xee;
+    assert!(MessageFrame::decode(&bytes).is_err());
+}
+
+#[test]
+fn enforces_size_limits() {
+    let mut oversized_id = frame(MessageType::Text, b"");
+    oversized_id.header.sender_id = "a".repeat(MAX_ID_LENGTH + 1);
+    assert!(oversized_id.encode().is_err());
+
+    let oversized_payload = frame(MessageType::Binary, &vec![0u8; MAX_PAYLOAD_LENGTH + 1]);
+    assert!(oversized_payload.encode().is_err());
+
+    // Declared payload length beyond the limit is rejected before reading the payload
+    let mut bytes = unhex(VECTORS[3].2);
+    let length_offset = bytes.len() - 4;
+    bytes[length_offset..].copy_from_slice(&(MAX_PAYLOAD_LENGTH as u32 + 1).to_be_bytes());
+    assert!(MessageFrame::decode(&bytes).is_err());
+}
+
+#[test]
+fn message_round_trips_without_json_expansion() {
+    let message = xipr_core::protocol::transport::Message::new(
+        "alice".to_string(),
+        "bob".to_string(),
+        MessageType::Binary,
+        vec![0xff; 64],
+    );
+
+    let frame = message.to_frame().unwrap();
+    assert_eq!(frame.payload.len(), 64);
+
+    let decoded = MessageFrame::decode(&frame.encode().unwrap()).unwrap();
+    let round_tripped = xipr_core::protocol::transport::Message::from_frame(&decoded).unwrap();
+    assert_eq!(round_tripped.content, message.content);
+    assert_eq!(round_tripped.id, message.id);
+}
@@ -0,0 +1,110 @@
+//! Account deletion API endpoints
+//!
+//! Deleting an account purges what the server holds for it: sessions,
+//! devices and their pre-keys, queued messages, group membership, passkeys,
+//! the second factor and stored media. A signed tombstone takes the user's place in the
+//! directory and a signed receipt of the purge goes back to the caller.
+
+use axum::{extract::State, http::StatusCode, response::Json as JsonResponse};
+use serde::Serialize;
+use std::collections::HashSet;
+use tracing::{info, warn};
+use xipr_core::protocol::deletion::{DeletionReceipt, PurgeSummary};
+
+use crate::api::SteppedUp;
+use crate::auth::DirectoryError;
+use crate::state::AppState;
+
+#[derive(Debug, Serialize)]
+pub struct DeletionKeyResponse {
+    pub key_id: u32,
+    pub public_key: Vec<u8>,
+}
+
+/// Delete the caller's account and everything the server holds for it
+///
+/// The last org-admin of an organisation with other members gets `409` and
+/// must hand the role on first.
+pub async fn delete_account(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+) -> Result<JsonResponse<DeletionReceipt>, StatusCode> {
+    let user = state
+        .auth
+        .get_user(&session.user_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    state
+        .auth
+        .ensure_deletable(&user.id)
+        .map_err(|error| match error {
+            DirectoryError::LastAdmin => StatusCode::CONFLICT,
+            _ => StatusCode::NOT_FOUND,
+        })?;
+
+    // Queues are the only backend that can fail, so they go first and a
+    // failure leaves the account intact for a retry
+    let devices = state.devices.devices_of(&user.id);
+    let mut purged = PurgeSummary::default();
+    for record in &devices {
+        purged.queued_messages += state
+            .queues
+            .purge(&record.device_id)
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)? as u64;
+    }
+
+    let mut groups = HashSet::new();
+    for record in devices {
+        let Ok(record) = state.devices.revoke(&user.id, &record.device_id) else {
+            continue;
+        };
+        purged.devices += 1;
+        purged.pre_keys += record.pre_keys.len() as u64;
+        state.sealed.forget_device(&record.device_id);
+        groups.extend(state.delivery.remove_device(&record.device_id));
+
+        // Anything queued while the device was still registered
+        match state.queues.purge(&record.device_id).await {
+            Ok(count) => purged.queued_messages += count as u64,
+            Err(e) => warn!("Queue purge of device {} failed: {}", record.device_id, e),
+        }
+    }
+    purged.groups = groups.len() as u64;
+    purged.sessions = state.auth.revoke_user(&user.id) as u64;
+
+    for passkey in state.passkeys.credentials_of(&user.id) {
+        if state
+            .passkeys
+            .remove(&user.id, &passkey.credential_id)
+            .is_ok()
+        {
+            purged.passkeys += 1;
+        }
+    }
+    purged.second_factor = state.mfa.disable(&user.id).is_ok();
+    purged.media_objects = u64::from(state.storage.delete_user_data(&user.id));
+    if let Err(e) = state.throttle.forget_account(&user.username).await {
+        warn!("Clearing login failures of user {} failed: {}", user.id, e);
+    }
+
+    let tombstone = state
+        .auth
+        .delete_user(&user.id)
+        .map_err(|_| StatusCode::CONFLICT)?;
+    let receipt = state.auth.deletion_receipt(&tombstone, purged);
+    info!(
+        "User {} deleted their account: receipt {}, {:?}",
+        user.id, receipt.receipt_id, receipt.purged
+    );
+
+    Ok(JsonResponse(receipt))
+}
+
+/// Key that tombstones and deletion receipts verify under
+pub async fn deletion_key(State(state): State<AppState>) -> JsonResponse<DeletionKeyResponse> {
+    let (key_id, public_key) = state.auth.deletion_key();
+    JsonResponse(DeletionKeyResponse {
+        key_id,
+        public_key: public_key.to_vec(),
+    })
+}
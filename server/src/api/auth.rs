@@ -1,12 +1,21 @@
 //! Authentication API endpoints
 
 use axum::{
-    extract::Json,
-    http::StatusCode,
-    response::Json as JsonResponse,
+    extract::{ConnectInfo, Json, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Json as JsonResponse, Response},
 };
 use serde::{Deserialize, Serialize};
-use xipr_core::protocol::auth::{AuthRequest, Session, User};
+use std::net::SocketAddr;
+use xipr_core::protocol::auth::{Session, User};
+use xipr_core::protocol::mfa::SecondFactor;
+use xipr_core::protocol::throttle::{PowChallenge, PowSolution, ThrottleDecision};
+
+use super::mfa::mfa_error;
+use super::Authenticated;
+use crate::mfa::MfaError;
+use crate::state::AppState;
+use crate::throttle::ChallengeIssue;
 
 #[derive(Debug, Deserialize)]
 pub struct RegisterRequest {
@@ -19,8 +28,17 @@ pub struct RegisterRequest {
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     pub username: String,
+    /// Not checked until OPAQUE lands; the second factor is what proves the user
     pub password: String,
-    pub device_id: String,
+    /// Id the server gave this device at an earlier login; a new one is assigned otherwise
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Ed25519 public key the device will sign request proofs with
+    pub signing_key: Option<Vec<u8>>,
+    /// Required once the user has enrolled one
+    pub second_factor: Option<SecondFactor>,
+    /// Answer to the challenge of an earlier response, once one was set
+    pub proof_of_work: Option<PowSolution>,
 }
 
 #[derive(Debug, Serialize)]
@@ -34,9 +52,25 @@ pub struct RegisterResponse {
 pub struct LoginResponse {
     pub success: bool,
     pub session: Option<Session>,
+    pub refresh_token: Option<String>,
+    /// Set when the login must be retried with `second_factor`
+    pub second_factor_required: bool,
+    /// Set when the login must be retried with this challenge solved in `proof_of_work`
+    pub challenge: Option<PowChallenge>,
     pub error: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefreshResponse {
+    pub session: Session,
+    pub refresh_token: String,
+}
+
 pub async fn register(
     Json(payload): Json<RegisterRequest>,
 ) -> Result<JsonResponse<RegisterResponse>, StatusCode> {
@@ -51,30 +85,211 @@ pub async fn register(
 }
 
 pub async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
     Json(payload): Json<LoginRequest>,
-) -> Result<JsonResponse<LoginResponse>, StatusCode> {
-    let auth_request = AuthRequest {
-        username: payload.username,
-        password: payload.password,
-        device_id: payload.device_id,
-    };
-    
-    match xipr_core::protocol::auth::AuthManager::authenticate(&auth_request) {
-        Ok(auth_response) => {
-            if auth_response.success {
-                Ok(JsonResponse(LoginResponse {
-                    success: true,
-                    session: auth_response.session,
-                    error: None,
-                }))
-            } else {
-                Ok(JsonResponse(LoginResponse {
+) -> Result<JsonResponse<LoginResponse>, Response> {
+    require_provable_device(
+        &state,
+        payload.device_id.as_deref(),
+        payload.signing_key.as_deref(),
+    )
+    .map_err(IntoResponse::into_response)?;
+
+    let source = source.ip().to_string();
+    let username = payload.username.clone();
+    match state
+        .throttle
+        .check(Some(&username), &source)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?
+    {
+        ThrottleDecision::Allow => {}
+        ThrottleDecision::Throttled { retry_after } => return Err(throttled(retry_after)),
+        ThrottleDecision::Challenge => {
+            let solved = match &payload.proof_of_work {
+                Some(solution) => state
+                    .throttle
+                    .redeem(&username, solution)
+                    .await
+                    .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?,
+                None => false,
+            };
+            if !solved {
+                let challenge = match state
+                    .throttle
+                    .challenge(&source)
+                    .await
+                    .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?
+                {
+                    ChallengeIssue::Issued(challenge) => challenge,
+                    ChallengeIssue::Throttled { retry_after } => return Err(throttled(retry_after)),
+                };
+                return Ok(JsonResponse(LoginResponse {
                     success: false,
                     session: None,
-                    error: auth_response.error,
-                }))
+                    refresh_token: None,
+                    second_factor_required: false,
+                    challenge: Some(challenge),
+                    error: Some("Proof of work required".to_string()),
+                }));
             }
         }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
+    
+    // No password verifier exists until OPAQUE lands, so a password proves nothing. Users
+    // log in with their enrolled second factor or their enrolment token here, or with a passkey.
+    let Some(factor) = &payload.second_factor else {
+        return Ok(JsonResponse(LoginResponse {
+            success: false,
+            session: None,
+            refresh_token: None,
+            second_factor_required: true,
+            challenge: None,
+            error: Some("Second factor required".to_string()),
+        }));
+    };
+    
+    // Unknown users and users without a factor look the same, and both count as
+    // failures so guessing at accounts is throttled like guessing at codes
+    let now = chrono::Utc::now().timestamp();
+    let Some(user) = state
+        .auth
+        .user_by_username(&username)
+        .filter(|user| state.mfa.has_factor(&user.id, now))
+    else {
+        state
+            .throttle
+            .record_failure(Some(&username), &source, "no usable credential")
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?;
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    };
+    
+    if let Err(e) = state.mfa.verify(&user.id, factor, now) {
+        state
+            .throttle
+            .record_failure(Some(&username), &source, "wrong second factor")
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?;
+        return Err(match e {
+            MfaError::LockedOut(_) => mfa_error(e),
+            _ => StatusCode::UNAUTHORIZED.into_response(),
+        });
+    }
+    
+    let (session, refresh_token) = admit_device(
+        &state,
+        &user.id,
+        payload.device_id.as_deref(),
+        payload.signing_key.as_deref(),
+    )
+    .map_err(IntoResponse::into_response)?;
+    state.mfa.step_up(&session, now);
+    state
+        .throttle
+        .record_success(&username)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?;
+    
+    Ok(JsonResponse(LoginResponse {
+        success: true,
+        session: Some(session),
+        refresh_token: Some(refresh_token),
+        second_factor_required: false,
+        challenge: None,
+        error: None,
+    }))
+}
+
+/// `429` telling the client how long to back off
+pub(crate) fn throttled(retry_after: i64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.max(1).to_string())],
+    )
+        .into_response()
+}
+
+/// `400` for a login that would give a device a session it cannot prove
+///
+/// When proofs are required, the device must send a signing key or have bound
+/// one before. Checked before any credential is spent on the login.
+pub(crate) fn require_provable_device(
+    state: &AppState,
+    device_id: Option<&str>,
+    signing_key: Option<&[u8]>,
+) -> Result<(), StatusCode> {
+    let bound = device_id.is_some_and(|id| state.devices.signing_key(id).is_some());
+    if signing_key.is_none() && !bound && state.auth.requires_device_proof() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+/// Register an authenticated user's device, open its session and start its
+/// refresh token family
+///
+/// The device keeps `device_id` if the server gave it to this user before,
+/// and gets a fresh id otherwise. A revoked device, or one whose bound signing
+/// key differs from `signing_key`, gets `409`.
+pub(crate) fn admit_device(
+    state: &AppState,
+    user_id: &str,
+    device_id: Option<&str>,
+    signing_key: Option<&[u8]>,
+) -> Result<(Session, String), StatusCode> {
+    let device_id = state
+        .devices
+        .register(user_id, device_id)
+        .map_err(|_| StatusCode::CONFLICT)?;
+    if let Some(key) = signing_key {
+        state
+            .devices
+            .bind_signing_key(&device_id, key)
+            .map_err(|_| StatusCode::CONFLICT)?;
+    }
+    let session = state
+        .auth
+        .create_session(user_id.to_string(), device_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let refresh_token = state.auth.issue_refresh_token(&session).to_string();
+    Ok((session, refresh_token))
+}
+
+/// Exchange a refresh token for a new access token and the next refresh token
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<JsonResponse<RefreshResponse>, StatusCode> {
+    let (session, refresh_token) = state
+        .auth
+        .refresh(&payload.refresh_token)
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    
+    Ok(JsonResponse(RefreshResponse {
+        session,
+        refresh_token: refresh_token.to_string(),
+    }))
+}
+
+/// End the caller's session, revoking its access and refresh tokens
+pub async fn logout(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+) -> StatusCode {
+    state.auth.logout(&session);
+    StatusCode::NO_CONTENT
+}
+
+/// The caller's user, with their organisation and roles
+pub async fn current_user(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+) -> Result<JsonResponse<User>, StatusCode> {
+    state
+        .auth
+        .get_user(&session.user_id)
+        .map(JsonResponse)
+        .ok_or(StatusCode::NOT_FOUND)
 }
\ No newline at end of file
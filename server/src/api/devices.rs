@@ -0,0 +1,314 @@
+//! Device management API endpoints
+//!
+//! Linking runs through a provisioning slot: the new device opens it and
+//! shows its id and one-time key as a QR code, an existing device uploads
+//! the sealed identity material to it, and the new device completes the
+//! link with the code from inside the envelope to get its first session.
+
+use axum::{
+    extract::{ConnectInfo, Json, Path, State},
+    http::StatusCode,
+    response::Json as JsonResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tracing::{info, warn};
+use xipr_core::crypto::keys::PreKey;
+use xipr_core::protocol::auth::{Permission, Session};
+use xipr_core::protocol::provisioning::ProvisionEnvelope;
+
+use crate::api::{authorize_tenant, device_in_organisation, Authenticated, SteppedUp};
+use crate::devices::{AttestationStatus, DeviceError, DeviceRecord, LinkCompletion};
+use crate::state::AppState;
+
+#[derive(Debug, Serialize)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub created_at: i64,
+    pub last_seen: i64,
+    /// Not seen for long enough that messages are no longer fanned out to it
+    pub stale: bool,
+    /// The device making the request
+    pub current: bool,
+    pub linked_by: Option<String>,
+    pub attestation: AttestationStatus,
+    /// Whether the device's sessions need proof of possession
+    pub key_bound: bool,
+    pub pre_keys: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceListResponse {
+    pub devices: Vec<DeviceInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeResponse {
+    pub sessions_revoked: usize,
+    /// Groups whose members were asked to commit the device's removal, URL-safe base64
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenLinkResponse {
+    pub provisioning_id: String,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GrantLinkRequest {
+    pub envelope: ProvisionEnvelope,
+    /// `link_code_digest` of the code sealed in the envelope
+    pub link_code_digest: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkEnvelopeResponse {
+    /// Unset until the linking device has uploaded it
+    pub envelope: Option<ProvisionEnvelope>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompleteLinkRequest {
+    pub link_code: Vec<u8>,
+    pub name: Option<String>,
+    /// Ed25519 public key the device will sign request proofs with
+    pub signing_key: Vec<u8>,
+    /// Hardware attestation evidence, if the device has any
+    pub attestation: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompleteLinkResponse {
+    pub device_id: String,
+    pub session: Session,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadPreKeysRequest {
+    pub pre_keys: Vec<PreKey>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadPreKeysResponse {
+    pub stored: usize,
+}
+
+pub async fn list_devices(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+) -> JsonResponse<DeviceListResponse> {
+    let now = chrono::Utc::now().timestamp();
+    let devices = state
+        .devices
+        .devices_of(&session.user_id)
+        .into_iter()
+        .map(|record| device_info(record, &session.device_id, now))
+        .collect();
+
+    JsonResponse(DeviceListResponse { devices })
+}
+
+/// Revoke one of the caller's devices
+///
+/// Its sessions end, its pre-keys and queued frames are deleted and it leaves
+/// every MLS group.
+/// Revoking the calling device itself is allowed.
+pub async fn revoke_device(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Path(device_id): Path<String>,
+) -> Result<JsonResponse<RevokeResponse>, StatusCode> {
+    state
+        .devices
+        .revoke(&session.user_id, &device_id)
+        .map_err(device_status)?;
+
+    let sessions_revoked = state.auth.revoke_device(&device_id);
+    state.sealed.forget_device(&device_id);
+    let groups = state.delivery.remove_device(&device_id);
+    if let Err(e) = state.queues.purge(&device_id).await {
+        warn!("Queue purge of revoked device {} failed: {}", device_id, e);
+    }
+    info!(
+        "Device {} of user {} revoked by {}: {} sessions, {} groups",
+        device_id,
+        session.user_id,
+        session.device_id,
+        sessions_revoked,
+        groups.len()
+    );
+
+    Ok(JsonResponse(RevokeResponse {
+        sessions_revoked,
+        groups: groups
+            .iter()
+            .map(|group_id| URL_SAFE_NO_PAD.encode(group_id))
+            .collect(),
+    }))
+}
+
+/// Open a provisioning slot; called by the new device, without a session
+pub async fn open_link(
+    State(state): State<AppState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+) -> Result<JsonResponse<OpenLinkResponse>, StatusCode> {
+    let (provisioning_id, expires_at) = state
+        .devices
+        .open_link(&source.ip().to_string())
+        .map_err(device_status)?;
+
+    Ok(JsonResponse(OpenLinkResponse {
+        provisioning_id,
+        expires_at,
+    }))
+}
+
+/// Upload identity material sealed to the new device; called by the linking device
+///
+/// Needs a stepped-up session: this hands over the user's identity keys.
+pub async fn grant_link(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+    Path(provisioning_id): Path<String>,
+    Json(payload): Json<GrantLinkRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let digest = payload
+        .link_code_digest
+        .try_into()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    state
+        .devices
+        .grant_link(
+            &provisioning_id,
+            &session.user_id,
+            &session.device_id,
+            payload.envelope,
+            digest,
+        )
+        .map_err(device_status)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Fetch the sealed envelope; the new device polls while this returns `202`
+pub async fn link_envelope(
+    State(state): State<AppState>,
+    Path(provisioning_id): Path<String>,
+) -> Result<(StatusCode, JsonResponse<LinkEnvelopeResponse>), StatusCode> {
+    match state.devices.link_envelope(&provisioning_id) {
+        Ok(envelope) => Ok((
+            StatusCode::OK,
+            JsonResponse(LinkEnvelopeResponse {
+                envelope: Some(envelope),
+            }),
+        )),
+        Err(DeviceError::LinkPending) => Ok((
+            StatusCode::ACCEPTED,
+            JsonResponse(LinkEnvelopeResponse { envelope: None }),
+        )),
+        Err(e) => Err(device_status(e)),
+    }
+}
+
+/// Register the new device and give it its first session
+pub async fn complete_link(
+    State(state): State<AppState>,
+    Path(provisioning_id): Path<String>,
+    Json(payload): Json<CompleteLinkRequest>,
+) -> Result<JsonResponse<CompleteLinkResponse>, StatusCode> {
+    let record = state
+        .devices
+        .complete_link(
+            &provisioning_id,
+            LinkCompletion {
+                link_code: &payload.link_code,
+                name: payload.name,
+                signing_key: &payload.signing_key,
+                attestation: payload.attestation.as_deref(),
+            },
+        )
+        .map_err(device_status)?;
+
+    let session = state
+        .auth
+        .create_session(record.user_id.clone(), record.device_id.clone())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let refresh_token = state.auth.issue_refresh_token(&session).to_string();
+    info!(
+        "Device {} of user {} linked by {}",
+        record.device_id,
+        record.user_id,
+        record.linked_by.as_deref().unwrap_or_default()
+    );
+
+    Ok(JsonResponse(CompleteLinkResponse {
+        device_id: record.device_id,
+        session,
+        refresh_token,
+    }))
+}
+
+/// Store pre-keys for the calling device
+pub async fn upload_pre_keys(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Json(payload): Json<UploadPreKeysRequest>,
+) -> Result<JsonResponse<UploadPreKeysResponse>, StatusCode> {
+    let stored = state
+        .devices
+        .add_pre_keys(&session.device_id, payload.pre_keys)
+        .map_err(device_status)?;
+
+    Ok(JsonResponse(UploadPreKeysResponse { stored }))
+}
+
+/// Claim one of a device's pre-keys to start a session with it
+///
+/// Only devices in the caller's organisation; others look unknown.
+pub async fn claim_pre_key(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Path(device_id): Path<String>,
+) -> Result<JsonResponse<PreKey>, StatusCode> {
+    let (_, org_id) = authorize_tenant(&state, &session, Permission::SendMessages)?;
+    if !device_in_organisation(&state, &org_id, &device_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    state
+        .devices
+        .claim_pre_key(&device_id)
+        .map(JsonResponse)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub(crate) fn device_info(record: DeviceRecord, current_device_id: &str, now: i64) -> DeviceInfo {
+    DeviceInfo {
+        stale: record.is_stale(now),
+        current: record.device_id == current_device_id,
+        key_bound: record.signing_key.is_some(),
+        pre_keys: record.pre_keys.len(),
+        device_id: record.device_id,
+        name: record.name,
+        created_at: record.created_at,
+        last_seen: record.last_seen,
+        linked_by: record.linked_by,
+        attestation: record.attestation,
+    }
+}
+
+fn device_status(error: DeviceError) -> StatusCode {
+    match error {
+        DeviceError::UnknownDevice | DeviceError::UnknownLink => StatusCode::NOT_FOUND,
+        DeviceError::Revoked
+        | DeviceError::KeyMismatch
+        | DeviceError::LinkUsed
+        | DeviceError::LinkPending => StatusCode::CONFLICT,
+        DeviceError::InvalidKey | DeviceError::MalformedEnvelope => StatusCode::BAD_REQUEST,
+        DeviceError::WrongLinkCode => StatusCode::FORBIDDEN,
+        DeviceError::TooManyLinks => StatusCode::TOO_MANY_REQUESTS,
+    }
+}
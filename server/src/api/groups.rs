@@ -0,0 +1,280 @@
+//! MLS group delivery API endpoints
+//!
+//! Groups belong to their creator's organisation, and only its members'
+//! devices can be added or welcomed.
+
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    response::Json as JsonResponse,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use xipr_core::protocol::auth::Permission;
+use xipr_core::protocol::transport::MessageFrame;
+
+use crate::api::{authorize_tenant, device_in_organisation, Authenticated};
+use crate::delivery::{DeliveryError, PendingWelcome};
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGroupRequest {
+    pub group_id: Vec<u8>,
+    /// Optional, and must be the caller's device if present
+    pub device_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WelcomeUpload {
+    pub device_id: String,
+    pub welcome: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitCommitRequest {
+    /// Optional, and must be the caller's device if present
+    pub device_id: Option<String>,
+    pub commit: Vec<u8>,
+    #[serde(default)]
+    pub welcomes: Vec<WelcomeUpload>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitApplicationRequest {
+    /// Optional, and must be the caller's device if present
+    pub device_id: Option<String>,
+    pub message: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceQuery {
+    /// Optional, and must be the caller's device if present
+    pub device_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InboxQuery {
+    /// Optional, and must be the caller's device if present
+    pub device_id: Option<String>,
+    /// Last queue sequence the caller has seen; earlier frames are skipped
+    #[serde(default)]
+    pub after: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AckRequest {
+    /// Optional, and must be the caller's device if present
+    pub device_id: Option<String>,
+    pub sequence: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GroupInfoResponse {
+    pub epoch: u64,
+    pub members: Vec<String>,
+    /// Revoked devices the next commit should remove
+    pub pending_removals: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubmitResponse {
+    pub success: bool,
+    pub epoch: Option<u64>,
+    pub group_sequence: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InboxResponse {
+    /// Queued frames in queue order; group messages are `ControlMessage::Group`
+    pub frames: Vec<MessageFrame>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AckResponse {
+    pub acknowledged: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WelcomesResponse {
+    pub welcomes: Vec<PendingWelcome>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadLettersResponse {
+    pub frames: Vec<MessageFrame>,
+}
+
+pub async fn create_group(
+    State(state): State<AppState>,
+    caller: Authenticated,
+    Json(payload): Json<CreateGroupRequest>,
+) -> Result<JsonResponse<GroupInfoResponse>, StatusCode> {
+    let device_id = caller.device(payload.device_id.as_deref())?;
+    let (_, org_id) = authorize_tenant(&state, &caller.0, Permission::SendMessages)?;
+    state
+        .delivery
+        .create_group(&org_id, payload.group_id, device_id.clone())
+        .map_err(delivery_status)?;
+
+    Ok(JsonResponse(GroupInfoResponse {
+        epoch: 0,
+        members: vec![device_id],
+        pending_removals: Vec::new(),
+    }))
+}
+
+pub async fn get_group(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Path(group_id): Path<String>,
+) -> Result<JsonResponse<GroupInfoResponse>, StatusCode> {
+    let group_id = decode_group_id(&group_id)?;
+    let (_, org_id) = authorize_tenant(&state, &session, Permission::SendMessages)?;
+    let epoch = state
+        .delivery
+        .group_epoch(&org_id, &group_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let members = state
+        .delivery
+        .group_members(&org_id, &group_id)
+        .unwrap_or_default();
+    if !members.contains(&session.device_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(JsonResponse(GroupInfoResponse {
+        epoch,
+        members,
+        pending_removals: state.delivery.pending_removals(&org_id, &group_id),
+    }))
+}
+
+pub async fn submit_commit(
+    State(state): State<AppState>,
+    caller: Authenticated,
+    Path(group_id): Path<String>,
+    Json(payload): Json<SubmitCommitRequest>,
+) -> Result<JsonResponse<SubmitResponse>, StatusCode> {
+    let device_id = caller.device(payload.device_id.as_deref())?;
+    let (_, org_id) = authorize_tenant(&state, &caller.0, Permission::SendMessages)?;
+    let group_id = decode_group_id(&group_id)?;
+    let welcomes = payload
+        .welcomes
+        .into_iter()
+        .map(|upload| (upload.device_id, upload.welcome))
+        .collect();
+
+    let epoch = state
+        .delivery
+        .submit_commit(&org_id, &group_id, &device_id, payload.commit, welcomes, |device| {
+            device_in_organisation(&state, &org_id, device)
+        })
+        .await
+        .map_err(delivery_status)?;
+
+    Ok(JsonResponse(SubmitResponse {
+        success: true,
+        epoch: Some(epoch),
+        group_sequence: None,
+    }))
+}
+
+pub async fn submit_application(
+    State(state): State<AppState>,
+    caller: Authenticated,
+    Path(group_id): Path<String>,
+    Json(payload): Json<SubmitApplicationRequest>,
+) -> Result<JsonResponse<SubmitResponse>, StatusCode> {
+    let device_id = caller.device(payload.device_id.as_deref())?;
+    let (_, org_id) = authorize_tenant(&state, &caller.0, Permission::SendMessages)?;
+    let group_id = decode_group_id(&group_id)?;
+    let (epoch, sequence) = state
+        .delivery
+        .submit_application(&org_id, &group_id, &device_id, payload.message)
+        .await
+        .map_err(delivery_status)?;
+
+    Ok(JsonResponse(SubmitResponse {
+        success: true,
+        epoch: Some(epoch),
+        group_sequence: Some(sequence),
+    }))
+}
+
+/// Frames queued for the caller's device after `after`, for clients without a socket
+///
+/// Nothing leaves the queue until it is acknowledged.
+pub async fn fetch_inbox(
+    State(state): State<AppState>,
+    caller: Authenticated,
+    Query(query): Query<InboxQuery>,
+) -> Result<JsonResponse<InboxResponse>, StatusCode> {
+    let device_id = caller.device(query.device_id.as_deref())?;
+    let pending = state
+        .queues
+        .pending_after(&device_id, query.after)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+    Ok(JsonResponse(InboxResponse {
+        frames: pending.into_iter().map(|queued| queued.frame).collect(),
+    }))
+}
+
+pub async fn ack_inbox(
+    State(state): State<AppState>,
+    caller: Authenticated,
+    Json(payload): Json<AckRequest>,
+) -> Result<JsonResponse<AckResponse>, StatusCode> {
+    let device_id = caller.device(payload.device_id.as_deref())?;
+    let acknowledged = state
+        .queues
+        .ack(&device_id, payload.sequence)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+    Ok(JsonResponse(AckResponse { acknowledged }))
+}
+
+pub async fn claim_welcomes(
+    State(state): State<AppState>,
+    caller: Authenticated,
+    Query(query): Query<DeviceQuery>,
+) -> Result<JsonResponse<WelcomesResponse>, StatusCode> {
+    let device_id = caller.device(query.device_id.as_deref())?;
+    Ok(JsonResponse(WelcomesResponse {
+        welcomes: state.delivery.claim_welcomes(&device_id),
+    }))
+}
+
+pub async fn fetch_dead_letters(
+    State(state): State<AppState>,
+    caller: Authenticated,
+    Query(query): Query<DeviceQuery>,
+) -> Result<JsonResponse<DeadLettersResponse>, StatusCode> {
+    let device_id = caller.device(query.device_id.as_deref())?;
+    let dead_letters = state
+        .queues
+        .dead_letters(&device_id)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+    Ok(JsonResponse(DeadLettersResponse {
+        frames: dead_letters.into_iter().map(|queued| queued.frame).collect(),
+    }))
+}
+
+fn decode_group_id(encoded: &str) -> Result<Vec<u8>, StatusCode> {
+    URL_SAFE_NO_PAD.decode(encoded).map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+fn delivery_status(error: DeliveryError) -> StatusCode {
+    match error {
+        DeliveryError::UnknownGroup => StatusCode::NOT_FOUND,
+        DeliveryError::GroupExists => StatusCode::CONFLICT,
+        DeliveryError::NotMember | DeliveryError::OtherOrganisation(_) => StatusCode::FORBIDDEN,
+        DeliveryError::StaleEpoch { .. } | DeliveryError::FutureEpoch { .. } => StatusCode::CONFLICT,
+        DeliveryError::UnexpectedWelcome(_) | DeliveryError::Malformed(_) => StatusCode::BAD_REQUEST,
+        DeliveryError::Queue(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
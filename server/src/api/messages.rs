@@ -1,17 +1,28 @@
 //! Message API endpoints
 
 use axum::{
-    extract::{Json, Query},
+    extract::{Json, Query, State},
     http::StatusCode,
     response::Json as JsonResponse,
 };
 use serde::{Deserialize, Serialize};
-use xipr_core::protocol::transport::Message;
+use xipr_core::protocol::auth::Permission;
+use xipr_core::protocol::fanout::{DeliveryReport, DeviceCopy};
+use xipr_core::protocol::transport::{Message, MessageRouter, MessageType};
+
+use crate::api::{authorize, Authenticated};
+use crate::state::AppState;
 
 #[derive(Debug, Deserialize)]
 pub struct SendMessageRequest {
+    /// Recipient user id
     pub recipient_id: String,
-    pub content: Vec<u8>,
+    /// One copy per recipient device
+    pub copies: Vec<DeviceCopy>,
+    /// One copy per other device of the sender
+    #[serde(default)]
+    pub sync_copies: Vec<DeviceCopy>,
+    /// `text` or `binary`
     pub message_type: String,
 }
 
@@ -26,6 +37,7 @@ pub struct GetMessagesQuery {
 pub struct SendMessageResponse {
     pub success: bool,
     pub message_id: Option<String>,
+    pub report: Option<DeliveryReport>,
     pub error: Option<String>,
 }
 
@@ -36,24 +48,69 @@ pub struct GetMessagesResponse {
 }
 
 pub async fn send_message(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
     Json(payload): Json<SendMessageRequest>,
 ) -> Result<JsonResponse<SendMessageResponse>, StatusCode> {
-    // This would validate the request and store the message
+    let sender = authorize(&state, &session, Permission::SendMessages)?;
+    let message_type = match payload.message_type.parse() {
+        Ok(message_type @ (MessageType::Text | MessageType::Binary)) => message_type,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    // Recipients in other organisations look the same as unknown ones
+    if !state.auth.same_organisation(&session.user_id, &payload.recipient_id) {
+        let deleted = sender.org_id.is_some_and(|org_id| {
+            state
+                .auth
+                .org_tombstone(&org_id, &payload.recipient_id)
+                .is_some()
+        });
+        return Err(if deleted {
+            StatusCode::GONE
+        } else {
+            StatusCode::NOT_FOUND
+        });
+    }
+    state.devices.touch(&session.device_id);
+    
     let message = Message::new(
-        "sender_id".to_string(), // Would come from auth
+        session.user_id.clone(),
         payload.recipient_id,
-        xipr_core::protocol::transport::MessageType::Text,
-        payload.content,
+        message_type,
+        Vec::new(),
+    );
+    
+    let fan_out = MessageRouter::fan_out(
+        &message,
+        &session.device_id,
+        payload.copies,
+        payload.sync_copies,
+        state.devices.as_ref(),
     );
     
+    for (device_id, delivery) in fan_out.deliveries {
+        let frame = delivery.to_frame().map_err(|_| StatusCode::BAD_REQUEST)?;
+        state
+            .queues
+            .enqueue(&device_id, frame)
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    }
+    
+    // Missing devices need a copy encrypted for them; the sender resends just those
+    let report = fan_out.report;
+    let error = (!report.missing.is_empty()).then(|| "No copy for some active devices".to_string());
+    
     Ok(JsonResponse(SendMessageResponse {
-        success: true,
+        success: error.is_none(),
         message_id: Some(message.id),
-        error: None,
+        report: Some(report),
+        error,
     }))
 }
 
 pub async fn get_messages(
+    Authenticated(_session): Authenticated,
     Query(_query): Query<GetMessagesQuery>,
 ) -> Result<JsonResponse<GetMessagesResponse>, StatusCode> {
     // This would fetch messages from storage
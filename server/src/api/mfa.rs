@@ -0,0 +1,154 @@
+//! Second factor and step-up API endpoints
+
+use axum::{
+    extract::{Json, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use xipr_core::protocol::mfa::SecondFactor;
+
+use crate::api::{Authenticated, SteppedUp};
+use crate::mfa::MfaError;
+use crate::state::AppState;
+
+/// Issuer shown next to the account in authenticator apps
+const TOTP_ISSUER: &str = "XIPRNET";
+
+#[derive(Debug, Serialize)]
+pub struct MfaStatusResponse {
+    pub totp_enabled: bool,
+    pub recovery_codes_remaining: usize,
+    /// When the calling session's step-up lapses, if it is stepped up
+    pub stepped_up_until: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EnrolmentResponse {
+    /// Base32 secret, for typing into an authenticator app
+    pub secret: String,
+    /// `otpauth://` URI, for showing as a QR code
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEnrolmentRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown once; the server keeps only digests
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StepUpRequest {
+    pub second_factor: SecondFactor,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepUpResponse {
+    pub stepped_up_until: i64,
+}
+
+pub async fn status(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+) -> JsonResponse<MfaStatusResponse> {
+    let now = chrono::Utc::now().timestamp();
+    JsonResponse(MfaStatusResponse {
+        totp_enabled: state.mfa.is_enrolled(&session.user_id),
+        recovery_codes_remaining: state.mfa.recovery_codes_remaining(&session.user_id),
+        stepped_up_until: state.mfa.stepped_up_until(&session, now),
+    })
+}
+
+/// Start TOTP enrolment; the secret is unused until confirmed with a code
+pub async fn begin_enrolment(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+) -> Result<JsonResponse<EnrolmentResponse>, Response> {
+    let secret = state
+        .mfa
+        .begin_enrolment(&session.user_id)
+        .map_err(mfa_error)?;
+
+    Ok(JsonResponse(EnrolmentResponse {
+        secret: secret.to_base32(),
+        uri: secret.provisioning_uri(TOTP_ISSUER, &session.user_id),
+    }))
+}
+
+/// Confirm TOTP enrolment with a code from the app and get recovery codes
+pub async fn confirm_enrolment(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Json(payload): Json<ConfirmEnrolmentRequest>,
+) -> Result<JsonResponse<RecoveryCodesResponse>, Response> {
+    let now = chrono::Utc::now().timestamp();
+    let recovery_codes = state
+        .mfa
+        .confirm_enrolment(&session.user_id, &payload.code, now)
+        .map_err(mfa_error)?;
+    state.mfa.step_up(&session, now);
+
+    Ok(JsonResponse(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turn off TOTP and discard the recovery codes
+pub async fn disable(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+) -> Result<StatusCode, Response> {
+    state.mfa.disable(&session.user_id).map_err(mfa_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replace the recovery codes; the old ones stop working
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+) -> Result<JsonResponse<RecoveryCodesResponse>, Response> {
+    let recovery_codes = state
+        .mfa
+        .regenerate_recovery_codes(&session.user_id)
+        .map_err(mfa_error)?;
+
+    Ok(JsonResponse(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Prove a second factor again to unlock sensitive actions for a few minutes
+pub async fn step_up(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Json(payload): Json<StepUpRequest>,
+) -> Result<JsonResponse<StepUpResponse>, Response> {
+    let now = chrono::Utc::now().timestamp();
+    state
+        .mfa
+        .verify(&session.user_id, &payload.second_factor, now)
+        .map_err(mfa_error)?;
+
+    Ok(JsonResponse(StepUpResponse {
+        stepped_up_until: state.mfa.step_up(&session, now),
+    }))
+}
+
+pub(crate) fn mfa_error(error: MfaError) -> Response {
+    match error {
+        MfaError::NotEnrolled | MfaError::AlreadyEnrolled | MfaError::NoPendingEnrolment => {
+            StatusCode::CONFLICT.into_response()
+        }
+        MfaError::InvalidCode => StatusCode::FORBIDDEN.into_response(),
+        MfaError::LockedOut(until) => {
+            let retry_after = (until - chrono::Utc::now().timestamp()).max(1);
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+            )
+                .into_response()
+        }
+        MfaError::Sealing => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
@@ -1,5 +1,392 @@
 //! API endpoints for XIPRNET server
 
+use axum::{
+    body::{self, Body},
+    extract::{FromRequestParts, Request, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Router,
+};
+use xipr_core::protocol::auth::{Permission, Session, User};
+use xipr_core::protocol::proof::{ProofTarget, RequestProof, PROOF_HEADER};
+
+use crate::state::AppState;
+
+pub mod account;
 pub mod auth;
+pub mod devices;
+pub mod groups;
 pub mod messages;
-pub mod sync;
\ No newline at end of file
+pub mod mfa;
+pub mod orgs;
+pub mod passkeys;
+pub mod realtime;
+pub mod sealed;
+pub mod sync;
+
+/// Every API route, behind the session-binding middleware
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/api/v1/auth/register", post(auth::register))
+        .route("/api/v1/auth/login", post(auth::login))
+        .route("/api/v1/auth/refresh", post(auth::refresh))
+        .route("/api/v1/auth/logout", post(auth::logout))
+        .route("/api/v1/auth/me", get(auth::current_user))
+        .route("/api/v1/account", delete(account::delete_account))
+        .route("/api/v1/account/deletion-key", get(account::deletion_key))
+        .route("/api/v1/auth/step-up", post(mfa::step_up))
+        .route("/api/v1/auth/mfa", get(mfa::status))
+        .route(
+            "/api/v1/auth/mfa/totp",
+            post(mfa::begin_enrolment).delete(mfa::disable),
+        )
+        .route(
+            "/api/v1/auth/mfa/totp/confirm",
+            post(mfa::confirm_enrolment),
+        )
+        .route(
+            "/api/v1/auth/mfa/recovery-codes",
+            post(mfa::regenerate_recovery_codes),
+        )
+        .route("/api/v1/auth/passkeys", get(passkeys::list_passkeys))
+        .route(
+            "/api/v1/auth/passkeys/{credential_id}",
+            delete(passkeys::remove_passkey),
+        )
+        .route(
+            "/api/v1/auth/passkeys/registrations",
+            post(passkeys::start_registration),
+        )
+        .route(
+            "/api/v1/auth/passkeys/registrations/{ceremony_id}",
+            post(passkeys::finish_registration),
+        )
+        .route(
+            "/api/v1/auth/passkeys/assertions",
+            post(passkeys::start_authentication),
+        )
+        .route(
+            "/api/v1/auth/passkeys/assertions/{ceremony_id}",
+            post(passkeys::finish_authentication),
+        )
+        .route("/api/v1/orgs", post(orgs::create_organisation))
+        .route("/api/v1/orgs", get(orgs::list_organisations))
+        .route("/api/v1/orgs/{org_id}/members", get(orgs::list_members))
+        .route("/api/v1/orgs/{org_id}/members", post(orgs::add_member))
+        .route(
+            "/api/v1/orgs/{org_id}/members/{user_id}/roles",
+            put(orgs::set_roles),
+        )
+        .route(
+            "/api/v1/orgs/{org_id}/members/{user_id}/devices",
+            get(orgs::member_devices),
+        )
+        .route(
+            "/api/v1/orgs/{org_id}/tombstones",
+            get(orgs::list_tombstones),
+        )
+        .route("/api/v1/messages", post(messages::send_message))
+        .route("/api/v1/messages", get(messages::get_messages))
+        .route("/api/v1/sync", post(sync::sync_messages))
+        .route("/api/v1/ws", get(realtime::connect))
+        .route("/api/v1/devices", get(devices::list_devices))
+        .route(
+            "/api/v1/devices/{device_id}",
+            delete(devices::revoke_device),
+        )
+        .route(
+            "/api/v1/devices/{device_id}/pre-key",
+            get(devices::claim_pre_key),
+        )
+        .route("/api/v1/devices/pre-keys", put(devices::upload_pre_keys))
+        .route("/api/v1/devices/links", post(devices::open_link))
+        .route(
+            "/api/v1/devices/links/{provisioning_id}",
+            put(devices::grant_link).get(devices::link_envelope),
+        )
+        .route(
+            "/api/v1/devices/links/{provisioning_id}/complete",
+            post(devices::complete_link),
+        )
+        .route("/api/v1/groups", post(groups::create_group))
+        .route("/api/v1/groups/{group_id}", get(groups::get_group))
+        .route(
+            "/api/v1/groups/{group_id}/commits",
+            post(groups::submit_commit),
+        )
+        .route(
+            "/api/v1/groups/{group_id}/messages",
+            post(groups::submit_application),
+        )
+        .route("/api/v1/delivery/inbox", get(groups::fetch_inbox))
+        .route("/api/v1/delivery/ack", post(groups::ack_inbox))
+        .route("/api/v1/delivery/welcomes", get(groups::claim_welcomes))
+        .route(
+            "/api/v1/delivery/dead-letters",
+            get(groups::fetch_dead_letters),
+        )
+        .route(
+            "/api/v1/sealed/certificate",
+            post(sealed::issue_certificate),
+        )
+        .route("/api/v1/sealed/signing-key", get(sealed::signing_key))
+        .route("/api/v1/sealed/token", put(sealed::set_delivery_token))
+        .route("/api/v1/sealed/{device_id}/messages", post(sealed::deliver))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state)
+}
+
+async fn health_check() -> &'static str {
+    "OK"
+}
+
+/// Token from an `Authorization: Bearer` header
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::to_string)
+}
+
+/// Largest body buffered to check a request proof, matching axum's default body limit
+const MAX_PROOF_BODY: usize = 2 * 1024 * 1024;
+
+/// Session for a token, with the proof of possession its device requires
+///
+/// Devices that bound a signing key must prove every use of their sessions.
+/// Devices without one pass on the token alone, unless proofs are required.
+pub(crate) fn bind_session(
+    state: &AppState,
+    target: &ProofTarget<'_>,
+    proof: Option<&str>,
+) -> Result<Session, AuthRejection> {
+    let session = state
+        .auth
+        .validate_session(target.token)
+        .ok_or(AuthRejection::InvalidToken)?;
+
+    let Some(key) = state.devices.signing_key(&session.device_id) else {
+        if state.auth.requires_device_proof() {
+            return Err(AuthRejection::InvalidProof);
+        }
+        return Ok(session);
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let proof: RequestProof = proof
+        .ok_or(AuthRejection::InvalidProof)?
+        .parse()
+        .map_err(|_| AuthRejection::InvalidProof)?;
+    proof
+        .verify(&key, target, now)
+        .map_err(|_| AuthRejection::InvalidProof)?;
+    if !state.auth.accept_proof_nonce(&proof, now) {
+        return Err(AuthRejection::InvalidProof);
+    }
+    Ok(session)
+}
+
+/// Outcome of binding a request to a session, left for [`Authenticated`]
+#[derive(Debug, Clone)]
+struct SessionBinding(Result<Session, AuthRejection>);
+
+/// Middleware binding requests with a bearer token to their session
+///
+/// Never rejects by itself: endpoints that need a session say so by taking
+/// [`Authenticated`], and public endpoints ignore the outcome.
+pub(crate) async fn authenticate(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = bearer_token(request.headers()) else {
+        return next.run(request).await;
+    };
+
+    let Some(proof) = request
+        .headers()
+        .get(PROOF_HEADER)
+        .and_then(|proof| proof.to_str().ok())
+        .map(str::to_string)
+    else {
+        let target = ProofTarget {
+            method: request.method().as_str(),
+            path: request.uri().path(),
+            query: request.uri().query().unwrap_or_default(),
+            body: &[],
+            token: &token,
+        };
+        let binding = bind_session(&state, &target, None);
+        request.extensions_mut().insert(SessionBinding(binding));
+        return next.run(request).await;
+    };
+
+    // The proof covers a hash of the body, so it has to be read up front
+    let (mut parts, body) = request.into_parts();
+    let body = match body::to_bytes(body, MAX_PROOF_BODY).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+
+    let target = ProofTarget {
+        method: parts.method.as_str(),
+        path: parts.uri.path(),
+        query: parts.uri.query().unwrap_or_default(),
+        body: &body,
+        token: &token,
+    };
+    let binding = bind_session(&state, &target, Some(&proof));
+    parts.extensions.insert(SessionBinding(binding));
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// Session bound to a request by its bearer token and, for device-bound sessions, its proof
+///
+/// Handlers take the caller's user and device from here, never from the request body.
+#[derive(Debug, Clone)]
+pub struct Authenticated(pub Session);
+
+impl Authenticated {
+    /// The caller's device, or `403` if the request names another one
+    pub fn device(&self, claimed: Option<&str>) -> Result<String, StatusCode> {
+        match claimed {
+            Some(device_id) if device_id != self.0.device_id => Err(StatusCode::FORBIDDEN),
+            _ => Ok(self.0.device_id.clone()),
+        }
+    }
+
+    /// The caller's user, or `403` if the request names another one
+    pub fn user(&self, claimed: Option<&str>) -> Result<String, StatusCode> {
+        match claimed {
+            Some(user_id) if user_id != self.0.user_id => Err(StatusCode::FORBIDDEN),
+            _ => Ok(self.0.user_id.clone()),
+        }
+    }
+}
+
+impl FromRequestParts<AppState> for Authenticated {
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<SessionBinding>() {
+            Some(SessionBinding(binding)) => binding.clone().map(Self),
+            None => Err(AuthRejection::MissingToken),
+        }
+    }
+}
+
+/// The caller, or `403` unless one of their roles grants the permission
+pub(crate) fn authorize(
+    state: &AppState,
+    session: &Session,
+    permission: Permission,
+) -> Result<User, StatusCode> {
+    state
+        .auth
+        .authorize(&session.user_id, permission)
+        .ok_or(StatusCode::FORBIDDEN)
+}
+
+/// The caller and their organisation, or `403` unless they hold the permission in one
+pub(crate) fn authorize_tenant(
+    state: &AppState,
+    session: &Session,
+    permission: Permission,
+) -> Result<(User, String), StatusCode> {
+    let user = authorize(state, session, permission)?;
+    let org_id = user.org_id.clone().ok_or(StatusCode::FORBIDDEN)?;
+    Ok((user, org_id))
+}
+
+/// The caller, holding the permission in the named organisation
+///
+/// Another organisation gets `404`, the same as one that does not exist.
+pub(crate) fn authorize_in(
+    state: &AppState,
+    session: &Session,
+    org_id: &str,
+    permission: Permission,
+) -> Result<User, StatusCode> {
+    let (user, own) = authorize_tenant(state, session, permission)?;
+    if own != org_id {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(user)
+}
+
+/// Whether a registered device belongs to a user of the organisation
+pub(crate) fn device_in_organisation(state: &AppState, org_id: &str, device_id: &str) -> bool {
+    state
+        .devices
+        .owner(device_id)
+        .is_some_and(|user_id| state.auth.org_member(org_id, &user_id).is_some())
+}
+
+/// Session that proved a second factor recently enough for sensitive actions
+///
+/// See [`crate::mfa::MfaService::stepped_up_until`].
+#[derive(Debug, Clone)]
+pub struct SteppedUp(pub Session);
+
+impl FromRequestParts<AppState> for SteppedUp {
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Authenticated(session) = Authenticated::from_request_parts(parts, state).await?;
+        let now = chrono::Utc::now().timestamp();
+        match state.mfa.stepped_up_until(&session, now) {
+            Some(_) => Ok(Self(session)),
+            None => Err(AuthRejection::StepUpRequired),
+        }
+    }
+}
+
+/// Why a request could not be bound to a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthRejection {
+    MissingToken,
+    /// Malformed, expired, revoked or signed with an unknown key
+    InvalidToken,
+    /// Proof of possession missing, invalid, stale or replayed
+    InvalidProof,
+    /// Valid session, but the action needs a recent second factor
+    StepUpRequired,
+}
+
+impl IntoResponse for AuthRejection {
+    fn into_response(self) -> Response {
+        // RFC 6750: a bare challenge when no credentials were sent
+        let challenge = match self {
+            Self::MissingToken => "Bearer",
+            Self::InvalidToken => "Bearer error=\"invalid_token\"",
+            Self::InvalidProof => {
+                "Bearer error=\"invalid_token\", error_description=\"proof of possession required\""
+            }
+            // RFC 9470
+            Self::StepUpRequired => {
+                "Bearer error=\"insufficient_user_authentication\", error_description=\"step-up authentication required\""
+            }
+        };
+        (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, HeaderValue::from_static(challenge))],
+        )
+            .into_response()
+    }
+}
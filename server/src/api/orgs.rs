@@ -0,0 +1,263 @@
+//! Organisation API endpoints
+//!
+//! Operators create organisations along with their first org-admin, and
+//! never see inside them afterwards. Org-admins add members and assign
+//! roles; auditors read member and device metadata. Every route is scoped
+//! to the caller's own organisation, and others answer `404`.
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Json as JsonResponse,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use xipr_core::protocol::auth::{Organisation, Permission, Role, User};
+use xipr_core::protocol::deletion::Tombstone;
+
+use crate::api::devices::{device_info, DeviceInfo};
+use crate::api::{authorize, authorize_in, Authenticated, SteppedUp};
+use crate::auth::DirectoryError;
+use crate::state::AppState;
+
+#[derive(Debug, Deserialize)]
+pub struct NewMember {
+    pub username: String,
+    pub email: String,
+    #[serde(default = "default_roles")]
+    pub roles: Vec<Role>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateOrganisationRequest {
+    pub name: String,
+    /// First org-admin; the role is added if missing
+    pub admin: NewMember,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateOrganisationResponse {
+    pub organisation: Organisation,
+    pub admin: NewMemberResponse,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrganisationListResponse {
+    pub organisations: Vec<Organisation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberInfo {
+    pub user_id: String,
+    pub username: String,
+    pub email: String,
+    pub roles: Vec<Role>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NewMemberResponse {
+    #[serde(flatten)]
+    pub member: MemberInfo,
+    /// One-time token the new user logs in with to enrol a factor; shown once
+    pub enrolment_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberListResponse {
+    pub members: Vec<MemberInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRolesRequest {
+    pub roles: Vec<Role>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberDevicesResponse {
+    pub devices: Vec<DeviceInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TombstoneListResponse {
+    pub tombstones: Vec<Tombstone>,
+}
+
+fn default_roles() -> Vec<Role> {
+    vec![Role::Member]
+}
+
+/// Create an organisation and its first org-admin; operators only
+pub async fn create_organisation(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+    Json(payload): Json<CreateOrganisationRequest>,
+) -> Result<JsonResponse<CreateOrganisationResponse>, StatusCode> {
+    authorize(&state, &session, Permission::ManageOrganisations)?;
+    let mut roles = organisation_roles(payload.admin.roles)?;
+    if !roles.contains(&Role::OrgAdmin) {
+        roles.push(Role::OrgAdmin);
+    }
+
+    let organisation = state.auth.create_organisation(payload.name);
+    let admin = state
+        .auth
+        .create_user(
+            payload.admin.username,
+            payload.admin.email,
+            Some(organisation.id.clone()),
+            roles,
+        )
+        .map_err(directory_status)?;
+    let admin = new_member(&state, admin)?;
+    info!(
+        "Organisation {} created by operator {}",
+        organisation.id, session.user_id
+    );
+
+    Ok(JsonResponse(CreateOrganisationResponse {
+        organisation,
+        admin,
+    }))
+}
+
+/// Every organisation, without its members; operators only
+pub async fn list_organisations(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+) -> Result<JsonResponse<OrganisationListResponse>, StatusCode> {
+    authorize(&state, &session, Permission::ManageOrganisations)?;
+    Ok(JsonResponse(OrganisationListResponse {
+        organisations: state.auth.organisations(),
+    }))
+}
+
+pub async fn list_members(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Path(org_id): Path<String>,
+) -> Result<JsonResponse<MemberListResponse>, StatusCode> {
+    authorize_in(&state, &session, &org_id, Permission::ViewMembers)?;
+    let members = state
+        .auth
+        .org_members(&org_id)
+        .into_iter()
+        .map(member_info)
+        .collect();
+
+    Ok(JsonResponse(MemberListResponse { members }))
+}
+
+pub async fn add_member(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+    Path(org_id): Path<String>,
+    Json(payload): Json<NewMember>,
+) -> Result<JsonResponse<NewMemberResponse>, StatusCode> {
+    authorize_in(&state, &session, &org_id, Permission::ManageMembers)?;
+    let roles = organisation_roles(payload.roles)?;
+    let member = state
+        .auth
+        .create_user(payload.username, payload.email, Some(org_id.clone()), roles)
+        .map_err(directory_status)?;
+    info!(
+        "User {} added to organisation {} by {}",
+        member.id, org_id, session.user_id
+    );
+
+    Ok(JsonResponse(new_member(&state, member)?))
+}
+
+pub async fn set_roles(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+    Path((org_id, user_id)): Path<(String, String)>,
+    Json(payload): Json<SetRolesRequest>,
+) -> Result<JsonResponse<MemberInfo>, StatusCode> {
+    authorize_in(&state, &session, &org_id, Permission::ManageMembers)?;
+    let member = state
+        .auth
+        .set_roles(&org_id, &user_id, payload.roles)
+        .map_err(directory_status)?;
+    info!(
+        "Roles of user {} in organisation {} set to {:?} by {}",
+        member.id, org_id, member.roles, session.user_id
+    );
+
+    Ok(JsonResponse(member_info(member)))
+}
+
+/// Device metadata of one member, for admins and auditors
+pub async fn member_devices(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Path((org_id, user_id)): Path<(String, String)>,
+) -> Result<JsonResponse<MemberDevicesResponse>, StatusCode> {
+    authorize_in(&state, &session, &org_id, Permission::ViewMembers)?;
+    let member = state
+        .auth
+        .org_member(&org_id, &user_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let now = chrono::Utc::now().timestamp();
+    let devices = state
+        .devices
+        .devices_of(&member.id)
+        .into_iter()
+        .map(|record| device_info(record, &session.device_id, now))
+        .collect();
+
+    Ok(JsonResponse(MemberDevicesResponse { devices }))
+}
+
+/// Signed tombstones of the organisation's deleted users
+pub async fn list_tombstones(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Path(org_id): Path<String>,
+) -> Result<JsonResponse<TombstoneListResponse>, StatusCode> {
+    authorize_in(&state, &session, &org_id, Permission::ViewMembers)?;
+    Ok(JsonResponse(TombstoneListResponse {
+        tombstones: state.auth.org_tombstones(&org_id),
+    }))
+}
+
+/// Roles for a user of an organisation; operator is a platform role
+fn organisation_roles(roles: Vec<Role>) -> Result<Vec<Role>, StatusCode> {
+    if roles.contains(&Role::Operator) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(roles)
+}
+
+fn member_info(user: User) -> MemberInfo {
+    MemberInfo {
+        user_id: user.id,
+        username: user.username,
+        email: user.email,
+        roles: user.roles,
+        created_at: user.created_at,
+    }
+}
+
+/// A just-created user with the enrolment token they start from
+fn new_member(state: &AppState, user: User) -> Result<NewMemberResponse, StatusCode> {
+    let now = chrono::Utc::now().timestamp();
+    let enrolment_token = state
+        .mfa
+        .issue_enrolment_token(&user.id, now)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(NewMemberResponse {
+        member: member_info(user),
+        enrolment_token,
+    })
+}
+
+fn directory_status(error: DirectoryError) -> StatusCode {
+    match error {
+        DirectoryError::UnknownOrganisation | DirectoryError::UnknownUser => StatusCode::NOT_FOUND,
+        DirectoryError::UsernameTaken | DirectoryError::LastAdmin => StatusCode::CONFLICT,
+        DirectoryError::OperatorRole => StatusCode::BAD_REQUEST,
+    }
+}
@@ -0,0 +1,280 @@
+//! Passkey API endpoints
+//!
+//! Registration adds a passkey to a signed-in, stepped-up user. Login with a
+//! passkey needs no password: the assertion requires user verification, so
+//! it counts as both factors and the new session starts stepped up.
+
+use axum::{
+    extract::{ConnectInfo, Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Json as JsonResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tracing::info;
+use xipr_core::protocol::auth::Session;
+use xipr_core::protocol::throttle::ThrottleDecision;
+use xipr_core::protocol::webauthn::{
+    AssertionResponse, AttestationType, RegistrationResponse, COSE_ALG_EDDSA, COSE_ALG_ES256,
+};
+
+use crate::api::auth::{admit_device, require_provable_device, throttled};
+use crate::api::{Authenticated, SteppedUp};
+use crate::passkeys::{PasskeyError, PasskeyRecord, CEREMONY_LIFETIME};
+use crate::state::AppState;
+
+#[derive(Debug, Serialize)]
+pub struct RegistrationOptions {
+    pub ceremony_id: String,
+    pub challenge: Vec<u8>,
+    pub rp_id: String,
+    /// `user.id` for the new credential
+    pub user_handle: Vec<u8>,
+    /// COSE algorithms accepted, in order of preference
+    pub algorithms: Vec<i64>,
+    /// Credentials the user already has, so the authenticator does not add another
+    pub exclude_credentials: Vec<Vec<u8>>,
+    pub timeout_secs: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FinishRegistrationRequest {
+    pub credential: RegistrationResponse,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthenticationOptions {
+    pub ceremony_id: String,
+    pub challenge: Vec<u8>,
+    pub rp_id: String,
+    pub timeout_secs: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginRequest {
+    pub assertion: AssertionResponse,
+    /// Id the server gave this device at an earlier login; a new one is assigned otherwise
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Ed25519 public key the device will sign request proofs with
+    pub signing_key: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasskeyLoginResponse {
+    pub session: Session,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasskeyInfo {
+    /// URL-safe base64, as used in the path to remove it
+    pub credential_id: String,
+    pub name: Option<String>,
+    pub algorithm: i64,
+    pub attestation: AttestationType,
+    pub created_at: i64,
+    pub last_used: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasskeyListResponse {
+    pub passkeys: Vec<PasskeyInfo>,
+}
+
+pub async fn list_passkeys(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+) -> JsonResponse<PasskeyListResponse> {
+    let passkeys = state
+        .passkeys
+        .credentials_of(&session.user_id)
+        .into_iter()
+        .map(passkey_info)
+        .collect();
+
+    JsonResponse(PasskeyListResponse { passkeys })
+}
+
+/// Start registering a passkey for the caller
+pub async fn start_registration(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+) -> Result<JsonResponse<RegistrationOptions>, StatusCode> {
+    let now = chrono::Utc::now().timestamp();
+    let (ceremony_id, challenge) = state
+        .passkeys
+        .start_registration(&session.user_id, now)
+        .map_err(passkey_status)?;
+
+    Ok(JsonResponse(RegistrationOptions {
+        ceremony_id,
+        challenge: challenge.to_vec(),
+        rp_id: state.passkeys.relying_party().id().to_string(),
+        user_handle: session.user_id.as_bytes().to_vec(),
+        algorithms: vec![COSE_ALG_EDDSA, COSE_ALG_ES256],
+        exclude_credentials: state
+            .passkeys
+            .credentials_of(&session.user_id)
+            .into_iter()
+            .map(|record| record.credential_id)
+            .collect(),
+        timeout_secs: CEREMONY_LIFETIME,
+    }))
+}
+
+/// Finish registering a passkey with the authenticator's response
+pub async fn finish_registration(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Path(ceremony_id): Path<String>,
+    Json(payload): Json<FinishRegistrationRequest>,
+) -> Result<JsonResponse<PasskeyInfo>, StatusCode> {
+    let now = chrono::Utc::now().timestamp();
+    let record = state
+        .passkeys
+        .finish_registration(
+            &ceremony_id,
+            &session.user_id,
+            &payload.credential,
+            payload.name,
+            now,
+        )
+        .map_err(passkey_status)?;
+    info!(
+        "Passkey registered for user {} ({:?} attestation)",
+        record.user_id, record.attestation
+    );
+
+    Ok(JsonResponse(passkey_info(record)))
+}
+
+/// Remove one of the caller's passkeys
+pub async fn remove_passkey(
+    State(state): State<AppState>,
+    SteppedUp(session): SteppedUp,
+    Path(credential_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    let credential_id = URL_SAFE_NO_PAD
+        .decode(&credential_id)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    state
+        .passkeys
+        .remove(&session.user_id, &credential_id)
+        .map_err(passkey_status)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Start a passkey login; needs no session
+pub async fn start_authentication(
+    State(state): State<AppState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+) -> Result<JsonResponse<AuthenticationOptions>, StatusCode> {
+    if !state
+        .passkeys
+        .allow_authentication(&source.ip().to_string())
+    {
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let (ceremony_id, challenge) = state
+        .passkeys
+        .start_authentication(now)
+        .map_err(passkey_status)?;
+
+    Ok(JsonResponse(AuthenticationOptions {
+        ceremony_id,
+        challenge: challenge.to_vec(),
+        rp_id: state.passkeys.relying_party().id().to_string(),
+        timeout_secs: CEREMONY_LIFETIME,
+    }))
+}
+
+/// Log in with a passkey assertion
+///
+/// Failures count against the source address, which is throttled like
+/// password logins; passkeys cannot be guessed, so there is no challenge.
+pub async fn finish_authentication(
+    State(state): State<AppState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    Path(ceremony_id): Path<String>,
+    Json(payload): Json<PasskeyLoginRequest>,
+) -> Result<JsonResponse<PasskeyLoginResponse>, Response> {
+    require_provable_device(
+        &state,
+        payload.device_id.as_deref(),
+        payload.signing_key.as_deref(),
+    )
+    .map_err(IntoResponse::into_response)?;
+
+    let source = source.ip().to_string();
+    let decision = state
+        .throttle
+        .check(None, &source)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?;
+    if let ThrottleDecision::Throttled { retry_after } = decision {
+        return Err(throttled(retry_after));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let record = match state
+        .passkeys
+        .finish_authentication(&ceremony_id, &payload.assertion, now)
+    {
+        Ok(record) => record,
+        Err(e) => {
+            if matches!(
+                e,
+                PasskeyError::UnknownCredential | PasskeyError::Verification(_)
+            ) {
+                state
+                    .throttle
+                    .record_failure(None, &source, "passkey assertion refused")
+                    .await
+                    .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())?;
+            }
+            return Err(match e {
+                PasskeyError::UnknownCredential => StatusCode::UNAUTHORIZED.into_response(),
+                e => passkey_status(e).into_response(),
+            });
+        }
+    };
+
+    let (session, refresh_token) = admit_device(
+        &state,
+        &record.user_id,
+        payload.device_id.as_deref(),
+        payload.signing_key.as_deref(),
+    )
+    .map_err(IntoResponse::into_response)?;
+    state.mfa.step_up(&session, now);
+
+    Ok(JsonResponse(PasskeyLoginResponse {
+        session,
+        refresh_token,
+    }))
+}
+
+fn passkey_info(record: PasskeyRecord) -> PasskeyInfo {
+    PasskeyInfo {
+        credential_id: URL_SAFE_NO_PAD.encode(&record.credential_id),
+        name: record.name,
+        algorithm: record.public_key.algorithm(),
+        attestation: record.attestation,
+        created_at: record.created_at,
+        last_used: record.last_used,
+    }
+}
+
+fn passkey_status(error: PasskeyError) -> StatusCode {
+    match error {
+        PasskeyError::UnknownCeremony | PasskeyError::UnknownCredential => StatusCode::NOT_FOUND,
+        PasskeyError::AlreadyRegistered | PasskeyError::TooManyCredentials => StatusCode::CONFLICT,
+        PasskeyError::Verification(_) => StatusCode::UNAUTHORIZED,
+    }
+}
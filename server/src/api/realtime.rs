@@ -0,0 +1,466 @@
+//! Real-time delivery over WebSocket
+//!
+//! Each binary WebSocket message carries exactly one encoded `MessageFrame`.
+//! The server pushes queued frames as they arrive, accepts sends and
+//! acknowledgements from the client, and keeps the link alive with heartbeats.
+//! Frames that stay unacknowledged past the redelivery timeout are sent again.
+//! Clients may negotiate payload compression on the upgrade request.
+
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use xipr_core::protocol::auth::{Permission, Session};
+use xipr_core::protocol::compression::{
+    CompressionAlgorithm, CompressionOffer, CompressionParams, FrameCompressor,
+};
+use xipr_core::protocol::fanout::DeviceDirectory;
+use xipr_core::protocol::proof::ProofTarget;
+use xipr_core::protocol::transport::{
+    ControlMessage, Message, MessageFrame, MessageRouter, MessageType,
+};
+
+use crate::api::{bind_session, AuthRejection, Authenticated};
+use crate::state::AppState;
+
+/// Interval between server heartbeats
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Connections that send nothing for this long are closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Sender id used for frames originated by the server itself
+const SERVER_SENDER_ID: &str = "server";
+
+/// Upgrade response header carrying the negotiated compression, if any
+const COMPRESSION_HEADER: &str = "x-xipr-compression";
+
+const SUPPORTED_COMPRESSION: &[CompressionAlgorithm] = &[CompressionAlgorithm::Zstd];
+
+#[derive(Debug, Deserialize)]
+pub struct ConnectQuery {
+    /// Bearer token, for clients that cannot set headers on the upgrade request
+    pub token: Option<String>,
+    /// Proof of possession for `token`, for device-bound sessions
+    pub proof: Option<String>,
+    /// Last sequence number the device acknowledged before reconnecting
+    #[serde(default)]
+    pub last_ack: u64,
+    /// Compression algorithms the client accepts, comma-separated in order of preference
+    pub compression: Option<String>,
+    /// Id of a compression dictionary the client has
+    pub dictionary: Option<u32>,
+}
+
+impl ConnectQuery {
+    fn compression_offer(&self) -> Result<CompressionOffer, StatusCode> {
+        let algorithms = match &self.compression {
+            Some(list) => list
+                .split(',')
+                .filter(|name| !name.trim().is_empty())
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?,
+            None => Vec::new(),
+        };
+
+        Ok(CompressionOffer {
+            algorithms,
+            dictionary_ids: self.dictionary.into_iter().collect(),
+        })
+    }
+}
+
+/// The query string a query-borne proof signs: all of it but the proof itself
+fn unsigned_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| pair.split('=').next() != Some("proof"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+pub async fn connect(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(query): Query<ConnectQuery>,
+    authenticated: Result<Authenticated, AuthRejection>,
+) -> Result<Response, Response> {
+    let session = match authenticated {
+        Ok(Authenticated(session)) => session,
+        // A query token was not seen by the middleware, so bind it here
+        Err(AuthRejection::MissingToken) => {
+            let token = query
+                .token
+                .as_deref()
+                .ok_or_else(|| AuthRejection::MissingToken.into_response())?;
+            let signed_query = unsigned_query(uri.query().unwrap_or_default());
+            let target = ProofTarget {
+                method: Method::GET.as_str(),
+                path: uri.path(),
+                query: &signed_query,
+                body: &[],
+                token,
+            };
+            bind_session(&state, &target, query.proof.as_deref())
+                .map_err(IntoResponse::into_response)?
+        }
+        Err(rejection) => return Err(rejection.into_response()),
+    };
+
+    let dictionary = state.compression_dictionary.as_deref();
+    let offer = query
+        .compression_offer()
+        .map_err(IntoResponse::into_response)?;
+    let params = CompressionParams::negotiate(&offer, SUPPORTED_COMPRESSION, dictionary);
+    let compressor = params
+        .map(|params| FrameCompressor::new(params, dictionary.cloned()))
+        .transpose()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+
+    let last_ack = query.last_ack;
+    let mut response =
+        ws.on_upgrade(move |socket| run_socket(socket, state, session, last_ack, compressor));
+    if let Some(params) = params {
+        if let Ok(value) = HeaderValue::from_str(&params.to_string()) {
+            response.headers_mut().insert(COMPRESSION_HEADER, value);
+        }
+    }
+    Ok(response)
+}
+
+/// Frames pushed on a link that the device has not acknowledged yet
+pub(crate) struct InFlight {
+    last_acked: u64,
+    last_sent: u64,
+    sent: VecDeque<(u64, Instant)>,
+}
+
+impl InFlight {
+    pub(crate) fn new(last_acked: u64) -> Self {
+        Self {
+            last_acked,
+            last_sent: last_acked,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Highest sequence pushed so far; the next push starts after it
+    pub(crate) fn last_sent(&self) -> u64 {
+        self.last_sent
+    }
+
+    pub(crate) fn sent(&mut self, sequence: u64) {
+        self.last_sent = sequence;
+        self.sent.push_back((sequence, Instant::now()));
+    }
+
+    pub(crate) fn acked(&mut self, sequence: u64) {
+        self.last_acked = self.last_acked.max(sequence);
+        while self.sent.front().is_some_and(|(sent, _)| *sent <= sequence) {
+            self.sent.pop_front();
+        }
+    }
+
+    /// Rewind to the last ack if the oldest unacknowledged frame has timed out
+    pub(crate) fn rewind_if_stale(&mut self, timeout: Duration) -> bool {
+        if self
+            .sent
+            .front()
+            .is_none_or(|(_, at)| at.elapsed() < timeout)
+        {
+            return false;
+        }
+
+        self.last_sent = self.last_acked;
+        self.sent.clear();
+        true
+    }
+}
+
+async fn run_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    session: Session,
+    last_ack: u64,
+    compressor: Option<FrameCompressor>,
+) {
+    let compressor = compressor.as_ref();
+    let device_id = session.device_id.clone();
+    state.devices.touch(&device_id);
+
+    // Everything the device acknowledged before reconnecting can go; the rest is replayed
+    if let Err(e) = state.queues.ack(&device_id, last_ack).await {
+        warn!("Failed to ack queue for device {}: {}", device_id, e);
+        return;
+    }
+
+    let mut updates = state.queues.subscribe(&device_id);
+    let mut in_flight = InFlight::new(last_ack);
+    let mut last_seen = Instant::now();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let redelivery_timeout = state.queues.config().redelivery_timeout;
+    let mut redelivery = tokio::time::interval(redelivery_timeout);
+
+    if push_pending(&mut socket, &state, &device_id, &mut in_flight, compressor)
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            changed = updates.changed() => {
+                if changed.is_err()
+                    || push_pending(&mut socket, &state, &device_id, &mut in_flight, compressor).await.is_err()
+                {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                match incoming {
+                    Some(Ok(WsMessage::Binary(bytes))) => {
+                        last_seen = Instant::now();
+                        let handled = match decode_frame(&bytes, compressor) {
+                            Ok(frame) => handle_frame(&state, &session, frame).await,
+                            Err(reason) => Err(reason),
+                        };
+                        match handled {
+                            Ok(Handled::Acked(acked)) => in_flight.acked(acked),
+                            Ok(Handled::Nothing) => {}
+                            Ok(Handled::Rejected { reply, .. }) => {
+                                if send_message(&mut socket, &reply).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(reason) => {
+                                warn!("Closing socket for device {}: {}", device_id, reason);
+                                break;
+                            }
+                        }
+                    }
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => last_seen = Instant::now(),
+                }
+            }
+            _ = redelivery.tick() => {
+                if in_flight.rewind_if_stale(redelivery_timeout) {
+                    debug!("Redelivering unacknowledged frames to device {}", device_id);
+                    if push_pending(&mut socket, &state, &device_id, &mut in_flight, compressor).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > IDLE_TIMEOUT || !state.auth.is_session_active(&session) {
+                    break;
+                }
+
+                if send_heartbeat(&mut socket, &device_id).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    debug!("Socket closed for device {}", device_id);
+}
+
+/// Send every queued frame the socket has not sent yet
+async fn push_pending(
+    socket: &mut WebSocket,
+    state: &AppState,
+    device_id: &str,
+    in_flight: &mut InFlight,
+    compressor: Option<&FrameCompressor>,
+) -> Result<(), String> {
+    let pending = state
+        .queues
+        .pending_after(device_id, in_flight.last_sent())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for queued in pending {
+        // Queues hold plain frames; compression is per socket
+        let frame = match compressor {
+            Some(compressor) => compressor.compress(queued.frame),
+            None => Ok(queued.frame),
+        };
+        let Ok(encoded) = frame.and_then(|frame| frame.encode()) else {
+            warn!(
+                "Dropping unencodable frame {} for device {}",
+                queued.sequence, device_id
+            );
+            continue;
+        };
+
+        socket
+            .send(WsMessage::Binary(encoded.into()))
+            .await
+            .map_err(|e| e.to_string())?;
+        in_flight.sent(queued.sequence);
+    }
+    Ok(())
+}
+
+async fn send_heartbeat(socket: &mut WebSocket, device_id: &str) -> Result<(), axum::Error> {
+    let heartbeat = Message::new(
+        SERVER_SENDER_ID.to_string(),
+        device_id.to_string(),
+        MessageType::Heartbeat,
+        Vec::new(),
+    );
+    send_message(socket, &heartbeat).await
+}
+
+/// Send a server-originated message outside the device queue
+async fn send_message(socket: &mut WebSocket, message: &Message) -> Result<(), axum::Error> {
+    let Ok(encoded) = message.to_frame().and_then(|frame| frame.encode()) else {
+        return Ok(());
+    };
+
+    socket.send(WsMessage::Binary(encoded.into())).await
+}
+
+fn decode_frame(
+    bytes: &[u8],
+    compressor: Option<&FrameCompressor>,
+) -> Result<MessageFrame, String> {
+    let frame = MessageFrame::decode(bytes).map_err(|e| e.to_string())?;
+    if !frame.header.compressed {
+        return Ok(frame);
+    }
+
+    let compressor = compressor.ok_or("compressed frame without negotiated compression")?;
+    compressor.decompress(frame).map_err(|e| e.to_string())
+}
+
+/// What handling a client frame asks of the link
+pub(crate) enum Handled {
+    Nothing,
+    /// The client acknowledged every frame up to this sequence
+    Acked(u64),
+    /// A `ControlMessage::Rejected` to send back on the message's conversation
+    Rejected {
+        conversation_id: String,
+        reply: Message,
+    },
+}
+
+/// Handle one frame from the client
+///
+/// Errors close the link; a message refused on its own merits is answered instead.
+pub(crate) async fn handle_frame(
+    state: &AppState,
+    session: &Session,
+    frame: MessageFrame,
+) -> Result<Handled, String> {
+    let mut message = Message::from_frame(&frame).map_err(|e| e.to_string())?;
+
+    match message.message_type {
+        MessageType::Heartbeat => Ok(Handled::Nothing),
+        MessageType::Control => match ControlMessage::from_message(&message) {
+            Ok(ControlMessage::Ack { sequence }) => {
+                state
+                    .queues
+                    .ack(&session.device_id, sequence)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(Handled::Acked(sequence))
+            }
+            Ok(ControlMessage::Authenticate { .. }) => {
+                Err("link already authenticated".to_string())
+            }
+            Ok(ControlMessage::Rejected { .. } | ControlMessage::Group(_)) => {
+                Err("only the server sends rejections and group messages".to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        },
+        // Sealed envelopes go through the unauthenticated endpoint, or they would be tied
+        // to this session
+        MessageType::Sealed => Err("sealed envelope on authenticated socket".to_string()),
+        MessageType::Text | MessageType::Binary => {
+            // The sender is whoever owns the socket, not whatever the frame claims
+            message.sender_id = session.user_id.clone();
+            if state
+                .auth
+                .authorize(&session.user_id, Permission::SendMessages)
+                .is_none()
+            {
+                return Err("not permitted to send messages".to_string());
+            }
+            if !state
+                .auth
+                .same_organisation(&session.user_id, &message.recipient_id)
+            {
+                debug!(
+                    "Dropping frame from {}: recipient outside its organisation",
+                    session.device_id
+                );
+                return Ok(Handled::Nothing);
+            }
+
+            let now = chrono::Utc::now().timestamp();
+            let accepted = {
+                let mut router = state.router.lock().unwrap();
+                router.accept_message(&session.device_id, &message, now)
+            };
+            match accepted {
+                Ok(()) => {}
+                // Clients resend after reconnecting, so a replay is dropped rather than fatal
+                Err(rejection) if rejection.is_replay() => {
+                    debug!("Dropping frame from {}: {}", session.device_id, rejection);
+                    return Ok(Handled::Nothing);
+                }
+                // A skewed clock or missing field spoils this message, not the link
+                Err(rejection) => {
+                    debug!("Rejecting frame from {}: {}", session.device_id, rejection);
+                    let reply = ControlMessage::Rejected {
+                        message_id: message.id.clone(),
+                        rejection,
+                    }
+                    .to_message(SERVER_SENDER_ID.to_string(), session.device_id.clone())
+                    .map_err(|e| e.to_string())?;
+                    return Ok(Handled::Rejected {
+                        conversation_id: message.recipient_id,
+                        reply,
+                    });
+                }
+            }
+
+            let mut devices = match MessageRouter::route_to_devices(&message, state.devices.as_ref()) {
+                Ok(devices) => devices,
+                Err(e) => {
+                    debug!("Dropping frame from {}: {}", session.device_id, e);
+                    return Ok(Handled::Nothing);
+                }
+            };
+
+            // The sender's other devices get the same payload as a sync copy
+            devices.extend(state.devices.active_devices(&session.user_id));
+            devices.retain(|device| *device != session.device_id);
+            devices.sort();
+            devices.dedup();
+
+            let frame = message.to_frame().map_err(|e| e.to_string())?;
+            for device in devices {
+                state
+                    .queues
+                    .enqueue(&device, frame.clone())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(Handled::Nothing)
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Sealed-sender API endpoints
+//!
+//! Certificates and delivery tokens are managed by authenticated devices.
+//! Sealed messages themselves are delivered without authentication, gated
+//! on the recipient's delivery token and rate limits.
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Json, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::Json as JsonResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use xipr_core::protocol::sealed::{DeliveryToken, SealedEnvelope, SenderCertificate};
+
+use crate::api::Authenticated;
+use crate::state::AppState;
+
+/// Header carrying the recipient's delivery token, base64 encoded
+pub const DELIVERY_TOKEN_HEADER: &str = "x-xipr-delivery-token";
+
+#[derive(Debug, Deserialize)]
+pub struct CertificateRequest {
+    pub identity_key: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryTokenRequest {
+    pub token: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SigningKeyResponse {
+    pub key_id: u32,
+    pub public_key: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SealedDeliveryResponse {
+    pub success: bool,
+}
+
+pub async fn issue_certificate(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Json(payload): Json<CertificateRequest>,
+) -> Result<JsonResponse<SenderCertificate>, StatusCode> {
+    if payload.identity_key.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    Ok(JsonResponse(
+        state
+            .sealed
+            .issue_certificate(&session, payload.identity_key),
+    ))
+}
+
+pub async fn signing_key(State(state): State<AppState>) -> JsonResponse<SigningKeyResponse> {
+    let (key_id, public_key) = state.sealed.signing_key();
+    JsonResponse(SigningKeyResponse {
+        key_id,
+        public_key: public_key.to_vec(),
+    })
+}
+
+pub async fn set_delivery_token(
+    State(state): State<AppState>,
+    Authenticated(session): Authenticated,
+    Json(payload): Json<DeliveryTokenRequest>,
+) -> Result<StatusCode, StatusCode> {
+    let token = DeliveryToken::from_bytes(&payload.token).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    state.sealed.set_delivery_token(&session.device_id, &token);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn deliver(
+    State(state): State<AppState>,
+    ConnectInfo(source): ConnectInfo<SocketAddr>,
+    Path(device_id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<JsonResponse<SealedDeliveryResponse>, StatusCode> {
+    if !state
+        .sealed
+        .allow_delivery(&source.ip().to_string(), &device_id)
+    {
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    // Unknown devices and wrong tokens look the same, so the endpoint cannot be used to
+    // probe which devices exist
+    let token = headers
+        .get(DELIVERY_TOKEN_HEADER)
+        .and_then(|value| STANDARD.decode(value.as_bytes()).ok())
+        .and_then(|bytes| DeliveryToken::from_bytes(&bytes).ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    if !state.sealed.check_delivery_token(&device_id, &token) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let envelope = SealedEnvelope::from_bytes(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if envelope.recipient_id != device_id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // The message id and timestamp are taken from the envelope, so resending it is caught
+    let message = envelope.to_message().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let now = chrono::Utc::now().timestamp();
+    match state.sealed.accept_delivery(&message, now) {
+        Ok(()) => {}
+        Err(rejection) if rejection.is_replay() => return Err(StatusCode::CONFLICT),
+        Err(_) => return Err(StatusCode::BAD_REQUEST),
+    }
+
+    let frame = message.to_frame().map_err(|_| StatusCode::BAD_REQUEST)?;
+    state
+        .queues
+        .enqueue(&device_id, frame)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+    Ok(JsonResponse(SealedDeliveryResponse { success: true }))
+}
@@ -5,9 +5,23 @@ use axum::{
     http::StatusCode,
     response::Json as JsonResponse,
 };
-use serde::{Serialize};
+use serde::{Deserialize, Serialize};
 use xipr_core::storage::sync::{SyncRequest, SyncResponse};
 
+use crate::api::Authenticated;
+
+/// Sync request as sent by clients; the user and device come from the session
+#[derive(Debug, Deserialize)]
+pub struct SyncApiRequest {
+    /// Optional, and must match the session if present
+    pub user_id: Option<String>,
+    /// Optional, and must match the session if present
+    pub device_id: Option<String>,
+    pub last_sync_timestamp: i64,
+    #[serde(default)]
+    pub conversation_ids: Vec<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct SyncApiResponse {
     pub success: bool,
@@ -16,8 +30,16 @@ pub struct SyncApiResponse {
 }
 
 pub async fn sync_messages(
-    Json(request): Json<SyncRequest>,
+    caller: Authenticated,
+    Json(payload): Json<SyncApiRequest>,
 ) -> Result<JsonResponse<SyncApiResponse>, StatusCode> {
+    let request = SyncRequest {
+        user_id: caller.user(payload.user_id.as_deref())?,
+        device_id: caller.device(payload.device_id.as_deref())?,
+        last_sync_timestamp: payload.last_sync_timestamp,
+        conversation_ids: payload.conversation_ids,
+    };
+
     match xipr_core::storage::sync::SyncManager::sync_messages(&request) {
         Ok(sync_response) => Ok(JsonResponse(SyncApiResponse {
             success: true,
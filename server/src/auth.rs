@@ -1,23 +1,125 @@
-use xipr_core::protocol::auth::{Session, User};
-use std::collections::HashMap;
+use xipr_core::protocol::auth::{
+    Organisation, Permission, RefreshToken, Role, Session, SessionKeyring, User,
+    REFRESH_TOKEN_LIFETIME,
+};
+use xipr_core::protocol::deletion::{DeletionReceipt, DeletionSigner, PurgeSummary, Tombstone};
+use xipr_core::protocol::proof::{RequestProof, MAX_PROOF_SKEW, PROOF_NONCE_LENGTH};
+use std::collections::{HashMap, HashSet};
 use std::sync::Mutex;
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Debug, Error)]
+pub enum DirectoryError {
+    #[error("unknown organisation")]
+    UnknownOrganisation,
+    #[error("unknown user")]
+    UnknownUser,
+    #[error("username taken")]
+    UsernameTaken,
+    #[error("operator is not an organisation role")]
+    OperatorRole,
+    #[error("organisation would have no admin left")]
+    LastAdmin,
+}
+
+/// Refresh tokens issued for one session
+struct RefreshFamily {
+    /// Session as of the latest access token
+    session: Session,
+    current: [u8; 32],
+    /// Digests of tokens already rotated away; presenting one of them is reuse
+    rotated: HashSet<[u8; 32]>,
+    expires_at: i64,
+}
 
 pub struct AuthService {
     users: Mutex<HashMap<String, User>>,
-    sessions: Mutex<HashMap<String, Session>>,
+    organisations: Mutex<HashMap<String, Organisation>>,
+    keyring: SessionKeyring,
+    /// Refresh token families by session id
+    families: Mutex<HashMap<String, RefreshFamily>>,
+    /// Revoked session ids, until their tokens would have expired anyway
+    ///
+    /// Kept on this node only; other nodes accept a revoked access token until
+    /// it expires, so the access token lifetime bounds revocation across nodes.
+    revoked: Mutex<HashMap<String, i64>>,
+    /// Whether devices without a bound signing key are refused; on by default
+    require_device_proof: bool,
+    /// Nonces of accepted request proofs, until the proofs would be too old anyway
+    proof_nonces: Mutex<HashMap<[u8; PROOF_NONCE_LENGTH], i64>>,
+    /// Tombstones of deleted users by user id, kept for good
+    tombstones: Mutex<HashMap<String, Tombstone>>,
+    deletion_signer: DeletionSigner,
 }
 
 impl AuthService {
-    pub fn new() -> Self {
+    pub fn new(keyring: SessionKeyring) -> Self {
         Self {
             users: Mutex::new(HashMap::new()),
-            sessions: Mutex::new(HashMap::new()),
+            organisations: Mutex::new(HashMap::new()),
+            keyring,
+            families: Mutex::new(HashMap::new()),
+            revoked: Mutex::new(HashMap::new()),
+            require_device_proof: true,
+            proof_nonces: Mutex::new(HashMap::new()),
+            tombstones: Mutex::new(HashMap::new()),
+            deletion_signer: DeletionSigner::generate(1),
         }
     }
     
-    pub fn create_user(&self, username: String, email: String) -> Result<User, String> {
-        let user = User::new(username, email);
+    /// Sign tombstones and deletion receipts with this key rather than an ephemeral one
+    pub fn with_deletion_signer(mut self, signer: DeletionSigner) -> Self {
+        self.deletion_signer = signer;
+        self
+    }
+    
+    /// Refuse sessions of devices that have not bound a signing key, or with
+    /// `false` accept them on the bearer token alone
+    pub fn with_required_device_proof(mut self, required: bool) -> Self {
+        self.require_device_proof = required;
+        self
+    }
+    
+    pub fn requires_device_proof(&self) -> bool {
+        self.require_device_proof
+    }
+    
+    pub fn create_organisation(&self, name: String) -> Organisation {
+        let organisation = Organisation::new(name);
+        let mut organisations = self.organisations.lock().unwrap();
+        organisations.insert(organisation.id.clone(), organisation.clone());
+        organisation
+    }
+    
+    /// Every organisation, oldest first; for operators, so without their members
+    pub fn organisations(&self) -> Vec<Organisation> {
+        let organisations = self.organisations.lock().unwrap();
+        let mut all: Vec<Organisation> = organisations.values().cloned().collect();
+        all.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        all
+    }
+    
+    /// Add a user, in an organisation unless they are a platform operator
+    pub fn create_user(
+        &self,
+        username: String,
+        email: String,
+        org_id: Option<String>,
+        roles: Vec<Role>,
+    ) -> Result<User, DirectoryError> {
+        if let Some(org_id) = &org_id {
+            if !self.organisations.lock().unwrap().contains_key(org_id) {
+                return Err(DirectoryError::UnknownOrganisation);
+            }
+        }
+        
+        let mut user = User::new(username, email).with_roles(roles);
+        user.org_id = org_id;
         let mut users = self.users.lock().unwrap();
+        if users.values().any(|existing| existing.username == user.username) {
+            return Err(DirectoryError::UsernameTaken);
+        }
         users.insert(user.id.clone(), user.clone());
         Ok(user)
     }
@@ -27,29 +129,299 @@ impl AuthService {
         users.get(user_id).cloned()
     }
     
-    pub fn create_session(&self, user_id: String, device_id: String) -> Session {
-        let session = Session::new(user_id, device_id);
-        let mut sessions = self.sessions.lock().unwrap();
-        sessions.insert(session.token.clone(), session.clone());
-        session
+    pub fn user_by_username(&self, username: &str) -> Option<User> {
+        let users = self.users.lock().unwrap();
+        users.values().find(|user| user.username == username).cloned()
+    }
+    
+    /// The user, if they hold the permission through any of their roles
+    pub fn authorize(&self, user_id: &str, permission: Permission) -> Option<User> {
+        self.get_user(user_id)
+            .filter(|user| user.has_permission(permission))
+    }
+    
+    /// Whether two users belong to the same organisation
+    pub fn same_organisation(&self, user_id: &str, other_id: &str) -> bool {
+        let users = self.users.lock().unwrap();
+        match (users.get(user_id), users.get(other_id)) {
+            (Some(user), Some(other)) => user.shares_organisation(other),
+            _ => false,
+        }
+    }
+    
+    /// A user of one organisation; users of others are indistinguishable from unknown ones
+    pub fn org_member(&self, org_id: &str, user_id: &str) -> Option<User> {
+        self.get_user(user_id)
+            .filter(|user| user.org_id.as_deref() == Some(org_id))
+    }
+    
+    /// An organisation's users, oldest first
+    pub fn org_members(&self, org_id: &str) -> Vec<User> {
+        let users = self.users.lock().unwrap();
+        let mut members: Vec<User> = users
+            .values()
+            .filter(|user| user.org_id.as_deref() == Some(org_id))
+            .cloned()
+            .collect();
+        members.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        members
+    }
+    
+    /// Replace the roles of a user of one organisation
+    ///
+    /// Operator is a platform role and cannot be granted here, and the last
+    /// org-admin cannot give up the role.
+    pub fn set_roles(
+        &self,
+        org_id: &str,
+        user_id: &str,
+        roles: Vec<Role>,
+    ) -> Result<User, DirectoryError> {
+        if roles.contains(&Role::Operator) {
+            return Err(DirectoryError::OperatorRole);
+        }
+        
+        let mut users = self.users.lock().unwrap();
+        let other_admins = users
+            .values()
+            .filter(|user| user.id != user_id && user.org_id.as_deref() == Some(org_id))
+            .any(|user| user.roles.contains(&Role::OrgAdmin));
+        let user = users
+            .get_mut(user_id)
+            .filter(|user| user.org_id.as_deref() == Some(org_id))
+            .ok_or(DirectoryError::UnknownUser)?;
+        if user.roles.contains(&Role::OrgAdmin) && !roles.contains(&Role::OrgAdmin) && !other_admins {
+            return Err(DirectoryError::LastAdmin);
+        }
+        
+        user.roles = roles;
+        Ok(user.clone())
+    }
+    
+    /// Refuse to delete the last org-admin of an organisation that has other members
+    pub fn ensure_deletable(&self, user_id: &str) -> Result<(), DirectoryError> {
+        let users = self.users.lock().unwrap();
+        let user = users.get(user_id).ok_or(DirectoryError::UnknownUser)?;
+        let (Some(org_id), true) = (&user.org_id, user.roles.contains(&Role::OrgAdmin)) else {
+            return Ok(());
+        };
+        
+        let mut others = users
+            .values()
+            .filter(|other| other.id != user_id && other.org_id.as_ref() == Some(org_id))
+            .peekable();
+        if others.peek().is_some() && !others.any(|other| other.roles.contains(&Role::OrgAdmin)) {
+            return Err(DirectoryError::LastAdmin);
+        }
+        Ok(())
+    }
+    
+    /// Remove a user from the directory and publish their tombstone in its place
+    ///
+    /// Their sessions, devices and everything else are the caller's to purge first.
+    pub fn delete_user(&self, user_id: &str) -> Result<Tombstone, DirectoryError> {
+        self.ensure_deletable(user_id)?;
+        let user = self
+            .users
+            .lock()
+            .unwrap()
+            .remove(user_id)
+            .ok_or(DirectoryError::UnknownUser)?;
+        
+        let now = chrono::Utc::now().timestamp();
+        let tombstone = self.deletion_signer.tombstone(user.id, user.org_id, now);
+        let mut tombstones = self.tombstones.lock().unwrap();
+        tombstones.insert(tombstone.user_id.clone(), tombstone.clone());
+        Ok(tombstone)
+    }
+    
+    /// Signed receipt for a deletion, listing what was purged
+    pub fn deletion_receipt(&self, tombstone: &Tombstone, purged: PurgeSummary) -> DeletionReceipt {
+        self.deletion_signer.receipt(tombstone, purged)
+    }
+    
+    /// Key id and public key that tombstones and deletion receipts verify under
+    pub fn deletion_key(&self) -> (u32, [u8; 32]) {
+        (self.deletion_signer.key_id(), self.deletion_signer.public_key())
     }
     
+    /// Tombstone of a deleted user of one organisation
+    pub fn org_tombstone(&self, org_id: &str, user_id: &str) -> Option<Tombstone> {
+        let tombstones = self.tombstones.lock().unwrap();
+        tombstones
+            .get(user_id)
+            .filter(|tombstone| tombstone.org_id.as_deref() == Some(org_id))
+            .cloned()
+    }
+    
+    /// Tombstones of an organisation's deleted users, oldest first
+    pub fn org_tombstones(&self, org_id: &str) -> Vec<Tombstone> {
+        let tombstones = self.tombstones.lock().unwrap();
+        let mut deleted: Vec<Tombstone> = tombstones
+            .values()
+            .filter(|tombstone| tombstone.org_id.as_deref() == Some(org_id))
+            .cloned()
+            .collect();
+        deleted.sort_by(|a, b| (a.deleted_at, &a.user_id).cmp(&(b.deleted_at, &b.user_id)));
+        deleted
+    }
+    
+    pub fn keyring(&self) -> &SessionKeyring {
+        &self.keyring
+    }
+    
+    pub fn create_session(&self, user_id: String, device_id: String) -> Result<Session, String> {
+        self.keyring
+            .issue(user_id, device_id, chrono::Utc::now().timestamp())
+            .map_err(|e| e.to_string())
+    }
+    
+    /// Session for a token, checked against the keyring alone
     pub fn validate_session(&self, token: &str) -> Option<Session> {
-        let mut sessions = self.sessions.lock().unwrap();
-        if let Some(session) = sessions.get(token) {
-            if session.is_expired() {
-                sessions.remove(token);
-                None
-            } else {
-                Some(session.clone())
-            }
-        } else {
-            None
+        let now = chrono::Utc::now().timestamp();
+        let session = self.keyring.verify(token, now).ok()?;
+
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, expires_at| *expires_at >= now);
+        if revoked.contains_key(&session.id) {
+            return None;
         }
+        Some(session)
     }
     
     pub fn revoke_session(&self, token: &str) -> bool {
-        let mut sessions = self.sessions.lock().unwrap();
-        sessions.remove(token).is_some()
+        let Some(session) = self.validate_session(token) else {
+            return false;
+        };
+
+        self.logout(&session);
+        true
+    }
+    
+    /// Start the refresh token family for a new session
+    pub fn issue_refresh_token(&self, session: &Session) -> RefreshToken {
+        let token = RefreshToken::generate(session.id.clone());
+        let family = RefreshFamily {
+            session: session.clone(),
+            current: token.digest(),
+            rotated: HashSet::new(),
+            expires_at: chrono::Utc::now().timestamp() + REFRESH_TOKEN_LIFETIME,
+        };
+
+        let mut families = self.families.lock().unwrap();
+        families.insert(session.id.clone(), family);
+        token
+    }
+    
+    /// Exchange a refresh token for a new access token and the next refresh token
+    ///
+    /// Reuse of a token that was already exchanged revokes the whole family:
+    /// either its holder or whoever used it first has a stolen copy.
+    pub fn refresh(&self, token: &str) -> Result<(Session, RefreshToken), String> {
+        let token: RefreshToken = token.parse().map_err(|_| "malformed refresh token")?;
+        let now = chrono::Utc::now().timestamp();
+
+        let mut families = self.families.lock().unwrap();
+        families.retain(|_, family| family.expires_at >= now);
+        let family = families
+            .get_mut(token.family_id())
+            .ok_or("unknown or expired refresh token")?;
+
+        let digest = token.digest();
+        if digest == family.current {
+            let session = self
+                .keyring
+                .reissue(&family.session, now)
+                .map_err(|e| e.to_string())?;
+            let next = token.rotate();
+
+            family.rotated.insert(family.current);
+            family.current = next.digest();
+            family.expires_at = now + REFRESH_TOKEN_LIFETIME;
+            family.session = session.clone();
+            return Ok((session, next));
+        }
+
+        if family.rotated.contains(&digest) {
+            let family = families.remove(token.family_id()).unwrap();
+            warn!(
+                "Refresh token reuse for session {} of user {}; revoking the session",
+                family.session.id, family.session.user_id
+            );
+            self.revoke(&family.session);
+            return Err("refresh token reused".to_string());
+        }
+
+        Err("invalid refresh token".to_string())
+    }
+    
+    /// End a session: its refresh tokens stop working and its access tokens are revoked
+    pub fn logout(&self, session: &Session) {
+        let family = self.families.lock().unwrap().remove(&session.id);
+        self.revoke(family.as_ref().map_or(session, |family| &family.session));
+    }
+    
+    /// End every session of a device, returning how many there were
+    pub fn revoke_device(&self, device_id: &str) -> usize {
+        let mut families = self.families.lock().unwrap();
+        let sessions: Vec<String> = families
+            .values()
+            .filter(|family| family.session.device_id == device_id)
+            .map(|family| family.session.id.clone())
+            .collect();
+
+        for session_id in &sessions {
+            if let Some(family) = families.remove(session_id) {
+                self.revoke(&family.session);
+            }
+        }
+        sessions.len()
+    }
+    
+    /// End every session of a user, on any device, returning how many there were
+    pub fn revoke_user(&self, user_id: &str) -> usize {
+        let mut families = self.families.lock().unwrap();
+        let sessions: Vec<String> = families
+            .values()
+            .filter(|family| family.session.user_id == user_id)
+            .map(|family| family.session.id.clone())
+            .collect();
+
+        for session_id in &sessions {
+            if let Some(family) = families.remove(session_id) {
+                self.revoke(&family.session);
+            }
+        }
+        sessions.len()
+    }
+    
+    /// Whether a session is neither revoked nor past the end of its refresh family
+    ///
+    /// Long-lived connections check this rather than the access token's expiry.
+    pub fn is_session_active(&self, session: &Session) -> bool {
+        let now = chrono::Utc::now().timestamp();
+        if self.revoked.lock().unwrap().contains_key(&session.id) {
+            return false;
+        }
+
+        let families = self.families.lock().unwrap();
+        families
+            .get(&session.id)
+            .is_some_and(|family| family.expires_at >= now)
+    }
+    
+    /// Record a verified proof's nonce, or refuse it if it was seen before
+    pub fn accept_proof_nonce(&self, proof: &RequestProof, now: i64) -> bool {
+        let mut nonces = self.proof_nonces.lock().unwrap();
+        nonces.retain(|_, expires_at| *expires_at >= now);
+        nonces
+            .insert(*proof.nonce(), proof.timestamp() + MAX_PROOF_SKEW)
+            .is_none()
+    }
+    
+    /// Deny a session's access tokens until the latest of them expires
+    fn revoke(&self, session: &Session) {
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.insert(session.id.clone(), session.expires_at);
     }
 }
\ No newline at end of file
@@ -0,0 +1,313 @@
+//! Server configuration
+//!
+//! Read from `XIPR_*` environment variables, e.g. `XIPR_REDIS_URL`.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use config::ConfigError;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+use xipr_core::protocol::auth::{SessionKey, SessionKeyring};
+use xipr_core::protocol::compression::CompressionDictionary;
+use xipr_core::protocol::deletion::DeletionSigner;
+use xipr_core::protocol::mfa::FactorKey;
+use xipr_core::protocol::quic::{self, QuicServer};
+use xipr_core::protocol::sealed::CertificateSigner;
+use xipr_core::protocol::throttle::{ThrottleLimits, ThrottlePolicy};
+use xipr_core::protocol::webauthn::RelyingParty;
+
+use crate::queue::QueueConfig;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerConfig {
+    /// Redis URL for delivery queues; queues are kept in memory when unset
+    pub redis_url: Option<String>,
+    /// Seconds an unacknowledged message is kept before it is dead-lettered
+    #[serde(default = "default_message_ttl_secs")]
+    pub message_ttl_secs: u64,
+    /// Seconds before an unacknowledged message is sent again
+    #[serde(default = "default_redelivery_timeout_secs")]
+    pub redelivery_timeout_secs: u64,
+    /// Base64 Ed25519 secret key that signs sender certificates
+    pub sender_cert_key: Option<String>,
+    /// Key id published alongside the sender certificate key
+    #[serde(default = "default_sender_cert_key_id")]
+    pub sender_cert_key_id: u32,
+    /// Base64 Ed25519 secret key that signs tombstones and deletion receipts
+    pub deletion_key: Option<String>,
+    /// Key id published alongside the deletion key
+    #[serde(default = "default_deletion_key_id")]
+    pub deletion_key_id: u32,
+    /// Base64 secret that session tokens are MAC'd with
+    pub session_key: Option<String>,
+    /// Key id put in new session tokens
+    #[serde(default = "default_session_key_id")]
+    pub session_key_id: u32,
+    /// Retired session keys that still verify, as `id:base64` separated by commas
+    pub session_previous_keys: Option<String>,
+    /// Base64 key that TOTP secrets are sealed under at rest
+    pub totp_key: Option<String>,
+    /// WebAuthn relying party id, the domain passkeys are scoped to
+    #[serde(default = "default_webauthn_rp_id")]
+    pub webauthn_rp_id: String,
+    /// Web origin WebAuthn ceremonies run on
+    #[serde(default = "default_webauthn_origin")]
+    pub webauthn_origin: String,
+    /// Username of the platform operator created at startup, who can create organisations
+    pub operator: Option<String>,
+    /// Failed logins for an account before each attempt needs a proof-of-work challenge
+    #[serde(default = "default_login_account_challenge_after")]
+    pub login_account_challenge_after: u32,
+    /// Failed logins for an account before attempts must wait out a backoff
+    #[serde(default = "default_login_account_backoff_after")]
+    pub login_account_backoff_after: u32,
+    /// Failed logins from an address before each attempt needs a challenge
+    #[serde(default = "default_login_source_challenge_after")]
+    pub login_source_challenge_after: u32,
+    /// Failed logins from an address before attempts must wait out a backoff
+    #[serde(default = "default_login_source_backoff_after")]
+    pub login_source_backoff_after: u32,
+    /// Seconds after the last failed login that failures are forgotten
+    #[serde(default = "default_login_failure_window_secs")]
+    pub login_failure_window_secs: i64,
+    /// Longest backoff between attempts, in seconds
+    #[serde(default = "default_login_max_backoff_secs")]
+    pub login_max_backoff_secs: i64,
+    /// Leading zero bits required of proof-of-work solutions
+    #[serde(default = "default_login_pow_difficulty")]
+    pub login_pow_difficulty: u8,
+    /// Proof-of-work challenges an address is handed within a challenge's lifetime
+    #[serde(default = "default_login_max_challenges")]
+    pub login_max_challenges: u32,
+    /// Authentication failures per minute that raise an alert; 0 turns alerts off
+    #[serde(default = "default_auth_failure_alert_threshold")]
+    pub auth_failure_alert_threshold: u64,
+    /// Refuse sessions of devices that have not bound a request signing key.
+    /// Turning this off lets a stolen token be used from anywhere.
+    #[serde(default = "default_require_device_proof")]
+    pub require_device_proof: bool,
+    /// Path to a zstd dictionary offered to clients for frame compression
+    pub compression_dictionary: Option<String>,
+    /// UDP address to serve QUIC on, next to HTTP; QUIC is off when unset
+    pub quic_addr: Option<String>,
+    /// PEM certificate chain presented on QUIC connections
+    pub quic_cert: Option<String>,
+    /// PEM private key for `quic_cert`
+    pub quic_key: Option<String>,
+}
+
+fn default_message_ttl_secs() -> u64 {
+    QueueConfig::default().message_ttl.as_secs()
+}
+
+fn default_redelivery_timeout_secs() -> u64 {
+    QueueConfig::default().redelivery_timeout.as_secs()
+}
+
+fn default_sender_cert_key_id() -> u32 {
+    1
+}
+
+fn default_deletion_key_id() -> u32 {
+    1
+}
+
+fn default_session_key_id() -> u32 {
+    1
+}
+
+fn default_login_account_challenge_after() -> u32 {
+    ThrottlePolicy::default().account.challenge_after
+}
+
+fn default_login_account_backoff_after() -> u32 {
+    ThrottlePolicy::default().account.backoff_after
+}
+
+fn default_login_source_challenge_after() -> u32 {
+    ThrottlePolicy::default().source.challenge_after
+}
+
+fn default_login_source_backoff_after() -> u32 {
+    ThrottlePolicy::default().source.backoff_after
+}
+
+fn default_login_failure_window_secs() -> i64 {
+    ThrottlePolicy::default().account.window
+}
+
+fn default_login_max_backoff_secs() -> i64 {
+    ThrottlePolicy::default().max_backoff
+}
+
+fn default_login_pow_difficulty() -> u8 {
+    ThrottlePolicy::default().pow_difficulty
+}
+
+fn default_login_max_challenges() -> u32 {
+    ThrottlePolicy::default().max_challenges
+}
+
+fn default_auth_failure_alert_threshold() -> u64 {
+    100
+}
+
+fn default_require_device_proof() -> bool {
+    true
+}
+
+fn default_webauthn_rp_id() -> String {
+    "localhost".to_string()
+}
+
+fn default_webauthn_origin() -> String {
+    "http://localhost:3000".to_string()
+}
+
+fn decode_secret(encoded: &str, name: &str) -> Result<[u8; 32], ConfigError> {
+    STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ConfigError::Message(format!("{} must be 32 base64-encoded bytes", name)))
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        config::Config::builder()
+            .add_source(config::Environment::with_prefix("XIPR"))
+            .build()?
+            .try_deserialize()
+    }
+
+    pub fn queue_config(&self) -> QueueConfig {
+        QueueConfig {
+            message_ttl: Duration::from_secs(self.message_ttl_secs),
+            redelivery_timeout: Duration::from_secs(self.redelivery_timeout_secs),
+        }
+    }
+
+    /// Signer for the configured sender certificate key, if one is set
+    pub fn certificate_signer(&self) -> Result<Option<CertificateSigner>, ConfigError> {
+        let Some(encoded) = &self.sender_cert_key else {
+            return Ok(None);
+        };
+
+        let secret = decode_secret(encoded, "sender_cert_key")?;
+
+        Ok(Some(CertificateSigner::from_bytes(
+            self.sender_cert_key_id,
+            &secret,
+        )))
+    }
+
+    /// Signer for the configured deletion key, if one is set
+    pub fn deletion_signer(&self) -> Result<Option<DeletionSigner>, ConfigError> {
+        let Some(encoded) = &self.deletion_key else {
+            return Ok(None);
+        };
+
+        let secret = decode_secret(encoded, "deletion_key")?;
+        Ok(Some(DeletionSigner::from_bytes(self.deletion_key_id, &secret)))
+    }
+
+    /// Keyring for the configured session key and previous keys, if a key is set
+    ///
+    /// Rotating means moving the current key into `session_previous_keys` and
+    /// setting a new one; tokens signed with either verify until the old key is
+    /// dropped from the list.
+    pub fn session_keyring(&self) -> Result<Option<SessionKeyring>, ConfigError> {
+        let Some(encoded) = &self.session_key else {
+            return Ok(None);
+        };
+
+        let secret = decode_secret(encoded, "session_key")?;
+        let mut keyring = SessionKeyring::new(SessionKey::from_bytes(self.session_key_id, &secret));
+
+        let previous = self.session_previous_keys.as_deref().unwrap_or_default();
+        for entry in previous.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (key_id, encoded) = entry
+                .split_once(':')
+                .and_then(|(key_id, encoded)| Some((key_id.trim().parse().ok()?, encoded)))
+                .ok_or_else(|| {
+                    ConfigError::Message(
+                        "session_previous_keys entries must be id:base64".to_string(),
+                    )
+                })?;
+            let secret = decode_secret(encoded, "session_previous_keys")?;
+            keyring.add_verification_key(SessionKey::from_bytes(key_id, &secret));
+        }
+
+        Ok(Some(keyring))
+    }
+
+    /// Key for sealing TOTP secrets, if one is set
+    pub fn factor_key(&self) -> Result<Option<FactorKey>, ConfigError> {
+        let Some(encoded) = &self.totp_key else {
+            return Ok(None);
+        };
+
+        let secret = decode_secret(encoded, "totp_key")?;
+        Ok(Some(FactorKey::from_bytes(&secret)))
+    }
+
+    pub fn throttle_policy(&self) -> ThrottlePolicy {
+        ThrottlePolicy {
+            account: ThrottleLimits {
+                challenge_after: self.login_account_challenge_after,
+                backoff_after: self.login_account_backoff_after,
+                window: self.login_failure_window_secs,
+            },
+            source: ThrottleLimits {
+                challenge_after: self.login_source_challenge_after,
+                backoff_after: self.login_source_backoff_after,
+                window: self.login_failure_window_secs,
+            },
+            max_backoff: self.login_max_backoff_secs,
+            pow_difficulty: self.login_pow_difficulty,
+            max_challenges: self.login_max_challenges,
+            ..ThrottlePolicy::default()
+        }
+    }
+
+    pub fn relying_party(&self) -> RelyingParty {
+        RelyingParty::new(&self.webauthn_rp_id, &self.webauthn_origin)
+    }
+
+    /// Compression dictionary loaded from the configured path, if one is set
+    pub fn compression_dictionary(&self) -> Result<Option<CompressionDictionary>, ConfigError> {
+        let Some(path) = &self.compression_dictionary else {
+            return Ok(None);
+        };
+
+        let bytes = std::fs::read(path).map_err(|e| {
+            ConfigError::Message(format!(
+                "cannot read compression dictionary {}: {}",
+                path, e
+            ))
+        })?;
+        Ok(Some(CompressionDictionary::new(bytes)))
+    }
+
+    /// QUIC endpoint bound to the configured address, if one is set
+    pub fn quic_server(&self) -> Result<Option<QuicServer>, ConfigError> {
+        let Some(addr) = &self.quic_addr else {
+            return Ok(None);
+        };
+
+        let addr = addr
+            .parse()
+            .map_err(|_| ConfigError::Message(format!("invalid quic_addr: {}", addr)))?;
+        let (Some(cert), Some(key)) = (&self.quic_cert, &self.quic_key) else {
+            return Err(ConfigError::Message(
+                "quic_addr requires quic_cert and quic_key".to_string(),
+            ));
+        };
+
+        let (cert_chain, key) = quic::load_pem_identity(Path::new(cert), Path::new(key))
+            .map_err(|e| ConfigError::Message(e.to_string()))?;
+        QuicServer::bind(addr, cert_chain, key)
+            .map(Some)
+            .map_err(|e| ConfigError::Message(format!("cannot bind QUIC endpoint: {}", e)))
+    }
+}
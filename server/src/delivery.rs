@@ -0,0 +1,382 @@
+//! MLS delivery service for XIPRNET server
+//!
+//! Totally orders handshake messages per group, fans out application
+//! messages to member devices and holds Welcome messages until claimed.
+//! Every group belongs to one organisation; to the others it does not exist.
+//!
+//! Fan-out goes through the per-device message queues, so group messages are
+//! acknowledged, redelivered and dead-lettered like any other frame.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use xipr_core::protocol::mls::{Commit, MlsContentType, MlsMessage, Proposal};
+use xipr_core::protocol::transport::ControlMessage;
+
+use crate::queue::{MessageQueues, QueueError};
+
+pub use xipr_core::protocol::mls::DeliveredMessage;
+
+#[derive(Debug, Error)]
+pub enum DeliveryError {
+    #[error("unknown group")]
+    UnknownGroup,
+
+    #[error("group already exists")]
+    GroupExists,
+
+    #[error("device is not a member of the group")]
+    NotMember,
+
+    #[error("device {0} belongs to another organisation")]
+    OtherOrganisation(String),
+
+    #[error("stale epoch: group is at {current}, message is for {received}")]
+    StaleEpoch { current: u64, received: u64 },
+
+    #[error("future epoch: group is at {current}, message is for {received}")]
+    FutureEpoch { current: u64, received: u64 },
+
+    #[error("welcome for device {0}, which the commit does not add")]
+    UnexpectedWelcome(String),
+
+    #[error("malformed message: {0}")]
+    Malformed(String),
+
+    #[error(transparent)]
+    Queue(#[from] QueueError),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingWelcome {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub welcome: Vec<u8>,
+}
+
+struct GroupLog {
+    org_id: String,
+    epoch: u64,
+    next_sequence: u64,
+    members: BTreeSet<String>,
+    /// Revoked devices still in the MLS tree, until a member commits their removal
+    pending_removals: BTreeSet<String>,
+}
+
+pub struct DeliveryService {
+    groups: Mutex<HashMap<Vec<u8>, GroupLog>>,
+    welcomes: Mutex<HashMap<String, Vec<PendingWelcome>>>,
+    queues: Arc<MessageQueues>,
+    /// Held from sequencing to queueing, so device queues follow group order
+    fanout: tokio::sync::Mutex<()>,
+}
+
+impl DeliveryService {
+    pub fn new(queues: Arc<MessageQueues>) -> Self {
+        Self {
+            groups: Mutex::new(HashMap::new()),
+            welcomes: Mutex::new(HashMap::new()),
+            queues,
+            fanout: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn create_group(
+        &self,
+        org_id: &str,
+        group_id: Vec<u8>,
+        creator_device_id: String,
+    ) -> Result<(), DeliveryError> {
+        let mut groups = self.groups.lock().unwrap();
+        if groups.contains_key(&group_id) {
+            return Err(DeliveryError::GroupExists);
+        }
+
+        groups.insert(
+            group_id,
+            GroupLog {
+                org_id: org_id.to_string(),
+                epoch: 0,
+                next_sequence: 0,
+                members: BTreeSet::from([creator_device_id]),
+                pending_removals: BTreeSet::new(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn group_epoch(&self, org_id: &str, group_id: &[u8]) -> Option<u64> {
+        let groups = self.groups.lock().unwrap();
+        scoped(&groups, org_id, group_id).map(|group| group.epoch)
+    }
+
+    pub fn group_members(&self, org_id: &str, group_id: &[u8]) -> Option<Vec<String>> {
+        let groups = self.groups.lock().unwrap();
+        scoped(&groups, org_id, group_id).map(|group| group.members.iter().cloned().collect())
+    }
+
+    /// Revoked devices that members should remove with their next commit
+    pub fn pending_removals(&self, org_id: &str, group_id: &[u8]) -> Vec<String> {
+        let groups = self.groups.lock().unwrap();
+        scoped(&groups, org_id, group_id)
+            .map(|group| group.pending_removals.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Take a revoked device out of every group it belongs to
+    ///
+    /// The server cannot change the MLS tree itself. The device stops receiving
+    /// and sending at once, and the remaining members are asked to commit its
+    /// removal. Returns the groups it was in; its queue is the caller's to purge.
+    pub fn remove_device(&self, device_id: &str) -> Vec<Vec<u8>> {
+        let mut groups = self.groups.lock().unwrap();
+        let mut affected = Vec::new();
+        for (group_id, group) in groups.iter_mut() {
+            if group.members.remove(device_id) {
+                group.pending_removals.insert(device_id.to_string());
+                affected.push(group_id.clone());
+            }
+        }
+        drop(groups);
+
+        self.welcomes.lock().unwrap().remove(device_id);
+        affected
+    }
+
+    /// Accept a commit only if it was built on the group's current epoch.
+    ///
+    /// The first commit for an epoch wins; every later one is rejected as stale
+    /// and its sender must process the winning commit and retry. Devices it adds
+    /// must pass `in_organisation`, and welcomes may only go to devices it adds.
+    pub async fn submit_commit(
+        &self,
+        org_id: &str,
+        group_id: &[u8],
+        sender_device_id: &str,
+        payload: Vec<u8>,
+        welcomes: Vec<(String, Vec<u8>)>,
+        in_organisation: impl Fn(&str) -> bool,
+    ) -> Result<u64, DeliveryError> {
+        let commit: Commit = serde_json::from_slice(&payload)
+            .map_err(|e| DeliveryError::Malformed(e.to_string()))?;
+
+        if commit.group_id != group_id {
+            return Err(DeliveryError::Malformed("commit group id does not match".to_string()));
+        }
+
+        let _fanout = self.fanout.lock().await;
+        let (recipients, message) = self.apply_commit(
+            org_id,
+            sender_device_id,
+            &commit,
+            payload,
+            welcomes,
+            in_organisation,
+        )?;
+        self.enqueue(&recipients, sender_device_id, message).await?;
+        Ok(commit.epoch + 1)
+    }
+
+    /// Fan an application message out to every member device except the sender.
+    ///
+    /// Only the envelope's group id, epoch and content type are read.
+    pub async fn submit_application(
+        &self,
+        org_id: &str,
+        group_id: &[u8],
+        sender_device_id: &str,
+        payload: Vec<u8>,
+    ) -> Result<(u64, u64), DeliveryError> {
+        let envelope: MlsMessage = serde_json::from_slice(&payload)
+            .map_err(|e| DeliveryError::Malformed(e.to_string()))?;
+
+        if envelope.group_id != group_id {
+            return Err(DeliveryError::Malformed("message group id does not match".to_string()));
+        }
+
+        if envelope.content_type != MlsContentType::Application {
+            return Err(DeliveryError::Malformed("expected an application message".to_string()));
+        }
+
+        let epoch = envelope.epoch;
+        let _fanout = self.fanout.lock().await;
+        let (recipients, message) =
+            self.sequence_application(org_id, group_id, sender_device_id, epoch, payload)?;
+        let sequence = message.group_sequence;
+        self.enqueue(&recipients, sender_device_id, message).await?;
+        Ok((epoch, sequence))
+    }
+
+    pub fn claim_welcomes(&self, device_id: &str) -> Vec<PendingWelcome> {
+        let mut welcomes = self.welcomes.lock().unwrap();
+        welcomes.remove(device_id).unwrap_or_default()
+    }
+
+    /// Check a commit against the group, apply it and hold its welcomes
+    ///
+    /// Returns the devices to queue it for, and the message to queue.
+    fn apply_commit(
+        &self,
+        org_id: &str,
+        sender_device_id: &str,
+        commit: &Commit,
+        payload: Vec<u8>,
+        welcomes: Vec<(String, Vec<u8>)>,
+        in_organisation: impl Fn(&str) -> bool,
+    ) -> Result<(Vec<String>, DeliveredMessage), DeliveryError> {
+        let group_id = commit.group_id.as_slice();
+        let mut groups = self.groups.lock().unwrap();
+        let group = groups
+            .get_mut(group_id)
+            .filter(|group| group.org_id == org_id)
+            .ok_or(DeliveryError::UnknownGroup)?;
+
+        if !group.members.contains(sender_device_id) {
+            return Err(DeliveryError::NotMember);
+        }
+
+        if commit.epoch < group.epoch {
+            return Err(DeliveryError::StaleEpoch {
+                current: group.epoch,
+                received: commit.epoch,
+            });
+        }
+
+        if commit.epoch > group.epoch {
+            return Err(DeliveryError::FutureEpoch {
+                current: group.epoch,
+                received: commit.epoch,
+            });
+        }
+
+        // Existing members, including those being removed, receive the commit
+        let recipients: Vec<String> = group.members.iter().cloned().collect();
+
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for proposal in &commit.proposals {
+            match proposal {
+                Proposal::Add(key_package) => added.push(key_package.credential.device_id.clone()),
+                Proposal::Remove { member_id } => removed.push(identity_to_device_id(member_id)?),
+                Proposal::GroupContextExtensions(_) => {}
+            }
+        }
+
+        if let Some(device_id) = added.iter().find(|device_id| !in_organisation(device_id)) {
+            return Err(DeliveryError::OtherOrganisation(device_id.clone()));
+        }
+
+        if let Some((device_id, _)) = welcomes
+            .iter()
+            .find(|(device_id, _)| !added.contains(device_id))
+        {
+            return Err(DeliveryError::UnexpectedWelcome(device_id.clone()));
+        }
+
+        group.members.extend(added);
+        for device_id in &removed {
+            group.members.remove(device_id);
+            group.pending_removals.remove(device_id);
+        }
+
+        let message = DeliveredMessage {
+            group_id: group_id.to_vec(),
+            group_sequence: group.next_sequence,
+            epoch: commit.epoch,
+            content_type: MlsContentType::Commit,
+            payload,
+        };
+        group.next_sequence += 1;
+        group.epoch += 1;
+        let new_epoch = group.epoch;
+        drop(groups);
+
+        let mut pending = self.welcomes.lock().unwrap();
+        for (device_id, welcome) in welcomes {
+            pending.entry(device_id).or_default().push(PendingWelcome {
+                group_id: group_id.to_vec(),
+                epoch: new_epoch,
+                welcome,
+            });
+        }
+
+        Ok((recipients, message))
+    }
+
+    /// Give an application message the group's next sequence number
+    fn sequence_application(
+        &self,
+        org_id: &str,
+        group_id: &[u8],
+        sender_device_id: &str,
+        epoch: u64,
+        payload: Vec<u8>,
+    ) -> Result<(Vec<String>, DeliveredMessage), DeliveryError> {
+        let mut groups = self.groups.lock().unwrap();
+        let group = groups
+            .get_mut(group_id)
+            .filter(|group| group.org_id == org_id)
+            .ok_or(DeliveryError::UnknownGroup)?;
+
+        if !group.members.contains(sender_device_id) {
+            return Err(DeliveryError::NotMember);
+        }
+
+        // Messages from past epochs are still decryptable by members that retain old secrets
+        if epoch > group.epoch {
+            return Err(DeliveryError::FutureEpoch {
+                current: group.epoch,
+                received: epoch,
+            });
+        }
+
+        let message = DeliveredMessage {
+            group_id: group_id.to_vec(),
+            group_sequence: group.next_sequence,
+            epoch,
+            content_type: MlsContentType::Application,
+            payload,
+        };
+        group.next_sequence += 1;
+        Ok((group.members.iter().cloned().collect(), message))
+    }
+
+    /// Queue `message` for every recipient but the sender, as a `Control` frame
+    ///
+    /// All copies share one message id, so a redelivered copy is recognisable.
+    async fn enqueue(
+        &self,
+        recipients: &[String],
+        sender_device_id: &str,
+        message: DeliveredMessage,
+    ) -> Result<(), DeliveryError> {
+        let control = ControlMessage::Group(message);
+        let mut template = control
+            .to_message(sender_device_id.to_string(), String::new())
+            .map_err(|e| DeliveryError::Malformed(e.to_string()))?;
+
+        for device_id in recipients.iter().filter(|id| id.as_str() != sender_device_id) {
+            template.recipient_id = device_id.clone();
+            let frame = template
+                .to_frame()
+                .map_err(|e| DeliveryError::Malformed(e.to_string()))?;
+            self.queues.enqueue(device_id, frame).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A group, if it belongs to the organisation
+fn scoped<'a>(
+    groups: &'a HashMap<Vec<u8>, GroupLog>,
+    org_id: &str,
+    group_id: &[u8],
+) -> Option<&'a GroupLog> {
+    groups.get(group_id).filter(|group| group.org_id == org_id)
+}
+
+fn identity_to_device_id(identity: &[u8]) -> Result<String, DeliveryError> {
+    String::from_utf8(identity.to_vec())
+        .map_err(|_| DeliveryError::Malformed("member identity is not a device id".to_string()))
+}
@@ -0,0 +1,420 @@
+//! Device registry for XIPRNET server
+//!
+//! Tracks which devices belong to which user, so messages addressed to a
+//! user can be fanned out to each of their devices. New devices are linked
+//! by an existing one through a provisioning slot, and revoked devices are
+//! forgotten along with their pre-keys; their ids are never reused.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use thiserror::Error;
+use xipr_core::crypto::keys::PreKey;
+use xipr_core::protocol::fanout::DeviceDirectory;
+use xipr_core::protocol::proof::VerifyingKey;
+use xipr_core::protocol::provisioning::{
+    link_code_digest, ProvisionEnvelope, PROVISIONING_LIFETIME,
+};
+
+use crate::ratelimit::RateLimiter;
+
+/// Devices not seen for this long are stale and no longer receive messages
+const STALE_AFTER_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Provisioning slots that may be open at once
+const MAX_PENDING_LINKS: usize = 10_000;
+
+/// Burst of provisioning slots allowed per source address
+const LINK_SOURCE_BURST: u32 = 10;
+const LINK_SOURCE_REFILL_PER_SEC: f64 = 1.0 / 60.0;
+
+/// Pre-keys a device may have stored at once
+const MAX_PRE_KEYS: usize = 100;
+
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    #[error("unknown device")]
+    UnknownDevice,
+
+    #[error("device was revoked")]
+    Revoked,
+
+    #[error("device is bound to another signing key")]
+    KeyMismatch,
+
+    #[error("invalid signing key")]
+    InvalidKey,
+
+    #[error("unknown or expired provisioning slot")]
+    UnknownLink,
+
+    #[error("provisioning slot already used")]
+    LinkUsed,
+
+    #[error("provisioning slot has no envelope yet")]
+    LinkPending,
+
+    #[error("wrong link code")]
+    WrongLinkCode,
+
+    #[error("too many provisioning slots")]
+    TooManyLinks,
+
+    #[error("malformed provisioning envelope")]
+    MalformedEnvelope,
+}
+
+/// Whether a device has proven it runs on genuine hardware
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationStatus {
+    /// No attestation was presented
+    Unattested,
+    /// Evidence was presented but is not checked; no attestation verifier exists yet
+    Unverified,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceRecord {
+    pub device_id: String,
+    pub user_id: String,
+    /// Name the user gave the device when linking it
+    pub name: Option<String>,
+    pub created_at: i64,
+    pub last_seen: i64,
+    /// Ed25519 key the device signs request proofs with, once bound
+    pub signing_key: Option<[u8; 32]>,
+    /// Device that linked this one, if it was linked rather than logged in
+    pub linked_by: Option<String>,
+    pub attestation: AttestationStatus,
+    /// One-time pre-keys other devices claim to start sessions with this one
+    pub pre_keys: Vec<PreKey>,
+}
+
+impl DeviceRecord {
+    fn new(user_id: &str, device_id: &str, now: i64) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            user_id: user_id.to_string(),
+            name: None,
+            created_at: now,
+            last_seen: now,
+            signing_key: None,
+            linked_by: None,
+            attestation: AttestationStatus::Unattested,
+            pre_keys: Vec::new(),
+        }
+    }
+
+    pub fn is_stale(&self, now: i64) -> bool {
+        now - self.last_seen > STALE_AFTER_SECS
+    }
+}
+
+/// A linking device's grant on a provisioning slot
+#[derive(Debug, Clone)]
+struct LinkGrant {
+    user_id: String,
+    linked_by: String,
+    envelope: ProvisionEnvelope,
+    link_code_digest: [u8; 32],
+}
+
+/// Provisioning slot opened by a new device
+#[derive(Debug, Clone)]
+struct PendingLink {
+    expires_at: i64,
+    grant: Option<LinkGrant>,
+}
+
+/// What a new device supplies to finish linking
+#[derive(Debug, Clone)]
+pub struct LinkCompletion<'a> {
+    pub link_code: &'a [u8],
+    pub name: Option<String>,
+    pub signing_key: &'a [u8],
+    pub attestation: Option<&'a [u8]>,
+}
+
+pub struct DeviceRegistry {
+    devices: Mutex<HashMap<String, DeviceRecord>>,
+    /// Ids of revoked devices, which may not register again
+    revoked: Mutex<HashSet<String>>,
+    links: Mutex<HashMap<String, PendingLink>>,
+    links_by_source: RateLimiter,
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceRegistry {
+    pub fn new() -> Self {
+        Self {
+            devices: Mutex::new(HashMap::new()),
+            revoked: Mutex::new(HashSet::new()),
+            links: Mutex::new(HashMap::new()),
+            links_by_source: RateLimiter::new(LINK_SOURCE_BURST, LINK_SOURCE_REFILL_PER_SEC),
+        }
+    }
+
+    /// Register a device for a user and return its id
+    ///
+    /// Device ids share one namespace across users, so the server picks them.
+    /// A device may keep an id it was given before; any other request gets a
+    /// fresh id, so nobody can claim an id ahead of the user it would belong to.
+    pub fn register(&self, user_id: &str, requested: Option<&str>) -> Result<String, DeviceError> {
+        if requested.is_some_and(|id| self.revoked.lock().unwrap().contains(id)) {
+            return Err(DeviceError::Revoked);
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let mut devices = self.devices.lock().unwrap();
+
+        if let Some(record) = requested
+            .and_then(|id| devices.get_mut(id))
+            .filter(|record| record.user_id == user_id)
+        {
+            record.last_seen = now;
+            return Ok(record.device_id.clone());
+        }
+
+        let device_id = uuid::Uuid::new_v4().to_string();
+        devices.insert(
+            device_id.clone(),
+            DeviceRecord::new(user_id, &device_id, now),
+        );
+        Ok(device_id)
+    }
+
+    /// Bind a registered device to its signing key
+    ///
+    /// A device keeps the first key bound to it; binding a different one fails,
+    /// so a stolen password cannot move the device's sessions to another key.
+    pub fn bind_signing_key(&self, device_id: &str, key: &[u8]) -> Result<(), DeviceError> {
+        let key = parse_signing_key(key)?;
+
+        let mut devices = self.devices.lock().unwrap();
+        let record = devices
+            .get_mut(device_id)
+            .ok_or(DeviceError::UnknownDevice)?;
+        match record.signing_key {
+            Some(bound) if bound != key => Err(DeviceError::KeyMismatch),
+            _ => {
+                record.signing_key = Some(key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Key that a device's request proofs must verify under, if one is bound
+    pub fn signing_key(&self, device_id: &str) -> Option<VerifyingKey> {
+        let devices = self.devices.lock().unwrap();
+        let key = devices.get(device_id)?.signing_key?;
+        VerifyingKey::from_bytes(&key).ok()
+    }
+
+    /// Record activity from a device
+    pub fn touch(&self, device_id: &str) {
+        let mut devices = self.devices.lock().unwrap();
+        if let Some(record) = devices.get_mut(device_id) {
+            record.last_seen = chrono::Utc::now().timestamp();
+        }
+    }
+
+    /// User a registered device belongs to
+    pub fn owner(&self, device_id: &str) -> Option<String> {
+        let devices = self.devices.lock().unwrap();
+        devices.get(device_id).map(|record| record.user_id.clone())
+    }
+
+    /// A user's devices, oldest first
+    pub fn devices_of(&self, user_id: &str) -> Vec<DeviceRecord> {
+        let devices = self.devices.lock().unwrap();
+        let mut owned: Vec<DeviceRecord> = devices
+            .values()
+            .filter(|record| record.user_id == user_id)
+            .cloned()
+            .collect();
+        owned.sort_by(|a, b| (a.created_at, &a.device_id).cmp(&(b.created_at, &b.device_id)));
+        owned
+    }
+
+    /// Forget one of a user's devices and its pre-keys, and retire its id
+    ///
+    /// Sessions and group membership live elsewhere; the caller revokes those.
+    pub fn revoke(&self, user_id: &str, device_id: &str) -> Result<DeviceRecord, DeviceError> {
+        let mut devices = self.devices.lock().unwrap();
+        match devices.get(device_id) {
+            Some(record) if record.user_id == user_id => {}
+            // Other users' devices are indistinguishable from unknown ones
+            _ => return Err(DeviceError::UnknownDevice),
+        }
+
+        self.revoked.lock().unwrap().insert(device_id.to_string());
+        Ok(devices.remove(device_id).unwrap())
+    }
+
+    /// Store pre-keys for a device, replacing any with the same ids
+    ///
+    /// Returns how many the device now has.
+    pub fn add_pre_keys(
+        &self,
+        device_id: &str,
+        pre_keys: Vec<PreKey>,
+    ) -> Result<usize, DeviceError> {
+        let mut devices = self.devices.lock().unwrap();
+        let record = devices
+            .get_mut(device_id)
+            .ok_or(DeviceError::UnknownDevice)?;
+
+        for pre_key in pre_keys {
+            record.pre_keys.retain(|stored| stored.id != pre_key.id);
+            record.pre_keys.push(pre_key);
+        }
+        // Past the limit the oldest go first
+        let excess = record.pre_keys.len().saturating_sub(MAX_PRE_KEYS);
+        record.pre_keys.drain(..excess);
+        Ok(record.pre_keys.len())
+    }
+
+    /// Take one of a device's pre-keys; each is handed out once
+    pub fn claim_pre_key(&self, device_id: &str) -> Option<PreKey> {
+        let mut devices = self.devices.lock().unwrap();
+        let record = devices.get_mut(device_id)?;
+        (!record.pre_keys.is_empty()).then(|| record.pre_keys.remove(0))
+    }
+
+    /// Open a provisioning slot for a new device, returning its id and expiry
+    pub fn open_link(&self, source: &str) -> Result<(String, i64), DeviceError> {
+        if !self.links_by_source.check(source) {
+            return Err(DeviceError::TooManyLinks);
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let mut links = self.links.lock().unwrap();
+        links.retain(|_, link| link.expires_at >= now);
+        if links.len() >= MAX_PENDING_LINKS {
+            return Err(DeviceError::TooManyLinks);
+        }
+
+        let provisioning_id = uuid::Uuid::new_v4().to_string();
+        let expires_at = now + PROVISIONING_LIFETIME;
+        links.insert(
+            provisioning_id.clone(),
+            PendingLink {
+                expires_at,
+                grant: None,
+            },
+        );
+        Ok((provisioning_id, expires_at))
+    }
+
+    /// Attach a linking device's sealed envelope to a slot; each slot takes one
+    pub fn grant_link(
+        &self,
+        provisioning_id: &str,
+        user_id: &str,
+        linked_by: &str,
+        envelope: ProvisionEnvelope,
+        link_code_digest: [u8; 32],
+    ) -> Result<(), DeviceError> {
+        envelope
+            .validate()
+            .map_err(|_| DeviceError::MalformedEnvelope)?;
+
+        let now = chrono::Utc::now().timestamp();
+        let mut links = self.links.lock().unwrap();
+        let link = links
+            .get_mut(provisioning_id)
+            .filter(|link| link.expires_at >= now)
+            .ok_or(DeviceError::UnknownLink)?;
+        if link.grant.is_some() {
+            return Err(DeviceError::LinkUsed);
+        }
+
+        link.grant = Some(LinkGrant {
+            user_id: user_id.to_string(),
+            linked_by: linked_by.to_string(),
+            envelope,
+            link_code_digest,
+        });
+        Ok(())
+    }
+
+    /// The sealed envelope for a slot, once a linking device has granted it
+    pub fn link_envelope(&self, provisioning_id: &str) -> Result<ProvisionEnvelope, DeviceError> {
+        let now = chrono::Utc::now().timestamp();
+        let links = self.links.lock().unwrap();
+        let link = links
+            .get(provisioning_id)
+            .filter(|link| link.expires_at >= now)
+            .ok_or(DeviceError::UnknownLink)?;
+        link.grant
+            .as_ref()
+            .map(|grant| grant.envelope.clone())
+            .ok_or(DeviceError::LinkPending)
+    }
+
+    /// Register the new device of a granted slot, closing the slot
+    ///
+    /// The link code proves the caller opened the envelope. A wrong code
+    /// closes the slot too, so codes cannot be guessed.
+    pub fn complete_link(
+        &self,
+        provisioning_id: &str,
+        completion: LinkCompletion<'_>,
+    ) -> Result<DeviceRecord, DeviceError> {
+        let signing_key = parse_signing_key(completion.signing_key)?;
+
+        let now = chrono::Utc::now().timestamp();
+        let link = self
+            .links
+            .lock()
+            .unwrap()
+            .remove(provisioning_id)
+            .filter(|link| link.expires_at >= now)
+            .ok_or(DeviceError::UnknownLink)?;
+        let grant = link.grant.ok_or(DeviceError::LinkPending)?;
+        if link_code_digest(completion.link_code) != grant.link_code_digest {
+            return Err(DeviceError::WrongLinkCode);
+        }
+
+        let device_id = uuid::Uuid::new_v4().to_string();
+        let mut record = DeviceRecord::new(&grant.user_id, &device_id, now);
+        record.name = completion.name;
+        record.signing_key = Some(signing_key);
+        record.linked_by = Some(grant.linked_by);
+        record.attestation = match completion.attestation {
+            Some(_) => AttestationStatus::Unverified,
+            None => AttestationStatus::Unattested,
+        };
+
+        let mut devices = self.devices.lock().unwrap();
+        devices.insert(device_id, record.clone());
+        Ok(record)
+    }
+}
+
+impl DeviceDirectory for DeviceRegistry {
+    fn active_devices(&self, user_id: &str) -> Vec<String> {
+        let now = chrono::Utc::now().timestamp();
+        let devices = self.devices.lock().unwrap();
+
+        let mut active: Vec<String> = devices
+            .values()
+            .filter(|record| record.user_id == user_id && !record.is_stale(now))
+            .map(|record| record.device_id.clone())
+            .collect();
+        active.sort();
+        active
+    }
+}
+
+fn parse_signing_key(key: &[u8]) -> Result<[u8; 32], DeviceError> {
+    let key: [u8; 32] = key.try_into().map_err(|_| DeviceError::InvalidKey)?;
+    VerifyingKey::from_bytes(&key).map_err(|_| DeviceError::InvalidKey)?;
+    Ok(key)
+}
@@ -0,0 +1,19 @@
+//! XIPRNET server library
+//!
+//! Services, API handlers and the router behind the server binary, kept in a
+//! library so they can be exercised without a listening socket.
+
+pub mod api;
+pub mod auth;
+pub mod config;
+pub mod delivery;
+pub mod devices;
+pub mod mfa;
+pub mod passkeys;
+pub mod queue;
+pub mod quic;
+pub mod ratelimit;
+pub mod sealed;
+pub mod state;
+pub mod storage;
+pub mod throttle;
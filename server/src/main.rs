@@ -2,17 +2,34 @@
 //! 
 //! Main server binary for the XIPRNET messaging system
 
-use axum::{
-    routing::{get, post},
-    Router,
-};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{error, info, warn};
 
-mod api;
-mod auth;
-mod storage;
+use xipr_server::api;
+use xipr_server::auth::AuthService;
+use xipr_server::config::ServerConfig;
+use xipr_server::mfa::MfaService;
+use xipr_server::passkeys::PasskeyService;
+use xipr_server::queue::{MemoryQueueStore, MessageQueues, QueueStore, RedisQueueStore};
+use xipr_server::quic;
+use xipr_server::sealed::SealedSenderService;
+use xipr_server::state::AppState;
+use xipr_server::throttle::{AttemptStore, LoginThrottle, MemoryAttemptStore, RedisAttemptStore};
+use xipr_core::protocol::auth::{Role, SessionKey, SessionKeyring};
+use xipr_core::protocol::deletion::DeletionSigner;
+use xipr_core::protocol::mfa::FactorKey;
+use xipr_core::protocol::sealed::CertificateSigner;
+use xipr_core::protocol::throttle::SystemClock;
+use xipr_core::utils::auth_failure_count;
+
+/// How often expired queue entries are moved to the dead-letter queues
+const DEAD_LETTER_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Period that authentication failures are counted over for alerting
+const AUTH_FAILURE_ALERT_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -21,18 +38,100 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     info!("Starting XIPRNET server...");
     
+    let config = ServerConfig::from_env()?;
+    
+    // Delivery queues live in Redis when configured, in memory otherwise
+    let store: Box<dyn QueueStore> = match &config.redis_url {
+        Some(url) => Box::new(RedisQueueStore::connect(url).await?),
+        None => {
+            warn!("No Redis URL configured, delivery queues will not survive a restart");
+            Box::new(MemoryQueueStore::new())
+        }
+    };
+    let queues = Arc::new(MessageQueues::new(store, config.queue_config()));
+    tokio::spawn(sweep_dead_letters(queues.clone()));
+    
+    // Login failure counts are shared through Redis too, so every node throttles alike
+    let attempts: Box<dyn AttemptStore> = match &config.redis_url {
+        Some(url) => Box::new(RedisAttemptStore::connect(url).await?),
+        None => Box::new(MemoryAttemptStore::new()),
+    };
+    let throttle = LoginThrottle::new(attempts, config.throttle_policy(), Arc::new(SystemClock));
+    if config.auth_failure_alert_threshold > 0 {
+        tokio::spawn(watch_auth_failures(config.auth_failure_alert_threshold));
+    }
+    
+    let signer = match config.certificate_signer()? {
+        Some(signer) => signer,
+        None => {
+            warn!("No sender certificate key configured, using an ephemeral key");
+            CertificateSigner::generate(config.sender_cert_key_id)
+        }
+    };
+    
+    let keyring = match config.session_keyring()? {
+        Some(keyring) => keyring,
+        None => {
+            warn!("No session key configured, using an ephemeral key; sessions will not survive a restart");
+            SessionKeyring::new(SessionKey::generate(config.session_key_id))
+        }
+    };
+    
+    let factor_key = match config.factor_key()? {
+        Some(key) => key,
+        None => {
+            warn!("No TOTP key configured, using an ephemeral key; enrolled second factors will not survive a restart");
+            FactorKey::generate()
+        }
+    };
+    
+    let deletion_signer = match config.deletion_signer()? {
+        Some(signer) => signer,
+        None => {
+            warn!("No deletion key configured, using an ephemeral key; tombstones and deletion receipts will not verify after a restart");
+            DeletionSigner::generate(config.deletion_key_id)
+        }
+    };
+    
+    let auth = AuthService::new(keyring)
+        .with_required_device_proof(config.require_device_proof)
+        .with_deletion_signer(deletion_signer);
+    let mfa = MfaService::new(factor_key);
+    match &config.operator {
+        Some(username) => {
+            let operator = auth.create_user(username.clone(), String::new(), None, vec![Role::Operator])?;
+            // Nobody can hand the operator a token, so it goes to the log once
+            let token = mfa.issue_enrolment_token(&operator.id, chrono::Utc::now().timestamp())?;
+            info!("Platform operator {} can create organisations", username);
+            warn!("Operator enrolment token, valid until a factor is enrolled: {}", token);
+        }
+        None => warn!("No operator configured, organisations cannot be created"),
+    }
+    
+    let mut state = AppState::new(
+        auth,
+        queues,
+        SealedSenderService::new(signer),
+        mfa,
+        PasskeyService::new(config.relying_party()),
+        throttle,
+    );
+    if let Some(dictionary) = config.compression_dictionary()? {
+        info!("Offering compression dictionary {}", dictionary.id());
+        state = state.with_compression_dictionary(dictionary);
+    }
+    
+    // QUIC runs next to HTTP, on its own UDP port
+    if let Some(server) = config.quic_server()? {
+        info!("QUIC listening on {}", server.local_addr()?);
+        tokio::spawn(quic::serve(server, state.clone()));
+    }
+    
     // Create CORS layer
     let cors = CorsLayer::permissive();
     
     // Create router
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/api/v1/auth/register", post(api::auth::register))
-        .route("/api/v1/auth/login", post(api::auth::login))
-        .route("/api/v1/messages", post(api::messages::send_message))
-        .route("/api/v1/messages", get(api::messages::get_messages))
-        .route("/api/v1/sync", post(api::sync::sync_messages))
-        .layer(cors);
+    let app = api::router(state).layer(cors);
     
     // Bind to address
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
@@ -41,11 +140,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let listener = tokio::net::TcpListener::bind(addr).await?;
     info!("TCP listener bound successfully");
     
-    axum::serve(listener, app).await?;
+    // Peer addresses are needed to rate limit unauthenticated endpoints
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
     
     Ok(())
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+/// Raise an alert whenever a period sees `threshold` authentication failures or more
+async fn watch_auth_failures(threshold: u64) {
+    let mut interval = tokio::time::interval(AUTH_FAILURE_ALERT_INTERVAL);
+    let mut seen = auth_failure_count();
+    loop {
+        interval.tick().await;
+        let count = auth_failure_count();
+        let failures = count - seen;
+        seen = count;
+        if failures >= threshold {
+            error!(
+                "ALERT: {} authentication failures in the last {}s, possible credential stuffing",
+                failures,
+                AUTH_FAILURE_ALERT_INTERVAL.as_secs()
+            );
+        }
+    }
+}
+
+async fn sweep_dead_letters(queues: Arc<MessageQueues>) {
+    let mut interval = tokio::time::interval(DEAD_LETTER_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match queues.sweep().await {
+            Ok(0) => {}
+            Ok(moved) => info!("Dead-lettered {} expired frames", moved),
+            Err(e) => warn!("Dead-letter sweep failed: {}", e),
+        }
+    }
 }
@@ -0,0 +1,350 @@
+//! Second factors, step-up authentication and lockout
+//!
+//! Password login needs a second factor until OPAQUE lands, so every user
+//! is handed a one-time enrolment token when they are created. It logs them
+//! in once, stepped up, to enrol TOTP or a passkey. Secrets are kept sealed
+//! under the server's factor key, and recovery codes and enrolment tokens
+//! only as digests. Sessions that have just proven a second factor are
+//! stepped up for a few minutes, which sensitive actions require.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use thiserror::Error;
+use tracing::warn;
+use xipr_core::protocol::auth::Session;
+use xipr_core::protocol::mfa::{
+    enrolment_token_digest, generate_enrolment_token, generate_recovery_codes,
+    recovery_code_digest, FactorKey, SealedFactor, SecondFactor, TotpSecret,
+};
+
+/// How long a session stays stepped up after proving a factor, in seconds
+pub const STEP_UP_WINDOW: i64 = 5 * 60;
+
+/// How long an enrolment token stays usable, in seconds
+pub const ENROLMENT_TOKEN_LIFETIME: i64 = 7 * 24 * 60 * 60;
+
+/// Failed second factors, within [`FAILURE_WINDOW`], before the user is locked out
+const MAX_FACTOR_FAILURES: u32 = 5;
+
+/// Window failures are counted over, and how long a lockout lasts, in seconds
+const FAILURE_WINDOW: i64 = 15 * 60;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum MfaError {
+    #[error("no second factor enrolled")]
+    NotEnrolled,
+    #[error("a second factor is already enrolled")]
+    AlreadyEnrolled,
+    #[error("no enrolment waiting for confirmation")]
+    NoPendingEnrolment,
+    #[error("invalid or already used code")]
+    InvalidCode,
+    #[error("too many failed attempts, locked until {0}")]
+    LockedOut(i64),
+    #[error("cannot access the TOTP secret")]
+    Sealing,
+}
+
+struct Enrolment {
+    secret: SealedFactor,
+    /// Unset until the user proves their app produces codes
+    confirmed: bool,
+    /// Last TOTP step accepted; earlier and equal steps are replays
+    last_step: Option<i64>,
+    /// Digests of recovery codes not used yet
+    recovery_codes: HashSet<[u8; 32]>,
+}
+
+struct EnrolmentToken {
+    digest: [u8; 32],
+    expires_at: i64,
+}
+
+struct Failures {
+    count: u32,
+    first_at: i64,
+    locked_until: Option<i64>,
+}
+
+pub struct MfaService {
+    key: FactorKey,
+    enrolments: Mutex<HashMap<String, Enrolment>>,
+    /// At most one unspent token per user, dropped once they enrol
+    enrolment_tokens: Mutex<HashMap<String, EnrolmentToken>>,
+    failures: Mutex<HashMap<String, Failures>>,
+    /// Session ids that proved a second factor, until their step-up lapses
+    step_ups: Mutex<HashMap<String, i64>>,
+}
+
+impl MfaService {
+    pub fn new(key: FactorKey) -> Self {
+        Self {
+            key,
+            enrolments: Mutex::new(HashMap::new()),
+            enrolment_tokens: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+            step_ups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the user has a confirmed second factor
+    pub fn is_enrolled(&self, user_id: &str) -> bool {
+        let enrolments = self.enrolments.lock().unwrap();
+        enrolments
+            .get(user_id)
+            .is_some_and(|enrolment| enrolment.confirmed)
+    }
+
+    /// Whether the user has a factor login can check: TOTP or an unspent enrolment token
+    pub fn has_factor(&self, user_id: &str, now: i64) -> bool {
+        self.is_enrolled(user_id)
+            || self
+                .enrolment_tokens
+                .lock()
+                .unwrap()
+                .get(user_id)
+                .is_some_and(|token| token.expires_at >= now)
+    }
+
+    /// Hand out a one-time token the user logs in with to enrol their first factor
+    ///
+    /// Replaces any earlier token. Users that have enrolled get none.
+    pub fn issue_enrolment_token(&self, user_id: &str, now: i64) -> Result<String, MfaError> {
+        if self.is_enrolled(user_id) {
+            return Err(MfaError::AlreadyEnrolled);
+        }
+
+        let token = generate_enrolment_token();
+        self.enrolment_tokens.lock().unwrap().insert(
+            user_id.to_string(),
+            EnrolmentToken {
+                digest: enrolment_token_digest(user_id, &token),
+                expires_at: now + ENROLMENT_TOKEN_LIFETIME,
+            },
+        );
+        Ok(token)
+    }
+
+    pub fn recovery_codes_remaining(&self, user_id: &str) -> usize {
+        let enrolments = self.enrolments.lock().unwrap();
+        enrolments
+            .get(user_id)
+            .filter(|enrolment| enrolment.confirmed)
+            .map_or(0, |enrolment| enrolment.recovery_codes.len())
+    }
+
+    /// Start TOTP enrolment with a new secret for the user's app
+    ///
+    /// Replaces any enrolment still waiting for confirmation.
+    pub fn begin_enrolment(&self, user_id: &str) -> Result<TotpSecret, MfaError> {
+        let mut enrolments = self.enrolments.lock().unwrap();
+        if enrolments
+            .get(user_id)
+            .is_some_and(|enrolment| enrolment.confirmed)
+        {
+            return Err(MfaError::AlreadyEnrolled);
+        }
+
+        let secret = TotpSecret::generate();
+        let sealed = self
+            .key
+            .seal(user_id, &secret)
+            .map_err(|_| MfaError::Sealing)?;
+        enrolments.insert(
+            user_id.to_string(),
+            Enrolment {
+                secret: sealed,
+                confirmed: false,
+                last_step: None,
+                recovery_codes: HashSet::new(),
+            },
+        );
+        Ok(secret)
+    }
+
+    /// Finish enrolment with a code from the app, returning the recovery codes
+    pub fn confirm_enrolment(
+        &self,
+        user_id: &str,
+        code: &str,
+        now: i64,
+    ) -> Result<Vec<String>, MfaError> {
+        self.check_lockout(user_id, now)?;
+
+        let mut enrolments = self.enrolments.lock().unwrap();
+        let enrolment = enrolments
+            .get_mut(user_id)
+            .filter(|enrolment| !enrolment.confirmed)
+            .ok_or(MfaError::NoPendingEnrolment)?;
+
+        let secret = self
+            .key
+            .open(user_id, &enrolment.secret)
+            .map_err(|_| MfaError::Sealing)?;
+        let Some(step) = secret.verify(code, now) else {
+            drop(enrolments);
+            return Err(self.record_failure(user_id, now));
+        };
+
+        let codes = generate_recovery_codes();
+        enrolment.confirmed = true;
+        enrolment.last_step = Some(step);
+        enrolment.recovery_codes = digests(user_id, &codes);
+        drop(enrolments);
+
+        self.enrolment_tokens.lock().unwrap().remove(user_id);
+        self.failures.lock().unwrap().remove(user_id);
+        Ok(codes)
+    }
+
+    /// Check a second factor; recovery codes and enrolment tokens are used up
+    ///
+    /// Repeated failures lock the user out, and a locked out user is refused
+    /// even with a correct factor.
+    pub fn verify(&self, user_id: &str, factor: &SecondFactor, now: i64) -> Result<(), MfaError> {
+        self.check_lockout(user_id, now)?;
+        if let SecondFactor::EnrolmentToken { token } = factor {
+            return self.redeem_enrolment_token(user_id, token, now);
+        }
+
+        let mut enrolments = self.enrolments.lock().unwrap();
+        let enrolment = enrolments
+            .get_mut(user_id)
+            .filter(|enrolment| enrolment.confirmed)
+            .ok_or(MfaError::NotEnrolled)?;
+
+        let accepted = match factor {
+            SecondFactor::Totp { code } => {
+                let secret = self
+                    .key
+                    .open(user_id, &enrolment.secret)
+                    .map_err(|_| MfaError::Sealing)?;
+                match secret.verify(code, now) {
+                    Some(step) if enrolment.last_step.is_none_or(|last| step > last) => {
+                        enrolment.last_step = Some(step);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            SecondFactor::RecoveryCode { code } => enrolment
+                .recovery_codes
+                .remove(&recovery_code_digest(user_id, code)),
+            SecondFactor::EnrolmentToken { .. } => false,
+        };
+        drop(enrolments);
+
+        if !accepted {
+            return Err(self.record_failure(user_id, now));
+        }
+        self.failures.lock().unwrap().remove(user_id);
+        Ok(())
+    }
+
+    /// Replace the user's recovery codes, invalidating the old ones
+    pub fn regenerate_recovery_codes(&self, user_id: &str) -> Result<Vec<String>, MfaError> {
+        let mut enrolments = self.enrolments.lock().unwrap();
+        let enrolment = enrolments
+            .get_mut(user_id)
+            .filter(|enrolment| enrolment.confirmed)
+            .ok_or(MfaError::NotEnrolled)?;
+
+        let codes = generate_recovery_codes();
+        enrolment.recovery_codes = digests(user_id, &codes);
+        Ok(codes)
+    }
+
+    /// Remove the user's second factor and recovery codes
+    pub fn disable(&self, user_id: &str) -> Result<(), MfaError> {
+        self.enrolments
+            .lock()
+            .unwrap()
+            .remove(user_id)
+            .map(|_| ())
+            .ok_or(MfaError::NotEnrolled)
+    }
+
+    /// Mark a session as having just proven a second factor, returning when that lapses
+    pub fn step_up(&self, session: &Session, now: i64) -> i64 {
+        let until = now + STEP_UP_WINDOW;
+        let mut step_ups = self.step_ups.lock().unwrap();
+        step_ups.retain(|_, until| *until >= now);
+        step_ups.insert(session.id.clone(), until);
+        until
+    }
+
+    /// When the session's step-up lapses, if it is stepped up
+    ///
+    /// Only a proven factor or a passkey login steps a session up; a fresh
+    /// session does not count on its own.
+    pub fn stepped_up_until(&self, session: &Session, now: i64) -> Option<i64> {
+        let until = *self.step_ups.lock().unwrap().get(&session.id)?;
+        (until >= now).then_some(until)
+    }
+
+    fn redeem_enrolment_token(&self, user_id: &str, token: &str, now: i64) -> Result<(), MfaError> {
+        if self.is_enrolled(user_id) {
+            return Err(MfaError::AlreadyEnrolled);
+        }
+
+        let mut tokens = self.enrolment_tokens.lock().unwrap();
+        let accepted = tokens.get(user_id).is_some_and(|issued| {
+            issued.expires_at >= now && issued.digest == enrolment_token_digest(user_id, token)
+        });
+        if !accepted {
+            drop(tokens);
+            return Err(self.record_failure(user_id, now));
+        }
+        tokens.remove(user_id);
+        drop(tokens);
+
+        self.failures.lock().unwrap().remove(user_id);
+        Ok(())
+    }
+
+    fn check_lockout(&self, user_id: &str, now: i64) -> Result<(), MfaError> {
+        let failures = self.failures.lock().unwrap();
+        match failures
+            .get(user_id)
+            .and_then(|failures| failures.locked_until)
+        {
+            Some(until) if until > now => Err(MfaError::LockedOut(until)),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure(&self, user_id: &str, now: i64) -> MfaError {
+        let mut failures = self.failures.lock().unwrap();
+        let entry = failures.entry(user_id.to_string()).or_insert(Failures {
+            count: 0,
+            first_at: now,
+            locked_until: None,
+        });
+        if now - entry.first_at > FAILURE_WINDOW {
+            *entry = Failures {
+                count: 0,
+                first_at: now,
+                locked_until: None,
+            };
+        }
+
+        entry.count += 1;
+        if entry.count >= MAX_FACTOR_FAILURES {
+            let until = now + FAILURE_WINDOW;
+            entry.locked_until = Some(until);
+            warn!(
+                "User {} locked out after {} failed second factors",
+                user_id, entry.count
+            );
+            return MfaError::LockedOut(until);
+        }
+        MfaError::InvalidCode
+    }
+}
+
+fn digests(user_id: &str, codes: &[String]) -> HashSet<[u8; 32]> {
+    codes
+        .iter()
+        .map(|code| recovery_code_digest(user_id, code))
+        .collect()
+}
@@ -0,0 +1,277 @@
+//! Passkey credentials and WebAuthn ceremonies
+//!
+//! Each ceremony gets a one-time challenge that is consumed by its first
+//! answer, right or wrong. Credentials are stored by id with their owner,
+//! public key and signature counter.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use thiserror::Error;
+use tracing::warn;
+use xipr_core::protocol::webauthn::{
+    generate_challenge, AssertionResponse, AttestationType, CredentialPublicKey,
+    RegistrationResponse, RelyingParty, WEBAUTHN_CHALLENGE_LENGTH,
+};
+
+use crate::ratelimit::RateLimiter;
+
+/// How long a ceremony's challenge can be answered, in seconds
+pub const CEREMONY_LIFETIME: i64 = 5 * 60;
+
+/// Most passkeys a user can register
+const MAX_CREDENTIALS_PER_USER: usize = 20;
+
+/// Ceremonies waiting for an answer, across all users; the oldest is dropped beyond this
+const MAX_PENDING_CEREMONIES: usize = 10_000;
+
+/// Burst of login ceremonies allowed per source address
+const CEREMONY_SOURCE_BURST: u32 = 20;
+const CEREMONY_SOURCE_REFILL_PER_SEC: f64 = 1.0 / 6.0;
+
+#[derive(Debug, Error)]
+pub enum PasskeyError {
+    #[error("unknown or expired ceremony")]
+    UnknownCeremony,
+    #[error("unknown credential")]
+    UnknownCredential,
+    #[error("credential already registered")]
+    AlreadyRegistered,
+    #[error("too many passkeys")]
+    TooManyCredentials,
+    #[error("verification failed: {0}")]
+    Verification(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PasskeyRecord {
+    pub credential_id: Vec<u8>,
+    pub user_id: String,
+    pub name: Option<String>,
+    pub public_key: CredentialPublicKey,
+    pub sign_count: u32,
+    pub aaguid: [u8; 16],
+    pub attestation: AttestationType,
+    pub created_at: i64,
+    pub last_used: Option<i64>,
+}
+
+enum Ceremony {
+    Registration { user_id: String },
+    Authentication,
+}
+
+struct PendingCeremony {
+    ceremony: Ceremony,
+    challenge: [u8; WEBAUTHN_CHALLENGE_LENGTH],
+    expires_at: i64,
+}
+
+pub struct PasskeyService {
+    rp: RelyingParty,
+    /// Credentials by credential id
+    credentials: Mutex<HashMap<Vec<u8>, PasskeyRecord>>,
+    /// Ceremonies by ceremony id
+    ceremonies: Mutex<HashMap<String, PendingCeremony>>,
+    /// Login ceremonies need no session, so they are limited per source address
+    ceremonies_by_source: RateLimiter,
+}
+
+impl PasskeyService {
+    pub fn new(rp: RelyingParty) -> Self {
+        Self {
+            rp,
+            credentials: Mutex::new(HashMap::new()),
+            ceremonies: Mutex::new(HashMap::new()),
+            ceremonies_by_source: RateLimiter::new(
+                CEREMONY_SOURCE_BURST,
+                CEREMONY_SOURCE_REFILL_PER_SEC,
+            ),
+        }
+    }
+
+    pub fn relying_party(&self) -> &RelyingParty {
+        &self.rp
+    }
+
+    pub fn credentials_of(&self, user_id: &str) -> Vec<PasskeyRecord> {
+        let credentials = self.credentials.lock().unwrap();
+        let mut records: Vec<PasskeyRecord> = credentials
+            .values()
+            .filter(|record| record.user_id == user_id)
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| record.created_at);
+        records
+    }
+
+    /// Start registering a passkey for a user, returning the ceremony id and challenge
+    pub fn start_registration(
+        &self,
+        user_id: &str,
+        now: i64,
+    ) -> Result<(String, [u8; WEBAUTHN_CHALLENGE_LENGTH]), PasskeyError> {
+        self.start(
+            Ceremony::Registration {
+                user_id: user_id.to_string(),
+            },
+            now,
+        )
+    }
+
+    pub fn finish_registration(
+        &self,
+        ceremony_id: &str,
+        user_id: &str,
+        response: &RegistrationResponse,
+        name: Option<String>,
+        now: i64,
+    ) -> Result<PasskeyRecord, PasskeyError> {
+        let pending = self.take(ceremony_id, now)?;
+        match &pending.ceremony {
+            Ceremony::Registration { user_id: owner } if owner == user_id => {}
+            _ => return Err(PasskeyError::UnknownCeremony),
+        }
+
+        let verified = self
+            .rp
+            .verify_registration(response, &pending.challenge, true)
+            .map_err(|e| PasskeyError::Verification(e.to_string()))?;
+
+        let mut credentials = self.credentials.lock().unwrap();
+        if credentials.contains_key(&verified.credential_id) {
+            return Err(PasskeyError::AlreadyRegistered);
+        }
+        let owned = credentials
+            .values()
+            .filter(|record| record.user_id == user_id)
+            .count();
+        if owned >= MAX_CREDENTIALS_PER_USER {
+            return Err(PasskeyError::TooManyCredentials);
+        }
+
+        let record = PasskeyRecord {
+            credential_id: verified.credential_id,
+            user_id: user_id.to_string(),
+            name,
+            public_key: verified.public_key,
+            sign_count: verified.sign_count,
+            aaguid: verified.aaguid,
+            attestation: verified.attestation,
+            created_at: now,
+            last_used: None,
+        };
+        credentials.insert(record.credential_id.clone(), record.clone());
+        Ok(record)
+    }
+
+    /// Take a rate limit token for starting a login ceremony from `source`
+    pub fn allow_authentication(&self, source: &str) -> bool {
+        self.ceremonies_by_source.check(source)
+    }
+
+    /// Start a login with any discoverable passkey
+    pub fn start_authentication(
+        &self,
+        now: i64,
+    ) -> Result<(String, [u8; WEBAUTHN_CHALLENGE_LENGTH]), PasskeyError> {
+        self.start(Ceremony::Authentication, now)
+    }
+
+    /// Check a login assertion, returning the credential it used
+    pub fn finish_authentication(
+        &self,
+        ceremony_id: &str,
+        assertion: &AssertionResponse,
+        now: i64,
+    ) -> Result<PasskeyRecord, PasskeyError> {
+        let pending = self.take(ceremony_id, now)?;
+        if !matches!(pending.ceremony, Ceremony::Authentication) {
+            return Err(PasskeyError::UnknownCeremony);
+        }
+
+        let mut credentials = self.credentials.lock().unwrap();
+        let record = credentials
+            .get_mut(&assertion.credential_id)
+            .ok_or(PasskeyError::UnknownCredential)?;
+        if assertion
+            .user_handle
+            .as_ref()
+            .is_some_and(|handle| handle.as_slice() != record.user_id.as_bytes())
+        {
+            return Err(PasskeyError::UnknownCredential);
+        }
+
+        let sign_count = self
+            .rp
+            .verify_assertion(
+                assertion,
+                &pending.challenge,
+                &record.public_key,
+                record.sign_count,
+                true,
+            )
+            .map_err(|e| {
+                warn!(
+                    "Passkey assertion for user {} refused: {}",
+                    record.user_id, e
+                );
+                PasskeyError::Verification(e.to_string())
+            })?;
+
+        record.sign_count = sign_count;
+        record.last_used = Some(now);
+        Ok(record.clone())
+    }
+
+    pub fn remove(&self, user_id: &str, credential_id: &[u8]) -> Result<(), PasskeyError> {
+        let mut credentials = self.credentials.lock().unwrap();
+        match credentials.get(credential_id) {
+            Some(record) if record.user_id == user_id => {
+                credentials.remove(credential_id);
+                Ok(())
+            }
+            _ => Err(PasskeyError::UnknownCredential),
+        }
+    }
+
+    fn start(
+        &self,
+        ceremony: Ceremony,
+        now: i64,
+    ) -> Result<(String, [u8; WEBAUTHN_CHALLENGE_LENGTH]), PasskeyError> {
+        let mut ceremonies = self.ceremonies.lock().unwrap();
+        ceremonies.retain(|_, pending| pending.expires_at >= now);
+        // Dropping the oldest keeps a flood from refusing every new ceremony
+        if ceremonies.len() >= MAX_PENDING_CEREMONIES {
+            let oldest = ceremonies
+                .iter()
+                .min_by_key(|(_, pending)| pending.expires_at)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                ceremonies.remove(&oldest);
+            }
+        }
+
+        let ceremony_id = uuid::Uuid::new_v4().to_string();
+        let challenge = generate_challenge();
+        ceremonies.insert(
+            ceremony_id.clone(),
+            PendingCeremony {
+                ceremony,
+                challenge,
+                expires_at: now + CEREMONY_LIFETIME,
+            },
+        );
+        Ok((ceremony_id, challenge))
+    }
+
+    fn take(&self, ceremony_id: &str, now: i64) -> Result<PendingCeremony, PasskeyError> {
+        self.ceremonies
+            .lock()
+            .unwrap()
+            .remove(ceremony_id)
+            .filter(|pending| pending.expires_at >= now)
+            .ok_or(PasskeyError::UnknownCeremony)
+    }
+}
@@ -0,0 +1,123 @@
+//! In-process queue store
+//!
+//! Used when no Redis URL is configured. Queues do not survive a restart.
+
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use xipr_core::protocol::transport::MessageFrame;
+
+use super::{QueueError, QueueStore, QueuedFrame};
+
+#[derive(Default)]
+struct DeviceQueue {
+    next_sequence: u64,
+    frames: VecDeque<QueuedFrame>,
+    dead_letters: Vec<QueuedFrame>,
+}
+
+#[derive(Default)]
+pub struct MemoryQueueStore {
+    queues: Mutex<HashMap<String, DeviceQueue>>,
+}
+
+impl MemoryQueueStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl QueueStore for MemoryQueueStore {
+    async fn push(
+        &self,
+        device_id: &str,
+        mut frame: MessageFrame,
+        enqueued_at: i64,
+    ) -> Result<u64, QueueError> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(device_id.to_string()).or_default();
+
+        queue.next_sequence += 1;
+        let sequence = queue.next_sequence;
+        frame.header.sequence_number = sequence;
+        queue.frames.push_back(QueuedFrame {
+            sequence,
+            enqueued_at,
+            frame,
+        });
+
+        Ok(sequence)
+    }
+
+    async fn ack(&self, device_id: &str, sequence: u64) -> Result<usize, QueueError> {
+        let mut queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get_mut(device_id) else {
+            return Ok(0);
+        };
+
+        let before = queue.frames.len();
+        queue.frames.retain(|queued| queued.sequence > sequence);
+        Ok(before - queue.frames.len())
+    }
+
+    async fn pending_after(
+        &self,
+        device_id: &str,
+        after: u64,
+    ) -> Result<Vec<QueuedFrame>, QueueError> {
+        let queues = self.queues.lock().unwrap();
+        Ok(queues
+            .get(device_id)
+            .map(|queue| {
+                queue
+                    .frames
+                    .iter()
+                    .filter(|queued| queued.sequence > after)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn dead_letter_before(&self, device_id: &str, cutoff: i64) -> Result<usize, QueueError> {
+        let mut queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get_mut(device_id) else {
+            return Ok(0);
+        };
+
+        let mut moved = 0;
+        while queue
+            .frames
+            .front()
+            .is_some_and(|queued| queued.enqueued_at < cutoff)
+        {
+            let expired = queue.frames.pop_front().unwrap();
+            queue.dead_letters.push(expired);
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    async fn dead_letters(&self, device_id: &str) -> Result<Vec<QueuedFrame>, QueueError> {
+        let queues = self.queues.lock().unwrap();
+        Ok(queues
+            .get(device_id)
+            .map(|queue| queue.dead_letters.clone())
+            .unwrap_or_default())
+    }
+
+    async fn devices(&self) -> Result<Vec<String>, QueueError> {
+        Ok(self.queues.lock().unwrap().keys().cloned().collect())
+    }
+
+    async fn purge(&self, device_id: &str) -> Result<usize, QueueError> {
+        Ok(self
+            .queues
+            .lock()
+            .unwrap()
+            .remove(device_id)
+            .map(|queue| queue.frames.len() + queue.dead_letters.len())
+            .unwrap_or(0))
+    }
+}
@@ -0,0 +1,174 @@
+//! Per-device message queues for XIPRNET server
+//!
+//! Frames are queued per recipient device with monotonically increasing
+//! sequence numbers and stay queued until the device acknowledges them.
+//! Delivery is at-least-once: unacknowledged frames are redelivered, and
+//! clients drop duplicates by message id. Frames that stay unacknowledged
+//! past the configured TTL are moved to a dead-letter queue.
+
+pub mod memory;
+pub mod redis;
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::watch;
+use xipr_core::protocol::transport::MessageFrame;
+
+pub use self::memory::MemoryQueueStore;
+pub use self::redis::RedisQueueStore;
+
+#[derive(Debug, Error)]
+pub enum QueueError {
+    #[error("queue backend error: {0}")]
+    Backend(String),
+
+    #[error("corrupt queue entry: {0}")]
+    Corrupt(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    /// Unacknowledged frames older than this are dead-lettered
+    pub message_ttl: Duration,
+    /// Sent frames that stay unacknowledged this long are sent again
+    pub redelivery_timeout: Duration,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            message_ttl: Duration::from_secs(30 * 24 * 60 * 60),
+            redelivery_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueuedFrame {
+    pub sequence: u64,
+    /// Unix timestamp the frame was queued at
+    pub enqueued_at: i64,
+    pub frame: MessageFrame,
+}
+
+/// Storage backend for device queues
+#[async_trait]
+pub trait QueueStore: Send + Sync {
+    /// Append a frame and return the sequence number assigned to it
+    async fn push(
+        &self,
+        device_id: &str,
+        frame: MessageFrame,
+        enqueued_at: i64,
+    ) -> Result<u64, QueueError>;
+
+    /// Remove every frame up to and including `sequence`
+    async fn ack(&self, device_id: &str, sequence: u64) -> Result<usize, QueueError>;
+
+    /// Frames with a sequence number greater than `after`, oldest first
+    async fn pending_after(
+        &self,
+        device_id: &str,
+        after: u64,
+    ) -> Result<Vec<QueuedFrame>, QueueError>;
+
+    /// Move frames queued before `cutoff` to the dead-letter queue
+    async fn dead_letter_before(&self, device_id: &str, cutoff: i64) -> Result<usize, QueueError>;
+
+    async fn dead_letters(&self, device_id: &str) -> Result<Vec<QueuedFrame>, QueueError>;
+
+    /// Devices that currently have a queue
+    async fn devices(&self) -> Result<Vec<String>, QueueError>;
+
+    /// Drop a device's queue, dead letters and sequence counter, returning
+    /// how many frames were dropped
+    async fn purge(&self, device_id: &str) -> Result<usize, QueueError>;
+}
+
+pub struct MessageQueues {
+    store: Box<dyn QueueStore>,
+    config: QueueConfig,
+    /// Latest sequence number queued per device, watched by connected sockets
+    notifiers: Mutex<HashMap<String, watch::Sender<u64>>>,
+}
+
+impl MessageQueues {
+    pub fn new(store: Box<dyn QueueStore>, config: QueueConfig) -> Self {
+        Self {
+            store,
+            config,
+            notifiers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &QueueConfig {
+        &self.config
+    }
+
+    /// Queue a frame for a device and return its sequence number
+    pub async fn enqueue(&self, device_id: &str, frame: MessageFrame) -> Result<u64, QueueError> {
+        let sequence = self
+            .store
+            .push(device_id, frame, chrono::Utc::now().timestamp())
+            .await?;
+
+        self.notifier(device_id).send_replace(sequence);
+        Ok(sequence)
+    }
+
+    pub async fn ack(&self, device_id: &str, sequence: u64) -> Result<usize, QueueError> {
+        self.store.ack(device_id, sequence).await
+    }
+
+    /// Unexpired frames after `after`; expired ones are dead-lettered first
+    pub async fn pending_after(
+        &self,
+        device_id: &str,
+        after: u64,
+    ) -> Result<Vec<QueuedFrame>, QueueError> {
+        self.store
+            .dead_letter_before(device_id, self.ttl_cutoff())
+            .await?;
+        self.store.pending_after(device_id, after).await
+    }
+
+    pub async fn dead_letters(&self, device_id: &str) -> Result<Vec<QueuedFrame>, QueueError> {
+        self.store.dead_letters(device_id).await
+    }
+
+    /// Dead-letter expired frames across every device queue
+    pub async fn sweep(&self) -> Result<usize, QueueError> {
+        let cutoff = self.ttl_cutoff();
+        let mut moved = 0;
+        for device_id in self.store.devices().await? {
+            moved += self.store.dead_letter_before(&device_id, cutoff).await?;
+        }
+        Ok(moved)
+    }
+
+    /// Drop everything queued for a device that no longer exists
+    pub async fn purge(&self, device_id: &str) -> Result<usize, QueueError> {
+        let purged = self.store.purge(device_id).await?;
+        self.notifiers.lock().unwrap().remove(device_id);
+        Ok(purged)
+    }
+
+    pub fn subscribe(&self, device_id: &str) -> watch::Receiver<u64> {
+        self.notifier(device_id).subscribe()
+    }
+
+    fn notifier(&self, device_id: &str) -> watch::Sender<u64> {
+        let mut notifiers = self.notifiers.lock().unwrap();
+        notifiers
+            .entry(device_id.to_string())
+            .or_insert_with(|| watch::channel(0).0)
+            .clone()
+    }
+
+    fn ttl_cutoff(&self) -> i64 {
+        chrono::Utc::now().timestamp() - self.config.message_ttl.as_secs() as i64
+    }
+}
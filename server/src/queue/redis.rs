@@ -0,0 +1,188 @@
+//! Redis-backed queue store
+//!
+//! Each device has a sorted set of queued frames scored by sequence number,
+//! a counter that hands out those sequence numbers, and a dead-letter list.
+//! Entries are the enqueue timestamp as a big-endian `i64` followed by the
+//! frame in the binary wire format.
+
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use xipr_core::protocol::transport::MessageFrame;
+
+use super::{QueueError, QueueStore, QueuedFrame};
+
+/// Set of every device that has a queue, walked by the TTL sweep.
+///
+/// Each kind of key has its own prefix so no device id can collide with this
+/// set or with another device's keys.
+const DEVICES_KEY: &str = "xipr:qdevices";
+
+/// How long dead-lettered frames are kept for inspection
+const DEAD_LETTER_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+
+impl From<redis::RedisError> for QueueError {
+    fn from(error: redis::RedisError) -> Self {
+        QueueError::Backend(error.to_string())
+    }
+}
+
+pub struct RedisQueueStore {
+    connection: MultiplexedConnection,
+}
+
+impl RedisQueueStore {
+    pub async fn connect(url: &str) -> Result<Self, QueueError> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+        Ok(Self { connection })
+    }
+}
+
+fn queue_key(device_id: &str) -> String {
+    format!("xipr:q:{}", device_id)
+}
+
+fn sequence_key(device_id: &str) -> String {
+    format!("xipr:qseq:{}", device_id)
+}
+
+fn dead_letter_key(device_id: &str) -> String {
+    format!("xipr:qdead:{}", device_id)
+}
+
+fn encode_entry(enqueued_at: i64, frame: &MessageFrame) -> Result<Vec<u8>, QueueError> {
+    let encoded = frame
+        .encode()
+        .map_err(|e| QueueError::Corrupt(e.to_string()))?;
+
+    let mut entry = Vec::with_capacity(8 + encoded.len());
+    entry.extend_from_slice(&enqueued_at.to_be_bytes());
+    entry.extend_from_slice(&encoded);
+    Ok(entry)
+}
+
+fn decode_entry(entry: &[u8]) -> Result<QueuedFrame, QueueError> {
+    let (timestamp, encoded) = entry
+        .split_first_chunk::<8>()
+        .ok_or_else(|| QueueError::Corrupt("entry shorter than its timestamp".to_string()))?;
+    let frame = MessageFrame::decode(encoded).map_err(|e| QueueError::Corrupt(e.to_string()))?;
+
+    Ok(QueuedFrame {
+        sequence: frame.header.sequence_number,
+        enqueued_at: i64::from_be_bytes(*timestamp),
+        frame,
+    })
+}
+
+#[async_trait]
+impl QueueStore for RedisQueueStore {
+    async fn push(
+        &self,
+        device_id: &str,
+        mut frame: MessageFrame,
+        enqueued_at: i64,
+    ) -> Result<u64, QueueError> {
+        let mut connection = self.connection.clone();
+
+        let sequence: u64 = connection.incr(sequence_key(device_id), 1).await?;
+        frame.header.sequence_number = sequence;
+        let entry = encode_entry(enqueued_at, &frame)?;
+
+        redis::pipe()
+            .atomic()
+            .zadd(queue_key(device_id), entry, sequence)
+            .ignore()
+            .sadd(DEVICES_KEY, device_id)
+            .ignore()
+            .query_async::<()>(&mut connection)
+            .await?;
+
+        Ok(sequence)
+    }
+
+    async fn ack(&self, device_id: &str, sequence: u64) -> Result<usize, QueueError> {
+        let mut connection = self.connection.clone();
+        let removed: usize = connection
+            .zrembyscore(queue_key(device_id), "-inf", sequence)
+            .await?;
+        Ok(removed)
+    }
+
+    async fn pending_after(
+        &self,
+        device_id: &str,
+        after: u64,
+    ) -> Result<Vec<QueuedFrame>, QueueError> {
+        let mut connection = self.connection.clone();
+        let entries: Vec<Vec<u8>> = connection
+            .zrangebyscore(queue_key(device_id), format!("({}", after), "+inf")
+            .await?;
+
+        entries.iter().map(|entry| decode_entry(entry)).collect()
+    }
+
+    async fn dead_letter_before(&self, device_id: &str, cutoff: i64) -> Result<usize, QueueError> {
+        let mut connection = self.connection.clone();
+        let entries: Vec<Vec<u8>> = connection.zrange(queue_key(device_id), 0, -1).await?;
+
+        // Sequence order is enqueue order, so the expired frames are a prefix
+        let expired: Vec<&Vec<u8>> = entries
+            .iter()
+            .take_while(
+                |entry| matches!(decode_entry(entry), Ok(queued) if queued.enqueued_at < cutoff),
+            )
+            .collect();
+
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for entry in &expired {
+            pipe.zrem(queue_key(device_id), *entry)
+                .ignore()
+                .rpush(dead_letter_key(device_id), *entry)
+                .ignore();
+        }
+        pipe.expire(dead_letter_key(device_id), DEAD_LETTER_RETENTION_SECS)
+            .ignore()
+            .query_async::<()>(&mut connection)
+            .await?;
+
+        Ok(expired.len())
+    }
+
+    async fn dead_letters(&self, device_id: &str) -> Result<Vec<QueuedFrame>, QueueError> {
+        let mut connection = self.connection.clone();
+        let entries: Vec<Vec<u8>> = connection.lrange(dead_letter_key(device_id), 0, -1).await?;
+
+        entries.iter().map(|entry| decode_entry(entry)).collect()
+    }
+
+    async fn devices(&self) -> Result<Vec<String>, QueueError> {
+        let mut connection = self.connection.clone();
+        let devices: Vec<String> = connection.smembers(DEVICES_KEY).await?;
+        Ok(devices)
+    }
+
+    async fn purge(&self, device_id: &str) -> Result<usize, QueueError> {
+        let mut connection = self.connection.clone();
+        let (queued, dead): (usize, usize) = redis::pipe()
+            .atomic()
+            .zcard(queue_key(device_id))
+            .llen(dead_letter_key(device_id))
+            .del(&[
+                queue_key(device_id),
+                sequence_key(device_id),
+                dead_letter_key(device_id),
+            ])
+            .ignore()
+            .srem(DEVICES_KEY, device_id)
+            .ignore()
+            .query_async(&mut connection)
+            .await?;
+        Ok(queued + dead)
+    }
+}
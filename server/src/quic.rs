@@ -0,0 +1,279 @@
+//! QUIC listener for XIPRNET server
+//!
+//! Serves the WebSocket session protocol over QUIC, next to the HTTP server.
+//! The client first opens the session stream and sends a
+//! `ControlMessage::Authenticate` frame on it; acks travel on it afterwards.
+//! Messages use one stream per conversation: the client opens streams for
+//! what it sends, and the server opens streams for what it pushes. QUIC
+//! keep-alives replace the WebSocket heartbeats.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use xipr_core::protocol::auth::Session;
+use xipr_core::protocol::proof::ProofTarget;
+use xipr_core::protocol::quic::{
+    ConversationStream, QuicLink, QuicServer, MAX_CONVERSATION_STREAMS, SESSION_CONVERSATION,
+};
+use xipr_core::protocol::transport::{ControlMessage, Message, MessageFrame};
+
+use crate::api::bind_session;
+use crate::api::realtime::{handle_frame, Handled, InFlight};
+use crate::state::AppState;
+
+/// Links that do not authenticate within this time are closed
+const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval between checks for a revoked or expired session
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Frames read from the client's streams but not yet handled
+const INCOMING_BUFFER: usize = 64;
+
+/// Conversation streams the server keeps open for pushes on one link
+const MAX_PUSH_STREAMS: usize = MAX_CONVERSATION_STREAMS as usize / 2;
+
+pub async fn serve(server: QuicServer, state: AppState) {
+    while let Some(link) = server.accept().await {
+        match link {
+            Ok(link) => {
+                tokio::spawn(run_link(link, state.clone()));
+            }
+            Err(e) => debug!("QUIC connection failed: {}", e),
+        }
+    }
+}
+
+async fn run_link(link: QuicLink, state: AppState) {
+    let (session, last_ack, control) =
+        match tokio::time::timeout(AUTHENTICATION_TIMEOUT, authenticate(&link, &state)).await {
+            Ok(Ok(authenticated)) => authenticated,
+            Ok(Err(reason)) => {
+                debug!(
+                    "Rejecting QUIC link from {}: {}",
+                    link.remote_address(),
+                    reason
+                );
+                link.close(&reason);
+                return;
+            }
+            Err(_) => {
+                link.close("authentication timed out");
+                return;
+            }
+        };
+
+    let device_id = session.device_id.clone();
+    state.devices.touch(&device_id);
+
+    // Everything the device acknowledged before reconnecting can go; the rest is replayed
+    if let Err(e) = state.queues.ack(&device_id, last_ack).await {
+        warn!("Failed to ack queue for device {}: {}", device_id, e);
+        link.close("queue unavailable");
+        return;
+    }
+
+    // Streams are read by their own tasks, so a stalled conversation holds up nothing else
+    let (incoming, mut frames) = mpsc::channel(INCOMING_BUFFER);
+    tokio::spawn(forward_frames(control, incoming.clone()));
+    tokio::spawn(accept_conversations(link.clone(), incoming));
+
+    let mut updates = state.queues.subscribe(&device_id);
+    let mut in_flight = InFlight::new(last_ack);
+    let mut outgoing = HashMap::new();
+    let mut session_check = tokio::time::interval(SESSION_CHECK_INTERVAL);
+    let redelivery_timeout = state.queues.config().redelivery_timeout;
+    let mut redelivery = tokio::time::interval(redelivery_timeout);
+
+    let mut reason = "closed".to_string();
+    if let Err(e) = push_pending(&link, &state, &session, &mut in_flight, &mut outgoing).await {
+        reason = e;
+    } else {
+        loop {
+            tokio::select! {
+                changed = updates.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    if let Err(e) = push_pending(&link, &state, &session, &mut in_flight, &mut outgoing).await {
+                        reason = e;
+                        break;
+                    }
+                }
+                frame = frames.recv() => {
+                    let handled = match frame {
+                        Some(Ok(frame)) => handle_frame(&state, &session, frame).await,
+                        Some(Err(e)) => Err(e),
+                        None => break,
+                    };
+                    match handled {
+                        Ok(Handled::Acked(acked)) => in_flight.acked(acked),
+                        Ok(Handled::Nothing) => {}
+                        Ok(Handled::Rejected { conversation_id, reply }) => {
+                            if let Err(e) = send_reply(&link, &mut outgoing, &conversation_id, &reply).await {
+                                reason = e;
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Closing QUIC link for device {}: {}", device_id, e);
+                            reason = e;
+                            break;
+                        }
+                    }
+                }
+                _ = redelivery.tick() => {
+                    if in_flight.rewind_if_stale(redelivery_timeout) {
+                        debug!("Redelivering unacknowledged frames to device {}", device_id);
+                        if let Err(e) = push_pending(&link, &state, &session, &mut in_flight, &mut outgoing).await {
+                            reason = e;
+                            break;
+                        }
+                    }
+                }
+                _ = session_check.tick() => {
+                    if !state.auth.is_session_active(&session) {
+                        reason = "session ended".to_string();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    link.close(&reason);
+    debug!("QUIC link closed for device {}", device_id);
+}
+
+/// Wait for the session stream and the `Authenticate` frame on it
+async fn authenticate(
+    link: &QuicLink,
+    state: &AppState,
+) -> Result<(Session, u64, ConversationStream), String> {
+    let mut control = link
+        .accept_conversation()
+        .await
+        .map_err(|e| e.to_string())?;
+    if control.conversation_id() != SESSION_CONVERSATION {
+        return Err("first stream must be the session stream".to_string());
+    }
+
+    let frame = control
+        .recv()
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("session stream finished before authenticating")?;
+    let message = Message::from_frame(&frame).map_err(|e| e.to_string())?;
+    let ControlMessage::Authenticate {
+        token,
+        last_ack,
+        proof,
+    } = ControlMessage::from_message(&message).map_err(|e| e.to_string())?
+    else {
+        return Err("expected an Authenticate frame".to_string());
+    };
+
+    let session = bind_session(state, &ProofTarget::link(&token), proof.as_deref())
+        .map_err(|_| "invalid session token or proof")?;
+    Ok((session, last_ack, control))
+}
+
+/// Accept conversation streams from the client and read each on its own task
+async fn accept_conversations(
+    link: QuicLink,
+    incoming: mpsc::Sender<Result<MessageFrame, String>>,
+) {
+    while let Ok(stream) = link.accept_conversation().await {
+        tokio::spawn(forward_frames(stream, incoming.clone()));
+    }
+}
+
+async fn forward_frames(
+    mut stream: ConversationStream,
+    incoming: mpsc::Sender<Result<MessageFrame, String>>,
+) {
+    loop {
+        let frame = match stream.recv().await {
+            Ok(Some(frame)) => Ok(frame),
+            Ok(None) => return,
+            Err(e) => Err(e.to_string()),
+        };
+        let failed = frame.is_err();
+        if incoming.send(frame).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Send every queued frame the link has not sent yet, each on its conversation's stream
+async fn push_pending(
+    link: &QuicLink,
+    state: &AppState,
+    session: &Session,
+    in_flight: &mut InFlight,
+    outgoing: &mut HashMap<String, ConversationStream>,
+) -> Result<(), String> {
+    let pending = state
+        .queues
+        .pending_after(&session.device_id, in_flight.last_sent())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for queued in pending {
+        // Sync copies of the user's own messages belong to the recipient's conversation
+        let header = &queued.frame.header;
+        let conversation_id = if header.sender_id == session.user_id {
+            header.recipient_id.clone()
+        } else {
+            header.sender_id.clone()
+        };
+
+        let stream = outgoing_stream(link, outgoing, conversation_id).await?;
+        stream
+            .send(&queued.frame)
+            .await
+            .map_err(|e| e.to_string())?;
+        in_flight.sent(queued.sequence);
+    }
+    Ok(())
+}
+
+/// Answer a refused message on the stream of the conversation it was sent in
+async fn send_reply(
+    link: &QuicLink,
+    outgoing: &mut HashMap<String, ConversationStream>,
+    conversation_id: &str,
+    reply: &Message,
+) -> Result<(), String> {
+    let frame = reply.to_frame().map_err(|e| e.to_string())?;
+    let stream = outgoing_stream(link, outgoing, conversation_id.to_string()).await?;
+    stream.send(&frame).await.map_err(|e| e.to_string())
+}
+
+/// The server's stream for a conversation, opened on first use
+async fn outgoing_stream<'a>(
+    link: &QuicLink,
+    outgoing: &'a mut HashMap<String, ConversationStream>,
+    conversation_id: String,
+) -> Result<&'a mut ConversationStream, String> {
+    if !outgoing.contains_key(&conversation_id) {
+        // The client grants a limited number of streams; finishing one frees it
+        if outgoing.len() >= MAX_PUSH_STREAMS {
+            if let Some(evicted) = outgoing.keys().next().cloned() {
+                if let Some(mut stream) = outgoing.remove(&evicted) {
+                    let _ = stream.finish();
+                }
+            }
+        }
+
+        let stream = link
+            .open_conversation(&conversation_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        outgoing.insert(conversation_id.clone(), stream);
+    }
+    Ok(outgoing
+        .get_mut(&conversation_id)
+        .expect("stream was just opened"))
+}
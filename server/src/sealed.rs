@@ -0,0 +1,87 @@
+//! Sealed-sender support for XIPRNET server
+//!
+//! Issues sender certificates to authenticated devices and gates the
+//! unauthenticated delivery endpoint on recipient delivery tokens.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use xipr_core::protocol::auth::Session;
+use xipr_core::protocol::replay::MessageRejection;
+use xipr_core::protocol::sealed::{CertificateSigner, DeliveryToken, SenderCertificate};
+use xipr_core::protocol::transport::{Message, MessageRouter};
+
+use crate::ratelimit::RateLimiter;
+
+/// Burst of sealed deliveries allowed per source address
+const SOURCE_BURST: u32 = 60;
+const SOURCE_REFILL_PER_SEC: f64 = 1.0;
+
+/// Burst of sealed deliveries allowed per recipient device
+const RECIPIENT_BURST: u32 = 120;
+const RECIPIENT_REFILL_PER_SEC: f64 = 2.0;
+
+pub struct SealedSenderService {
+    signer: CertificateSigner,
+    /// Delivery token digests by device id
+    tokens: Mutex<HashMap<String, [u8; 32]>>,
+    by_source: RateLimiter,
+    by_recipient: RateLimiter,
+    /// Replay windows for sealed messages, one per recipient device since senders are unknown
+    replay: Mutex<MessageRouter>,
+}
+
+impl SealedSenderService {
+    pub fn new(signer: CertificateSigner) -> Self {
+        Self {
+            signer,
+            tokens: Mutex::new(HashMap::new()),
+            by_source: RateLimiter::new(SOURCE_BURST, SOURCE_REFILL_PER_SEC),
+            by_recipient: RateLimiter::new(RECIPIENT_BURST, RECIPIENT_REFILL_PER_SEC),
+            replay: Mutex::new(MessageRouter::default()),
+        }
+    }
+
+    pub fn issue_certificate(&self, session: &Session, identity_key: Vec<u8>) -> SenderCertificate {
+        self.signer.issue(
+            session.user_id.clone(),
+            session.device_id.clone(),
+            identity_key,
+            chrono::Utc::now().timestamp(),
+        )
+    }
+
+    /// Key id and public key clients should trust certificates from
+    pub fn signing_key(&self) -> (u32, [u8; 32]) {
+        (self.signer.key_id(), self.signer.public_key())
+    }
+
+    /// Replace the device's delivery token, invalidating the previous one
+    pub fn set_delivery_token(&self, device_id: &str, token: &DeliveryToken) {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.insert(device_id.to_string(), token.digest());
+    }
+
+    pub fn check_delivery_token(&self, device_id: &str, token: &DeliveryToken) -> bool {
+        let tokens = self.tokens.lock().unwrap();
+        tokens
+            .get(device_id)
+            .is_some_and(|digest| *digest == token.digest())
+    }
+
+    /// Drop a revoked device's delivery token, so sealed deliveries to it fail
+    pub fn forget_device(&self, device_id: &str) {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.remove(device_id);
+    }
+
+    /// Take a rate limit token for both the source and the recipient
+    pub fn allow_delivery(&self, source: &str, recipient: &str) -> bool {
+        self.by_source.check(source) && self.by_recipient.check(recipient)
+    }
+
+    /// Check a sealed message's timestamp and refuse an envelope its recipient was already sent
+    pub fn accept_delivery(&self, message: &Message, now: i64) -> Result<(), MessageRejection> {
+        let mut replay = self.replay.lock().unwrap();
+        replay.accept_message(&message.recipient_id, message, now)
+    }
+}
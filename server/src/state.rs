@@ -0,0 +1,60 @@
+//! Shared application state for XIPRNET server
+
+use crate::auth::AuthService;
+use crate::delivery::DeliveryService;
+use crate::devices::DeviceRegistry;
+use crate::mfa::MfaService;
+use crate::passkeys::PasskeyService;
+use crate::queue::MessageQueues;
+use crate::sealed::SealedSenderService;
+use crate::storage::StorageService;
+use crate::throttle::LoginThrottle;
+use std::sync::{Arc, Mutex};
+use xipr_core::protocol::compression::CompressionDictionary;
+use xipr_core::protocol::transport::MessageRouter;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub auth: Arc<AuthService>,
+    pub delivery: Arc<DeliveryService>,
+    pub devices: Arc<DeviceRegistry>,
+    pub mfa: Arc<MfaService>,
+    pub passkeys: Arc<PasskeyService>,
+    pub queues: Arc<MessageQueues>,
+    pub sealed: Arc<SealedSenderService>,
+    pub storage: Arc<StorageService>,
+    pub throttle: Arc<LoginThrottle>,
+    pub router: Arc<Mutex<MessageRouter>>,
+    /// Dictionary offered to WebSocket clients that negotiate compression
+    pub compression_dictionary: Option<Arc<CompressionDictionary>>,
+}
+
+impl AppState {
+    pub fn new(
+        auth: AuthService,
+        queues: Arc<MessageQueues>,
+        sealed: SealedSenderService,
+        mfa: MfaService,
+        passkeys: PasskeyService,
+        throttle: LoginThrottle,
+    ) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            delivery: Arc::new(DeliveryService::new(queues.clone())),
+            devices: Arc::new(DeviceRegistry::new()),
+            mfa: Arc::new(mfa),
+            passkeys: Arc::new(passkeys),
+            queues,
+            sealed: Arc::new(sealed),
+            storage: Arc::new(StorageService::new()),
+            throttle: Arc::new(throttle),
+            router: Arc::new(Mutex::new(MessageRouter::default())),
+            compression_dictionary: None,
+        }
+    }
+
+    pub fn with_compression_dictionary(mut self, dictionary: CompressionDictionary) -> Self {
+        self.compression_dictionary = Some(Arc::new(dictionary));
+        self
+    }
+}
@@ -9,6 +9,12 @@ pub struct StorageService {
     user_storage: Mutex<HashMap<String, Vec<u8>>>,
 }
 
+impl Default for StorageService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StorageService {
     pub fn new() -> Self {
         Self {
@@ -0,0 +1,71 @@
+//! In-process attempt store
+//!
+//! Used when no Redis URL is configured. Counts are per node and do not
+//! survive a restart.
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use xipr_core::protocol::throttle::{Attempts, PowChallenge};
+
+use super::{AttemptStore, ThrottleError};
+
+/// Keys held above which expired ones are pruned
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Default)]
+pub struct MemoryAttemptStore {
+    /// Attempts by key, with the time they are forgotten
+    attempts: Mutex<HashMap<String, (Attempts, i64)>>,
+    challenges: Mutex<HashMap<Vec<u8>, PowChallenge>>,
+}
+
+impl MemoryAttemptStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AttemptStore for MemoryAttemptStore {
+    async fn attempts(&self, key: &str) -> Result<Option<Attempts>, ThrottleError> {
+        let attempts = self.attempts.lock().unwrap();
+        Ok(attempts.get(key).map(|(attempts, _)| *attempts))
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: i64,
+        window: i64,
+    ) -> Result<Attempts, ThrottleError> {
+        let mut attempts = self.attempts.lock().unwrap();
+        if attempts.len() > PRUNE_THRESHOLD {
+            attempts.retain(|_, (_, forget_at)| *forget_at > now);
+        }
+
+        let previous = attempts
+            .get(key)
+            .filter(|(_, forget_at)| *forget_at > now)
+            .map(|(previous, _)| *previous);
+        let recorded = Attempts::record_failure(previous, now, window);
+        attempts.insert(key.to_string(), (recorded, now + window));
+        Ok(recorded)
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), ThrottleError> {
+        self.attempts.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn put_challenge(&self, challenge: &PowChallenge, now: i64) -> Result<(), ThrottleError> {
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.retain(|_, outstanding| outstanding.expires_at > now);
+        challenges.insert(challenge.seed.clone(), challenge.clone());
+        Ok(())
+    }
+
+    async fn take_challenge(&self, seed: &[u8]) -> Result<Option<PowChallenge>, ThrottleError> {
+        Ok(self.challenges.lock().unwrap().remove(seed))
+    }
+}
@@ -0,0 +1,176 @@
+//! Login throttling for XIPRNET server
+//!
+//! Failed logins are counted per account and per source address, in Redis
+//! when configured so every node sees the same counts. The policy in
+//! `xipr_core::protocol::throttle` turns the counts into a decision: let the
+//! attempt through, ask for a proof-of-work challenge, or refuse it until a
+//! backoff has passed. Challenges are single use, and each source address
+//! is only handed so many at a time, so asking for them cannot fill the store.
+
+pub mod memory;
+pub mod redis;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use thiserror::Error;
+use xipr_core::protocol::throttle::{
+    Attempts, Clock, PowChallenge, PowSolution, ThrottleDecision, ThrottlePolicy,
+};
+use xipr_core::utils::log_auth_failure;
+
+pub use self::memory::MemoryAttemptStore;
+pub use self::redis::RedisAttemptStore;
+
+#[derive(Debug, Error)]
+pub enum ThrottleError {
+    #[error("throttle backend error: {0}")]
+    Backend(String),
+}
+
+/// Storage backend for failure counts and outstanding challenges
+#[async_trait]
+pub trait AttemptStore: Send + Sync {
+    /// Failures recorded for a key and not yet forgotten
+    async fn attempts(&self, key: &str) -> Result<Option<Attempts>, ThrottleError>;
+
+    /// Count a failure, forgetting the key `window` seconds after it
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: i64,
+        window: i64,
+    ) -> Result<Attempts, ThrottleError>;
+
+    async fn clear(&self, key: &str) -> Result<(), ThrottleError>;
+
+    async fn put_challenge(&self, challenge: &PowChallenge, now: i64) -> Result<(), ThrottleError>;
+
+    /// Remove and return an outstanding challenge
+    async fn take_challenge(&self, seed: &[u8]) -> Result<Option<PowChallenge>, ThrottleError>;
+}
+
+fn account_key(account: &str) -> String {
+    format!("account:{}", account.to_lowercase())
+}
+
+fn source_key(source: &str) -> String {
+    format!("source:{}", source)
+}
+
+fn challenges_key(source: &str) -> String {
+    format!("challenges:{}", source)
+}
+
+/// What a client that must solve a challenge gets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChallengeIssue {
+    Issued(PowChallenge),
+    /// The source has been handed too many challenges and must wait this many seconds
+    Throttled {
+        retry_after: i64,
+    },
+}
+
+pub struct LoginThrottle {
+    store: Box<dyn AttemptStore>,
+    policy: ThrottlePolicy,
+    clock: Arc<dyn Clock>,
+}
+
+impl LoginThrottle {
+    pub fn new(
+        store: Box<dyn AttemptStore>,
+        policy: ThrottlePolicy,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            store,
+            policy,
+            clock,
+        }
+    }
+
+    /// Decide on an attempt from a source address, for an account if it is known yet
+    pub async fn check(
+        &self,
+        account: Option<&str>,
+        source: &str,
+    ) -> Result<ThrottleDecision, ThrottleError> {
+        let account = match account {
+            Some(account) => self.store.attempts(&account_key(account)).await?,
+            None => None,
+        };
+        let source = self.store.attempts(&source_key(source)).await?;
+        Ok(self
+            .policy
+            .evaluate(account.as_ref(), source.as_ref(), self.clock.now()))
+    }
+
+    /// Hand out a challenge that must be solved for the account, unless the
+    /// source has had `max_challenges` within a challenge lifetime already
+    ///
+    /// Issued challenges are counted like failures, under their own key.
+    pub async fn challenge(&self, source: &str) -> Result<ChallengeIssue, ThrottleError> {
+        let now = self.clock.now();
+        let lifetime = self.policy.challenge_lifetime;
+        let key = challenges_key(source);
+        if let Some(issued) = self.store.attempts(&key).await? {
+            if !issued.expired(now, lifetime) && issued.failures >= self.policy.max_challenges {
+                return Ok(ChallengeIssue::Throttled {
+                    retry_after: issued.last_failure + lifetime - now,
+                });
+            }
+        }
+        self.store.record_failure(&key, now, lifetime).await?;
+
+        let challenge = PowChallenge::generate(self.policy.pow_difficulty, now + lifetime);
+        self.store.put_challenge(&challenge, now).await?;
+        Ok(ChallengeIssue::Issued(challenge))
+    }
+
+    /// Whether a solution answers an outstanding challenge; each one answers once
+    pub async fn redeem(
+        &self,
+        account: &str,
+        solution: &PowSolution,
+    ) -> Result<bool, ThrottleError> {
+        let Some(challenge) = self.store.take_challenge(&solution.seed).await? else {
+            return Ok(false);
+        };
+        Ok(challenge.verify(account, solution, self.clock.now()))
+    }
+
+    /// Count a failed attempt against its source and, if known, its account
+    pub async fn record_failure(
+        &self,
+        account: Option<&str>,
+        source: &str,
+        reason: &str,
+    ) -> Result<(), ThrottleError> {
+        let now = self.clock.now();
+        if let Some(account) = account {
+            self.store
+                .record_failure(&account_key(account), now, self.policy.account.window)
+                .await?;
+        }
+        self.store
+            .record_failure(&source_key(source), now, self.policy.source.window)
+            .await?;
+
+        log_auth_failure(
+            account.unwrap_or("unknown"),
+            &format!("{} from {}", reason, source),
+        );
+        Ok(())
+    }
+
+    /// Forget an account's failures once it logs in; its source keeps its count
+    pub async fn record_success(&self, account: &str) -> Result<(), ThrottleError> {
+        self.store.clear(&account_key(account)).await
+    }
+
+    /// Forget a deleted account's failures
+    pub async fn forget_account(&self, account: &str) -> Result<(), ThrottleError> {
+        self.store.clear(&account_key(account)).await
+    }
+}
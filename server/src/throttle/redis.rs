@@ -0,0 +1,109 @@
+//! Redis-backed attempt store
+//!
+//! Each key is a hash of its failure count and last failure time, set to
+//! expire a window after the last failure. Challenges are stored as JSON
+//! under their seed until they expire or are taken.
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use xipr_core::protocol::throttle::{Attempts, PowChallenge};
+
+use super::{AttemptStore, ThrottleError};
+
+impl From<redis::RedisError> for ThrottleError {
+    fn from(error: redis::RedisError) -> Self {
+        ThrottleError::Backend(error.to_string())
+    }
+}
+
+pub struct RedisAttemptStore {
+    connection: MultiplexedConnection,
+}
+
+impl RedisAttemptStore {
+    pub async fn connect(url: &str) -> Result<Self, ThrottleError> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+        Ok(Self { connection })
+    }
+}
+
+fn attempts_key(key: &str) -> String {
+    format!("xipr:throttle:{}", key)
+}
+
+fn challenge_key(seed: &[u8]) -> String {
+    format!("xipr:challenge:{}", URL_SAFE_NO_PAD.encode(seed))
+}
+
+#[async_trait]
+impl AttemptStore for RedisAttemptStore {
+    async fn attempts(&self, key: &str) -> Result<Option<Attempts>, ThrottleError> {
+        let mut connection = self.connection.clone();
+        let (failures, last_failure): (Option<u32>, Option<i64>) = connection
+            .hget(attempts_key(key), &["failures", "last"])
+            .await?;
+
+        Ok(failures
+            .zip(last_failure)
+            .map(|(failures, last_failure)| Attempts {
+                failures,
+                last_failure,
+            }))
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        now: i64,
+        window: i64,
+    ) -> Result<Attempts, ThrottleError> {
+        let mut connection = self.connection.clone();
+        let key = attempts_key(key);
+
+        // The key expires a window after the last failure, so the count never outlives it
+        let (failures,): (u32,) = redis::pipe()
+            .atomic()
+            .hincr(&key, "failures", 1)
+            .hset(&key, "last", now)
+            .ignore()
+            .expire(&key, window)
+            .ignore()
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(Attempts {
+            failures,
+            last_failure: now,
+        })
+    }
+
+    async fn clear(&self, key: &str) -> Result<(), ThrottleError> {
+        let mut connection = self.connection.clone();
+        connection.del::<_, ()>(attempts_key(key)).await?;
+        Ok(())
+    }
+
+    async fn put_challenge(&self, challenge: &PowChallenge, now: i64) -> Result<(), ThrottleError> {
+        let mut connection = self.connection.clone();
+        let encoded =
+            serde_json::to_vec(challenge).map_err(|e| ThrottleError::Backend(e.to_string()))?;
+        let ttl = (challenge.expires_at - now).max(1) as u64;
+        connection
+            .set_ex::<_, _, ()>(challenge_key(&challenge.seed), encoded, ttl)
+            .await?;
+        Ok(())
+    }
+
+    async fn take_challenge(&self, seed: &[u8]) -> Result<Option<PowChallenge>, ThrottleError> {
+        let mut connection = self.connection.clone();
+        let encoded: Option<Vec<u8>> = connection.get_del(challenge_key(seed)).await?;
+        encoded
+            .map(|encoded| {
+                serde_json::from_slice(&encoded).map_err(|e| ThrottleError::Backend(e.to_string()))
+            })
+            .transpose()
+    }
+}
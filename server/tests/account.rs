@@ -0,0 +1,73 @@
+//! Account deletion through the HTTP API
+
+use axum::body::{to_bytes, Body};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Request, StatusCode};
+use std::sync::Arc;
+use tower::ServiceExt;
+use xipr_core::protocol::auth::{SessionKey, SessionKeyring};
+use xipr_core::protocol::deletion::DeletionReceipt;
+use xipr_core::protocol::mfa::FactorKey;
+use xipr_core::protocol::sealed::CertificateSigner;
+use xipr_core::protocol::throttle::{SystemClock, ThrottlePolicy};
+use xipr_core::protocol::webauthn::RelyingParty;
+use xipr_server::api;
+use xipr_server::auth::AuthService;
+use xipr_server::mfa::MfaService;
+use xipr_server::passkeys::PasskeyService;
+use xipr_server::queue::{MemoryQueueStore, MessageQueues, QueueConfig};
+use xipr_server::sealed::SealedSenderService;
+use xipr_server::state::AppState;
+use xipr_server::throttle::{LoginThrottle, MemoryAttemptStore};
+
+fn state() -> AppState {
+    let keyring = SessionKeyring::new(SessionKey::generate(1));
+    let queues = MessageQueues::new(Box::new(MemoryQueueStore::new()), QueueConfig::default());
+    AppState::new(
+        // Sessions made directly here have no device key to prove
+        AuthService::new(keyring).with_required_device_proof(false),
+        Arc::new(queues),
+        SealedSenderService::new(CertificateSigner::generate(1)),
+        MfaService::new(FactorKey::generate()),
+        PasskeyService::new(RelyingParty::new("xipr.example", "https://xipr.example")),
+        LoginThrottle::new(
+            Box::new(MemoryAttemptStore::new()),
+            ThrottlePolicy::default(),
+            Arc::new(SystemClock),
+        ),
+    )
+}
+
+#[tokio::test]
+async fn deleting_an_account_purges_its_media() {
+    let state = state();
+    let alice = state
+        .auth
+        .create_user("alice".to_string(), String::new(), None, Vec::new())
+        .unwrap();
+    let session = state
+        .auth
+        .create_session(alice.id.clone(), "alice-phone".to_string())
+        .unwrap();
+    state.mfa.step_up(&session, chrono::Utc::now().timestamp());
+    state.storage.store_user_data(&alice.id, b"avatar".to_vec());
+
+    let request = Request::delete("/api/v1/account")
+        .header(AUTHORIZATION, format!("Bearer {}", session.token))
+        .body(Body::empty())
+        .unwrap();
+    let response = api::router(state.clone()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let receipt: DeletionReceipt = serde_json::from_slice(&bytes).unwrap();
+
+    assert!(state.storage.get_user_data(&alice.id).is_none());
+    assert_eq!(receipt.purged.media_objects, 1);
+
+    // The count is covered by the signature
+    let (_, public_key) = state.auth.deletion_key();
+    receipt.verify(&public_key).unwrap();
+    let mut forged = receipt.clone();
+    forged.purged.media_objects = 0;
+    assert!(forged.verify(&public_key).is_err());
+}
@@ -0,0 +1,373 @@
+//! Commit ordering, epoch checks and fan-out in the MLS delivery service
+
+use std::ops::Deref;
+use std::sync::Arc;
+use xipr_core::protocol::mls::{
+    Commit, DeliveredMessage, MlsClient, MlsConfig, MlsContentType, MlsMessage, Proposal,
+};
+use xipr_core::protocol::transport::{ControlMessage, Message};
+use xipr_server::delivery::{DeliveryError, DeliveryService};
+use xipr_server::queue::{MemoryQueueStore, MessageQueues, QueueConfig};
+
+const ORG: &str = "acme";
+const GROUP: &[u8] = b"group";
+
+fn anyone(_: &str) -> bool {
+    true
+}
+
+fn commit(epoch: u64, committer: &str, proposals: Vec<Proposal>) -> Vec<u8> {
+    serde_json::to_vec(&Commit {
+        group_id: GROUP.to_vec(),
+        epoch,
+        committer: committer.as_bytes().to_vec(),
+        proposals,
+    })
+    .unwrap()
+}
+
+fn add(device_id: &str) -> Proposal {
+    let client = MlsClient::for_device(device_id.to_string(), device_id.to_string()).unwrap();
+    Proposal::Add(
+        client
+            .key_package(MlsConfig::new(GROUP.to_vec()).cipher_suite)
+            .unwrap(),
+    )
+}
+
+fn remove(device_id: &str) -> Proposal {
+    Proposal::Remove {
+        member_id: device_id.as_bytes().to_vec(),
+    }
+}
+
+/// The delivery service and the device queues it fans out to
+struct Delivery {
+    service: DeliveryService,
+    queues: Arc<MessageQueues>,
+}
+
+impl Delivery {
+    /// Group messages queued for a device, acknowledging them as a client would
+    async fn fetch_messages(&self, device_id: &str) -> Vec<DeliveredMessage> {
+        let pending = self.queues.pending_after(device_id, 0).await.unwrap();
+        if let Some(last) = pending.last() {
+            self.queues.ack(device_id, last.sequence).await.unwrap();
+        }
+
+        pending
+            .iter()
+            .map(|queued| {
+                let message = Message::from_frame(&queued.frame).unwrap();
+                match ControlMessage::from_message(&message).unwrap() {
+                    ControlMessage::Group(delivered) => delivered,
+                    other => panic!("expected a group message, got {other:?}"),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Deref for Delivery {
+    type Target = DeliveryService;
+
+    fn deref(&self) -> &DeliveryService {
+        &self.service
+    }
+}
+
+/// A group of alice and bob at epoch 1
+async fn group() -> Delivery {
+    let queues = Arc::new(MessageQueues::new(
+        Box::new(MemoryQueueStore::new()),
+        QueueConfig::default(),
+    ));
+    let service = DeliveryService::new(queues.clone());
+    service
+        .create_group(ORG, GROUP.to_vec(), "alice".to_string())
+        .unwrap();
+    service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(0, "alice", vec![add("bob")]),
+            Vec::new(),
+            anyone,
+        )
+        .await
+        .unwrap();
+    Delivery { service, queues }
+}
+
+fn members(service: &DeliveryService) -> Vec<String> {
+    service.group_members(ORG, GROUP).unwrap()
+}
+
+#[tokio::test]
+async fn the_first_commit_for_an_epoch_wins() {
+    let service = group().await;
+    service.fetch_messages("bob").await;
+
+    let epoch = service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "bob",
+            commit(1, "bob", Vec::new()),
+            Vec::new(),
+            anyone,
+        )
+        .await
+        .unwrap();
+    assert_eq!(epoch, 2);
+
+    // Alice built hers on the same epoch and lost the race
+    let result = service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(1, "alice", vec![add("carol")]),
+            Vec::new(),
+            anyone,
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(DeliveryError::StaleEpoch {
+            current: 2,
+            received: 1
+        })
+    ));
+    assert_eq!(members(&service), ["alice", "bob"]);
+
+    // Only the winner reached the other member, in group order
+    let inbox = service.fetch_messages("alice").await;
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].epoch, 1);
+    assert_eq!(inbox[0].content_type, MlsContentType::Commit);
+    assert!(service.fetch_messages("bob").await.is_empty());
+}
+
+#[tokio::test]
+async fn commits_for_a_future_epoch_are_rejected() {
+    let service = group().await;
+    let result = service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(5, "alice", Vec::new()),
+            Vec::new(),
+            anyone,
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(DeliveryError::FutureEpoch {
+            current: 1,
+            received: 5
+        })
+    ));
+    assert_eq!(service.group_epoch(ORG, GROUP), Some(1));
+}
+
+#[tokio::test]
+async fn a_malformed_proposal_leaves_the_group_untouched() {
+    let service = group().await;
+    service.fetch_messages("bob").await;
+
+    let malformed = Proposal::Remove {
+        member_id: vec![0xff, 0xfe],
+    };
+    let result = service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(1, "alice", vec![add("carol"), remove("bob"), malformed]),
+            vec![("carol".to_string(), b"welcome".to_vec())],
+            anyone,
+        )
+        .await;
+    assert!(matches!(result, Err(DeliveryError::Malformed(_))));
+
+    assert_eq!(service.group_epoch(ORG, GROUP), Some(1));
+    assert_eq!(members(&service), ["alice", "bob"]);
+    assert!(service.fetch_messages("bob").await.is_empty());
+    assert!(service.claim_welcomes("carol").is_empty());
+}
+
+#[tokio::test]
+async fn outsiders_may_not_be_added() {
+    let service = group().await;
+    let result = service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(1, "alice", vec![add("mallory")]),
+            Vec::new(),
+            |device_id| device_id != "mallory",
+        )
+        .await;
+    assert!(matches!(result, Err(DeliveryError::OtherOrganisation(device)) if device == "mallory"));
+    assert_eq!(members(&service), ["alice", "bob"]);
+    assert_eq!(service.group_epoch(ORG, GROUP), Some(1));
+}
+
+#[tokio::test]
+async fn welcomes_only_go_to_devices_the_commit_adds() {
+    let service = group().await;
+    service.fetch_messages("bob").await;
+
+    let result = service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(1, "alice", vec![add("carol")]),
+            vec![
+                ("carol".to_string(), b"welcome".to_vec()),
+                ("bob".to_string(), b"not a welcome".to_vec()),
+            ],
+            anyone,
+        )
+        .await;
+    assert!(matches!(result, Err(DeliveryError::UnexpectedWelcome(device)) if device == "bob"));
+
+    assert_eq!(service.group_epoch(ORG, GROUP), Some(1));
+    assert_eq!(members(&service), ["alice", "bob"]);
+    assert!(service.fetch_messages("bob").await.is_empty());
+    assert!(service.claim_welcomes("bob").is_empty());
+    assert!(service.claim_welcomes("carol").is_empty());
+}
+
+#[tokio::test]
+async fn removals_apply_and_welcomes_wait_to_be_claimed() {
+    let service = group().await;
+    service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(1, "alice", vec![add("carol"), remove("bob")]),
+            vec![("carol".to_string(), b"welcome".to_vec())],
+            anyone,
+        )
+        .await
+        .unwrap();
+    assert_eq!(members(&service), ["alice", "carol"]);
+
+    // The removed member still learns of its removal
+    let inbox = service.fetch_messages("bob").await;
+    assert_eq!(inbox.last().unwrap().epoch, 1);
+
+    let welcomes = service.claim_welcomes("carol");
+    assert_eq!(welcomes.len(), 1);
+    assert_eq!(welcomes[0].epoch, 2);
+    assert!(service.claim_welcomes("carol").is_empty());
+}
+
+#[tokio::test]
+async fn handshake_and_application_messages_share_one_order() {
+    let service = group().await;
+    service.fetch_messages("bob").await;
+
+    let application = |epoch: u64| {
+        serde_json::to_vec(&MlsMessage {
+            group_id: GROUP.to_vec(),
+            epoch,
+            content_type: MlsContentType::Application,
+            ciphertext: vec![1, 2, 3],
+        })
+        .unwrap()
+    };
+
+    service
+        .submit_application(ORG, GROUP, "alice", application(1))
+        .await
+        .unwrap();
+    service
+        .submit_commit(
+            ORG,
+            GROUP,
+            "alice",
+            commit(1, "alice", Vec::new()),
+            Vec::new(),
+            anyone,
+        )
+        .await
+        .unwrap();
+    // Still decryptable by members that keep the previous epoch's secret
+    service
+        .submit_application(ORG, GROUP, "alice", application(1))
+        .await
+        .unwrap();
+    assert!(matches!(
+        service
+            .submit_application(ORG, GROUP, "alice", application(3))
+            .await,
+        Err(DeliveryError::FutureEpoch { .. })
+    ));
+
+    let sequences: Vec<u64> = service
+        .fetch_messages("bob")
+        .await
+        .iter()
+        .map(|message| message.group_sequence)
+        .collect();
+    assert_eq!(sequences, [1, 2, 3]);
+    assert!(service.fetch_messages("alice").await.is_empty());
+}
+
+#[tokio::test]
+async fn groups_are_invisible_to_other_organisations() {
+    let service = group().await;
+    assert_eq!(service.group_epoch("other", GROUP), None);
+    assert!(matches!(
+        service
+            .submit_commit(
+                "other",
+                GROUP,
+                "alice",
+                commit(1, "alice", Vec::new()),
+                Vec::new(),
+                anyone
+            )
+            .await,
+        Err(DeliveryError::UnknownGroup)
+    ));
+}
+
+#[tokio::test]
+async fn group_messages_stay_queued_until_acknowledged() {
+    let service = group().await;
+    let application = serde_json::to_vec(&MlsMessage {
+        group_id: GROUP.to_vec(),
+        epoch: 1,
+        content_type: MlsContentType::Application,
+        ciphertext: vec![1, 2, 3],
+    })
+    .unwrap();
+    service
+        .submit_application(ORG, GROUP, "alice", application)
+        .await
+        .unwrap();
+
+    // A fetch whose response was lost leaves everything in place
+    let first = service.queues.pending_after("bob", 0).await.unwrap();
+    let again = service.queues.pending_after("bob", 0).await.unwrap();
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].sequence, again[0].sequence);
+    assert_eq!(
+        first[0].frame.header.message_id,
+        again[0].frame.header.message_id
+    );
+
+    let delivered = service.fetch_messages("bob").await;
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].content_type, MlsContentType::Application);
+    assert!(service.fetch_messages("bob").await.is_empty());
+}
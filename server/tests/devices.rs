@@ -0,0 +1,71 @@
+//! Device registration in the `DeviceRegistry`
+
+use xipr_core::protocol::provisioning::{ProvisionEnvelope, ProvisionMessage, ProvisioningKeys};
+use xipr_server::devices::{DeviceError, DeviceRegistry};
+
+#[test]
+fn device_ids_are_assigned_by_the_server() {
+    let registry = DeviceRegistry::new();
+
+    // A chosen id is never taken as is, so it cannot be claimed ahead of its owner
+    let mallory = registry.register("mallory", Some("bob-phone")).unwrap();
+    assert_ne!(mallory, "bob-phone");
+    let bob = registry.register("bob", Some("bob-phone")).unwrap();
+    assert_ne!(bob, mallory);
+
+    // Devices keep their own id across logins, but not someone else's
+    assert_eq!(registry.register("bob", Some(&bob)).unwrap(), bob);
+    let stolen = registry.register("mallory", Some(&bob)).unwrap();
+    assert_ne!(stolen, bob);
+    assert_eq!(registry.owner(&bob).as_deref(), Some("bob"));
+
+    let first = registry.register("bob", None).unwrap();
+    assert_ne!(first, bob);
+    assert_eq!(registry.devices_of("bob").len(), 2);
+}
+
+#[test]
+fn revoked_devices_cannot_log_back_in() {
+    let registry = DeviceRegistry::new();
+    let device = registry.register("alice", None).unwrap();
+
+    registry.revoke("alice", &device).unwrap();
+    assert!(matches!(
+        registry.register("alice", Some(&device)),
+        Err(DeviceError::Revoked)
+    ));
+}
+
+#[test]
+fn link_grants_with_malformed_envelopes_are_refused() {
+    let registry = DeviceRegistry::new();
+    let (provisioning_id, _) = registry.open_link("192.0.2.1").unwrap();
+    let keys = ProvisioningKeys::generate(provisioning_id.clone());
+    let message = ProvisionMessage::new(
+        "alice".to_string(),
+        "alice-phone".to_string(),
+        vec![1; 32],
+        vec![2; 32],
+    );
+    let envelope = ProvisionEnvelope::seal(&keys.code(), &message).unwrap();
+    let grant = |envelope: ProvisionEnvelope| {
+        registry.grant_link(
+            &provisioning_id,
+            "alice",
+            "alice-phone",
+            envelope,
+            message.link_code_digest(),
+        )
+    };
+
+    let mut short_nonce = envelope.clone();
+    short_nonce.nonce.truncate(8);
+    assert!(matches!(
+        grant(short_nonce),
+        Err(DeviceError::MalformedEnvelope)
+    ));
+
+    // The slot is still open for a well-formed envelope
+    grant(envelope).unwrap();
+    assert!(registry.link_envelope(&provisioning_id).is_ok());
+}
@@ -0,0 +1,360 @@
+//! Password login through the HTTP API
+
+use axum::body::{to_bytes, Body};
+use axum::extract::ConnectInfo;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use axum::http::{Method, Request, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tower::ServiceExt;
+use xipr_core::protocol::auth::{Session, SessionKey, SessionKeyring};
+use xipr_core::protocol::mfa::{FactorKey, TotpSecret};
+use xipr_core::protocol::proof::{ProofTarget, RequestProof, SigningKey, PROOF_HEADER};
+use xipr_core::protocol::sealed::CertificateSigner;
+use xipr_core::protocol::throttle::{FakeClock, PowChallenge, ThrottleLimits, ThrottlePolicy};
+use xipr_core::protocol::webauthn::RelyingParty;
+use xipr_server::api;
+use xipr_server::auth::AuthService;
+use xipr_server::mfa::MfaService;
+use xipr_server::passkeys::PasskeyService;
+use xipr_server::queue::{MemoryQueueStore, MessageQueues, QueueConfig};
+use xipr_server::sealed::SealedSenderService;
+use xipr_server::state::AppState;
+use xipr_server::throttle::{LoginThrottle, MemoryAttemptStore};
+
+const NOW: i64 = 1_700_000_000;
+
+fn state(policy: ThrottlePolicy, clock: FakeClock) -> AppState {
+    let keyring = SessionKeyring::new(SessionKey::generate(1));
+    let queues = MessageQueues::new(Box::new(MemoryQueueStore::new()), QueueConfig::default());
+    AppState::new(
+        AuthService::new(keyring),
+        Arc::new(queues),
+        SealedSenderService::new(CertificateSigner::generate(1)),
+        MfaService::new(FactorKey::generate()),
+        PasskeyService::new(RelyingParty::new("xipr.example", "https://xipr.example")),
+        LoginThrottle::new(Box::new(MemoryAttemptStore::new()), policy, Arc::new(clock)),
+    )
+}
+
+/// Public half of the signing key every test device binds
+fn device_key() -> Vec<u8> {
+    SigningKey::from_bytes(&[7; 32])
+        .verifying_key()
+        .to_bytes()
+        .to_vec()
+}
+
+async fn send(app: &Router, body: Value) -> Response {
+    let mut request = Request::post("/api/v1/auth/login")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 1], 4000))));
+
+    app.clone().oneshot(request).await.unwrap()
+}
+
+async fn login(app: &Router, body: Value) -> (StatusCode, Value) {
+    read(send(app, body).await).await
+}
+
+/// A request from the test device, with its bearer token and proof
+async fn call(
+    app: &Router,
+    session: &Session,
+    method: Method,
+    path: &str,
+    body: Value,
+) -> (StatusCode, Value) {
+    let body = body.to_string();
+    let proof = RequestProof::sign(
+        &SigningKey::from_bytes(&[7; 32]),
+        &ProofTarget {
+            method: method.as_str(),
+            path,
+            query: "",
+            body: body.as_bytes(),
+            token: &session.token,
+        },
+        chrono::Utc::now().timestamp(),
+    );
+    let request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, format!("Bearer {}", session.token))
+        .header(PROOF_HEADER, proof.to_string())
+        .body(Body::from(body))
+        .unwrap();
+
+    read(app.clone().oneshot(request).await.unwrap()).await
+}
+
+async fn read(response: Response) -> (StatusCode, Value) {
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+#[tokio::test]
+async fn username_and_password_alone_do_not_log_in() {
+    let state = state(ThrottlePolicy::default(), FakeClock::new(NOW));
+    state
+        .auth
+        .create_user("alice".to_string(), String::new(), None, Vec::new())
+        .unwrap();
+    let app = api::router(state);
+
+    let (status, body) = login(
+        &app,
+        json!({ "username": "alice", "password": "anything", "signing_key": device_key() }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["second_factor_required"], true);
+    assert!(body["session"].is_null());
+
+    // Without an enrolled factor there is nothing to check, so any factor is refused
+    let (status, _) = login(
+        &app,
+        json!({
+            "username": "alice",
+            "password": "anything",
+            "signing_key": device_key(),
+            "second_factor": { "type": "totp", "code": "123456" },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn enrolled_users_log_in_with_their_factor_and_are_stepped_up() {
+    let state = state(ThrottlePolicy::default(), FakeClock::new(NOW));
+    let alice = state
+        .auth
+        .create_user("alice".to_string(), String::new(), None, Vec::new())
+        .unwrap();
+    let now = chrono::Utc::now().timestamp();
+    let secret = state.mfa.begin_enrolment(&alice.id).unwrap();
+    let recovery_codes = state
+        .mfa
+        .confirm_enrolment(&alice.id, &secret.code_at(now), now)
+        .unwrap();
+
+    // A fresh session proves nothing by itself
+    let session = state
+        .auth
+        .create_session(alice.id.clone(), "alice-phone".to_string())
+        .unwrap();
+    assert!(state.mfa.stepped_up_until(&session, now).is_none());
+
+    let app = api::router(state.clone());
+    let (status, body) = login(
+        &app,
+        json!({
+            "username": "alice",
+            "password": "anything",
+            "signing_key": device_key(),
+            "second_factor": { "type": "recovery_code", "code": recovery_codes[0] },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["success"], true);
+    let session: Session = serde_json::from_value(body["session"].clone()).unwrap();
+    assert!(state.mfa.stepped_up_until(&session, now).is_some());
+}
+
+#[tokio::test]
+async fn repeated_bad_logins_are_challenged_then_backed_off() {
+    let policy = ThrottlePolicy {
+        account: ThrottleLimits {
+            challenge_after: 2,
+            backoff_after: 3,
+            window: 60 * 60,
+        },
+        pow_difficulty: 4,
+        ..ThrottlePolicy::default()
+    };
+    let app = api::router(state(policy, FakeClock::new(NOW)));
+    let attempt = json!({
+        "username": "mallory",
+        "password": "guess",
+        "signing_key": device_key(),
+        "second_factor": { "type": "totp", "code": "000000" },
+    });
+
+    // An account that does not exist fails like a wrong code
+    for _ in 0..2 {
+        let (status, _) = login(&app, attempt.clone()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    let (status, body) = login(&app, attempt.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["success"], false);
+    let challenge: PowChallenge = serde_json::from_value(body["challenge"].clone()).unwrap();
+
+    // The solved challenge buys one more attempt, whose failure crosses into backoff
+    let mut solved = attempt.clone();
+    solved["proof_of_work"] = serde_json::to_value(challenge.solve("mallory")).unwrap();
+    let (status, _) = login(&app, solved).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let response = send(&app, attempt).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[RETRY_AFTER], "1");
+}
+
+#[tokio::test]
+async fn devices_without_a_signing_key_get_no_session() {
+    let state = state(ThrottlePolicy::default(), FakeClock::new(NOW));
+    let alice = state
+        .auth
+        .create_user("alice".to_string(), String::new(), None, Vec::new())
+        .unwrap();
+    let now = chrono::Utc::now().timestamp();
+    let secret = state.mfa.begin_enrolment(&alice.id).unwrap();
+    let recovery_codes = state
+        .mfa
+        .confirm_enrolment(&alice.id, &secret.code_at(now), now)
+        .unwrap();
+    let app = api::router(state.clone());
+    let attempt = |signing_key: Option<Vec<u8>>| {
+        json!({
+            "username": "alice",
+            "password": "anything",
+            "signing_key": signing_key,
+            "second_factor": { "type": "recovery_code", "code": recovery_codes[0] },
+        })
+    };
+
+    // Its bearer token alone would be usable from anywhere
+    let (status, _) = login(&app, attempt(None)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Refused before the factor was checked, so the code still works
+    let (status, body) = login(&app, attempt(Some(device_key()))).await;
+    assert_eq!(status, StatusCode::OK);
+    let session: Session = serde_json::from_value(body["session"].clone()).unwrap();
+    assert!(state.devices.signing_key(&session.device_id).is_some());
+}
+
+#[tokio::test]
+async fn new_users_enrol_their_first_factor_with_their_enrolment_token() {
+    let state = state(ThrottlePolicy::default(), FakeClock::new(NOW));
+    let now = chrono::Utc::now().timestamp();
+    let alice = state
+        .auth
+        .create_user("alice".to_string(), String::new(), None, Vec::new())
+        .unwrap();
+    let token = state.mfa.issue_enrolment_token(&alice.id, now).unwrap();
+    let app = api::router(state.clone());
+    let with_token = json!({
+        "username": "alice",
+        "password": "anything",
+        "signing_key": device_key(),
+        "second_factor": { "type": "enrolment_token", "token": token },
+    });
+
+    let (status, body) = login(&app, with_token.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let session: Session = serde_json::from_value(body["session"].clone()).unwrap();
+
+    // The token is spent by that login
+    let (status, _) = login(&app, with_token).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Stepped up by the token, so enrolment can start
+    let (status, body) = call(
+        &app,
+        &session,
+        Method::POST,
+        "/api/v1/auth/mfa/totp",
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = TotpSecret::from_base32(body["secret"].as_str().unwrap()).unwrap();
+    let (status, body) = call(
+        &app,
+        &session,
+        Method::POST,
+        "/api/v1/auth/mfa/totp/confirm",
+        json!({ "code": secret.code_at(chrono::Utc::now().timestamp()) }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let recovery_code = body["recovery_codes"][0].as_str().unwrap().to_string();
+
+    let (_, status_body) = call(&app, &session, Method::GET, "/api/v1/auth/mfa", json!({})).await;
+    assert_eq!(status_body["totp_enabled"], true);
+
+    // From now on the enrolled factor logs in, and enrolled users get no new token
+    let (status, body) = login(
+        &app,
+        json!({
+            "username": "alice",
+            "password": "anything",
+            "signing_key": device_key(),
+            "second_factor": { "type": "recovery_code", "code": recovery_code },
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let session: Session = serde_json::from_value(body["session"].clone()).unwrap();
+    assert!(state.mfa.stepped_up_until(&session, now).is_some());
+    assert!(state.mfa.issue_enrolment_token(&alice.id, now).is_err());
+}
+
+#[tokio::test]
+async fn an_address_is_only_handed_so_many_challenges() {
+    let policy = ThrottlePolicy {
+        source: ThrottleLimits {
+            challenge_after: 1,
+            backoff_after: 100,
+            window: 60 * 60,
+        },
+        max_challenges: 2,
+        ..ThrottlePolicy::default()
+    };
+    let clock = FakeClock::new(NOW);
+    let app = api::router(state(policy, clock.clone()));
+    let attempt = |username: &str| {
+        json!({
+            "username": username,
+            "password": "guess",
+            "signing_key": device_key(),
+            "second_factor": { "type": "totp", "code": "000000" },
+        })
+    };
+    let (status, _) = login(&app, attempt("mallory")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Asking is free for the client, so a source gets a few, whatever account it names
+    for username in ["mallory", "trent"] {
+        let (status, body) = login(&app, attempt(username)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["challenge"].is_object());
+    }
+    let response = send(&app, attempt("victor")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()[RETRY_AFTER], "300");
+
+    // Once those could have expired it gets more
+    clock.advance(300);
+    let (status, body) = login(&app, attempt("victor")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["challenge"].is_object());
+}
@@ -0,0 +1,23 @@
+//! Limits on passkey login ceremonies
+
+use xipr_core::protocol::webauthn::RelyingParty;
+use xipr_server::passkeys::PasskeyService;
+
+#[test]
+fn login_ceremonies_are_limited_per_source() {
+    let passkeys = PasskeyService::new(RelyingParty::new("xipr.example", "https://xipr.example"));
+
+    let allowed = (0..100)
+        .take_while(|_| passkeys.allow_authentication("192.0.2.1"))
+        .count();
+    assert!(
+        (1..100).contains(&allowed),
+        "{} ceremonies allowed",
+        allowed
+    );
+    assert!(!passkeys.allow_authentication("192.0.2.1"));
+
+    // Other sources keep their own allowance
+    assert!(passkeys.allow_authentication("192.0.2.2"));
+    passkeys.start_authentication(1_000).unwrap();
+}